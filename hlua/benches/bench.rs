@@ -15,6 +15,46 @@ fn criterion_benchmark(c: &mut Criterion) {
     c.bench_function("vec 10", |b| b.iter(|| read_vec("vec10", black_box(&mut lua))));
     c.bench_function("vec 100", |b| b.iter(|| read_vec("vec100", black_box(&mut lua))));
 
+    lua.execute::<()>(
+        r#"
+            config = {}
+            for i = 1, 20 do
+                config["section" .. i] = {
+                    name = "section " .. i,
+                    enabled = i % 2 == 0,
+                    values = { 1, 2, 3, 4, 5, 6, 7, 8, 9, 10 },
+                    nested = { a = { b = { c = i } } },
+                }
+            end
+        "#,
+    )
+    .unwrap();
+    c.bench_function("nested table into AnyLuaValue", |b| {
+        b.iter(|| black_box(&mut lua).get::<hlua::AnyLuaValue, _>("config").unwrap())
+    });
+
+    lua.execute::<()>(
+        r#"
+            matrix = {}
+            for i = 1, 50 do
+                matrix[i] = {}
+                for j = 1, 50 do matrix[i][j] = i * j end
+            end
+        "#,
+    )
+    .unwrap();
+    c.bench_function("nested arrays into AnyLuaValue", |b| {
+        b.iter(|| black_box(&mut lua).get::<hlua::AnyLuaValue, _>("matrix").unwrap())
+    });
+
+    lua.execute::<()>("function lfunc(x) return x end").unwrap();
+    c.bench_function("[lua -> lua] call lfunc(u32): u32 (x10000)", |b| {
+        b.iter(|| lua.execute::<()>("for i=0,10000 do lfunc(1) end").unwrap())
+    });
+    c.bench_function("[lua] empty loop (x10000)", |b| {
+        b.iter(|| lua.execute::<()>("for i=0,10000 do end").unwrap())
+    });
+
     lua.set("func", hlua::function0(|| 1));
     c.bench_function("[lua -> c] call func(): 1 (x10000)", |b| {
         b.iter(|| lua.execute::<()>("for i=0,10000 do func() end").unwrap())
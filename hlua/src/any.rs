@@ -1,17 +1,65 @@
-use crate::AsMutLua;
+use std::{
+    cmp::Ordering,
+    hash::{Hash, Hasher},
+};
 
-use crate::{LuaNil, LuaRead, LuaTable, Push, PushGuard, PushOne, Void};
+use crate::{ffix, AsMutLua, LuaContext};
+
+use crate::{LuaNil, LuaRead, Push, PushGuard, PushOne, Void};
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct AnyLuaString(pub Vec<u8>);
 
+/// A floating point number that can be compared and hashed.
+///
+/// Numbers are compared according to the IEEE 754 total order, which means that `NaN` is equal to
+/// itself and that `-0.0` and `0.0` are distinct.
+#[derive(Clone, Copy, Debug)]
+pub struct LuaFloat(pub f64);
+
+impl PartialEq for LuaFloat {
+    #[inline]
+    fn eq(&self, other: &LuaFloat) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for LuaFloat {}
+
+impl PartialOrd for LuaFloat {
+    #[inline]
+    fn partial_cmp(&self, other: &LuaFloat) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LuaFloat {
+    #[inline]
+    fn cmp(&self, other: &LuaFloat) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl Hash for LuaFloat {
+    #[inline]
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // Two numbers are equal in the total order if and only if their bits are equal.
+        self.0.to_bits().hash(state)
+    }
+}
+
 /// Represents any value that can be stored by Lua
 #[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum AnyHashableLuaValue {
     LuaString(String),
     LuaAnyString(AnyLuaString),
     LuaInteger(i32),
+    /// A number that isn't an integer fitting in an `i32`.
+    LuaFloat(LuaFloat),
     LuaBoolean(bool),
+    /// The entries of a table. When a table contains itself, directly or not, it is read as
+    /// `LuaOther` where it appears inside itself. Tables nested more than 128 levels deep are
+    /// read as `LuaOther` too.
     LuaArray(Vec<(AnyHashableLuaValue, AnyHashableLuaValue)>),
     LuaNil,
 
@@ -25,9 +73,16 @@ pub enum AnyHashableLuaValue {
 pub enum AnyLuaValue {
     LuaString(String),
     LuaAnyString(AnyLuaString),
+    /// A number. This is always pushed as a float, even when it is integral, since the integers
+    /// of Lua 5.4 wrap around on overflow while floats don't.
     LuaNumber(f64),
+    /// An integer. With Lua 5.4, this is pushed with the integer subtype, and reading it back
+    /// gives a `LuaNumber`. Other versions don't have an integer subtype and push a float.
     LuaInteger(i32),
     LuaBoolean(bool),
+    /// The entries of a table. When a table contains itself, directly or not, it is read as
+    /// `LuaOther` where it appears inside itself. Tables nested more than 128 levels deep are
+    /// read as `LuaOther` too.
     LuaArray(Vec<(AnyLuaValue, AnyLuaValue)>),
     LuaNil,
 
@@ -91,18 +146,132 @@ where
                 .or_else(|lua| LuaRead::lua_read_at_position(lua, index).map(Value::LuaBoolean)),
             ffi::LUA_TNUMBER => Err(raw_lua)
                 .or_else(|lua| LuaRead::lua_read_at_position(lua, index).map(Value::LuaNumber)),
+            // Reading a `String` fails on invalid UTF-8, which falls through to `LuaAnyString`.
             ffi::LUA_TSTRING => Err(raw_lua)
                 .or_else(|lua| LuaRead::lua_read_at_position(lua, index).map(Value::LuaString))
                 .or_else(|lua| LuaRead::lua_read_at_position(lua, index).map(Value::LuaAnyString)),
-            ffi::LUA_TTABLE => LuaTable::lua_read_at_position(raw_lua, index)
-                .map(|mut v| v.iter::<Value, Value>().flatten().collect())
-                .map(Value::LuaArray),
+            // Tables are read on a copy at the top of the stack, which gives them an absolute
+            // index that stays valid while their content is pushed.
+            ffi::LUA_TTABLE => unsafe {
+                ffi::lua_pushvalue(raw_lua.as_ptr(), index);
+                let top = ffi::lua_gettop(raw_lua.as_ptr());
+                let value = read_table(raw_lua, top, &mut Vec::new(), &|_, _| false);
+                ffi::lua_pop(raw_lua.as_ptr(), 1);
+                Ok(value)
+            },
             _ => Ok(Value::LuaOther),
         }
         .or(Ok(Value::LuaOther))
     }
 }
 
+// Returns how many entries to allocate when reading the table at `index`.
+//
+// The length of a table is usually the number of elements of its sequence, which is a lower bound
+// of the number of entries. However Lua may return any border of the table, and a sparse table can
+// have a border much larger than its number of entries, so we only trust it up to a limit.
+#[inline]
+pub(crate) fn table_capacity(lua: LuaContext, index: i32) -> usize {
+    const MAX_PREALLOCATED: usize = 1 << 16;
+    unsafe { ffix::lua_rawlen(lua, index) }.min(MAX_PREALLOCATED)
+}
+
+// Maximum number of nested tables read into a value, past which reading would overflow the stack
+// of the thread before the stack of Lua.
+const MAX_DEPTH: usize = 128;
+
+// Value that can hold the entries of a table, which lets `AnyLuaValue` and `AnyHashableLuaValue`
+// share the code that reads tables.
+pub(crate) trait TableValue: Sized {
+    fn array(entries: Vec<(Self, Self)>) -> Self;
+
+    fn other() -> Self;
+
+    // Reads the value at `index`, which isn't a table.
+    fn read_scalar(lua: LuaContext, index: i32) -> Self;
+}
+
+impl TableValue for AnyLuaValue {
+    #[inline]
+    fn array(entries: Vec<(AnyLuaValue, AnyLuaValue)>) -> AnyLuaValue {
+        AnyLuaValue::LuaArray(entries)
+    }
+
+    #[inline]
+    fn other() -> AnyLuaValue {
+        AnyLuaValue::LuaOther
+    }
+
+    #[inline]
+    fn read_scalar(lua: LuaContext, index: i32) -> AnyLuaValue {
+        AnyLuaValue::lua_read_at_position(lua, index).unwrap_or(AnyLuaValue::LuaOther)
+    }
+}
+
+impl TableValue for AnyHashableLuaValue {
+    #[inline]
+    fn array(entries: Vec<(AnyHashableLuaValue, AnyHashableLuaValue)>) -> AnyHashableLuaValue {
+        AnyHashableLuaValue::LuaArray(entries)
+    }
+
+    #[inline]
+    fn other() -> AnyHashableLuaValue {
+        AnyHashableLuaValue::LuaOther
+    }
+
+    #[inline]
+    fn read_scalar(lua: LuaContext, index: i32) -> AnyHashableLuaValue {
+        AnyHashableLuaValue::lua_read_at_position(lua, index)
+            .unwrap_or(AnyHashableLuaValue::LuaOther)
+    }
+}
+
+// Reads the table at the absolute position `index`, leaving out the entries for which `skip`
+// returns true. `path` contains the tables being read. A table that contains itself, directly or
+// not, is read as `other()` where it appears inside itself, and so are tables nested too deeply.
+pub(crate) unsafe fn read_table<V: TableValue>(
+    lua: LuaContext,
+    index: i32,
+    path: &mut Vec<*const libc::c_void>,
+    skip: &dyn Fn(i32, i32) -> bool,
+) -> V {
+    let ptr = ffi::lua_topointer(lua.as_ptr(), index);
+    // Each level of nesting pushes a key, a value, and a copy of the key.
+    if path.len() >= MAX_DEPTH || path.contains(&ptr) || ffi::lua_checkstack(lua.as_ptr(), 3) == 0 {
+        return V::other();
+    }
+    path.push(ptr);
+
+    let mut entries = Vec::with_capacity(table_capacity(lua, index));
+    ffi::lua_pushnil(lua.as_ptr());
+    while ffi::lua_next(lua.as_ptr(), index) != 0 {
+        let top = ffi::lua_gettop(lua.as_ptr());
+        if !skip(top - 1, top) {
+            // Reading the key is done on a copy, so that strings read from numbers don't
+            // confuse `lua_next`.
+            ffi::lua_pushvalue(lua.as_ptr(), top - 1);
+            let key = read_value(lua, top + 1, path);
+            ffi::lua_pop(lua.as_ptr(), 1);
+            entries.push((key, read_value(lua, top, path)));
+        }
+        ffi::lua_pop(lua.as_ptr(), 1);
+    }
+
+    path.pop();
+    V::array(entries)
+}
+
+unsafe fn read_value<V: TableValue>(
+    lua: LuaContext,
+    index: i32,
+    path: &mut Vec<*const libc::c_void>,
+) -> V {
+    match ffi::lua_istable(lua.as_ptr(), index) {
+        true => read_table(lua, index, path, &|_, _| false),
+        false => V::read_scalar(lua, index),
+    }
+}
+
 impl<'lua, L> Push<L> for AnyHashableLuaValue
 where
     L: AsMutLua<'lua>,
@@ -120,6 +289,7 @@ where
             AnyHashableLuaValue::LuaString(val) => val.push_no_err(raw_lua),
             AnyHashableLuaValue::LuaAnyString(val) => val.push_no_err(raw_lua),
             AnyHashableLuaValue::LuaInteger(val) => val.push_no_err(raw_lua),
+            AnyHashableLuaValue::LuaFloat(LuaFloat(val)) => val.push_no_err(raw_lua),
             AnyHashableLuaValue::LuaBoolean(val) => val.push_no_err(raw_lua),
             AnyHashableLuaValue::LuaArray(val) => {
                 // Pushing a `Vec<(AnyLuaValue, AnyLuaValue)>` on a `L` requires calling the
@@ -157,15 +327,24 @@ where
             ffi::LUA_TNIL => Ok(Value::LuaNil),
             ffi::LUA_TBOOLEAN => Err(raw_lua)
                 .or_else(|lua| LuaRead::lua_read_at_position(lua, index).map(Value::LuaBoolean)),
-            ffi::LUA_TNUMBER => Err(raw_lua)
-                .or_else(|lua| LuaRead::lua_read_at_position(lua, index).map(Value::LuaInteger))
-                .or_else(|lua| LuaRead::lua_read_at_position(lua, index).map(Value::LuaString)),
+            ffi::LUA_TNUMBER => LuaRead::lua_read_at_position(raw_lua, index).map(|n: f64| {
+                if n.fract() == 0.0 && n >= i32::MIN as f64 && n <= i32::MAX as f64 {
+                    Value::LuaInteger(n as i32)
+                } else {
+                    Value::LuaFloat(LuaFloat(n))
+                }
+            }),
+            // Reading a `String` fails on invalid UTF-8, which falls through to `LuaAnyString`.
             ffi::LUA_TSTRING => Err(raw_lua)
                 .or_else(|lua| LuaRead::lua_read_at_position(lua, index).map(Value::LuaString))
                 .or_else(|lua| LuaRead::lua_read_at_position(lua, index).map(Value::LuaAnyString)),
-            ffi::LUA_TTABLE => LuaTable::lua_read_at_position(raw_lua, index)
-                .map(|mut v| v.iter::<Value, Value>().flatten().collect())
-                .map(Value::LuaArray),
+            ffi::LUA_TTABLE => unsafe {
+                ffi::lua_pushvalue(raw_lua.as_ptr(), index);
+                let top = ffi::lua_gettop(raw_lua.as_ptr());
+                let value = read_table(raw_lua, top, &mut Vec::new(), &|_, _| false);
+                ffi::lua_pop(raw_lua.as_ptr(), 1);
+                Ok(value)
+            },
 
             _ => Ok(Value::LuaOther),
         }
@@ -173,9 +352,79 @@ where
     }
 }
 
+/// Converts a string made of a single character, for example to read a table with `char` keys
+/// through `TypedKeys`. Returns the value back if it isn't such a string.
+impl TryFrom<AnyHashableLuaValue> for char {
+    type Error = AnyHashableLuaValue;
+
+    fn try_from(value: AnyHashableLuaValue) -> Result<char, AnyHashableLuaValue> {
+        if let AnyHashableLuaValue::LuaString(s) = &value {
+            let mut chars = s.chars();
+            if let (Some(c), None) = (chars.next(), chars.next()) {
+                return Ok(c);
+            }
+        }
+        Err(value)
+    }
+}
+
+impl AnyLuaValue {
+    /// Compares two values like Lua would compare their content.
+    ///
+    /// Contrary to `==`, the entries of two `LuaArray`s are compared regardless of their order,
+    /// which is the order in which Lua happened to iterate over the tables. Numbers are equal
+    /// whether they are stored as a `LuaNumber` or as a `LuaInteger`, and strings whether they
+    /// are stored as a `LuaString` or as a `LuaAnyString`.
+    ///
+    /// Comparing two arrays takes a time proportional to the product of their sizes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::AnyLuaValue;
+    ///
+    /// let mut lua = hlua::Lua::new();
+    /// let a: AnyLuaValue = lua.execute("return { x = 1, y = { 2, 3 }, z = 'a' }").unwrap();
+    /// let b: AnyLuaValue = lua.execute("return { z = 'a', y = { 2, 3 }, x = 1 }").unwrap();
+    /// let c: AnyLuaValue = lua.execute("return { z = 'a', y = { 3, 2 }, x = 1 }").unwrap();
+    ///
+    /// assert!(a.deep_eq(&b));
+    /// assert!(!a.deep_eq(&c));
+    /// ```
+    pub fn deep_eq(&self, other: &AnyLuaValue) -> bool {
+        use AnyLuaValue as Value;
+
+        match (self, other) {
+            (Value::LuaArray(a), Value::LuaArray(b)) => {
+                // Keys are unique in a table, so matching each key once is enough.
+                a.len() == b.len()
+                    && a.iter().all(|(key, value)| {
+                        b.iter()
+                            .find(|(other_key, _)| key.deep_eq(other_key))
+                            .map_or(false, |(_, other_value)| value.deep_eq(other_value))
+                    })
+            },
+            (Value::LuaNumber(a), Value::LuaNumber(b)) => a == b,
+            (Value::LuaNumber(a), Value::LuaInteger(b)) => *a == f64::from(*b),
+            (Value::LuaInteger(a), Value::LuaNumber(b)) => f64::from(*a) == *b,
+            (Value::LuaInteger(a), Value::LuaInteger(b)) => a == b,
+            (Value::LuaString(a), Value::LuaString(b)) => a == b,
+            (Value::LuaString(a), Value::LuaAnyString(b)) => a.as_bytes() == b.0,
+            (Value::LuaAnyString(a), Value::LuaString(b)) => a.0 == b.as_bytes(),
+            (Value::LuaAnyString(a), Value::LuaAnyString(b)) => a == b,
+            (Value::LuaBoolean(a), Value::LuaBoolean(b)) => a == b,
+            (Value::LuaNil, Value::LuaNil) => true,
+            (Value::LuaOther, Value::LuaOther) => true,
+            _ => false,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{AnyHashableLuaValue, AnyLuaString, AnyLuaValue, Lua, LuaFunction, LuaNil};
+    use crate::{
+        AnyHashableLuaValue, AnyLuaString, AnyLuaValue, Lua, LuaFloat, LuaFunction, LuaNil,
+    };
 
     #[test]
     fn read_numbers() {
@@ -198,6 +447,27 @@ mod tests {
         assert_eq!(z, AnyLuaValue::LuaNumber(-2.0));
     }
 
+    #[test]
+    fn push_integral_numbers() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+
+        lua.set("a", AnyLuaValue::LuaNumber(3.0));
+        lua.set("b", AnyLuaValue::LuaInteger(3));
+        lua.set("x", AnyLuaValue::LuaNumber(65536.0));
+        assert!(lua.execute::<bool>("return a == b").unwrap());
+
+        // Integral numbers are pushed as floats, whose arithmetic doesn't wrap around.
+        assert_eq!(lua.execute::<f64>("return x * x").unwrap(), 4294967296.0);
+
+        #[cfg(feature = "_luaapi_54")]
+        {
+            let types: String =
+                lua.execute("return math.type(a) .. math.type(b) .. math.type(x * x)").unwrap();
+            assert_eq!(types, "floatintegerfloat");
+        }
+    }
+
     #[test]
     fn read_hashable_numbers() {
         let mut lua = Lua::new();
@@ -214,6 +484,15 @@ mod tests {
 
         let z: AnyHashableLuaValue = lua.get("c").unwrap();
         assert_eq!(z, AnyHashableLuaValue::LuaString("4".to_owned()));
+
+        lua.set("d", 2.5f64);
+        lua.set("e", 2f64.powi(40));
+
+        let d: AnyHashableLuaValue = lua.get("d").unwrap();
+        assert_eq!(d, AnyHashableLuaValue::LuaFloat(LuaFloat(2.5)));
+
+        let e: AnyHashableLuaValue = lua.get("e").unwrap();
+        assert_eq!(e, AnyHashableLuaValue::LuaFloat(LuaFloat(2f64.powi(40))));
     }
 
     #[test]
@@ -400,6 +679,11 @@ mod tests {
 
         let x: i32 = lua.get("a").unwrap();
         assert_eq!(x, 3);
+
+        lua.set("b", AnyHashableLuaValue::LuaFloat(LuaFloat(-1.25)));
+
+        let y: f64 = lua.get("b").unwrap();
+        assert_eq!(y, -1.25);
     }
 
     #[test]
@@ -483,4 +767,138 @@ mod tests {
             _ => panic!("Decoded to wrong variant"),
         }
     }
+
+    #[test]
+    fn non_utf_8_table_keys() {
+        let mut lua = Lua::new();
+        lua.execute::<()>(r"t = { ['\xff'] = 1, ['\xffabc'] = 2, abc = 3 }").unwrap();
+
+        let key = |bytes: &[u8]| AnyHashableLuaValue::LuaAnyString(AnyLuaString(bytes.to_vec()));
+        let mut entries = match lua.get::<AnyHashableLuaValue, _>("t").unwrap() {
+            AnyHashableLuaValue::LuaArray(entries) => entries,
+            other => panic!("{:?}", other),
+        };
+        entries.sort_by_key(|(_, value)| match value {
+            AnyHashableLuaValue::LuaInteger(n) => *n,
+            other => panic!("{:?}", other),
+        });
+        let keys: Vec<_> = entries.into_iter().map(|(key, _)| key).collect();
+        assert_eq!(
+            keys,
+            [key(b"\xff"), key(b"\xffabc"), AnyHashableLuaValue::LuaString("abc".to_owned())]
+        );
+
+        let table: AnyLuaValue = lua.get("t").unwrap();
+        let expected: AnyLuaValue =
+            lua.execute(r"return { ['\xff'] = 1, ['\xffabc'] = 2, abc = 3 }").unwrap();
+        assert!(table.deep_eq(&expected));
+        match table {
+            AnyLuaValue::LuaArray(entries) => assert!(entries.iter().any(|(key, _)| {
+                *key == AnyLuaValue::LuaAnyString(AnyLuaString(b"\xff".to_vec()))
+            })),
+            other => panic!("{:?}", other),
+        }
+
+        // The keys are pushed back as the same bytes.
+        let copy: AnyHashableLuaValue = lua.get("t").unwrap();
+        lua.set("copy", copy);
+        assert_eq!(lua.execute::<i32>(r"return copy['\xff'] + copy['\xffabc']").unwrap(), 3);
+    }
+
+    #[test]
+    fn deep_equality() {
+        use AnyLuaValue::*;
+
+        let pair = |k: AnyLuaValue, v: AnyLuaValue| (k, v);
+        let a = LuaArray(vec![
+            pair(LuaString("a".to_owned()), LuaNumber(1.0)),
+            pair(LuaNumber(1.0), LuaArray(vec![pair(LuaInteger(1), LuaBoolean(true))])),
+        ]);
+        let b = LuaArray(vec![
+            pair(LuaInteger(1), LuaArray(vec![pair(LuaNumber(1.0), LuaBoolean(true))])),
+            pair(LuaAnyString(AnyLuaString(b"a".to_vec())), LuaInteger(1)),
+        ]);
+        assert!(a != b);
+        assert!(a.deep_eq(&b) && b.deep_eq(&a));
+
+        let c = LuaArray(vec![pair(LuaString("a".to_owned()), LuaNumber(1.0))]);
+        assert!(!a.deep_eq(&c) && !c.deep_eq(&a));
+        let d = LuaArray(vec![
+            pair(LuaString("a".to_owned()), LuaNumber(1.0)),
+            pair(LuaNumber(1.0), LuaArray(vec![pair(LuaInteger(1), LuaBoolean(false))])),
+        ]);
+        assert!(!a.deep_eq(&d));
+
+        assert!(LuaNil.deep_eq(&LuaNil) && !LuaNil.deep_eq(&LuaBoolean(false)));
+        assert!(!LuaAnyString(AnyLuaString(b"1".to_vec())).deep_eq(&LuaNumber(1.0)));
+    }
+
+    #[test]
+    fn cyclic_tables() {
+        let mut lua = Lua::new();
+
+        let value: AnyLuaValue = lua.execute("local a = { 1 }; a.self = a; return a").unwrap();
+        let entries = match value {
+            AnyLuaValue::LuaArray(entries) => entries,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(entries.len(), 2);
+        assert!(entries.contains(&(AnyLuaValue::LuaNumber(1.0), AnyLuaValue::LuaNumber(1.0))));
+        assert!(entries.contains(&(AnyLuaValue::LuaString("self".into()), AnyLuaValue::LuaOther)));
+
+        // A table that appears twice without containing itself is read twice.
+        let value: AnyLuaValue = lua.execute("local t = { 5 }; return { t, t }").unwrap();
+        let inner =
+            AnyLuaValue::LuaArray(vec![(AnyLuaValue::LuaNumber(1.0), AnyLuaValue::LuaNumber(5.0))]);
+        assert!(value.deep_eq(&AnyLuaValue::LuaArray(vec![
+            (AnyLuaValue::LuaNumber(1.0), inner.clone()),
+            (AnyLuaValue::LuaNumber(2.0), inner),
+        ])));
+    }
+
+    #[test]
+    fn cyclic_hashable_tables() {
+        use AnyHashableLuaValue as Value;
+
+        let mut lua = Lua::new();
+
+        let value: Value = lua.execute("t = {}; t.self = t; t[t] = 1; return t").unwrap();
+        let mut entries = match value {
+            Value::LuaArray(entries) => entries,
+            other => panic!("{:?}", other),
+        };
+        entries.sort();
+        assert_eq!(
+            entries,
+            [
+                (Value::LuaString("self".into()), Value::LuaOther),
+                (Value::LuaOther, Value::LuaInteger(1)),
+            ]
+        );
+
+        let value: Value = lua.execute("local a = {}; a.b = { a = a }; return a").unwrap();
+        let inner = Value::LuaArray(vec![(Value::LuaString("a".into()), Value::LuaOther)]);
+        assert_eq!(value, Value::LuaArray(vec![(Value::LuaString("b".into()), inner)]));
+    }
+
+    #[test]
+    fn deeply_nested_tables() {
+        // Returns the number of nested tables in `value`.
+        fn depth(value: &AnyLuaValue) -> usize {
+            match value {
+                AnyLuaValue::LuaArray(entries) => entries.first().map_or(0, |(_, v)| depth(v)) + 1,
+                _ => 0,
+            }
+        }
+
+        let mut lua = Lua::new();
+        lua.execute::<()>("t = {} for i = 1, 127 do t = { t } end").unwrap();
+        assert_eq!(depth(&lua.get::<AnyLuaValue, _>("t").unwrap()), 128);
+
+        // Deeper tables are cut, instead of overflowing the stack.
+        lua.execute::<()>("t = { t }; deep = t for i = 1, 50000 do deep = { deep } end").unwrap();
+        assert_eq!(depth(&lua.get::<AnyLuaValue, _>("t").unwrap()), 128);
+        assert_eq!(depth(&lua.get::<AnyLuaValue, _>("deep").unwrap()), 128);
+        assert!(matches!(lua.get("deep"), Some(AnyHashableLuaValue::LuaArray(_))));
+    }
 }
@@ -0,0 +1,419 @@
+use std::{
+    ffi::{CStr, CString},
+    mem::MaybeUninit,
+    ops::BitOr,
+    panic::{self, AssertUnwindSafe},
+};
+
+use crate::{
+    ffix, panic_handler, push_userdata, LuaContext, LuaRead, Push, PushGuard, ScriptFailure,
+    UserdataOnStack,
+};
+
+// Key of the hook in the registry.
+const HOOK_REGISTRY_KEY: &str = "hlua_hook";
+
+// Key of the table associating functions with the names given by `Lua::set_name_for_function`,
+// in the registry.
+const NAMES_REGISTRY_KEY: &str = "hlua_function_names";
+
+/// Description of a function being executed, as returned by `Lua::stack_trace`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackFrame {
+    /// Printable version of the source of the function, such as the name of the file it was
+    /// loaded from, `[C]` for functions written in C or Rust, or the beginning of the code for
+    /// chunks loaded from a string.
+    pub source: String,
+    /// Line being executed, or `None` if it isn't known, for example in Rust callbacks.
+    pub line: Option<u32>,
+    /// Name of the function, or `None` if Lua couldn't find one. Functions named with
+    /// `Lua::set_name_for_function` always have that name.
+    pub name: Option<String>,
+}
+
+/// Returns the frames of the call stack, starting with the innermost one.
+pub(crate) fn stack_trace(lua: LuaContext) -> Vec<StackFrame> {
+    let mut frames = Vec::new();
+
+    for level in 0.. {
+        unsafe {
+            let mut ar = MaybeUninit::<ffi::lua_Debug>::zeroed();
+            if ffi::lua_getstack(lua.as_ptr(), level, ar.as_mut_ptr()) == 0 {
+                break;
+            }
+            frames.push(frame_info(lua.as_ptr(), ar.as_mut_ptr()));
+        }
+    }
+
+    frames
+}
+
+/// Returns the traceback built by `luaL_traceback`, starting at `level` and preceded by
+/// `message` unless it is empty.
+pub(crate) fn traceback(lua: LuaContext, message: &str, level: i32) -> String {
+    let message = CString::new(message.replace('\0', "")).unwrap();
+    let message = match message.as_bytes() {
+        [] => std::ptr::null(),
+        _ => message.as_ptr(),
+    };
+
+    unsafe {
+        ffi::luaL_traceback(lua.as_ptr(), lua.as_ptr(), message, level);
+        let traceback: Option<String> = LuaRead::lua_read(PushGuard::new(lua, 1)).ok();
+        traceback.unwrap_or_default()
+    }
+}
+
+// Fills `ar`, which must have been filled by `lua_getstack` or passed to a hook, and returns the
+// corresponding frame.
+unsafe fn frame_info(lua: *mut ffi::lua_State, ar: *mut ffi::lua_Debug) -> StackFrame {
+    ffi::lua_getinfo(lua, b"Slnf\0".as_ptr().cast(), ar);
+    let given_name = pop_function_name(lua);
+    let ar = &*ar;
+
+    let source = CStr::from_ptr(ar.short_src.as_ptr()).to_string_lossy().into_owned();
+    let line = u32::try_from(ar.currentline).ok();
+    let name = given_name.or_else(|| {
+        (!ar.name.is_null()).then(|| CStr::from_ptr(ar.name).to_string_lossy().into_owned())
+    });
+    StackFrame { source, line, name }
+}
+
+/// Associates `name` with the function at the top of the stack, which is left in place.
+///
+/// The association doesn't prevent the function from being garbage collected.
+pub(crate) fn set_function_name(lua: LuaContext, name: &str) {
+    let raw_lua = lua.as_ptr();
+    unsafe {
+        NAMES_REGISTRY_KEY.push_no_err(lua).forget_internal();
+        ffi::lua_rawget(raw_lua, ffi::LUA_REGISTRYINDEX);
+        if ffi::lua_isnil(raw_lua, -1) {
+            ffi::lua_pop(raw_lua, 1);
+            ffi::lua_createtable(raw_lua, 0, 0);
+            ffi::lua_createtable(raw_lua, 0, 1);
+            "__mode".push_no_err(lua).forget_internal();
+            "k".push_no_err(lua).forget_internal();
+            ffi::lua_rawset(raw_lua, -3);
+            ffi::lua_setmetatable(raw_lua, -2);
+
+            NAMES_REGISTRY_KEY.push_no_err(lua).forget_internal();
+            ffi::lua_pushvalue(raw_lua, -2);
+            ffi::lua_rawset(raw_lua, ffi::LUA_REGISTRYINDEX);
+        }
+
+        ffi::lua_pushvalue(raw_lua, -2);
+        name.push_no_err(lua).forget_internal();
+        ffi::lua_rawset(raw_lua, -3);
+        ffi::lua_pop(raw_lua, 1);
+    }
+}
+
+/// Returns the name given with `Lua::set_name_for_function` to the Rust callback that is running
+/// on `lua`, if any.
+pub(crate) fn running_function_name(lua: LuaContext) -> Option<String> {
+    unsafe {
+        let mut ar = MaybeUninit::<ffi::lua_Debug>::zeroed();
+        if ffi::lua_getstack(lua.as_ptr(), 0, ar.as_mut_ptr()) == 0 {
+            return None;
+        }
+        ffi::lua_getinfo(lua.as_ptr(), b"f\0".as_ptr().cast(), ar.as_mut_ptr());
+        pop_function_name(lua.as_ptr())
+    }
+}
+
+// Pops the function at the top of the stack, and returns the name given to it with
+// `Lua::set_name_for_function`.
+unsafe fn pop_function_name(lua: *mut ffi::lua_State) -> Option<String> {
+    ffi::lua_pushlstring(lua, NAMES_REGISTRY_KEY.as_ptr().cast(), NAMES_REGISTRY_KEY.len());
+    ffi::lua_rawget(lua, ffi::LUA_REGISTRYINDEX);
+    if !ffi::lua_istable(lua, -1) {
+        ffi::lua_pop(lua, 2);
+        return None;
+    }
+
+    ffi::lua_pushvalue(lua, -2);
+    ffi::lua_rawget(lua, -2);
+    let raw_lua = LuaContext::new_unchecked(lua);
+    let name: Option<String> = LuaRead::lua_read_at_position(raw_lua, -1).ok();
+    ffi::lua_pop(lua, 3);
+    name
+}
+
+/// Events for which the function set with `Lua::set_hook` is called.
+///
+/// Masks can be combined with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HookMask {
+    mask: libc::c_int,
+    count: libc::c_int,
+}
+
+impl HookMask {
+    /// No event.
+    pub const NONE: HookMask = HookMask { mask: 0, count: 0 };
+    /// When a function is called.
+    pub const CALL: HookMask = HookMask { mask: ffi::LUA_MASKCALL, count: 0 };
+    /// When a function returns.
+    pub const RETURN: HookMask = HookMask { mask: ffi::LUA_MASKRET, count: 0 };
+    /// When the interpreter starts executing a new line of code.
+    pub const LINE: HookMask = HookMask { mask: ffi::LUA_MASKLINE, count: 0 };
+
+    /// After every `count` instructions. A count of zero selects no event.
+    #[inline]
+    pub fn every(count: u32) -> HookMask {
+        match count {
+            0 => HookMask::NONE,
+            count => HookMask {
+                mask: ffi::LUA_MASKCOUNT,
+                count: count.min(libc::c_int::MAX as u32) as libc::c_int,
+            },
+        }
+    }
+}
+
+impl BitOr for HookMask {
+    type Output = HookMask;
+
+    #[inline]
+    fn bitor(self, other: HookMask) -> HookMask {
+        HookMask { mask: self.mask | other.mask, count: self.count.max(other.count) }
+    }
+}
+
+/// Event that triggered a call to the hook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    /// A function is being called.
+    Call,
+    /// A function is being called as a tail call. Not reported by LuaJIT.
+    TailCall,
+    /// A function is returning.
+    Return,
+    /// A new line of code is being executed.
+    Line,
+    /// The number of instructions given to `HookMask::every` has been executed.
+    Count,
+}
+
+/// Information passed to the function set with `Lua::set_hook`.
+#[derive(Debug)]
+pub struct HookContext {
+    lua: *mut ffi::lua_State,
+    ar: *mut ffi::lua_Debug,
+}
+
+impl HookContext {
+    /// Returns the event that triggered the call.
+    #[inline]
+    pub fn event(&self) -> HookEvent {
+        match unsafe { (*self.ar).event } {
+            ffi::LUA_HOOKCALL => HookEvent::Call,
+            ffi::LUA_HOOKLINE => HookEvent::Line,
+            ffi::LUA_HOOKCOUNT => HookEvent::Count,
+            #[cfg(not(feature = "_luaapi_51"))]
+            ffi::LUA_HOOKTAILCALL => HookEvent::TailCall,
+            _ => HookEvent::Return,
+        }
+    }
+
+    /// Returns the line being executed, or `None` if it isn't known.
+    #[inline]
+    pub fn line(&self) -> Option<u32> {
+        unsafe {
+            if (*self.ar).event != ffi::LUA_HOOKLINE {
+                ffi::lua_getinfo(self.lua, b"l\0".as_ptr().cast(), self.ar);
+            }
+            u32::try_from((*self.ar).currentline).ok()
+        }
+    }
+
+    /// Returns the description of the function being executed.
+    #[inline]
+    pub fn frame(&self) -> StackFrame {
+        unsafe { frame_info(self.lua, self.ar) }
+    }
+}
+
+type HookFn = Box<dyn FnMut(&HookContext) + Send>;
+
+struct Hook(HookFn);
+
+/// Installs `hook` for the events of `mask`, or removes the hook if `hook` is `None`.
+pub(crate) fn set_hook(lua: LuaContext, mask: HookMask, hook: Option<HookFn>) {
+    unsafe {
+        HOOK_REGISTRY_KEY.push_no_err(lua).forget();
+        match hook {
+            Some(hook) => {
+                push_userdata(Hook(hook), lua, |_| {}).forget();
+            },
+            None => ffi::lua_pushnil(lua.as_ptr()),
+        }
+        ffi::lua_rawset(lua.as_ptr(), ffi::LUA_REGISTRYINDEX);
+
+        match mask.mask {
+            0 => ffi::lua_sethook(lua.as_ptr(), None, 0, 0),
+            _ => ffi::lua_sethook(lua.as_ptr(), Some(hook_wrapper), mask.mask, mask.count),
+        };
+    }
+}
+
+// Called by Lua on the events selected when the hook was set.
+extern "C" fn hook_wrapper(lua: *mut ffi::lua_State, ar: *mut ffi::lua_Debug) {
+    let raw_lua = unsafe { LuaContext::new_unchecked(lua) };
+
+    let result = {
+        unsafe {
+            HOOK_REGISTRY_KEY.push_no_err(raw_lua).forget();
+            ffi::lua_rawget(lua, ffi::LUA_REGISTRYINDEX);
+        }
+        let guard = unsafe { PushGuard::new(raw_lua, 1) };
+
+        match UserdataOnStack::<Hook, _>::lua_read(guard) {
+            Ok(mut hook) => {
+                let ctx = HookContext { lua, ar };
+                panic::catch_unwind(AssertUnwindSafe(|| (hook.0)(&ctx)))
+            },
+            Err(_) => Ok(()),
+        }
+    };
+
+    // Unwinding through the Lua stack frames isn't allowed, so panics are turned into Lua errors.
+    if let Err(payload) = result {
+        {
+            let msg = panic_handler::panic_message(&*payload);
+            panic_handler::notify(raw_lua, ScriptFailure::CallbackPanic(msg));
+            format!("rust hook panicked: {}", msg).push_no_err(raw_lua).forget_internal();
+        }
+        // `lua_error` doesn't return, so the payload has to be dropped before.
+        drop(payload);
+        unsafe { ffix::lua_error(lua) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{
+        function0, function1, AsLua, HookEvent, HookMask, InsideCallback, Lua, StackFrame,
+    };
+
+    #[test]
+    fn stack_trace_from_callback() {
+        let mut lua = Lua::new();
+        assert!(lua.stack_trace().is_empty());
+
+        let frames = Arc::new(Mutex::new(Vec::<StackFrame>::new()));
+        let frames2 = frames.clone();
+        lua.set(
+            "trace",
+            function0(move || {
+                *frames2.lock().unwrap() = unsafe { InsideCallback::with(|lua| lua.stack_trace()) };
+            }),
+        );
+
+        lua.execute::<()>(
+            "function outer()\n  inner()\nend\nfunction inner() trace() end\nouter()",
+        )
+        .unwrap();
+
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[0].source, "[C]");
+        assert_eq!(frames[0].line, None);
+        assert_eq!(frames[1].name.as_deref(), Some("inner"));
+        assert_eq!(frames[1].line, Some(4));
+        assert_eq!(frames[2].name.as_deref(), Some("outer"));
+        assert_eq!(frames[2].line, Some(2));
+        assert_eq!(frames[3].line, Some(5));
+    }
+
+    #[test]
+    fn traceback_from_callback() {
+        let mut lua = Lua::new();
+        assert_eq!(lua.traceback("", 0), "stack traceback:");
+        assert_eq!(lua.traceback("nothing", 0), "nothing\nstack traceback:");
+
+        lua.set(
+            "traceback",
+            function0(|| -> String {
+                unsafe { InsideCallback::with(|lua| lua.traceback("here", 1)) }
+            }),
+        );
+        let traceback: String =
+            lua.execute("function inner()\n  return traceback()\nend\nreturn inner()").unwrap();
+
+        assert!(traceback.starts_with("here\nstack traceback:\n"), "{}", traceback);
+        assert!(traceback.contains(":2: in function"), "{}", traceback);
+        assert!(!traceback.contains("[C]: in function 'traceback'"), "{}", traceback);
+    }
+
+    #[test]
+    fn named_callbacks() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("audio = {}").unwrap();
+        lua.set_path(
+            "audio.set_volume",
+            function1(|volume: u8| -> Option<String> {
+                assert!(volume <= 100, "volume too high");
+                unsafe { InsideCallback::with(|lua| lua.stack_trace()[0].name.clone()) }
+            }),
+        );
+        assert!(!lua.set_name_for_function("audio.missing", "missing"));
+        assert!(!lua.set_name_for_function("audio", "audio"));
+        assert!(lua.set_name_for_function("audio.set_volume", "set_volume"));
+
+        let name: String = lua.execute("local f = audio.set_volume; return f(5)").unwrap();
+        assert_eq!(name, "set_volume");
+
+        match lua.execute::<()>("local f = audio.set_volume; f(200)") {
+            Err(crate::LuaError::ExecutionError(msg)) => {
+                assert!(
+                    msg.contains("rust callback 'set_volume' panicked: volume too high"),
+                    "{}",
+                    msg
+                )
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match lua.execute::<()>("audio.set_volume('loud')") {
+            Err(crate::LuaError::ExecutionError(msg)) => {
+                assert!(msg.contains("callback function 'set_volume'"), "{}", msg)
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn hook_sees_calls_and_can_be_removed() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("function foo() end").unwrap();
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let calls2 = calls.clone();
+        lua.set_hook(HookMask::CALL | HookMask::RETURN, move |ctx| {
+            if ctx.event() == HookEvent::Call {
+                calls2.lock().unwrap().push(ctx.frame().name);
+            }
+        });
+
+        lua.execute::<()>("foo()").unwrap();
+        assert!(calls.lock().unwrap().contains(&Some("foo".to_owned())));
+
+        lua.remove_hook();
+        calls.lock().unwrap().clear();
+        lua.execute::<()>("foo()").unwrap();
+        assert!(calls.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn panicking_hook_aborts_script() {
+        let mut lua = Lua::new();
+        lua.set_hook(HookMask::every(1000), |_| panic!("too long"));
+
+        match lua.execute::<()>("while true do end") {
+            Err(crate::LuaError::ExecutionError(msg)) => assert!(msg.contains("too long")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{any, ffix, AnyLuaValue, LuaContext, LuaType};
+
+// Names of the standard libraries in `package.loaded`, including `_G` itself.
+const LIBRARY_TABLES: &[&str] = &[
+    "_G",
+    "bit",
+    "bit32",
+    "coroutine",
+    "debug",
+    "ffi",
+    "io",
+    "jit",
+    "math",
+    "os",
+    "package",
+    "string",
+    "table",
+    "utf8",
+];
+
+/// Reads the table of globals into an `AnyLuaValue::LuaArray`, without the tables of the
+/// standard libraries unless `include_libs` is true.
+pub(crate) fn globals(lua: LuaContext, include_libs: bool) -> AnyLuaValue {
+    unsafe {
+        let libs = match include_libs {
+            true => Vec::new(),
+            false => library_tables(lua),
+        };
+
+        ffix::lua_pushglobaltable(lua);
+        let globals = ffi::lua_gettop(lua.as_ptr());
+
+        // Libraries are recognized by identity rather than by the name of the global, so that
+        // the tables of the user that are named like a library are still dumped.
+        let skip = |_: i32, value: i32| {
+            LuaType::at(lua, value) == Some(LuaType::Table)
+                && libs.contains(&ffi::lua_topointer(lua.as_ptr(), value))
+        };
+
+        let mut path = Vec::new();
+        let value = any::read_table(lua, globals, &mut path, &skip);
+        ffi::lua_pop(lua.as_ptr(), 1);
+        value
+    }
+}
+
+// Returns the addresses of the tables of the standard libraries that are loaded, which are
+// stored in the `_LOADED` table of the registry.
+unsafe fn library_tables(lua: LuaContext) -> Vec<*const libc::c_void> {
+    const LOADED: &str = "_LOADED";
+
+    let raw_lua = lua.as_ptr();
+    ffi::lua_pushlstring(raw_lua, LOADED.as_ptr().cast(), LOADED.len());
+    ffi::lua_rawget(raw_lua, ffi::LUA_REGISTRYINDEX);
+    if !ffi::lua_istable(raw_lua, -1) {
+        ffi::lua_pop(raw_lua, 1);
+        return Vec::new();
+    }
+
+    let mut tables = Vec::new();
+    for name in LIBRARY_TABLES {
+        ffi::lua_pushlstring(raw_lua, name.as_ptr().cast(), name.len());
+        ffi::lua_rawget(raw_lua, -2);
+        if ffi::lua_istable(raw_lua, -1) {
+            tables.push(ffi::lua_topointer(raw_lua, -1));
+        }
+        ffi::lua_pop(raw_lua, 1);
+    }
+    ffi::lua_pop(raw_lua, 1);
+    tables
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AnyLuaValue, AsLua, Lua};
+
+    fn entries(value: AnyLuaValue) -> Vec<(AnyLuaValue, AnyLuaValue)> {
+        match value {
+            AnyLuaValue::LuaArray(entries) => entries,
+            other => panic!("{:?}", other),
+        }
+    }
+
+    fn find<'a>(entries: &'a [(AnyLuaValue, AnyLuaValue)], name: &str) -> Option<&'a AnyLuaValue> {
+        entries
+            .iter()
+            .find(|(key, _)| matches!(key, AnyLuaValue::LuaString(key) if key == name))
+            .map(|(_, value)| value)
+    }
+
+    #[test]
+    fn dump_globals() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.execute::<()>("a = 5; b = { x = 'y' }; string_like = { 1 }; math = 3").unwrap();
+
+        let globals = entries(lua.dump_globals());
+        assert_eq!(find(&globals, "a"), Some(&AnyLuaValue::LuaNumber(5.0)));
+        assert_eq!(entries(find(&globals, "b").unwrap().clone()).len(), 1);
+        assert!(find(&globals, "string_like").is_some());
+        assert_eq!(find(&globals, "print"), Some(&AnyLuaValue::LuaOther));
+        for lib in ["_G", "string", "table", "package", "io", "os", "debug"] {
+            assert!(find(&globals, lib).is_none(), "{}", lib);
+        }
+        // Only the tables of the libraries are skipped.
+        assert_eq!(find(&globals, "math"), Some(&AnyLuaValue::LuaNumber(3.0)));
+    }
+
+    #[test]
+    fn dump_tables_named_like_libraries() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.execute::<()>("strings = string; string = { 1 }; jit = { 2 }; ffi = { 3 }").unwrap();
+
+        let globals = entries(lua.dump_globals());
+        assert!(find(&globals, "strings").is_none());
+        assert_eq!(entries(find(&globals, "string").unwrap().clone()).len(), 1);
+        #[cfg(not(feature = "_luaapi_51"))]
+        {
+            assert_eq!(entries(find(&globals, "jit").unwrap().clone()).len(), 1);
+            assert_eq!(entries(find(&globals, "ffi").unwrap().clone()).len(), 1);
+        }
+    }
+
+    #[test]
+    fn dump_globals_with_libs() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+
+        let globals = entries(lua.dump_globals_with_libs());
+        assert_eq!(find(&globals, "_G"), Some(&AnyLuaValue::LuaOther));
+        let string = entries(find(&globals, "string").unwrap().clone());
+        assert_eq!(find(&string, "format"), Some(&AnyLuaValue::LuaOther));
+        assert!(find(&globals, "package").is_some());
+    }
+
+    #[test]
+    fn cycles() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("a = {}; a.self = a; a.child = { parent = a }; b = { a, a }").unwrap();
+
+        let globals = entries(lua.dump_globals());
+        let a = entries(find(&globals, "a").unwrap().clone());
+        assert_eq!(find(&a, "self"), Some(&AnyLuaValue::LuaOther));
+        let child = entries(find(&a, "child").unwrap().clone());
+        assert_eq!(find(&child, "parent"), Some(&AnyLuaValue::LuaOther));
+
+        // A table that appears twice without containing itself is read both times.
+        let b = entries(find(&globals, "b").unwrap().clone());
+        assert!(b.iter().all(|(_, value)| matches!(value, AnyLuaValue::LuaArray(_))));
+    }
+}
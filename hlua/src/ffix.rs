@@ -30,3 +30,31 @@ pub unsafe fn lua_pushglobaltable(lua: LuaContext) {
         () => ffi::lua_pushglobaltable(lua.as_ptr()),
     };
 }
+
+/// Pops the table at the top of the stack and makes it the table of globals.
+#[inline(always)]
+pub unsafe fn lua_setglobaltable(lua: LuaContext) {
+    match () {
+        #[cfg(feature = "_luaapi_51")]
+        () => ffi::lua_replace(lua.as_ptr(), ffi::LUA_GLOBALSINDEX as _),
+        #[cfg(any(feature = "_luaapi_52", feature = "_luaapi_54"))]
+        () => ffi::lua_rawseti(lua.as_ptr(), ffi::LUA_REGISTRYINDEX, ffi::LUA_RIDX_GLOBALS as _),
+    };
+}
+
+/// Total memory in use by Lua, in bytes.
+#[inline(always)]
+pub unsafe fn lua_memory_used(lua: LuaContext) -> usize {
+    let kbytes = ffi::lua_gc(lua.as_ptr(), ffi::LUA_GCCOUNT as _, 0) as usize;
+    let bytes = ffi::lua_gc(lua.as_ptr(), ffi::LUA_GCCOUNTB as _, 0) as usize;
+    kbytes * 1024 + bytes
+}
+
+/// Address of the registry, which identifies the Lua context and is shared by all its threads.
+#[inline(always)]
+pub unsafe fn lua_registry_address(lua: LuaContext) -> usize {
+    ffi::lua_pushvalue(lua.as_ptr(), ffi::LUA_REGISTRYINDEX);
+    let address = ffi::lua_topointer(lua.as_ptr(), -1) as usize;
+    ffi::lua_pop(lua.as_ptr(), 1);
+    address
+}
@@ -1,9 +1,22 @@
 use crate::{
-    ffix, values::LuaNil, AsLua, AsMutLua, LuaContext, LuaRead, Push, PushGuard, PushOne, Void,
+    debug, ffix, panic_handler, readonly, values::LuaNil, AnyLuaValue, AsLua, AsMutLua, LuaContext,
+    LuaRead, Push, PushGuard, PushOne, ScriptFailure, StackFrame, Void,
 };
 
 use ptr::NonNull;
-use std::{fmt::Display, marker::PhantomData, mem, ptr};
+use std::{
+    any::Any,
+    borrow::{Borrow, Cow},
+    cell::{Cell, RefCell},
+    ffi::CString,
+    fmt::Display,
+    marker::PhantomData,
+    mem,
+    ops::Deref,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+    rc::Rc,
+};
 
 macro_rules! impl_function {
     ($name:ident, $($p:ident),*) => (
@@ -64,6 +77,97 @@ impl_function!(function8, A, B, C, D, E, F, G, H);
 impl_function!(function9, A, B, C, D, E, F, G, H, I);
 impl_function!(function10, A, B, C, D, E, F, G, H, I, J);
 
+/// Wraps a function or closure that accepts any number of parameters.
+///
+/// All the arguments passed by the caller are read as `AnyLuaValue`s and given to the function
+/// in order. Use [`Variadic`](struct.Variadic.html) with one of the `functionN` functions instead
+/// if the parameters must be of a specific type, or if some of them are fixed.
+///
+/// # Example
+///
+/// ```
+/// use hlua::AnyLuaValue;
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.set("count", hlua::function_variadic(|args: Vec<AnyLuaValue>| args.len() as u32));
+///
+/// assert_eq!(lua.execute::<u32>("return count(1, 'a', nil, {})").unwrap(), 4);
+/// ```
+#[inline]
+pub fn function_variadic<Z, R>(
+    mut f: Z,
+) -> Function<impl FnMut(Variadic<AnyLuaValue>) -> R, (Variadic<AnyLuaValue>,), R>
+where
+    Z: FnMut(Vec<AnyLuaValue>) -> R,
+{
+    function1(move |args: Variadic<AnyLuaValue>| f(args.0))
+}
+
+/// All the remaining parameters of a callback, each read as a `T`.
+///
+/// Reading a `Variadic` reads every value from its position to the top of the stack, which means
+/// that it must be the last parameter of the function. The read fails if one of the values can't
+/// be read as a `T`.
+///
+/// # Example
+///
+/// ```
+/// use hlua::Variadic;
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.set("sum", hlua::function2(|name: String, values: Variadic<i32>| {
+///     format!("{}: {}", name, values.iter().sum::<i32>())
+/// }));
+///
+/// assert_eq!(lua.execute::<String>("return sum('total', 1, 2, 3)").unwrap(), "total: 6");
+/// assert_eq!(lua.execute::<String>("return sum('none')").unwrap(), "none: 0");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variadic<T>(pub Vec<T>);
+
+impl<T> Deref for Variadic<T> {
+    type Target = Vec<T>;
+
+    #[inline]
+    fn deref(&self) -> &Vec<T> {
+        &self.0
+    }
+}
+
+impl<'lua, L, T> LuaRead<L> for Variadic<T>
+where
+    L: AsLua<'lua>,
+    T: for<'a> LuaRead<&'a mut L>,
+{
+    fn lua_read_at_position(mut lua: L, index: i32) -> Result<Variadic<T>, L> {
+        // A callback without arguments reads its parameters at index 0.
+        let (first, last) = match index {
+            0 => return Ok(Variadic(Vec::new())),
+            i if i < 0 => (i, -1),
+            i => (i, unsafe { ffi::lua_gettop(lua.as_lua().as_ptr()) }),
+        };
+
+        let mut values = Vec::with_capacity((last - first + 1).max(0) as usize);
+        for i in first..=last {
+            match T::lua_read_at_position(&mut lua, i) {
+                Ok(value) => values.push(value),
+                Err(_) => return Err(lua),
+            }
+        }
+        Ok(Variadic(values))
+    }
+
+    #[inline]
+    fn lua_read_out_of_bounds(_: L) -> Result<Variadic<T>, L> {
+        Ok(Variadic(Vec::new()))
+    }
+
+    #[inline]
+    fn expected() -> Cow<'static, str> {
+        Cow::Owned(format!("any number of {}", T::expected()))
+    }
+}
+
 /// Opaque type containing a Rust function or closure.
 ///
 /// In order to build an instance of this struct, you need to use one of the `functionN` functions.
@@ -111,6 +215,19 @@ impl_function!(function10, A, B, C, D, E, F, G, H, I, J);
 /// lua.execute::<()>("bar = foo; a = bar(12)").unwrap();
 /// ```
 ///
+/// # Function pointers
+///
+/// Plain `fn` pointers can be pushed directly, without going through a `functionN` helper:
+///
+/// ```
+/// # use hlua::Lua;
+/// # let mut lua = Lua::new();
+/// fn add(a: i32, b: i32) -> i32 { a + b }
+/// lua.set("add", add as fn(i32, i32) -> i32);
+///
+/// assert_eq!(lua.execute::<i32>("return add(3, 4)").unwrap(), 7);
+/// ```
+///
 /// # Multiple return values
 ///
 /// The Lua language supports functions that return multiple values at once.
@@ -188,6 +305,36 @@ pub trait FunctionExt<P> {
     fn call_mut(&mut self, params: P) -> Self::Output;
 }
 
+// Pushes `function` as a userdata, unless it is zero-sized, and returns the number of values
+// pushed. These values must be the first upvalues of the closure that calls `wrapper::<T, _, _>`.
+unsafe fn push_function_data<T>(lua: LuaContext, function: T) -> libc::c_int {
+    // TODO: What more exactly is T, and do we need to ensure alignment?
+    let raw_lua = lua.as_ptr();
+
+    // We can skip pushing the pointer when it's zero-sized.
+    if mem::size_of::<T>() == 0 {
+        mem::forget(function);
+        return 0;
+    }
+
+    // Pushing the function pointer as a userdata.
+    let lua_data = ffi::lua_newuserdata(raw_lua, mem::size_of::<T>() as libc::size_t);
+    ptr::write(lua_data.cast::<T>(), function);
+
+    // Only assign "__gc" if T needs to be dropped.
+    if mem::needs_drop::<T>() {
+        ffi::lua_newtable(raw_lua);
+
+        "__gc".push_no_err(lua).forget_internal();
+        ffi::lua_pushcfunction(raw_lua, Some(closure_destructor_wrapper::<T>));
+        ffi::lua_rawset(raw_lua, -3);
+
+        ffi::lua_setmetatable(raw_lua, -2);
+    }
+
+    1
+}
+
 // Called when an object inside Lua is being dropped.
 #[inline]
 extern "C" fn closure_destructor_wrapper<T>(lua: *mut ffi::lua_State) -> libc::c_int {
@@ -224,40 +371,15 @@ macro_rules! impl_function_ext {
             type Err = Void;
             #[inline]
             fn push_to_lua(self, mut lua: L) -> Result<PushGuard<L>, (Void, L)> {
+                let raw_lua = lua.as_mut_lua();
                 unsafe {
-                    let raw_lua_ctx = lua.as_mut_lua();
-                    let raw_lua_ptr = raw_lua_ctx.as_ptr();
-                    // TODO: What more exactly is Z, and do we need to ensure alignment?
-
-                    // We can skip pushing the pointer when it's zero-sized.
-                    let has_data = mem::size_of::<Z>() != 0;
-                    if has_data {
-                        // Pushing the function pointer as a userdata.
-                        let lua_data = ffi::lua_newuserdata(
-                            raw_lua_ptr,
-                            mem::size_of::<Z>() as libc::size_t
-                        );
-
-                        let lua_data = lua_data.cast::<Z>();
-                        ptr::write(lua_data, self.function);
-                    }
-
-                    // Only assign "__gc" if Z needs to be dropped.
-                    if mem::needs_drop::<Z>() {
-                        ffi::lua_newtable(raw_lua_ptr);
-
-                        "__gc".push_no_err(raw_lua_ctx).forget_internal();
-                        ffi::lua_pushcfunction(raw_lua_ptr, Some(closure_destructor_wrapper::<Z>));
-                        ffi::lua_rawset(raw_lua_ptr, -3);
-
-                        ffi::lua_setmetatable(raw_lua_ptr, -2);
-                    }
+                    let hidden_upvalues = push_function_data(raw_lua, self);
 
                     // pushing wrapper as a closure
                     let wrapper: RawFunction = wrapper::<Self, _, R>;
-                    ffi::lua_pushcclosure(raw_lua_ptr, Some(wrapper), has_data as libc::c_int);
-                    Ok(PushGuard { lua, size: 1, raw_lua: raw_lua_ctx })
+                    ffi::lua_pushcclosure(raw_lua.as_ptr(), Some(wrapper), hidden_upvalues);
                 }
+                Ok(PushGuard { lua, size: 1, raw_lua })
             }
         }
 
@@ -268,6 +390,26 @@ macro_rules! impl_function_ext {
                   R: for<'a> Push<&'a mut InsideCallback> + 'static
         {
         }
+
+        impl<'lua, L, R $(,$p: 'static)*> Push<L> for fn($($p),*) -> R
+        where
+            L: AsMutLua<'lua>,
+            ($($p,)*): for<'p> LuaRead<&'p mut InsideCallback>,
+            R: for<'a> Push<&'a mut InsideCallback> + 'static
+        {
+            type Err = Void;
+            #[inline]
+            fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+                Function { function: self, marker: PhantomData::<(($($p,)*), R)> }.push_to_lua(lua)
+            }
+        }
+
+        impl<'lua, L, R $(,$p: 'static)*> PushOne<L> for fn($($p),*) -> R
+            where L: AsMutLua<'lua>,
+                  ($($p,)*): for<'p> LuaRead<&'p mut InsideCallback>,
+                  R: for<'a> Push<&'a mut InsideCallback> + 'static
+        {
+        }
     )
 }
 
@@ -283,13 +425,430 @@ impl_function_ext!(A, B, C, D, E, F, G, H);
 impl_function_ext!(A, B, C, D, E, F, G, H, I);
 impl_function_ext!(A, B, C, D, E, F, G, H, I, J);
 
+impl<F, P, R> Function<F, P, R> {
+    /// Attaches Lua values to the function, which the callback can read and modify with
+    /// `InsideCallback::upvalue` and `InsideCallback::set_upvalue`.
+    ///
+    /// `upvalues` can be a single value or a tuple, whose elements are the upvalues `1`, `2`,
+    /// etc. They are stored in the Lua closure itself, which makes them a cheap way to keep
+    /// state between calls. If the function is a closure that captures nothing, pushing it
+    /// doesn't allocate any userdata. A function can have at most 254 upvalues.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::InsideCallback;
+    ///
+    /// let mut lua = hlua::Lua::new();
+    /// let counter = hlua::function0(|| -> i32 {
+    ///     unsafe {
+    ///         InsideCallback::with(|lua| {
+    ///             let count = lua.upvalue::<i32>(1).unwrap() + 1;
+    ///             lua.set_upvalue(1, count);
+    ///             count
+    ///         })
+    ///     }
+    /// });
+    /// lua.set("next_id", counter.with_upvalues(100));
+    ///
+    /// assert_eq!(lua.execute::<i32>("next_id(); return next_id()").unwrap(), 102);
+    /// ```
+    #[inline]
+    pub fn with_upvalues<U>(self, upvalues: U) -> WithUpvalues<F, P, R, U> {
+        WithUpvalues { function: self, upvalues }
+    }
+}
+
+/// Function pushed with additional upvalues, as returned by `Function::with_upvalues`.
+#[derive(Debug)]
+pub struct WithUpvalues<F, P, R, U> {
+    function: Function<F, P, R>,
+    upvalues: U,
+}
+
+impl<'lua, L, F, P, R, U, E> Push<L> for WithUpvalues<F, P, R, U>
+where
+    L: AsMutLua<'lua>,
+    F: 'lua,
+    Function<F, P, R>: FunctionExt<P, Output = R>,
+    P: for<'p> LuaRead<&'p mut InsideCallback> + 'static,
+    R: for<'a> Push<&'a mut InsideCallback> + 'static,
+    U: for<'a> Push<&'a mut L, Err = E>,
+    E: Into<Void>,
+{
+    type Err = Void;
+
+    #[inline]
+    fn push_to_lua(self, mut lua: L) -> Result<PushGuard<L>, (Void, L)> {
+        let raw_lua = lua.as_mut_lua();
+        unsafe {
+            let hidden_upvalues = push_function_data(raw_lua, self.function);
+            let upvalues = self.upvalues.push_no_err(&mut lua).forget_internal();
+            if upvalues > 254 {
+                ffi::lua_pop(raw_lua.as_ptr(), hidden_upvalues + upvalues);
+                panic!("a function can have at most 254 upvalues");
+            }
+
+            let wrapper: RawFunction = wrapper::<Function<F, P, R>, P, R>;
+            ffi::lua_pushcclosure(raw_lua.as_ptr(), Some(wrapper), hidden_upvalues + upvalues);
+        }
+        Ok(PushGuard { lua, size: 1, raw_lua })
+    }
+}
+
+impl<'lua, L, F, P, R, U, E> PushOne<L> for WithUpvalues<F, P, R, U>
+where
+    L: AsMutLua<'lua>,
+    F: 'lua,
+    Function<F, P, R>: FunctionExt<P, Output = R>,
+    P: for<'p> LuaRead<&'p mut InsideCallback> + 'static,
+    R: for<'a> Push<&'a mut InsideCallback> + 'static,
+    U: for<'a> Push<&'a mut L, Err = E>,
+    E: Into<Void>,
+{
+}
+
+// Closure of a `ScopedFunction`, which is taken out when the scope that created it ends.
+pub(crate) type ScopedSlot<P, R> = Rc<RefCell<Option<Box<dyn FnMut(P) -> R>>>>;
+
+// Function whose closure borrows data for the duration of a `Scope`, see `Scope::set_closure`.
+pub(crate) struct ScopedFunction<P, R> {
+    pub(crate) slot: ScopedSlot<P, R>,
+}
+
+impl<P, R> FunctionExt<P> for ScopedFunction<P, R> {
+    type Output = R;
+
+    #[inline]
+    fn call_mut(&mut self, params: P) -> R {
+        // `scoped_wrapper` has checked that the closure is there and isn't already running.
+        let mut slot = self.slot.borrow_mut();
+        (slot.as_mut().unwrap())(params)
+    }
+}
+
+impl<'lua, L, P, R> Push<L> for ScopedFunction<P, R>
+where
+    L: AsMutLua<'lua>,
+    P: for<'p> LuaRead<&'p mut InsideCallback> + 'static,
+    R: for<'a> Push<&'a mut InsideCallback> + 'static,
+{
+    type Err = Void;
+
+    #[inline]
+    fn push_to_lua(self, mut lua: L) -> Result<PushGuard<L>, (Void, L)> {
+        let raw_lua = lua.as_mut_lua();
+        unsafe {
+            let hidden_upvalues = push_function_data(raw_lua, self);
+            let wrapper: RawFunction = scoped_wrapper::<P, R>;
+            ffi::lua_pushcclosure(raw_lua.as_ptr(), Some(wrapper), hidden_upvalues);
+        }
+        Ok(PushGuard { lua, size: 1, raw_lua })
+    }
+}
+
+impl<'lua, L, P, R> PushOne<L> for ScopedFunction<P, R>
+where
+    L: AsMutLua<'lua>,
+    P: for<'p> LuaRead<&'p mut InsideCallback> + 'static,
+    R: for<'a> Push<&'a mut InsideCallback> + 'static,
+{
+}
+
+// Raises an error instead of calling the closure once its scope has ended, or when it is called
+// from within itself, which would give it two mutable borrows of the same data.
+extern "C" fn scoped_wrapper<P, R>(lua: *mut ffi::lua_State) -> libc::c_int
+where
+    P: for<'p> LuaRead<&'p mut InsideCallback> + 'static,
+    R: for<'p> Push<&'p mut InsideCallback>,
+{
+    #[cold]
+    #[inline(never)]
+    fn err_unavailable(lua: LuaContext, reason: &str) -> ! {
+        {
+            let error = match debug::running_function_name(lua) {
+                Some(name) => format!("callback function '{}' {}", name, reason),
+                None => format!("callback function {}", reason),
+            };
+            error.push_no_err(lua).forget_internal();
+        }
+        unsafe { ffix::lua_error(lua.as_ptr()) };
+    }
+
+    let data = unsafe { ffi::lua_touserdata(lua, ffi::lua_upvalueindex(1)) };
+    let data = unsafe { &*data.cast::<ScopedFunction<P, R>>() };
+    let reason = match data.slot.try_borrow() {
+        Ok(closure) if closure.is_some() => None,
+        Ok(_) => Some("can't be called after its scope has ended"),
+        Err(_) => Some("can't be called from within itself"),
+    };
+    if let Some(reason) = reason {
+        err_unavailable(unsafe { NonNull::new_unchecked(lua) }, reason);
+    }
+
+    wrapper::<ScopedFunction<P, R>, P, R>(lua)
+}
+
 /// Opaque type that represents the Lua context when inside a callback.
 ///
 /// Some types (like `Result`) can only be returned from a callback and not written inside a
 /// Lua variable. This type is here to enforce this restriction.
+///
+/// # Calling back into Lua
+///
+/// While a callback runs, `InsideCallback::with` gives access to the Lua context that called it.
+/// This can be used to read and write global variables, and to call Lua functions.
+///
+/// ```
+/// let mut lua = hlua::Lua::new();
+/// lua.execute::<()>("function double(x) return x * 2 end").unwrap();
+///
+/// lua.set("quadruple", hlua::function1(|x: i32| -> i32 {
+///     // The script doesn't read any userdata.
+///     unsafe {
+///         hlua::InsideCallback::with(|lua| {
+///             let mut double: hlua::LuaFunction<_> = lua.get("double").unwrap();
+///             let twice: i32 = double.call_with_args(x).unwrap();
+///             double.call_with_args(twice).unwrap()
+///         })
+///     }
+/// }));
+///
+/// assert_eq!(lua.execute::<i32>("return quadruple(3)").unwrap(), 12);
+/// ```
 #[derive(Debug)]
 pub struct InsideCallback {
     lua: LuaContext,
+    // Number of upvalues of the running callback that are used by hlua, before the ones given
+    // with `Function::with_upvalues`.
+    hidden_upvalues: libc::c_int,
+}
+
+thread_local! {
+    // Why the last parameter that failed to be read was refused, if it gave a reason.
+    static READ_FAILURE: Cell<Option<&'static str>> = const { Cell::new(None) };
+}
+
+// Gives the reason why reading a parameter of a callback is failing, which is reported in the
+// error raised by the call instead of the generic message about the types of the parameters.
+#[inline]
+pub(crate) fn set_read_failure(reason: &'static str) {
+    READ_FAILURE.with(|failure| failure.set(Some(reason)));
+}
+
+thread_local! {
+    // Lua context and number of hidden upvalues of the callback that this thread is running, if
+    // any.
+    static CURRENT_CALLBACK: Cell<Option<(LuaContext, libc::c_int)>> = const { Cell::new(None) };
+}
+
+// Restores the value of `CURRENT_CALLBACK` when dropped.
+struct RestoreCurrent(Option<(LuaContext, libc::c_int)>);
+
+impl Drop for RestoreCurrent {
+    #[inline]
+    fn drop(&mut self) {
+        CURRENT_CALLBACK.with(|current| current.set(self.0));
+    }
+}
+
+impl InsideCallback {
+    /// Calls `f` with the Lua context of the callback that is running.
+    ///
+    /// # Panic
+    ///
+    /// Panics if no callback is running on this thread, or if it is called again from `f`
+    /// without going through another callback.
+    ///
+    /// # Safety
+    ///
+    /// Each callback gets its own context, even when it is called by Lua code that runs from the
+    /// context of another callback. A userdata read as a `UserdataOnStack` gives a `&mut`
+    /// reference to its content, so while `f` or an outer callback holds one, the Lua code that
+    /// `f` runs must not call anything that reads the same userdata again through this function.
+    #[inline]
+    pub unsafe fn with<F, R>(f: F) -> R
+    where
+        F: FnOnce(&mut InsideCallback) -> R,
+    {
+        let (lua, hidden_upvalues) = CURRENT_CALLBACK
+            .with(Cell::take)
+            .expect("InsideCallback::with must be called from inside a callback");
+        let _restore = RestoreCurrent(Some((lua, hidden_upvalues)));
+        f(&mut InsideCallback { lua, hidden_upvalues })
+    }
+
+    /// Returns the raw `lua_State` of the running callback, for operations that hlua doesn't
+    /// wrap.
+    ///
+    /// Using the pointer is unsafe. Code that uses it must:
+    ///
+    /// - leave the stack as it found it, since the values below the top are the parameters of
+    ///   the callback,
+    /// - not close the state, yield, or otherwise let the callback return early through a Lua
+    ///   error, which would skip the destructors of the Rust values of the callback,
+    /// - not keep the pointer after the callback has returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{ffi, InsideCallback, Lua};
+    ///
+    /// let mut lua = Lua::new();
+    /// lua.set("num_args", hlua::function0(|| {
+    ///     unsafe { InsideCallback::with(|lua| ffi::lua_gettop(lua.raw_state())) }
+    /// }));
+    ///
+    /// assert_eq!(lua.execute::<i32>("return num_args(1, 2, 3)").unwrap(), 3);
+    /// ```
+    #[inline]
+    pub fn raw_state(&self) -> *mut ffi::lua_State {
+        self.lua.as_ptr()
+    }
+
+    /// Reads the upvalue `n` of the running callback, given with `Function::with_upvalues`.
+    ///
+    /// Upvalues are numbered from `1`. Returns `None` if the callback doesn't have this upvalue or
+    /// if it can't be read as `V`.
+    #[inline]
+    pub fn upvalue<'l, V>(&'l mut self, n: u8) -> Option<V>
+    where
+        V: LuaRead<PushGuard<&'l mut InsideCallback>>,
+    {
+        let index = self.upvalue_index(n)?;
+        let raw_lua = self.lua;
+
+        unsafe { ffi::lua_pushvalue(raw_lua.as_ptr(), index) };
+        let guard = PushGuard { lua: self, size: 1, raw_lua };
+        LuaRead::lua_read(guard).ok()
+    }
+
+    /// Replaces the upvalue `n` of the running callback, given with `Function::with_upvalues`.
+    ///
+    /// The new value is seen by the next calls of the same callback.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the callback doesn't have this upvalue.
+    #[inline]
+    pub fn set_upvalue<V, E>(&mut self, n: u8, value: V)
+    where
+        V: for<'a> PushOne<&'a mut InsideCallback, Err = E>,
+        E: Into<Void>,
+    {
+        let index = self.upvalue_index(n).expect("the callback doesn't have this upvalue");
+        let raw_lua = self.lua.as_ptr();
+
+        value.push_no_err(self).assert_one_and_forget();
+        unsafe {
+            match () {
+                #[cfg(feature = "_luaapi_54")]
+                () => {
+                    ffi::lua_copy(raw_lua, -1, index);
+                    ffi::lua_pop(raw_lua, 1);
+                },
+                #[cfg(not(feature = "_luaapi_54"))]
+                () => ffi::lua_replace(raw_lua, index),
+            }
+        }
+    }
+
+    // Returns the pseudo-index of the upvalue `n` given with `Function::with_upvalues`, if the
+    // running callback has it.
+    fn upvalue_index(&self, n: u8) -> Option<libc::c_int> {
+        let n = libc::c_int::from(n);
+        if n == 0 {
+            return None;
+        }
+
+        let index = ffi::lua_upvalueindex(self.hidden_upvalues + n);
+        // The upvalues that don't exist are `none`.
+        match unsafe { ffi::lua_isnone(self.lua.as_ptr(), index) } {
+            true => None,
+            false => Some(index),
+        }
+    }
+
+    /// Returns the frames of the Lua call stack, starting with the innermost one, which is the
+    /// running callback.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.set("where", hlua::function0(|| -> u32 {
+    ///     unsafe { hlua::InsideCallback::with(|lua| lua.stack_trace()[1].line.unwrap()) }
+    /// }));
+    ///
+    /// assert_eq!(lua.execute::<u32>("\n\nreturn where()").unwrap(), 3);
+    /// ```
+    #[inline]
+    pub fn stack_trace(&self) -> Vec<StackFrame> {
+        debug::stack_trace(self.lua)
+    }
+
+    /// Returns a traceback of the Lua call stack, as built by `luaL_traceback`.
+    ///
+    /// The traceback starts at the frame `level`, where 0 is the running callback and 1 is the
+    /// function that called it, and is preceded by `message` unless it is empty.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.set("traceback", hlua::function0(|| -> String {
+    ///     unsafe { hlua::InsideCallback::with(|lua| lua.traceback("called from", 1)) }
+    /// }));
+    ///
+    /// let traceback: String = lua.execute("return traceback()").unwrap();
+    /// assert!(traceback.starts_with("called from\nstack traceback:"));
+    /// ```
+    #[inline]
+    pub fn traceback(&self, message: &str, level: i32) -> String {
+        debug::traceback(self.lua, message, level)
+    }
+
+    /// Reads the value of a global variable.
+    ///
+    /// Works like `Lua::get`.
+    #[inline]
+    pub fn get<'l, V, I>(&'l mut self, name: I) -> Option<V>
+    where
+        I: Borrow<str>,
+        V: LuaRead<PushGuard<&'l mut InsideCallback>>,
+    {
+        let raw_lua = self.lua;
+
+        let name = CString::new(name.borrow()).unwrap();
+        unsafe { ffi::lua_getglobal(raw_lua.as_ptr(), name.as_ptr()) };
+        let guard = PushGuard { lua: self, size: 1, raw_lua };
+
+        match unsafe { ffi::lua_isnil(raw_lua.as_ptr(), -1) } {
+            true => None,
+            false => LuaRead::lua_read(guard).ok(),
+        }
+    }
+
+    /// Modifies the value of a global variable.
+    ///
+    /// Works like `Lua::set`.
+    #[inline]
+    pub fn set<I, V, E>(&mut self, name: I, value: V)
+    where
+        I: Borrow<str>,
+        V: for<'a> PushOne<&'a mut InsideCallback, Err = E>,
+        E: Into<Void>,
+    {
+        let raw_lua = self.lua;
+
+        unsafe {
+            ffix::lua_pushglobaltable(raw_lua);
+            name.borrow().push_no_err(raw_lua).assert_one_and_forget();
+            value.push_no_err(self).assert_one_and_forget();
+            readonly::set_global(raw_lua);
+            ffi::lua_pop(raw_lua.as_ptr(), 1);
+        }
+    }
 }
 
 unsafe impl<'a, 'lua> AsLua<'lua> for &'a InsideCallback {
@@ -351,8 +910,37 @@ where
 {
     #[cold]
     #[inline(never)]
-    fn err_wrong_type(lua: LuaContext) -> ! {
-        "wrong parameter types for callback function".push_no_err(lua).forget_internal();
+    fn err_wrong_type(lua: LuaContext, reason: Option<&str>) -> ! {
+        {
+            let name = debug::running_function_name(lua);
+            let error = match (name, reason) {
+                (Some(name), Some(reason)) => format!("callback function '{}' {}", name, reason),
+                (None, Some(reason)) => format!("callback function {}", reason),
+                (Some(name), None) => {
+                    format!("wrong parameter types for callback function '{}'", name)
+                },
+                (None, None) => "wrong parameter types for callback function".to_owned(),
+            };
+            error.push_no_err(lua).forget_internal();
+        }
+        unsafe { ffix::lua_error(lua.as_ptr()) };
+    }
+
+    // Unwinding through the Lua stack frames isn't allowed, so panics are turned into Lua errors.
+    #[cold]
+    #[inline(never)]
+    fn err_panicked(lua: LuaContext, payload: Box<dyn Any + Send>) -> ! {
+        {
+            let msg = panic_handler::panic_message(&*payload);
+            panic_handler::notify(lua, ScriptFailure::CallbackPanic(msg));
+            let error = match debug::running_function_name(lua) {
+                Some(name) => format!("rust callback '{}' panicked: {}", name, msg),
+                None => format!("rust callback panicked: {}", msg),
+            };
+            error.push_no_err(lua).forget_internal();
+        }
+        // `lua_error` doesn't return, so the payload has to be dropped before.
+        drop(payload);
         unsafe { ffix::lua_error(lua.as_ptr()) };
     }
 
@@ -363,17 +951,29 @@ where
     };
 
     // creating a temporary Lua context in order to pass it to push & read functions
-    let mut tmp_lua = InsideCallback { lua: unsafe { NonNull::new_unchecked(lua) } };
+    let mut tmp_lua = InsideCallback {
+        lua: unsafe { NonNull::new_unchecked(lua) },
+        hidden_upvalues: (std::mem::size_of::<T>() != 0) as libc::c_int,
+    };
 
     // trying to read the arguments
     let argc = unsafe { ffi::lua_gettop(lua) };
+    READ_FAILURE.with(|failure| failure.set(None));
     let args = match LuaRead::lua_read_at_position(&mut tmp_lua, -argc as libc::c_int) {
         Ok(a) => a,
-        Err(_) => err_wrong_type(tmp_lua.lua),
+        Err(_) => err_wrong_type(tmp_lua.lua, READ_FAILURE.with(Cell::take)),
     };
 
     let data = unsafe { &mut *data_raw.cast::<T>() };
-    let ret_value = data.call_mut(args);
+    let call = || {
+        let current = Some((tmp_lua.lua, tmp_lua.hidden_upvalues));
+        let _restore = RestoreCurrent(CURRENT_CALLBACK.with(|c| c.replace(current)));
+        data.call_mut(args)
+    };
+    let ret_value = match panic::catch_unwind(AssertUnwindSafe(call)) {
+        Ok(ret_value) => ret_value,
+        Err(payload) => err_panicked(tmp_lua.lua, payload),
+    };
 
     // pushing back the result of the function on the stack
     let nb = match ret_value.push_to_lua(&mut tmp_lua) {
@@ -386,7 +986,10 @@ where
 
 #[cfg(test)]
 mod tests {
-    use crate::{function0, function1, function2, Lua, LuaError};
+    use crate::{
+        function0, function1, function2, function_variadic, AnyLuaValue, InsideCallback, Lua,
+        LuaError, LuaFunction, Variadic,
+    };
 
     use std::sync::Arc;
 
@@ -429,6 +1032,158 @@ mod tests {
         assert_eq!(val, 10);
     }
 
+    #[test]
+    fn variadic_arguments() {
+        let mut lua = Lua::new();
+
+        lua.set(
+            "describe",
+            function_variadic(|args: Vec<AnyLuaValue>| {
+                let numbers = args.iter().filter(|a| matches!(a, AnyLuaValue::LuaNumber(_)));
+                format!("{} {}", args.len(), numbers.count())
+            }),
+        );
+
+        assert_eq!(lua.execute::<String>("return describe()").unwrap(), "0 0");
+        assert_eq!(lua.execute::<String>("return describe(1, 'a', nil, 2.5)").unwrap(), "4 2");
+    }
+
+    #[test]
+    fn scoped_closure() {
+        let mut lua = Lua::new();
+        let mut total = 0;
+
+        lua.scope(|scope| {
+            scope.set_closure("add", |n: i32| total += n);
+            scope.set_closure("twice", function2(|a: i32, b: i32| a * 2 + b));
+            scope.execute::<()>("add(3); add(4)").unwrap();
+            assert_eq!(scope.execute::<i32>("return twice(5, 1)").unwrap(), 11);
+        });
+        assert_eq!(total, 7);
+
+        match lua.execute::<()>("add(1)") {
+            Err(LuaError::ExecutionError(msg)) => {
+                assert!(msg.contains("can't be called after its scope has ended"), "{}", msg)
+            },
+            _ => panic!(),
+        };
+        assert_eq!(total, 7);
+    }
+
+    #[test]
+    fn scoped_closure_dropped_with_scope() {
+        let mut lua = Lua::new();
+        let captured = Arc::new(());
+
+        lua.scope(|scope| {
+            let captured = captured.clone();
+            scope.set_closure("f", move || Arc::strong_count(&captured) as u32);
+            assert_eq!(scope.execute::<u32>("return f()").unwrap(), 2);
+        });
+        assert_eq!(Arc::strong_count(&captured), 1);
+    }
+
+    #[test]
+    fn scoped_closure_reentrancy() {
+        let mut lua = Lua::with_libs(crate::LuaLibs::BASE);
+        let mut calls = 0;
+
+        lua.scope(|scope| {
+            scope.set_closure("reenter", |_: ()| {
+                calls += 1;
+                unsafe {
+                    InsideCallback::with(|lua| {
+                        let mut f: LuaFunction<_> = lua.get("reenter").unwrap();
+                        f.call::<()>().is_err()
+                    })
+                }
+            });
+            assert!(scope.execute::<bool>("return reenter()").unwrap());
+            assert!(scope.execute::<bool>("return select(2, pcall(reenter, nil))").unwrap());
+        });
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn variadic_typed_rest() {
+        let mut lua = Lua::new();
+
+        lua.set("sum", function2(|base: i32, rest: Variadic<i32>| base + rest.iter().sum::<i32>()));
+
+        assert_eq!(lua.execute::<i32>("return sum(1)").unwrap(), 1);
+        assert_eq!(lua.execute::<i32>("return sum(1, 2, 3, 4)").unwrap(), 10);
+        match lua.execute::<i32>("return sum(1, 2, 'a')") {
+            Err(LuaError::ExecutionError(msg)) => {
+                assert!(msg.contains("wrong parameter types"), "{}", msg)
+            },
+            _ => panic!(),
+        };
+    }
+
+    #[test]
+    fn function_pointers() {
+        let mut lua = Lua::new();
+
+        fn ret5() -> i32 {
+            5
+        }
+        fn add(val1: i32, val2: i32) -> i32 {
+            val1 + val2
+        }
+        fn double(val: u32) -> u32 {
+            val * 2
+        }
+
+        lua.set("ret5", ret5 as fn() -> i32);
+        lua.set("add", add as fn(i32, i32) -> i32);
+
+        // Registering a batch of functions sharing the same signature.
+        let unary: [(&str, fn(u32) -> u32); 2] = [("double", double), ("square", |v| v * v)];
+        for (name, f) in unary {
+            lua.set(name, f);
+        }
+
+        assert_eq!(lua.execute::<i32>("return ret5()").unwrap(), 5);
+        assert_eq!(lua.execute::<i32>("return add(3, 7)").unwrap(), 10);
+        assert_eq!(lua.execute::<u32>("return double(4) + square(3)").unwrap(), 17);
+    }
+
+    #[test]
+    fn upvalues() {
+        let mut lua = Lua::new();
+
+        // Without captures, the upvalues start right after the closure.
+        let counter = function0(|| -> String {
+            unsafe {
+                InsideCallback::with(|lua| {
+                    assert!(lua.upvalue::<i32>(0).is_none());
+                    assert!(lua.upvalue::<i32>(3).is_none());
+                    let count = lua.upvalue::<i32>(2).unwrap() + 1;
+                    lua.set_upvalue(2, count);
+                    format!("{}{}", lua.upvalue::<String>(1).unwrap(), count)
+                })
+            }
+        });
+        lua.set("a", counter.with_upvalues(("a", 0)));
+
+        // With captures, the captured data is hidden.
+        let step = Arc::new(5);
+        let adder = function1(move |x: i32| -> i32 {
+            let total =
+                unsafe { InsideCallback::with(|lua| lua.upvalue::<i32>(1).unwrap()) } + x * *step;
+            unsafe { InsideCallback::with(|lua| lua.set_upvalue(1, total)) };
+            total
+        });
+        lua.set("add", adder.with_upvalues(1));
+
+        assert_eq!(lua.execute::<String>("a(); a(); return a()").unwrap(), "a3");
+        assert_eq!(lua.execute::<i32>("add(1); return add(2)").unwrap(), 16);
+
+        // Outside of `with_upvalues`, callbacks have no upvalues.
+        lua.set("none", function0(|| unsafe { InsideCallback::with(|lua| lua.upvalue::<i32>(1)) }));
+        assert_eq!(lua.execute::<Option<i32>>("return none()").unwrap(), None);
+    }
+
     #[test]
     fn wrong_arguments_types() {
         let mut lua = Lua::new();
@@ -466,6 +1221,61 @@ mod tests {
         }
     }
 
+    #[test]
+    fn call_back_into_lua() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+
+        lua.set(
+            "count",
+            function1(|n: i32| {
+                unsafe {
+                    InsideCallback::with(|lua| {
+                        let calls = lua.get::<i32, _>("calls").unwrap_or(0);
+                        lua.set("calls", calls + 1);
+                    })
+                };
+                n
+            }),
+        );
+        lua.set(
+            "apply",
+            function2(|name: String, arg: i32| -> i32 {
+                unsafe {
+                    InsideCallback::with(|lua| {
+                        let mut f: LuaFunction<_> = lua.get(name).unwrap();
+                        f.call_with_args(arg).unwrap()
+                    })
+                }
+            }),
+        );
+
+        // Lua calling Rust calling Lua calling Rust.
+        lua.execute::<()>("function twice(x) return count(x) * 2 end").unwrap();
+        assert_eq!(lua.execute::<i32>("return apply('twice', 4) + apply('twice', 1)").unwrap(), 10);
+        assert_eq!(lua.get::<i32, _>("calls"), Some(2));
+
+        // Errors raised by the inner Lua code don't escape the callback.
+        lua.set(
+            "try",
+            function0(|| -> bool {
+                unsafe {
+                    InsideCallback::with(|lua| {
+                        let mut f: LuaFunction<_> = lua.get("error").unwrap();
+                        f.call_with_args::<(), _, _>("oops").is_err()
+                    })
+                }
+            }),
+        );
+        assert!(lua.execute::<bool>("return try()").unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "from inside a callback")]
+    fn inside_callback_outside_of_callback() {
+        unsafe { InsideCallback::with(|_| ()) };
+    }
+
     #[test]
     fn closures() {
         let mut lua = Lua::new();
@@ -0,0 +1,137 @@
+use crate::{ffix, AsMutLua, LuaContext, Push, PushGuard, PushOne, Void};
+
+/// A string stored once in the registry of a Lua context, which can then be pushed cheaply.
+///
+/// Pushing a `&str` makes Lua hash the string and look it up in its table of interned strings
+/// every time. An `InternedString` keeps a reference to the Lua string instead, so that pushing
+/// it only copies that reference. This is useful for the keys that are pushed repeatedly, for
+/// example the field names of objects serialized in a loop.
+///
+/// The string can be pushed into the Lua context that created it and into its threads. Since
+/// dropping an `InternedString` can't reach the context, the Lua string stays in the registry
+/// until it is passed to `release`.
+///
+/// # Panic
+///
+/// Pushing or releasing the string in another Lua context panics.
+///
+/// # Example
+///
+/// ```
+/// let mut lua = hlua::Lua::new();
+/// let name = hlua::InternedString::new(&mut lua, "name");
+///
+/// lua.execute::<()>("t = {}").unwrap();
+/// let mut table: hlua::LuaTable<_> = lua.get("t").unwrap();
+/// table.set(&name, "hlua");
+/// assert_eq!(table.get::<String, _, _>("name").unwrap(), "hlua");
+/// ```
+#[derive(Debug)]
+pub struct InternedString {
+    id: libc::c_int,
+    // Address of the registry of the context that holds the string.
+    registry: usize,
+    value: String,
+}
+
+impl InternedString {
+    /// Stores `value` in the registry of `lua`.
+    #[inline]
+    pub fn new<'lua, L>(lua: L, value: &str) -> InternedString
+    where
+        L: AsMutLua<'lua>,
+    {
+        let mut lua = lua;
+        let raw_lua = lua.as_mut_lua();
+
+        let id = unsafe {
+            ffi::lua_pushlstring(raw_lua.as_ptr(), value.as_ptr().cast(), value.len());
+            ffi::luaL_ref(raw_lua.as_ptr(), ffi::LUA_REGISTRYINDEX)
+        };
+        let registry = unsafe { ffix::lua_registry_address(raw_lua) };
+
+        InternedString { id, registry, value: value.to_owned() }
+    }
+
+    /// Returns the content of the string.
+    #[inline]
+    pub fn as_str(&self) -> &str {
+        &self.value
+    }
+
+    /// Removes the string from the registry of `lua`, which must be the context that created it.
+    #[inline]
+    pub fn release<'lua, L>(self, lua: L)
+    where
+        L: AsMutLua<'lua>,
+    {
+        let mut lua = lua;
+        let raw_lua = lua.as_mut_lua();
+        self.check_context(raw_lua);
+        unsafe { ffi::luaL_unref(raw_lua.as_ptr(), ffi::LUA_REGISTRYINDEX, self.id) };
+    }
+
+    // Panics if `lua` isn't the context that holds the string, whose registry slot `id` may
+    // hold anything.
+    #[inline]
+    fn check_context(&self, lua: LuaContext) {
+        if unsafe { ffix::lua_registry_address(lua) } != self.registry {
+            panic!("InternedString used in another Lua context than the one that created it");
+        }
+    }
+}
+
+impl<'lua, L> Push<L> for &InternedString
+where
+    L: AsMutLua<'lua>,
+{
+    type Err = Void;
+
+    #[inline]
+    fn push_to_lua(self, mut lua: L) -> Result<PushGuard<L>, (Void, L)> {
+        let raw_lua = lua.as_mut_lua();
+        self.check_context(raw_lua);
+        unsafe {
+            ffi::lua_rawgeti(raw_lua.as_ptr(), ffi::LUA_REGISTRYINDEX, self.id as _);
+            debug_assert_eq!(ffi::lua_type(raw_lua.as_ptr(), -1), ffi::LUA_TSTRING);
+        }
+        Ok(PushGuard { lua, size: 1, raw_lua })
+    }
+}
+
+impl<'lua, L> PushOne<L> for &InternedString where L: AsMutLua<'lua> {}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AsLua, InternedString, Lua, LuaTable};
+
+    #[test]
+    fn interned_keys() {
+        let mut lua = Lua::new();
+        let keys: Vec<_> = ["a", "b"].iter().map(|k| InternedString::new(&mut lua, k)).collect();
+        assert_eq!(keys[1].as_str(), "b");
+
+        lua.execute::<()>("t = {}").unwrap();
+        {
+            let mut table: LuaTable<_> = lua.get("t").unwrap();
+            for (n, key) in (0..10).zip(keys.iter().cycle()) {
+                table.set(key, n);
+            }
+        }
+        assert_eq!(lua.execute::<i32>("return t.a * 10 + t.b").unwrap(), 89);
+
+        for key in keys {
+            key.release(&mut lua);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "another Lua context")]
+    fn interned_in_another_context() {
+        let mut lua = Lua::new();
+        let key = InternedString::new(&mut lua, "a");
+
+        let mut other = Lua::new();
+        other.set("a", &key);
+    }
+}
@@ -72,7 +72,9 @@
 //!   [`LuaCodeFromReader`](struct.LuaCodeFromReader.html) structs. Since pushing these structs can
 //!   result in an error, you need to use [`checked_set`](struct.Lua.html#method.checked_set)
 //!   instead of `set`.
-//! - `Vec`s and `HashMap`s whose content is pushable.
+//! - `Vec`s, boxed slices and `HashMap`s whose content is pushable.
+//! - Ranges such as `1..=10`, which are pushed as arrays containing every element. Pushing a
+//!   large range builds a table just as large.
 //! - As a special case, `Result` can be pushed only as the return type of a Rust function or
 //!   closure. If they contain an error, the Rust function call is considered to have failed.
 //! - As a special case, tuples can be pushed when they are the return type of a Rust function or
@@ -90,9 +92,13 @@
 //!
 //! You can load values that implement [the `LuaRead` trait](trait.LuaRead.html):
 //!
-//! - Integers, floating point numbers and booleans.
+//! - Integers, floating point numbers and booleans. Only `true` and `false` are read as booleans:
+//!   other values aren't converted using the truthiness rules of Lua, where `0` is true.
+//! - `Option<T>`, which is `None` if the value is `nil`, and otherwise is read like `T`. This
+//!   means that reading a number as an `Option<bool>` fails instead of giving `Some(false)`.
 //! - `String` and [`StringInLua`](struct.StringInLua.html) (ie. the equivalent of `&str`). Loading
 //!   the latter has no cost while loading a `String` performs an allocation.
+//!   [`InlineString`](struct.InlineString.html) copies short strings without allocating.
 //! - Any function (Lua or Rust), with [the `LuaFunction` struct](struct.LuaFunction.html). This
 //!   can then be used to execute the function.
 //! - [The `AnyLuaValue` struct](struct.AnyLuaValue.html). This enumeration represents any possible
@@ -104,6 +110,10 @@
 //!   the return type of [`execute`](struct.Lua.html#method.execute).
 //! - TODO: userdata
 //!
+//! `get` returns `None` when the read fails. [The `try_get` method](struct.Lua.html#method.try_get)
+//! returns a [`ReadError`](struct.ReadError.html) instead, which tells what was expected and what
+//! was found.
+//!
 #![allow(clippy::missing_safety_doc)] // TODO: Document instead
 #![warn(clippy::ptr_as_ptr)]
 
@@ -125,38 +135,79 @@ pub extern crate lua54_sys as ffi;
 pub extern crate luajit2_sys as ffi;
 
 use std::{
-    borrow::Borrow,
+    borrow::{Borrow, Cow},
     convert::From,
     error::Error,
     ffi::{CStr, CString},
-    fmt, io,
-    io::{Error as IoError, Read},
+    fmt,
+    fs::File,
+    io,
+    io::{BufReader, Error as IoError, Read},
     marker::PhantomData,
+    path::Path,
     ptr::NonNull,
 };
 
-pub use any::{AnyHashableLuaValue, AnyLuaString, AnyLuaValue};
+pub use any::{AnyHashableLuaValue, AnyLuaString, AnyLuaValue, LuaFloat};
+pub use debug::{HookContext, HookEvent, HookMask, StackFrame};
 pub use functions_write::{
     function, function0, function1, function10, function2, function3, function4, function5,
-    function6, function7, function8, function9, Function, InsideCallback,
+    function6, function7, function8, function9, function_variadic, Function, InsideCallback,
+    Variadic, WithUpvalues,
+};
+pub use interned::InternedString;
+pub use libs::{LuaBuilder, LuaLibs};
+pub use lua_functions::{
+    LoadMode, LuaCallback, LuaCode, LuaCodeFromReader, LuaFunction, LuaFunctionCallError,
+    LuaResults,
+};
+#[cfg(feature = "impl-serde")]
+pub use lua_serde::LuaSerdeError;
+pub use lua_tables::{
+    FieldError, LuaTable, LuaTableIterator, LuaTablePairs, TableReader, TableWriter, WalkedValue,
 };
-pub use lua_functions::{LuaCode, LuaCodeFromReader, LuaFunction, LuaFunctionCallError};
-pub use lua_tables::{LuaTable, LuaTableIterator};
-pub use rust_tables::IntoIteratorWrapper;
+pub use panic_handler::ScriptFailure;
+pub use rust_tables::{
+    ExactKeys, IntoIteratorWrapper, SplitTable, TaggedRead, TaggedType, TypedKeys,
+};
+pub use scope::Scope;
+pub use try_from_lua::{ReadError, TryFromLua};
 pub use tuples::TuplePushError;
-pub use userdata::{push_userdata, read_userdata, UserdataOnStack};
-pub use values::{LuaNil, StringInLua};
+pub use userdata::{
+    push_userdata, push_userdata_no_drop, read_userdata, userdata_type_id, DynUserdata, Locked,
+    MetatableBuilder, SharedUserdata, Upgraded, UserdataCasts, UserdataOnStack, WeakUserdata,
+};
+pub use values::{
+    raw_equal, read_bytes_into, InlineString, LightUserdata, LuaNil, LuaNull, LuaNumber, LuaType,
+    Nullable, PushDisplay, ReadBytesError, StrictRead, StrictType, StringInLua,
+};
+pub use weak_ref::WeakLuaRef;
 
 mod any;
+mod debug;
+mod dump;
 mod ffix;
 mod functions_write;
+mod interned;
+mod libs;
 mod lua_functions;
+#[cfg(feature = "impl-serde")]
+mod lua_serde;
 mod lua_tables;
 mod macros;
+mod package;
+mod panic_handler;
+mod readonly;
+mod repl;
 mod rust_tables;
+mod scope;
+mod try_from_lua;
 mod tuples;
 mod userdata;
 mod values;
+#[cfg(feature = "_luaapi_54")]
+mod warn;
+mod weak_ref;
 
 /// Main object of the library.
 ///
@@ -437,6 +488,15 @@ pub trait LuaRead<L>: Sized {
     fn lua_read_out_of_bounds(lua: L) -> Result<Self, L> {
         Err(lua)
     }
+
+    /// Describes the values that can be read, such as `"integer"` or `"table"`.
+    ///
+    /// This is used by [`TryFromLua`](trait.TryFromLua.html) to report why a read failed. The
+    /// default is the name of the Rust type.
+    #[inline]
+    fn expected() -> Cow<'static, str> {
+        Cow::Borrowed(std::any::type_name::<Self>())
+    }
 }
 
 /// Error that can happen when executing Lua code.
@@ -493,6 +553,62 @@ impl From<io::Error> for LuaError {
     }
 }
 
+/// Status of a Lua thread, as returned by `lua_status`.
+///
+/// See `Lua::status`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LuaStatus {
+    /// The thread is not running anything, or finished running a coroutine.
+    Ok,
+    /// The thread is a coroutine that yielded and can be resumed.
+    Yield,
+    /// The last resume raised a runtime error.
+    ErrRun,
+    /// The last resume failed because of a syntax error.
+    ErrSyntax,
+    /// The last resume failed because of a memory allocation error.
+    ErrMem,
+    /// The last resume failed because of an error while running a `__gc` metamethod.
+    #[cfg(feature = "_luaapi_52")]
+    ErrGcMm,
+    /// The last resume failed because of an error while running the message handler.
+    ErrErr,
+}
+
+impl LuaStatus {
+    #[inline]
+    fn from_code(code: libc::c_int) -> LuaStatus {
+        match code {
+            ffi::LUA_OK => LuaStatus::Ok,
+            ffi::LUA_YIELD => LuaStatus::Yield,
+            ffi::LUA_ERRSYNTAX => LuaStatus::ErrSyntax,
+            ffi::LUA_ERRMEM => LuaStatus::ErrMem,
+            #[cfg(feature = "_luaapi_52")]
+            ffi::LUA_ERRGCMM => LuaStatus::ErrGcMm,
+            ffi::LUA_ERRERR => LuaStatus::ErrErr,
+            // `LUA_ERRRUN`, and any code added by future versions.
+            _ => LuaStatus::ErrRun,
+        }
+    }
+}
+
+/// Memory used by Lua before and after a call to `Lua::collect_garbage_and_shrink`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct GcReport {
+    /// Bytes in use before the collection.
+    pub bytes_before: usize,
+    /// Bytes in use after the collection.
+    pub bytes_after: usize,
+}
+
+impl GcReport {
+    /// Returns the number of bytes that have been freed.
+    #[inline]
+    pub fn freed(&self) -> usize {
+        self.bytes_before.saturating_sub(self.bytes_after)
+    }
+}
+
 impl<'lua> Default for Lua<'lua> {
     fn default() -> Lua<'lua> {
         Self::new()
@@ -538,19 +654,253 @@ impl<'lua> Lua<'lua> {
         Lua { lua, must_be_closed: true, marker: PhantomData }
     }
 
+    /// Builds a new Lua context in which exactly the standard libraries of `libs` are open.
+    ///
+    /// This is a shortcut for `Lua::builder().libs(libs).build()`. Contrary to calling the
+    /// `open_*` methods one by one, the set of libraries available to the scripts is visible in
+    /// a single place, which makes it easier to audit sandboxes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{Lua, LuaLibs};
+    /// let mut lua = Lua::with_libs(LuaLibs::BASE | LuaLibs::MATH);
+    /// assert_eq!(lua.execute::<i32>("return math.max(1, 2)").unwrap(), 2);
+    /// ```
+    #[inline]
+    #[must_use]
+    pub fn with_libs(libs: LuaLibs) -> Lua<'lua> {
+        Lua::builder().libs(libs).build()
+    }
+
+    /// Returns a builder for a new Lua context. See `LuaBuilder`.
+    #[inline]
+    pub fn builder() -> LuaBuilder {
+        LuaBuilder::default()
+    }
+
     /// Takes an existing `lua_State` and build a Lua object from it.
     ///
+    /// This is useful when the state is owned by a host application, for example when writing a
+    /// plugin loaded into an existing Lua interpreter.
+    ///
     /// If `close_at_the_end` is true, `lua_close` will be called on the `lua_State` in the
-    /// destructor.
+    /// destructor. Otherwise the state is only borrowed and is left open when the `Lua` is
+    /// dropped.
+    ///
+    /// # Safety
+    ///
+    /// `lua` must point to a valid `lua_State` that stays alive for as long as the returned `Lua`
+    /// exists. If `close_at_the_end` is true, nothing else must close the state.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `lua` is null.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let raw = unsafe { hlua::ffi::luaL_newstate() };
+    ///
+    /// {
+    ///     let mut lua = unsafe { hlua::Lua::from_existing_state(raw, false) };
+    ///     lua.set("a", 12);
+    /// }
+    ///
+    /// // The state is still usable after the `Lua` has been dropped.
+    /// let mut lua = unsafe { hlua::Lua::from_existing_state(raw, true) };
+    /// assert_eq!(lua.get::<i32, _>("a"), Some(12));
+    /// ```
     #[inline]
     pub unsafe fn from_existing_state<T>(lua: *mut T, close_at_the_end: bool) -> Lua<'lua> {
         Lua {
-            lua: NonNull::new_unchecked(lua.cast()),
+            lua: NonNull::new(lua.cast()).expect("from_existing_state called with a null pointer"),
             must_be_closed: close_at_the_end,
             marker: PhantomData,
         }
     }
 
+    /// Destroys the `Lua` without closing its `lua_State`, and returns the state.
+    ///
+    /// This gives the ownership of a state created by `Lua::new` to the caller, which becomes
+    /// responsible for calling `lua_close`. For a `Lua` created by `from_existing_state`, this is
+    /// the same as dropping it with `close_at_the_end` set to false.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.set("a", 12);
+    /// let raw = lua.into_inner();
+    ///
+    /// let mut lua = unsafe { hlua::Lua::from_existing_state(raw, true) };
+    /// assert_eq!(lua.get::<i32, _>("a"), Some(12));
+    /// ```
+    #[inline]
+    pub fn into_inner(self) -> *mut ffi::lua_State {
+        let lua = self.lua.as_ptr();
+        std::mem::forget(self);
+        lua
+    }
+
+    /// Returns the status of the Lua thread.
+    ///
+    /// This is `LuaStatus::Ok` for a regular context. For a context wrapping a coroutine, this
+    /// tells whether the last resume yielded, finished, or failed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{Lua, LuaStatus};
+    /// let lua = Lua::new();
+    /// assert_eq!(lua.status(), LuaStatus::Ok);
+    /// ```
+    #[inline]
+    pub fn status(&self) -> LuaStatus {
+        LuaStatus::from_code(unsafe { ffi::lua_status(self.lua.as_ptr()) })
+    }
+
+    /// Sets a function that is called whenever a script fails, replacing the previous one.
+    ///
+    /// The handler is called when a Rust callback panics, and when `execute`,
+    /// `execute_from_reader`, `do_file` or `call_global` return an error. Panicking callbacks
+    /// raise a Lua error, so if the script doesn't catch it the handler is called a second time
+    /// with the resulting error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use hlua::ScriptFailure;
+    ///
+    /// let mut lua = hlua::Lua::new();
+    /// let log = Arc::new(Mutex::new(Vec::new()));
+    ///
+    /// let log2 = log.clone();
+    /// lua.set_panic_handler(move |failure| {
+    ///     if let ScriptFailure::Error(err) = failure {
+    ///         log2.lock().unwrap().push(err.to_string());
+    ///     }
+    /// });
+    ///
+    /// assert!(lua.execute::<()>("this is not lua").is_err());
+    /// assert_eq!(log.lock().unwrap().len(), 1);
+    /// ```
+    #[inline]
+    pub fn set_panic_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(ScriptFailure) + Send + 'static,
+    {
+        panic_handler::set(self.lua, Box::new(handler));
+    }
+
+    /// Installs the conversions used to read `DynUserdata<D>`, replacing the previous ones for
+    /// `D`. See `UserdataCasts`.
+    #[inline]
+    pub fn set_userdata_casts<D>(&mut self, casts: UserdataCasts<D>)
+    where
+        D: ?Sized + 'static,
+    {
+        userdata::set_casts(self.lua, casts);
+    }
+
+    /// Sets a function that Lua calls on the events selected by `mask`, replacing the previous
+    /// one.
+    ///
+    /// This wraps `lua_sethook`, and can be used to build debuggers or profilers. The hook
+    /// receives a `HookContext` describing the event and the function being executed. If the
+    /// hook panics, the panic is turned into a Lua error raised by the running code.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    /// use hlua::{HookEvent, HookMask};
+    ///
+    /// let mut lua = hlua::Lua::new();
+    /// let lines = Arc::new(Mutex::new(Vec::new()));
+    ///
+    /// let lines2 = lines.clone();
+    /// lua.set_hook(HookMask::LINE, move |ctx| {
+    ///     assert_eq!(ctx.event(), HookEvent::Line);
+    ///     lines2.lock().unwrap().push(ctx.line().unwrap());
+    /// });
+    ///
+    /// lua.execute::<()>("local a = 1\nlocal b = 2\n").unwrap();
+    /// assert_eq!(*lines.lock().unwrap(), [1, 2]);
+    /// ```
+    #[inline]
+    pub fn set_hook<F>(&mut self, mask: HookMask, hook: F)
+    where
+        F: FnMut(&HookContext) + Send + 'static,
+    {
+        debug::set_hook(self.lua, mask, Some(Box::new(hook)));
+    }
+
+    /// Removes the function set with `set_hook`, if any.
+    #[inline]
+    pub fn remove_hook(&mut self) {
+        debug::set_hook(self.lua, HookMask::NONE, None);
+    }
+
+    /// Sets a function that receives the messages of the `warn` function of Lua 5.4, replacing
+    /// the previous one.
+    ///
+    /// This wraps `lua_setwarnf`. Messages made of several pieces are joined before being passed
+    /// to the handler, and control messages such as `"@on"` are ignored. Warnings emitted by the
+    /// runtime itself, for example for errors in finalizers, are passed to the handler as well.
+    /// If the handler panics, the panic is reported to the panic handler and otherwise ignored.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::sync::{Arc, Mutex};
+    ///
+    /// let mut lua = hlua::Lua::with_libs(hlua::LuaLibs::BASE);
+    /// let warnings = Arc::new(Mutex::new(Vec::new()));
+    ///
+    /// let warnings2 = warnings.clone();
+    /// lua.set_warn_handler(move |msg| warnings2.lock().unwrap().push(msg.to_owned()));
+    ///
+    /// lua.execute::<()>(r#"warn("disk ", "almost full")"#).unwrap();
+    /// assert_eq!(*warnings.lock().unwrap(), ["disk almost full"]);
+    /// ```
+    #[inline]
+    #[cfg(feature = "_luaapi_54")]
+    pub fn set_warn_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(&str) + Send + 'static,
+    {
+        warn::set(self.lua, Box::new(handler));
+    }
+
+    /// Runs two full garbage collection cycles, and returns the memory used before and after.
+    ///
+    /// Objects with a finalizer, such as userdata, are only freed by the cycle after the one that
+    /// ran their finalizer, so a single `collectgarbage()` often leaves them in memory. This is
+    /// meant to be called at natural boundaries, for example after reading or writing large
+    /// tables.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.execute::<()>("big = {} for i = 1, 10000 do big[i] = {} end").unwrap();
+    /// lua.set("big", hlua::LuaNil);
+    ///
+    /// let report = lua.collect_garbage_and_shrink();
+    /// assert!(report.bytes_after < report.bytes_before);
+    /// ```
+    #[inline]
+    pub fn collect_garbage_and_shrink(&mut self) -> GcReport {
+        unsafe {
+            let bytes_before = ffix::lua_memory_used(self.lua);
+            ffi::lua_gc(self.lua.as_ptr(), ffi::LUA_GCCOLLECT as _, 0);
+            ffi::lua_gc(self.lua.as_ptr(), ffi::LUA_GCCOLLECT as _, 0);
+            GcReport { bytes_before, bytes_after: ffix::lua_memory_used(self.lua) }
+        }
+    }
+
     /// Opens all standard Lua libraries.
     ///
     /// See the reference for the standard library here:
@@ -570,6 +920,95 @@ impl<'lua> Lua<'lua> {
         unsafe { ffi::luaL_openlibs(self.lua.as_ptr()) };
     }
 
+    /// Returns the frames of the Lua call stack, starting with the innermost one.
+    ///
+    /// The stack is empty unless Lua code is running, which means that this is mostly useful
+    /// from a callback, through `InsideCallback::stack_trace`.
+    #[inline]
+    pub fn stack_trace(&self) -> Vec<StackFrame> {
+        debug::stack_trace(self.lua)
+    }
+
+    /// Returns a traceback of the Lua call stack, as built by `luaL_traceback`.
+    ///
+    /// The traceback starts at the frame `level`, where 0 is the innermost one, and is preceded
+    /// by `message` unless it is empty. Like `stack_trace`, this is mostly useful from a
+    /// callback, through `InsideCallback::traceback`.
+    #[inline]
+    pub fn traceback(&self, message: &str, level: i32) -> String {
+        debug::traceback(self.lua, message, level)
+    }
+
+    /// Returns the number of values on the Lua stack, as returned by `lua_gettop`.
+    ///
+    /// This is mostly useful to debug implementations of `Push` and `LuaRead`. When no value is
+    /// borrowed from the stack, for example by a `LuaTable` or a `PushGuard`, this is zero.
+    #[inline]
+    pub fn stack_size(&self) -> i32 {
+        unsafe { ffi::lua_gettop(self.lua.as_ptr()) }
+    }
+
+    /// Calls `f` and, in debug builds, checks that it leaves as many values on the stack as
+    /// there were before.
+    ///
+    /// # Panic
+    ///
+    /// Panics in debug builds if the size of the stack has changed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// let value = lua.with_balanced_stack(|lua| {
+    ///     lua.set("a", 5);
+    ///     lua.get::<i32, _>("a")
+    /// });
+    /// assert_eq!(value, Some(5));
+    /// ```
+    #[inline]
+    pub fn with_balanced_stack<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut Lua<'lua>) -> R,
+    {
+        let size = self.stack_size();
+        let result = f(self);
+        debug_assert_eq!(self.stack_size(), size, "the size of the Lua stack has changed");
+        result
+    }
+
+    /// Gives a name to the function stored at `path`, which is a global variable or a path to a
+    /// field such as `audio.set_volume`, as accepted by `get_path`.
+    ///
+    /// Lua finds the names of functions by looking at how they are called, so a Rust callback
+    /// stored under several names or called through a local variable often has no name. The name
+    /// given here follows the function itself, and is used by `stack_trace` and in the errors
+    /// raised when the callback receives parameters of the wrong types or panics. It doesn't
+    /// change the tracebacks built by Lua itself, such as the ones of `debug.traceback`.
+    ///
+    /// Returns `false` and does nothing if there is no function at `path`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.set("set_volume", hlua::function1(|_: u8| {}));
+    /// assert!(lua.set_name_for_function("set_volume", "set_volume"));
+    ///
+    /// match lua.execute::<()>("local f = set_volume; f('loud')") {
+    ///     Err(hlua::LuaError::ExecutionError(msg)) => assert!(msg.contains("'set_volume'")),
+    ///     _ => unreachable!(),
+    /// }
+    /// ```
+    pub fn set_name_for_function(&mut self, path: &str, name: &str) -> bool {
+        match self.get_path::<LuaFunction<_>>(path) {
+            Some(function) => {
+                debug::set_function_name(function.as_lua(), name);
+                true
+            },
+            None => false,
+        }
+    }
+
     /// Opens base library.
     ///
     /// https://www.lua.org/manual/5.2/manual.html#pdf-luaopen_base
@@ -666,6 +1105,54 @@ impl<'lua> Lua<'lua> {
         unsafe { ffi::lua_call(lua_ptr, 1, 0) };
     }
 
+    /// Appends `dir` to `package.path`, so that `require "name"` also looks for `dir/name.lua`
+    /// and `dir/name/init.lua`.
+    ///
+    /// Returns an error if the package library isn't open, or if the path of `dir` isn't valid
+    /// UTF-8 or contains a `;` or a `?`, which have a special meaning in `package.path`.
+    #[inline]
+    pub fn add_package_path<P>(&mut self, dir: P) -> Result<(), LuaError>
+    where
+        P: AsRef<Path>,
+    {
+        package::add_path(self.lua, dir.as_ref())
+    }
+
+    /// Lets `require` load modules provided by `searcher`, which receives the name of a module
+    /// and returns its code, or `None` if it doesn't know this module.
+    ///
+    /// The searcher is inserted in `package.searchers` (`package.loaders` in LuaJIT) so that it
+    /// is tried right after `package.preload`, before the files of `package.path`. This is useful
+    /// to load modules embedded in the binary. Calling this function again replaces the previous
+    /// searcher.
+    ///
+    /// The code can be Lua source code or precompiled bytecode. If it doesn't compile, `require`
+    /// raises an error. Returns an error if the package library isn't open.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.openlibs();
+    ///
+    /// lua.set_searcher(|name| match name {
+    ///     "greeting" => Some("return { hello = function() return 'hi' end }"),
+    ///     _ => None,
+    /// })
+    /// .unwrap();
+    ///
+    /// let hi: String = lua.execute("return require('greeting').hello()").unwrap();
+    /// assert_eq!(hi, "hi");
+    /// ```
+    #[inline]
+    pub fn set_searcher<F, S>(&mut self, mut searcher: F) -> Result<(), LuaError>
+    where
+        F: FnMut(&str) -> Option<S> + Send + 'static,
+        S: Into<Vec<u8>>,
+    {
+        package::set_searcher(self.lua, Box::new(move |name| searcher(name).map(Into::into)))
+    }
+
     /// Opens string library.
     ///
     /// https://www.lua.org/manual/5.2/manual.html#pdf-luaopen_string
@@ -733,79 +1220,515 @@ impl<'lua> Lua<'lua> {
     where
         T: for<'g> LuaRead<PushGuard<&'g mut PushGuard<&'a mut Lua<'lua>>>>,
     {
-        let mut f = lua_functions::LuaFunction::load(self, code)?;
-        f.call()
+        let raw_lua = self.as_mut_lua();
+        let mut f = lua_functions::LuaFunction::load(self, code)
+            .map_err(|err| panic_handler::report(raw_lua, err))?;
+        f.call().map_err(|err| panic_handler::report(raw_lua, err))
     }
 
-    /// Executes some Lua code on the context.
-    ///
-    /// This does the same thing as [the `execute` method](#method.execute), but the code to
-    /// execute is loaded from an object that implements `Read`.
+    /// Checks that some Lua code compiles, without running it.
     ///
-    /// Use this method when you potentially have a large amount of code (for example if you read
-    /// the code from a file) in order to avoid having to put everything in memory first before
-    /// passing it to the Lua interpreter.
+    /// The code is parsed the same way as by `execute`, and the resulting function is discarded
+    /// right away. Precompiled bytecode is refused. Returns a `LuaError::SyntaxError` if the code
+    /// isn't valid. Contrary to `execute`, the error isn't passed to the panic handler, since
+    /// no script has failed.
     ///
     /// # Example
     ///
-    /// ```no_run
-    /// use std::fs::File;
-    /// use hlua::Lua;
-    ///
+    /// ```
+    /// use hlua::{Lua, LuaError};
     /// let mut lua = Lua::new();
-    /// let script = File::open("script.lua").unwrap();
-    /// lua.execute_from_reader::<(), _>(script).unwrap();
+    ///
+    /// assert!(lua.load_and_compile_only("x = 1").is_ok());
+    /// // The code hasn't been run.
+    /// assert_eq!(lua.get::<i32, _>("x"), None);
+    ///
+    /// match lua.load_and_compile_only("x = ") {
+    ///     Err(LuaError::SyntaxError(msg)) => assert!(msg.contains("unexpected symbol")),
+    ///     other => panic!("unexpected result: {:?}", other),
+    /// }
     /// ```
     #[inline]
-    pub fn execute_from_reader<'a, T, R>(&'a mut self, code: R) -> Result<T, LuaError>
-    where
-        T: for<'g> LuaRead<PushGuard<&'g mut PushGuard<&'a mut Lua<'lua>>>>,
-        R: Read,
-    {
-        let mut f = lua_functions::LuaFunction::load_from_reader(self, code)?;
-        f.call()
+    pub fn load_and_compile_only(&mut self, code: &str) -> Result<(), LuaError> {
+        lua_functions::LuaFunction::load_text_only(self, code.as_bytes(), "chunk").map(drop)
     }
 
-    /// Reads the value of a global variable.
-    ///
-    /// Returns `None` if the variable doesn't exist or has the wrong type.
+    /// Executes some Lua code on the context, and keeps all its return values on the stack.
     ///
-    /// The type must implement the `LuaRead` trait. See
-    /// [the documentation at the crate root](index.html#pushing-and-loading-values) for more
-    /// information.
+    /// Contrary to `execute`, the return values aren't read. The returned `LuaResults` gives
+    /// access to each of them, so that the caller can decide what to read based on their number
+    /// and types. See `LuaFunction::call_results`.
     ///
     /// # Example
     ///
     /// ```
-    /// use hlua::Lua;
+    /// use hlua::{Lua, LuaType};
+    ///
     /// let mut lua = Lua::new();
-    /// lua.execute::<()>("a = 5").unwrap();
-    /// let a: i32 = lua.get("a").unwrap();
-    /// assert_eq!(a, 5);
+    /// let mut results = lua.execute_results("return nil, 'not found'").unwrap();
+    /// assert_eq!(results.len(), 2);
+    /// if results.type_of(0) == Some(LuaType::Nil) {
+    ///     assert_eq!(results.read::<String>(1).unwrap(), "not found");
+    /// }
     /// ```
     #[inline]
-    #[allow(clippy::needless_lifetimes)] // clippy: false positive
-    pub fn get<'l, V, I>(&'l mut self, index: I) -> Option<V>
-    where
-        I: Borrow<str>,
-        V: LuaRead<PushGuard<&'l mut Lua<'lua>>>,
-    {
+    pub fn execute_results<'a>(
+        &'a mut self,
+        code: &str,
+    ) -> Result<LuaResults<LuaFunction<PushGuard<&'a mut Lua<'lua>>>>, LuaError> {
         let raw_lua = self.as_mut_lua();
-
-        let index = CString::new(index.borrow()).unwrap();
-        unsafe { ffi::lua_getglobal(raw_lua.as_ptr(), index.as_ptr()) };
-        let guard = PushGuard { lua: self, size: 1, raw_lua };
-
-        match unsafe { ffi::lua_isnil(raw_lua.as_ptr(), -1) } {
-            true => None,
-            false => LuaRead::lua_read(guard).ok(),
+        let f = lua_functions::LuaFunction::load(self, code)
+            .map_err(|err| panic_handler::report(raw_lua, err))?;
+        match f.into_call_results(()) {
+            Ok(results) => Ok(results),
+            Err(LuaFunctionCallError::LuaError(err)) => Err(panic_handler::report(raw_lua, err)),
+            Err(LuaFunctionCallError::PushError(_)) => unreachable!(),
         }
     }
 
-    /// Reads the value of a global, capturing the context by value.
+    /// Executes some Lua code on the context, and reads all its return values.
+    ///
+    /// This is a shortcut for `execute_results` followed by reading each value as an
+    /// `AnyLuaValue`, for code whose number of return values isn't known in advance. Errors are
+    /// passed to the panic handler like with `execute`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{AnyLuaValue, Lua};
+    ///
+    /// let mut lua = Lua::new();
+    /// let values = lua.execute_all("return 1, 'two', nil").unwrap();
+    /// assert_eq!(values.len(), 3);
+    /// assert_eq!(values[1], AnyLuaValue::LuaString("two".to_owned()));
+    /// assert_eq!(values[2], AnyLuaValue::LuaNil);
+    ///
+    /// assert!(lua.execute_all("x = 1").unwrap().is_empty());
+    /// ```
     #[inline]
-    pub fn into_get<V, I>(mut self, index: I) -> Result<V, PushGuard<Self>>
-    where
+    pub fn execute_all(&mut self, code: &str) -> Result<Vec<AnyLuaValue>, LuaError> {
+        let mut results = self.execute_results(code)?;
+        Ok((0..results.len())
+            .map(|n| results.read(n).expect("AnyLuaValue can always be read"))
+            .collect())
+    }
+
+    /// Runs a line typed in an interactive interpreter, and returns all its results.
+    ///
+    /// Like in the interactive mode of the stock `lua` interpreter, the line is first compiled as
+    /// an expression, as if it was preceded by `return`, so that typing `1 + 2` gives `3`. If it
+    /// isn't a valid expression, it is compiled as a statement instead, and a syntax error then
+    /// refers to the statement. Contrary to `execute`, errors aren't passed to the panic handler.
+    ///
+    /// A syntax error whose message ends with `<eof>` means that the statement is incomplete,
+    /// for example the first line of a function definition. See `repl`, which then reads more
+    /// lines.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{AnyLuaValue, Lua};
+    ///
+    /// let mut lua = Lua::new();
+    /// let string = |s: &str| AnyLuaValue::LuaString(s.to_owned());
+    /// assert_eq!(lua.eval_line("x = 'hello'").unwrap(), []);
+    /// assert_eq!(lua.eval_line("x, x .. '!'").unwrap(), [string("hello"), string("hello!")]);
+    /// ```
+    pub fn eval_line(&mut self, line: &str) -> Result<Vec<AnyLuaValue>, LuaError> {
+        fn call_all(f: LuaFunction<PushGuard<&mut Lua>>) -> Result<Vec<AnyLuaValue>, LuaError> {
+            let mut results = f.into_call_results(()).map_err(LuaError::from)?;
+            Ok((0..results.len())
+                .map(|n| results.read(n).expect("AnyLuaValue can always be read"))
+                .collect())
+        }
+
+        let expression = format!("return {}", line);
+        if let Ok(f) = LuaFunction::load_text_only(&mut *self, expression.as_bytes(), "stdin") {
+            return call_all(f);
+        }
+        call_all(LuaFunction::load_text_only(self, line.as_bytes(), "stdin")?)
+    }
+
+    /// Runs an interactive interpreter that reads code from `input`, until its end.
+    ///
+    /// Each line is run with `eval_line`, and its results are written to `output`, separated by
+    /// tabs, along with the error messages. Incomplete statements are continued on the next
+    /// lines, with a `>>` prompt instead of `>`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let mut lua = hlua::Lua::with_libs(hlua::LuaLibs::ALL);
+    /// lua.repl(std::io::stdin().lock(), std::io::stdout()).unwrap();
+    /// ```
+    #[inline]
+    pub fn repl<R, W>(&mut self, input: R, output: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        repl::run(self, input, output)
+    }
+
+    /// Executes some Lua code on the context.
+    ///
+    /// This does the same thing as [the `execute` method](#method.execute), but the code to
+    /// execute is loaded from an object that implements `Read`.
+    ///
+    /// Use this method when you potentially have a large amount of code (for example if you read
+    /// the code from a file) in order to avoid having to put everything in memory first before
+    /// passing it to the Lua interpreter.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use hlua::Lua;
+    ///
+    /// let mut lua = Lua::new();
+    /// let script = File::open("script.lua").unwrap();
+    /// lua.execute_from_reader::<(), _>(script).unwrap();
+    /// ```
+    #[inline]
+    pub fn execute_from_reader<'a, T, R>(&'a mut self, code: R) -> Result<T, LuaError>
+    where
+        T: for<'g> LuaRead<PushGuard<&'g mut PushGuard<&'a mut Lua<'lua>>>>,
+        R: Read,
+    {
+        let raw_lua = self.as_mut_lua();
+        let mut f = lua_functions::LuaFunction::load_from_reader(self, code)
+            .map_err(|err| panic_handler::report(raw_lua, err))?;
+        f.call().map_err(|err| panic_handler::report(raw_lua, err))
+    }
+
+    /// Loads the Lua file at `path` and executes it, like Lua's `dofile`.
+    ///
+    /// The file is read with [the `execute_from_reader` method](#method.execute_from_reader).
+    /// Failing to open or read the file returns a `LuaError::ReadError`, a file that isn't valid
+    /// Lua code returns a `LuaError::SyntaxError`, and errors while running it return a
+    /// `LuaError::ExecutionError`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use hlua::Lua;
+    ///
+    /// let mut lua = Lua::new();
+    /// let config: hlua::AnyLuaValue = lua.do_file("config.lua").unwrap();
+    /// ```
+    #[inline]
+    pub fn do_file<'a, T, P>(&'a mut self, path: P) -> Result<T, LuaError>
+    where
+        T: for<'g> LuaRead<PushGuard<&'g mut PushGuard<&'a mut Lua<'lua>>>>,
+        P: AsRef<Path>,
+    {
+        let file = File::open(path)
+            .map_err(|err| panic_handler::report(self.lua, LuaError::ReadError(err)))?;
+        self.execute_from_reader(BufReader::new(file))
+    }
+
+    /// Calls the global function `name` with `args`, and reads its return value.
+    ///
+    /// This is the same as reading the global as a `LuaFunction` and calling `call_with_args`
+    /// on it, in one step. Arguments are passed like for `call_with_args`.
+    ///
+    /// Returns a `LuaError::ExecutionError` if the global variable isn't a function or if the
+    /// function raises an error, and a `LuaError::WrongType` if the return value doesn't match
+    /// `R`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.execute::<()>("time = 0; function on_update(dt) time = time + dt; return time end")
+    ///     .unwrap();
+    ///
+    /// for _ in 0..3 {
+    ///     lua.call_global::<f64, _, _>("on_update", 0.5).unwrap();
+    /// }
+    /// assert_eq!(lua.call_global::<f64, _, _>("on_update", 0.5).unwrap(), 2.0);
+    /// assert!(lua.call_global::<(), _, _>("missing", ()).is_err());
+    /// ```
+    #[inline]
+    pub fn call_global<'a, R, A, E>(&'a mut self, name: &str, args: A) -> Result<R, LuaError>
+    where
+        A: for<'r> Push<&'r mut LuaFunction<PushGuard<&'a mut Lua<'lua>>>, Err = E>,
+        E: Into<Void>,
+        R: for<'g> LuaRead<PushGuard<&'g mut PushGuard<&'a mut Lua<'lua>>>>,
+    {
+        let raw_lua = self.as_mut_lua();
+        let mut function: LuaFunction<_> = match self.get(name) {
+            Some(function) => function,
+            None => {
+                let msg = format!("global '{}' is not a function", name);
+                return Err(panic_handler::report(raw_lua, LuaError::ExecutionError(msg)));
+            },
+        };
+
+        match function.call_with_args(args) {
+            Ok(value) => Ok(value),
+            Err(LuaFunctionCallError::LuaError(err)) => Err(panic_handler::report(raw_lua, err)),
+            Err(LuaFunctionCallError::PushError(_)) => unreachable!(),
+        }
+    }
+
+    /// Loads a chunk of source code or precompiled bytecode without running it.
+    ///
+    /// This is a shortcut for
+    /// [`LuaFunction::load_buffer`](struct.LuaFunction.html#method.load_buffer).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::Lua;
+    /// let mut lua = Lua::new();
+    ///
+    /// let mut chunk = lua.load_buffer(b"return 2 + 2", "=sum").unwrap();
+    /// assert_eq!(chunk.call::<i32>().unwrap(), 4);
+    /// ```
+    #[inline]
+    pub fn load_buffer<'a>(
+        &'a mut self,
+        code: &[u8],
+        name: &str,
+    ) -> Result<LuaFunction<PushGuard<&'a mut Lua<'lua>>>, LuaError> {
+        LuaFunction::load_buffer(self, code, name)
+    }
+
+    /// Loads a chunk of source code without running it, refusing precompiled bytecode.
+    ///
+    /// This is a shortcut for
+    /// [`LuaFunction::load_text_only`](struct.LuaFunction.html#method.load_text_only), which
+    /// should be preferred over `load_buffer` for code coming from untrusted sources.
+    #[inline]
+    pub fn load_text_only<'a>(
+        &'a mut self,
+        code: &[u8],
+        name: &str,
+    ) -> Result<LuaFunction<PushGuard<&'a mut Lua<'lua>>>, LuaError> {
+        LuaFunction::load_text_only(self, code, name)
+    }
+
+    /// Loads a chunk without running it, accepting only the kinds of chunks allowed by `mode`.
+    ///
+    /// This is a shortcut for
+    /// [`LuaFunction::load_with_mode`](struct.LuaFunction.html#method.load_with_mode).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{LoadMode, Lua};
+    /// let mut lua = Lua::new();
+    ///
+    /// assert!(lua.load_with_mode(b"return 1", "=source", LoadMode::Binary).is_err());
+    /// ```
+    #[inline]
+    pub fn load_with_mode<'a>(
+        &'a mut self,
+        code: &[u8],
+        name: &str,
+        mode: LoadMode,
+    ) -> Result<LuaFunction<PushGuard<&'a mut Lua<'lua>>>, LuaError> {
+        LuaFunction::load_with_mode(self, code, name, mode)
+    }
+
+    /// Reads the value of a global variable.
+    ///
+    /// Returns `None` if the variable doesn't exist or has the wrong type.
+    ///
+    /// The type must implement the `LuaRead` trait. See
+    /// [the documentation at the crate root](index.html#pushing-and-loading-values) for more
+    /// information.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::Lua;
+    /// let mut lua = Lua::new();
+    /// lua.execute::<()>("a = 5").unwrap();
+    /// let a: i32 = lua.get("a").unwrap();
+    /// assert_eq!(a, 5);
+    /// ```
+    #[inline]
+    #[allow(clippy::needless_lifetimes)] // clippy: false positive
+    pub fn get<'l, V, I>(&'l mut self, index: I) -> Option<V>
+    where
+        I: Borrow<str>,
+        V: LuaRead<PushGuard<&'l mut Lua<'lua>>>,
+    {
+        let raw_lua = self.as_mut_lua();
+
+        let index = CString::new(index.borrow()).unwrap();
+        unsafe { ffi::lua_getglobal(raw_lua.as_ptr(), index.as_ptr()) };
+        let guard = PushGuard { lua: self, size: 1, raw_lua };
+
+        match unsafe { ffi::lua_isnil(raw_lua.as_ptr(), -1) } {
+            true => None,
+            false => LuaRead::lua_read(guard).ok(),
+        }
+    }
+
+    /// Reads the value of a global variable, and describes why the read failed if it does.
+    ///
+    /// Contrary to `get`, a variable that doesn't exist is read as `nil`, which succeeds for
+    /// types such as `Option<T>`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.execute::<()>("a = 5; t = {}").unwrap();
+    ///
+    /// assert_eq!(lua.try_get::<i32, _>("a").unwrap(), 5);
+    /// assert_eq!(lua.try_get::<Option<i32>, _>("missing").unwrap(), None);
+    ///
+    /// let err = lua.try_get::<String, _>("t").unwrap_err();
+    /// assert_eq!(err.expected(), "string");
+    /// assert_eq!(err.found(), Some(hlua::LuaType::Table));
+    /// assert_eq!(err.to_string(), "expected string, found table at global t");
+    /// ```
+    #[inline]
+    #[allow(clippy::needless_lifetimes)] // clippy: false positive
+    pub fn try_get<'l, V, I>(&'l mut self, name: I) -> Result<V, ReadError>
+    where
+        I: Borrow<str>,
+        V: TryFromLua<PushGuard<&'l mut Lua<'lua>>>,
+    {
+        let raw_lua = self.as_mut_lua();
+
+        let name = name.borrow();
+        let c_name = CString::new(name).unwrap();
+        unsafe { ffi::lua_getglobal(raw_lua.as_ptr(), c_name.as_ptr()) };
+        let guard = PushGuard { lua: self, size: 1, raw_lua };
+
+        TryFromLua::try_from_lua(guard, -1)
+            .map_err(|(err, _)| err.with_location(format!("global {}", name)))
+    }
+
+    /// Returns the type of a global variable, without reading its value.
+    ///
+    /// This lets code that handles values of any type, such as inspectors or serializers, choose
+    /// how to read a global before reading it, without converting a large table into an
+    /// `AnyLuaValue` first. Returns `LuaType::Nil` if the variable doesn't exist, or if `name`
+    /// contains a NUL byte, which `lua_getglobal` can't look up.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{Lua, LuaType};
+    ///
+    /// let mut lua = Lua::new();
+    /// lua.execute::<()>("a = 5; b = { 1, 2, 3 }").unwrap();
+    ///
+    /// assert_eq!(lua.type_of("a"), LuaType::Number);
+    /// assert_eq!(lua.type_of("b"), LuaType::Table);
+    /// assert_eq!(lua.type_of("c"), LuaType::Nil);
+    /// ```
+    #[inline]
+    pub fn type_of<I>(&mut self, name: I) -> LuaType
+    where
+        I: Borrow<str>,
+    {
+        let raw_lua = self.as_mut_lua();
+
+        let name = match CString::new(name.borrow()) {
+            Ok(name) => name,
+            Err(_) => return LuaType::Nil,
+        };
+        unsafe { ffi::lua_getglobal(raw_lua.as_ptr(), name.as_ptr()) };
+        let _guard = PushGuard { lua: self, size: 1, raw_lua };
+
+        LuaType::at(raw_lua, -1).expect("lua_getglobal always pushes a value")
+    }
+
+    /// Reads all the global variables, for example to inspect what a script left behind.
+    ///
+    /// Returns an `AnyLuaValue::LuaArray` of the names and values of the globals. The tables of
+    /// the standard libraries that are in `package.loaded`, such as `string` or `_G`, are left
+    /// out whatever the name of the global that holds them; see
+    /// [`dump_globals_with_libs`](#method.dump_globals_with_libs) to include them. Functions and
+    /// userdata are read as `LuaOther`, and so is a table that contains itself, directly or not,
+    /// where it appears inside of itself.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{AnyLuaValue, Lua};
+    ///
+    /// let mut lua = Lua::new();
+    /// lua.openlibs();
+    /// lua.execute::<()>("greeting = 'hello'; names = { 'a' }").unwrap();
+    ///
+    /// let globals = match lua.dump_globals() {
+    ///     AnyLuaValue::LuaArray(entries) => entries,
+    ///     _ => unreachable!(),
+    /// };
+    /// let string = |s: &str| AnyLuaValue::LuaString(s.to_owned());
+    /// assert!(globals.iter().any(|(k, v)| *k == string("greeting") && *v == string("hello")));
+    /// assert!(globals.iter().all(|(k, _)| *k != string("string")));
+    /// ```
+    #[inline]
+    pub fn dump_globals(&mut self) -> AnyLuaValue {
+        dump::globals(self.as_mut_lua(), false)
+    }
+
+    /// Same as [`dump_globals`](#method.dump_globals), but includes the tables of the standard
+    /// libraries.
+    #[inline]
+    pub fn dump_globals_with_libs(&mut self) -> AnyLuaValue {
+        dump::globals(self.as_mut_lua(), true)
+    }
+
+    /// Reads the value of a global variable into a type that implements `serde::Deserialize`.
+    ///
+    /// Unlike `get`, this reads nested tables directly into the Rust type, without going through
+    /// `AnyLuaValue`. Tables whose keys are the integers `1..n` are read as sequences, and other
+    /// tables as maps. Enum variants are read either from a string for unit variants, or from a
+    /// table with a single entry whose key is the name of the variant. Tables that contain
+    /// themselves, or that are nested more than 128 levels deep, return an error.
+    ///
+    /// Requires the `impl-serde` feature.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "impl-serde")] {
+    /// use hlua::Lua;
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Config {
+    ///     size: u32,
+    ///     names: Vec<String>,
+    /// }
+    ///
+    /// let mut lua = Lua::new();
+    /// lua.execute::<()>("config = { size = 3, names = { 'a', 'b' } }").unwrap();
+    ///
+    /// let config: Config = lua.get_de("config").unwrap();
+    /// assert_eq!(config.size, 3);
+    /// assert_eq!(config.names, ["a", "b"]);
+    /// # }
+    /// ```
+    #[cfg(feature = "impl-serde")]
+    pub fn get_de<T, I>(&mut self, name: I) -> Result<T, LuaSerdeError>
+    where
+        I: Borrow<str>,
+        T: serde::de::DeserializeOwned,
+    {
+        let raw_lua = self.as_mut_lua();
+
+        let name = CString::new(name.borrow()).unwrap();
+        unsafe { ffi::lua_getglobal(raw_lua.as_ptr(), name.as_ptr()) };
+        let _guard = PushGuard { lua: self, size: 1, raw_lua };
+
+        lua_serde::from_stack(raw_lua, -1)
+    }
+
+    /// Reads the value of a global, capturing the context by value.
+    #[inline]
+    pub fn into_get<V, I>(mut self, index: I) -> Result<V, PushGuard<Self>>
+    where
         I: Borrow<str>,
         V: LuaRead<PushGuard<Lua<'lua>>>,
     {
@@ -821,67 +1744,427 @@ impl<'lua> Lua<'lua> {
         }
     }
 
-    /// Modifies the value of a global variable.
-    ///
-    /// If you want to write an array, you are encouraged to use
-    /// [the `empty_array` method](#method.empty_array) instead.
+    /// Modifies the value of a global variable.
+    ///
+    /// If you want to write an array, you are encouraged to use
+    /// [the `empty_array` method](#method.empty_array) instead.
+    ///
+    /// The type must implement the `PushOne` trait, and pushing it must not be able to fail. Use
+    /// [the `checked_set` method](#method.checked_set) for values such as `LuaCode` whose push
+    /// can fail. See [the documentation at the crate root](index.html#pushing-and-loading-values)
+    /// for more information.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::Lua;
+    /// let mut lua = Lua::new();
+    ///
+    /// lua.set("a", 12);
+    /// let six: i32 = lua.execute("return a / 2;").unwrap();
+    /// assert_eq!(six, 6);
+    /// ```
+    #[inline]
+    pub fn set<I, V, E>(&mut self, index: I, value: V)
+    where
+        I: Borrow<str>,
+        for<'a> V: PushOne<&'a mut Lua<'lua>, Err = E>,
+        E: Into<Void>,
+    {
+        match self.checked_set(index, value) {
+            Ok(_) => (),
+            Err(_) => unreachable!(),
+        }
+    }
+
+    /// Modifies the value of a global variable, returning the error of the push if it fails.
+    ///
+    /// Contrary to `set`, this accepts values whose push can fail. The global variable is left
+    /// untouched in case of an error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{Lua, LuaCode, LuaError};
+    /// let mut lua = Lua::new();
+    ///
+    /// lua.checked_set("f", LuaCode("return 5")).unwrap();
+    /// assert_eq!(lua.execute::<i32>("return f()").unwrap(), 5);
+    ///
+    /// match lua.checked_set("f", LuaCode("this is not lua")) {
+    ///     Err(LuaError::SyntaxError(_)) => (),
+    ///     other => panic!("unexpected result: {:?}", other),
+    /// }
+    /// assert_eq!(lua.execute::<i32>("return f()").unwrap(), 5);
+    /// ```
+    #[inline]
+    pub fn checked_set<I, V, E>(&mut self, index: I, value: V) -> Result<(), E>
+    where
+        I: Borrow<str>,
+        for<'a> V: PushOne<&'a mut Lua<'lua>, Err = E>,
+    {
+        unsafe {
+            // TODO: can be simplified
+            let mut me = self;
+
+            ffix::lua_pushglobaltable(me.lua);
+
+            index.borrow().push_no_err(&mut me).assert_one_and_forget();
+
+            match value.push_to_lua(me) {
+                Ok(pushed) => pushed.assert_one_and_forget(),
+                Err((err, lua)) => {
+                    ffi::lua_pop(lua.lua.as_ptr(), 2);
+                    return Err(err);
+                },
+            };
+            readonly::set_global(me.lua);
+            ffi::lua_pop(me.lua.as_ptr(), 1);
+            Ok(())
+        }
+    }
+
+    /// Modifies the value of a global variable with a value that implements `serde::Serialize`.
+    ///
+    /// The value is built directly on the Lua stack, without going through `AnyLuaValue`. This
+    /// uses the same mapping as [`get_de`](#method.get_de): sequences become tables indexed from
+    /// `1`, structs and maps become tables with keys, unit variants become strings, and other
+    /// variants become a table with a single entry whose key is the name of the variant. `None`
+    /// and `()` become `nil`.
+    ///
+    /// Integers outside of the range of `i32` are pushed as floats, which keeps them exact up to
+    /// 2^53.
+    ///
+    /// Returns an error if the value can't be represented in Lua, for example if a map has a
+    /// `None` key. The global variable is left untouched in case of an error.
+    ///
+    /// Requires the `impl-serde` feature.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "impl-serde")] {
+    /// use hlua::Lua;
+    /// use serde::Serialize;
+    ///
+    /// #[derive(Serialize)]
+    /// struct Config {
+    ///     size: u32,
+    ///     names: Vec<String>,
+    /// }
+    ///
+    /// let mut lua = Lua::new();
+    /// let config = Config { size: 3, names: vec!["a".to_owned(), "b".to_owned()] };
+    /// lua.set_ser("config", &config).unwrap();
+    ///
+    /// assert_eq!(lua.execute::<u32>("return config.size").unwrap(), 3);
+    /// assert_eq!(lua.execute::<String>("return config.names[2]").unwrap(), "b");
+    /// # }
+    /// ```
+    #[cfg(feature = "impl-serde")]
+    #[inline]
+    pub fn set_ser<I, T>(&mut self, index: I, value: &T) -> Result<(), LuaSerdeError>
+    where
+        I: Borrow<str>,
+        T: serde::Serialize + ?Sized,
+    {
+        self.checked_set(index, lua_serde::Serialized(value))
+    }
+
+    /// Sets the value of a global variable that scripts can read but not modify.
+    ///
+    /// Assigning the variable from Lua raises an error. The value is stored outside of the table
+    /// of globals, which gets a metatable whose `__index` and `__newindex` give access to it
+    /// while refusing assignments. This replaces the `__index` and `__newindex` of a metatable
+    /// previously set on that table. Scripts that have access to `rawset` or `setmetatable` can
+    /// still work around the protection.
+    ///
+    /// The value can still be replaced from Rust with `set` or `set_readonly`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.set_readonly("MAX_PLAYERS", 8);
+    ///
+    /// assert_eq!(lua.execute::<i32>("return MAX_PLAYERS").unwrap(), 8);
+    /// assert!(lua.execute::<()>("MAX_PLAYERS = 100").is_err());
+    /// assert_eq!(lua.get::<i32, _>("MAX_PLAYERS"), Some(8));
+    /// ```
+    #[inline]
+    pub fn set_readonly<I, V, E>(&mut self, index: I, value: V)
+    where
+        I: Borrow<str>,
+        for<'a> V: PushOne<&'a mut Lua<'lua>, Err = E>,
+        E: Into<Void>,
+    {
+        value.push_no_err(&mut *self).assert_one_and_forget();
+        readonly::set(self.lua, index.borrow());
+    }
+
+    /// Modifies the value of a global variable and returns its previous value.
     ///
-    /// The type must implement the `PushOne` trait. See
-    /// [the documentation at the crate root](index.html#pushing-and-loading-values) for more
-    /// information.
+    /// Returns `None` if the variable didn't exist (ie. was `nil`) before the call. This is
+    /// useful to temporarily override a global and restore it afterwards.
     ///
     /// # Example
     ///
     /// ```
-    /// use hlua::Lua;
+    /// use hlua::{AnyLuaValue, Lua};
     /// let mut lua = Lua::new();
     ///
-    /// lua.set("a", 12);
-    /// let six: i32 = lua.execute("return a / 2;").unwrap();
-    /// assert_eq!(six, 6);
+    /// assert_eq!(lua.replace("a", 12), None);
+    /// assert_eq!(lua.replace("a", 15), Some(AnyLuaValue::LuaNumber(12.0)));
+    /// assert_eq!(lua.get::<i32, _>("a"), Some(15));
     /// ```
     #[inline]
-    pub fn set<I, V, E>(&mut self, index: I, value: V)
+    pub fn replace<I, V, E>(&mut self, index: I, value: V) -> Option<AnyLuaValue>
     where
         I: Borrow<str>,
         for<'a> V: PushOne<&'a mut Lua<'lua>, Err = E>,
         E: Into<Void>,
     {
-        match self.checked_set(index, value) {
-            Ok(_) => (),
-            Err(_) => unreachable!(),
+        let previous = self.get(index.borrow());
+        self.set(index, value);
+        previous
+    }
+
+    /// Reads the value at a dotted path of nested tables, for example `a.b.c`.
+    ///
+    /// Returns `None` if one of the intermediate values isn't a table, or if the value doesn't
+    /// exist or can't be read as `V`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::Lua;
+    /// let mut lua = Lua::new();
+    ///
+    /// lua.execute::<()>("config = { window = { width = 800 } }").unwrap();
+    /// assert_eq!(lua.get_path::<i32>("config.window.width"), Some(800));
+    /// assert_eq!(lua.get_path::<i32>("config.window.height"), None);
+    /// assert_eq!(lua.get_path::<i32>("config.window.width.foo"), None);
+    /// ```
+    #[inline]
+    pub fn get_path<'l, V>(&'l mut self, path: &str) -> Option<V>
+    where
+        V: LuaRead<PushGuard<&'l mut Lua<'lua>>>,
+    {
+        let raw_lua = self.as_mut_lua();
+
+        unsafe {
+            ffix::lua_pushglobaltable(raw_lua);
+            let mut pushed = 1;
+
+            for part in path.split('.') {
+                if !ffi::lua_istable(raw_lua.as_ptr(), -1) {
+                    ffi::lua_pop(raw_lua.as_ptr(), pushed);
+                    return None;
+                }
+
+                part.push_no_err(raw_lua).assert_one_and_forget();
+                ffi::lua_gettable(raw_lua.as_ptr(), -2);
+                pushed += 1;
+            }
+
+            // The guard also pops the tables that were traversed.
+            let guard = PushGuard { lua: self, size: pushed, raw_lua };
+            match ffi::lua_isnil(raw_lua.as_ptr(), -1) {
+                true => None,
+                false => LuaRead::lua_read(guard).ok(),
+            }
         }
     }
 
-    /// Modifies the value of a global variable.
-    // TODO: docs
+    /// Modifies the value at a dotted path of nested tables, for example `a.b.c`.
+    ///
+    /// Intermediate tables are created if they don't exist. Intermediate values that exist but
+    /// aren't tables are replaced with new tables.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::Lua;
+    /// let mut lua = Lua::new();
+    ///
+    /// lua.set_path("config.window.width", 800);
+    /// lua.set_path("config.window.title", "hello");
+    ///
+    /// let width: i32 = lua.execute("return config.window.width").unwrap();
+    /// assert_eq!(width, 800);
+    /// assert_eq!(lua.get_path::<String>("config.window.title").unwrap(), "hello");
+    /// ```
     #[inline]
-    pub fn checked_set<I, V, E>(&mut self, index: I, value: V) -> Result<(), E>
+    pub fn set_path<V, E>(&mut self, path: &str, value: V)
     where
-        I: Borrow<str>,
         for<'a> V: PushOne<&'a mut Lua<'lua>, Err = E>,
+        E: Into<Void>,
     {
+        let raw_lua = self.as_mut_lua();
+        let mut parts = path.split('.');
+        let last = parts.next_back().unwrap_or_default();
+
         unsafe {
-            // TODO: can be simplified
-            let mut me = self;
+            ffix::lua_pushglobaltable(raw_lua);
+            let mut pushed = 1;
+
+            for part in parts {
+                part.push_no_err(raw_lua).assert_one_and_forget();
+                ffi::lua_gettable(raw_lua.as_ptr(), -2);
+
+                if !ffi::lua_istable(raw_lua.as_ptr(), -1) {
+                    ffi::lua_pop(raw_lua.as_ptr(), 1);
+                    ffi::lua_newtable(raw_lua.as_ptr());
+                    part.push_no_err(raw_lua).assert_one_and_forget();
+                    ffi::lua_pushvalue(raw_lua.as_ptr(), -2);
+                    ffi::lua_settable(raw_lua.as_ptr(), -4);
+                }
+                pushed += 1;
+            }
 
-            ffix::lua_pushglobaltable(me.lua);
+            last.push_no_err(raw_lua).assert_one_and_forget();
+            value.push_no_err(&mut *self).assert_one_and_forget();
+            match pushed {
+                1 => readonly::set_global(raw_lua),
+                _ => ffi::lua_settable(raw_lua.as_ptr(), -3),
+            }
+            ffi::lua_pop(raw_lua.as_ptr(), pushed);
+        }
+    }
 
-            index.borrow().push_no_err(&mut me).assert_one_and_forget();
+    /// Sets each of the globals of `names` to nil. Names can be dotted paths of nested tables,
+    /// for example `os.execute`, in which case only the last field is removed.
+    ///
+    /// Names that don't exist, or whose intermediate values aren't tables, are ignored. The tables
+    /// are accessed without invoking their metamethods, so scripts can't run code or raise errors
+    /// while globals are being stripped.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::Lua;
+    /// let mut lua = Lua::new();
+    /// lua.openlibs();
+    ///
+    /// lua.strip_globals(&["io", "os.execute", "dofile"]);
+    /// assert!(lua.execute::<bool>("return io == nil and os.execute == nil").unwrap());
+    /// assert!(lua.execute::<bool>("return os.time ~= nil").unwrap());
+    /// ```
+    pub fn strip_globals(&mut self, names: &[&str]) {
+        let raw_lua = self.as_mut_lua();
 
-            match value.push_to_lua(me) {
-                Ok(pushed) => pushed.assert_one_and_forget(),
-                Err((err, lua)) => {
-                    ffi::lua_pop(lua.lua.as_ptr(), 2);
-                    return Err(err);
-                },
-            };
-            ffi::lua_settable(me.lua.as_ptr(), -3);
-            ffi::lua_pop(me.lua.as_ptr(), 1);
-            Ok(())
+        for name in names {
+            let mut parts = name.split('.');
+            let last = parts.next_back().unwrap_or_default();
+            let first = parts.next();
+
+            unsafe {
+                readonly::push_owner(raw_lua, first.unwrap_or(last));
+                let mut pushed = 1;
+
+                for part in first.into_iter().chain(parts) {
+                    part.push_no_err(raw_lua).assert_one_and_forget();
+                    ffi::lua_rawget(raw_lua.as_ptr(), -2);
+                    pushed += 1;
+                    if !ffi::lua_istable(raw_lua.as_ptr(), -1) {
+                        break;
+                    }
+                }
+
+                if ffi::lua_istable(raw_lua.as_ptr(), -1) {
+                    last.push_no_err(raw_lua).assert_one_and_forget();
+                    ffi::lua_pushnil(raw_lua.as_ptr());
+                    ffi::lua_rawset(raw_lua.as_ptr(), -3);
+                }
+                ffi::lua_pop(raw_lua.as_ptr(), pushed);
+            }
         }
     }
 
+    /// Removes the globals that give access to the filesystem, the environment, the process or
+    /// the internals of the Lua context, and the functions that load code or bytecode.
+    ///
+    /// This removes `dofile`, `loadfile`, `load`, `loadstring`, `require`, `module`,
+    /// `collectgarbage`, `getfenv`, `setfenv` and `string.dump`, the `io`, `package`, `debug`,
+    /// `ffi` and `jit` libraries, and the functions of the `os` library other than `os.time`,
+    /// `os.clock`, `os.date` and `os.difftime`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::Lua;
+    /// let mut lua = Lua::new();
+    /// lua.openlibs();
+    ///
+    /// lua.strip_unsafe();
+    /// assert!(lua.execute::<bool>("return io == nil and require == nil").unwrap());
+    /// assert!(lua.execute::<bool>("return os.execute == nil and os.time ~= nil").unwrap());
+    /// ```
+    #[inline]
+    pub fn strip_unsafe(&mut self) {
+        self.strip_globals(&[
+            "dofile",
+            "loadfile",
+            "load",
+            "loadstring",
+            "require",
+            "module",
+            "collectgarbage",
+            "getfenv",
+            "setfenv",
+            "string.dump",
+            "io",
+            "package",
+            "debug",
+            "ffi",
+            "jit",
+            "os.execute",
+            "os.exit",
+            "os.getenv",
+            "os.remove",
+            "os.rename",
+            "os.setlocale",
+            "os.tmpname",
+        ]);
+    }
+
+    /// Runs `f` with a `Scope` that can expose data borrowed from Rust to Lua code.
+    ///
+    /// Contrary to `push_userdata`, userdata created through the scope don't need to own their
+    /// content. Instead they are invalidated when this function returns, even if Lua still holds
+    /// them. See `Scope` for more information.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #[macro_use] extern crate hlua;
+    /// # fn main() {
+    /// struct State { log: Vec<String> }
+    /// implement_lua_read!(State);
+    ///
+    /// let mut lua = hlua::Lua::new();
+    /// let mut state = State { log: Vec::new() };
+    ///
+    /// lua.scope(|scope| {
+    ///     scope.set_userdata("state", &mut state, |meta| {
+    ///         hlua::MetatableBuilder::new(meta)
+    ///             .method("log", |s: &mut State, msg: String| s.log.push(msg));
+    ///     });
+    ///     scope.execute::<()>("state:log('hello')").unwrap();
+    /// });
+    ///
+    /// assert_eq!(state.log, ["hello"]);
+    /// # }
+    /// ```
+    #[inline]
+    pub fn scope<'scope, F, R>(&'scope mut self, f: F) -> R
+    where
+        F: FnOnce(&mut Scope<'scope, 'lua>) -> R,
+    {
+        let mut scope = Scope::new(self);
+        f(&mut scope)
+    }
+
     /// Sets the value of a global variable to an empty array, then loads it.
     ///
     /// This is the function you should use if you want to set the value of a global variable to
@@ -922,7 +2205,7 @@ impl<'lua> Lua<'lua> {
             ffix::lua_pushglobaltable(me.lua);
             index.borrow().push_no_err(&mut me).forget();
             ffi::lua_newtable(me.lua.as_ptr());
-            ffi::lua_settable(me.lua.as_ptr(), -3);
+            readonly::set_global(me.lua);
             ffi::lua_pop(me.lua.as_ptr(), 1);
 
             // TODO: cleaner implementation
@@ -930,6 +2213,59 @@ impl<'lua> Lua<'lua> {
         }
     }
 
+    /// Loads the metatable of a global table, creating the table and the metatable if they don't
+    /// exist.
+    ///
+    /// This is a shortcut for the usual initialization of a namespace table whose metatable
+    /// provides a fallback, for example with an `__index` entry. Returns `None` if the global
+    /// exists but isn't a table, or if `name` contains a NUL byte. See
+    /// `LuaTable::get_or_create_metatable`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{AnyLuaValue, Lua};
+    ///
+    /// let mut lua = Lua::new();
+    /// lua.global_metatable("app").unwrap().set(
+    ///     "__index",
+    ///     hlua::function2(|_: AnyLuaValue, key: String| format!("default {}", key)),
+    /// );
+    ///
+    /// lua.execute::<()>("app.name = 'demo'").unwrap();
+    /// let value: String = lua.execute("return app.name .. ', ' .. app.mode").unwrap();
+    /// assert_eq!(value, "demo, default mode");
+    /// ```
+    #[inline]
+    pub fn global_metatable<'a, I>(
+        &'a mut self,
+        name: I,
+    ) -> Option<LuaTable<PushGuard<&'a mut Lua<'lua>>>>
+    where
+        I: Borrow<str>,
+    {
+        let c_name = CString::new(name.borrow()).ok()?;
+        match self.type_of(name.borrow()) {
+            LuaType::Nil => drop(self.empty_array(name.borrow())),
+            LuaType::Table => (),
+            _ => return None,
+        }
+
+        let raw_lua = self.as_lua();
+        unsafe {
+            ffi::lua_getglobal(raw_lua.as_ptr(), c_name.as_ptr());
+            if ffi::lua_getmetatable(raw_lua.as_ptr(), -1) == 0 {
+                ffi::lua_newtable(raw_lua.as_ptr());
+                ffi::lua_pushvalue(raw_lua.as_ptr(), -1);
+                ffi::lua_setmetatable(raw_lua.as_ptr(), -3);
+            }
+        }
+
+        // The guard pops both the table and its metatable.
+        let guard = PushGuard { lua: self, size: 2, raw_lua };
+        LuaRead::lua_read(guard).ok()
+    }
+
     /// Loads the array containing the global variables.
     ///
     /// In lua, the global variables accessible from the lua code are all part of a table which
@@ -975,12 +2311,63 @@ impl<'lua> Lua<'lua> {
         let guard = PushGuard { lua: self, size: 1, raw_lua };
         LuaRead::lua_read(guard).ok().unwrap()
     }
+
+    /// Replaces the table of global variables with a new table that only contains the globals
+    /// named in `keep`.
+    ///
+    /// This runs the code executed afterwards in a restricted environment, which is the usual way
+    /// of sandboxing Lua code. If `keep` contains `_G`, the `_G` of the new table refers to the
+    /// new table itself. Read-only globals become regular globals of the new table.
+    ///
+    /// Lua functions that have already been loaded keep using the previous table.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::Lua;
+    /// let mut lua = Lua::new();
+    /// lua.openlibs();
+    /// lua.set("secret", 42);
+    ///
+    /// lua.set_global_table(&["print", "string", "_G"]);
+    /// lua.set("answer", 12);
+    ///
+    /// let visible: bool = lua
+    ///     .execute("return secret == nil and os == nil and string ~= nil and _G.answer == 12")
+    ///     .unwrap();
+    /// assert!(visible);
+    /// ```
+    pub fn set_global_table(&mut self, keep: &[&str]) {
+        let raw_lua = self.as_mut_lua();
+
+        unsafe {
+            ffix::lua_pushglobaltable(raw_lua);
+            ffi::lua_createtable(raw_lua.as_ptr(), 0, keep.len() as _);
+
+            for name in keep {
+                name.push_no_err(raw_lua).assert_one_and_forget();
+                if *name == "_G" {
+                    ffi::lua_pushvalue(raw_lua.as_ptr(), -2);
+                } else {
+                    ffi::lua_pushvalue(raw_lua.as_ptr(), -1);
+                    ffi::lua_gettable(raw_lua.as_ptr(), -4);
+                }
+                ffi::lua_rawset(raw_lua.as_ptr(), -3);
+            }
+
+            ffix::lua_setglobaltable(raw_lua);
+            ffi::lua_pop(raw_lua.as_ptr(), 1);
+        }
+        readonly::reset(raw_lua);
+    }
 }
 
 impl<'lua> Drop for Lua<'lua> {
     #[inline]
     fn drop(&mut self) {
         if self.must_be_closed {
+            #[cfg(feature = "_luaapi_54")]
+            warn::clear(self.lua);
             unsafe { ffi::lua_close(self.lua.as_ptr()) }
         }
     }
@@ -997,7 +2384,14 @@ impl<L> Drop for PushGuard<L> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{Lua, LuaError};
+    use std::{
+        ffi::CString,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{
+        function0, AnyLuaValue, AsLua, Lua, LuaError, LuaNil, LuaStatus, Push, ScriptFailure,
+    };
 
     #[test]
     fn open_base_opens_base_library() {
@@ -1012,6 +2406,299 @@ mod tests {
         assert_eq!(result, true);
     }
 
+    #[test]
+    fn collect_garbage_reports_memory() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("big = {} for i = 1, 1000 do big[i] = { i } end").unwrap();
+
+        let report = lua.collect_garbage_and_shrink();
+        assert!(report.bytes_after > 0);
+        let in_use = report.bytes_after;
+
+        lua.set("big", LuaNil);
+        let report = lua.collect_garbage_and_shrink();
+        assert_eq!(report.bytes_before, in_use);
+        assert!(report.freed() > 1000 * 16);
+        assert_eq!(report.freed(), report.bytes_before - report.bytes_after);
+    }
+
+    #[test]
+    fn stack_size_and_balance() {
+        let mut lua = Lua::new();
+        assert_eq!(lua.stack_size(), 0);
+
+        lua.with_balanced_stack(|lua| {
+            let guard = "a".push_no_err(&mut *lua);
+            assert_eq!(guard.size(), 1);
+            assert_eq!(unsafe { ffi::lua_gettop(guard.as_lua().as_ptr()) }, 1);
+            drop(guard);
+        });
+
+        lua.execute::<()>("t = {}").unwrap();
+        let table = lua.get::<crate::LuaTable<_>, _>("t").unwrap();
+        drop(table);
+        assert_eq!(lua.stack_size(), 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "the size of the Lua stack has changed")]
+    fn unbalanced_stack_panics() {
+        let mut lua = Lua::new();
+        lua.with_balanced_stack(|lua| "leaked".push_no_err(lua).forget_internal());
+    }
+
+    #[test]
+    fn call_global_functions() {
+        let mut lua = Lua::with_libs(crate::LuaLibs::BASE);
+        lua.execute::<()>("function add(a, b) return a + b end; function fail() error('oops') end")
+            .unwrap();
+        lua.set("not_a_function", 5);
+
+        let failures = Arc::new(Mutex::new(0));
+        let failures2 = failures.clone();
+        lua.set_panic_handler(move |_| *failures2.lock().unwrap() += 1);
+
+        assert_eq!(lua.call_global::<i32, _, _>("add", (2, 3)).unwrap(), 5);
+        match lua.call_global::<(), _, _>("fail", ()) {
+            Err(LuaError::ExecutionError(msg)) => assert!(msg.contains("oops"), "{}", msg),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match lua.call_global::<(), _, _>("not_a_function", ()) {
+            Err(LuaError::ExecutionError(msg)) => assert!(msg.contains("'not_a_function'")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(lua.call_global::<String, _, _>("add", (1, 1)).is_ok());
+        assert!(matches!(lua.call_global::<bool, _, _>("add", (1, 1)), Err(LuaError::WrongType)));
+
+        assert_eq!(*failures.lock().unwrap(), 3);
+        assert_eq!(lua.stack_size(), 0);
+    }
+
+    #[test]
+    fn replace_returns_previous_value() {
+        let mut lua = Lua::new();
+
+        assert_eq!(lua.replace("a", "hello"), None);
+        assert_eq!(lua.replace("a", 5), Some(AnyLuaValue::LuaString("hello".to_owned())));
+        assert_eq!(lua.replace("a", LuaNil), Some(AnyLuaValue::LuaNumber(5.0)));
+        assert_eq!(lua.get::<i32, _>("a"), None);
+    }
+
+    #[test]
+    fn borrowed_existing_state_is_not_closed() {
+        unsafe {
+            let raw = ffi::luaL_newstate();
+
+            let mut lua = Lua::from_existing_state(raw, false);
+            lua.set("a", 5);
+            drop(lua);
+
+            // The state must still be alive, and values set through hlua visible from the C API.
+            ffi::lua_getglobal(raw, b"a\0".as_ptr().cast());
+            assert_eq!(ffi::lua_tointegerx(raw, -1, std::ptr::null_mut()), 5);
+            ffi::lua_pop(raw, 1);
+
+            ffi::lua_close(raw);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn existing_state_rejects_null() {
+        let _ = unsafe { Lua::from_existing_state(std::ptr::null_mut::<ffi::lua_State>(), false) };
+    }
+
+    #[test]
+    fn nested_paths() {
+        let mut lua = Lua::new();
+
+        lua.set_path("a.b.c", 5);
+        lua.set_path("a.b.d", "hello");
+        lua.set_path("top", true);
+        assert_eq!(lua.execute::<i32>("return a.b.c").unwrap(), 5);
+        assert_eq!(lua.get_path::<String>("a.b.d").unwrap(), "hello");
+        assert_eq!(lua.get_path::<bool>("top"), Some(true));
+        assert_eq!(lua.get_path::<i32>("a.x.c"), None);
+
+        // Non-table intermediate values are replaced.
+        lua.set_path("a.b.c.d", 1);
+        assert_eq!(lua.get_path::<i32>("a.b.c.d"), Some(1));
+        assert_eq!(lua.get_path::<String>("a.b.d").unwrap(), "hello");
+
+        // The stack must be left untouched.
+    }
+
+    #[test]
+    fn checked_set_failure() {
+        let mut lua = Lua::new();
+        lua.set("f", 3);
+
+        assert!(lua.checked_set("f", crate::LuaCode("(")).is_err());
+        assert_eq!(lua.get::<i32, _>("f"), Some(3));
+    }
+
+    #[test]
+    fn compile_only() {
+        let failures = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = failures.clone();
+
+        let mut lua = Lua::new();
+        lua.set_panic_handler(move |_| {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        });
+
+        lua.load_and_compile_only("error('not run')").unwrap();
+        assert!(matches!(lua.load_and_compile_only("end"), Err(LuaError::SyntaxError(_))));
+        assert!(lua.load_and_compile_only("\x1bLua").is_err());
+        assert_eq!(failures.load(std::sync::atomic::Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn replace_global_table() {
+        let mut lua = Lua::with_libs(crate::LuaLibs::BASE);
+        lua.set_readonly("VERSION", 3);
+        lua.execute::<()>("secret = 'hidden'; function reveal() return secret end").unwrap();
+
+        lua.set_global_table(&["VERSION", "reveal", "missing"]);
+        assert_eq!(lua.get::<String, _>("secret"), None);
+        assert_eq!(lua.execute::<String>("return reveal()").unwrap(), "hidden");
+        assert!(lua.execute::<bool>("return _G == nil and print == nil").unwrap());
+
+        // The read-only global has been copied as a regular one.
+        lua.execute::<()>("VERSION = VERSION + 1").unwrap();
+        assert_eq!(lua.get::<i32, _>("VERSION"), Some(4));
+
+        // New read-only globals apply to the new table.
+        lua.set_readonly("LIMIT", 2);
+        assert_eq!(lua.execute::<i32>("return LIMIT").unwrap(), 2);
+        assert!(lua.execute::<()>("LIMIT = 3").is_err());
+        lua.set("LIMIT", 5);
+        assert_eq!(lua.get::<i32, _>("LIMIT"), Some(5));
+    }
+
+    #[test]
+    fn strip_globals() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.set("answer", 42);
+        lua.set_readonly("VERSION", 1);
+
+        lua.strip_globals(&["answer", "VERSION", "os.execute", "missing.field", "answer.x"]);
+        assert_eq!(lua.get::<i32, _>("answer"), None);
+        assert_eq!(lua.get::<i32, _>("VERSION"), None);
+        assert!(lua.execute::<bool>("return os.execute == nil and os.time ~= nil").unwrap());
+        assert!(lua.execute::<bool>("return missing == nil").unwrap());
+
+        lua.execute::<()>(
+            r#"
+            guarded = setmetatable({}, { __newindex = function() error("boom") end })
+            setmetatable(_G, { __index = function() error("boom") end })
+        "#,
+        )
+        .unwrap();
+        lua.strip_globals(&["guarded.field", "unknown.field"]);
+        lua.execute::<()>("setmetatable(_G, nil)").unwrap();
+
+        lua.strip_unsafe();
+        let stripped: bool = lua
+            .execute("return io == nil and dofile == nil and require == nil and string.dump == nil")
+            .unwrap();
+        assert!(stripped);
+        assert!(lua.execute::<bool>("return os.getenv == nil and os.clock ~= nil").unwrap());
+        assert_eq!(lua.execute::<String>("return string.rep('a', 2)").unwrap(), "aa");
+    }
+
+    #[test]
+    fn do_file() {
+        let dir = std::env::temp_dir().join(format!("hlua-do-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, code: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, code).unwrap();
+            path
+        };
+
+        let mut lua = Lua::new();
+        let ok = write("ok.lua", "x = 5; return x * 2");
+        let syntax = write("syntax.lua", "return +");
+        let runtime = write("runtime.lua", "return nil + 1");
+
+        assert_eq!(lua.do_file::<i32, _>(&ok).unwrap(), 10);
+        assert_eq!(lua.get::<i32, _>("x"), Some(5));
+        assert!(matches!(lua.do_file::<(), _>(&syntax), Err(LuaError::SyntaxError(_))));
+        assert!(matches!(lua.do_file::<(), _>(&runtime), Err(LuaError::ExecutionError(_))));
+        assert!(matches!(
+            lua.do_file::<(), _>(dir.join("missing.lua")),
+            Err(LuaError::ReadError(_))
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn panic_handler_sees_callback_panics() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let failures2 = failures.clone();
+        lua.set_panic_handler(move |failure| {
+            let entry = match failure {
+                ScriptFailure::CallbackPanic(msg) => format!("panic: {}", msg),
+                ScriptFailure::Error(err) => format!("error: {}", err),
+            };
+            failures2.lock().unwrap().push(entry);
+        });
+
+        lua.set("boom", function0(|| -> i32 { panic!("kaboom") }));
+
+        // The script can catch the panic like any other error.
+        let caught: bool = lua.execute("return not pcall(boom)").unwrap();
+        assert!(caught);
+        assert_eq!(*failures.lock().unwrap(), ["panic: kaboom"]);
+
+        match lua.execute::<()>("boom()") {
+            Err(LuaError::ExecutionError(msg)) => assert!(msg.contains("kaboom"), "{}", msg),
+            _ => panic!("the panic wasn't turned into an error"),
+        }
+        let failures = failures.lock().unwrap();
+        assert_eq!(failures.len(), 3);
+        assert!(failures[2].starts_with("error: "));
+    }
+
+    #[test]
+    fn status_of_yielded_coroutine() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        assert_eq!(lua.status(), LuaStatus::Ok);
+
+        unsafe {
+            let raw_thread = ffi::lua_newthread(lua.as_lua().as_ptr());
+            let mut thread = Lua::from_existing_state(raw_thread, false);
+
+            let code = CString::new("coroutine.yield(1)").unwrap();
+            assert_eq!(ffi::luaL_loadstring(raw_thread, code.as_ptr()), 0);
+
+            let resume = |raw_thread| match () {
+                #[cfg(feature = "_luaapi_51")]
+                () => ffi::lua_resume(raw_thread, 0),
+                #[cfg(feature = "_luaapi_52")]
+                () => ffi::lua_resume(raw_thread, std::ptr::null_mut(), 0),
+                #[cfg(feature = "_luaapi_54")]
+                () => ffi::lua_resume(raw_thread, std::ptr::null_mut(), 0, &mut 0),
+            };
+
+            resume(raw_thread);
+            assert_eq!(thread.status(), LuaStatus::Yield);
+            ffi::lua_settop(raw_thread, 0);
+
+            resume(raw_thread);
+            assert_eq!(thread.status(), LuaStatus::Ok);
+            assert_eq!(thread.execute::<i32>("return 1").unwrap(), 1);
+        }
+    }
+
     #[test]
     fn opening_all_libraries_doesnt_panic() {
         let mut lua = Lua::new();
@@ -1041,4 +2728,124 @@ mod tests {
         #[cfg(feature = "_luaapi_54")]
         lua.open_utf8();
     }
+
+    #[test]
+    fn global_types() {
+        let mut lua = Lua::new();
+        lua.set("f", function0(|| 1));
+        lua.set("s", "x");
+        lua.execute::<()>("t = {}; b = false").unwrap();
+
+        assert_eq!(lua.type_of("f"), crate::LuaType::Function);
+        assert_eq!(lua.type_of("s"), crate::LuaType::String);
+        assert_eq!(lua.type_of("t"), crate::LuaType::Table);
+        assert_eq!(lua.type_of("b"), crate::LuaType::Boolean);
+        assert_eq!(lua.type_of(String::from("missing")), crate::LuaType::Nil);
+        assert_eq!(lua.type_of("t\0"), crate::LuaType::Nil);
+    }
+
+    #[test]
+    fn global_metatables() {
+        let mut lua = Lua::with_libs(crate::LuaLibs::BASE);
+        lua.execute::<()>("existing = { a = 1 }; number = 5").unwrap();
+
+        lua.global_metatable("existing").unwrap().set("marker", true);
+        lua.global_metatable("created").unwrap().set("marker", true);
+        assert!(lua.global_metatable("number").is_none());
+        assert!(lua.global_metatable("bad\0name").is_none());
+
+        // The metatable is reused by later calls.
+        assert_eq!(
+            lua.global_metatable("existing").unwrap().get::<bool, _, _>("marker"),
+            Some(true)
+        );
+        assert!(lua
+            .execute::<bool>("return existing.a == 1 and getmetatable(created).marker")
+            .unwrap());
+    }
+
+    #[test]
+    fn execute_all() {
+        let mut lua = Lua::new();
+
+        let values = lua.execute_all("return 1, 'two', nil, { 3 }").unwrap();
+        assert_eq!(values.len(), 4);
+        assert_eq!(values[0], AnyLuaValue::LuaNumber(1.0));
+        assert_eq!(values[2], AnyLuaValue::LuaNil);
+        assert!(matches!(values[3], AnyLuaValue::LuaArray(_)));
+
+        // The number of values is only known at runtime.
+        lua.set("n", 100);
+        let code = "local function r(i) if i <= n then return i, r(i + 1) end end return r(1)";
+        let values = lua.execute_all(code).unwrap();
+        assert_eq!(values.len(), 100);
+        assert_eq!(values[99], AnyLuaValue::LuaNumber(100.0));
+
+        assert!(lua.execute_all("x = 1").unwrap().is_empty());
+        assert!(matches!(lua.execute_all("return ("), Err(LuaError::SyntaxError(_))));
+        assert!(matches!(lua.execute_all("error_here()"), Err(LuaError::ExecutionError(_))));
+    }
+
+    #[test]
+    fn eval_lines() {
+        let mut lua = Lua::new();
+        assert!(lua.eval_line("t = { 1, name = 'x' }").unwrap().is_empty());
+        assert_eq!(
+            lua.eval_line("t.name, #t, nil").unwrap(),
+            [
+                AnyLuaValue::LuaString("x".to_owned()),
+                AnyLuaValue::LuaNumber(1.0),
+                AnyLuaValue::LuaNil
+            ]
+        );
+        assert!(matches!(lua.eval_line("t ="), Err(LuaError::SyntaxError(_))));
+        assert!(matches!(lua.eval_line("t.a.b = 1"), Err(LuaError::ExecutionError(_))));
+    }
+
+    #[test]
+    fn repl() {
+        let mut lua = Lua::new();
+        let input = "x = 2\nx * 3\nfunction f(a)\nreturn { a, 'b' }\nend\nf(x)\n)\n";
+        let mut output = Vec::new();
+        lua.repl(input.as_bytes(), &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let mut lines = output.split("> ").skip(1);
+        assert_eq!(lines.next(), Some(""));
+        assert_eq!(lines.next(), Some("6\n"));
+        assert_eq!(lines.next(), Some(">"));
+        assert_eq!(lines.next(), Some(">"));
+        assert_eq!(lines.next(), Some(""));
+        assert_eq!(lines.next(), Some("{ [1] = 2, [2] = \"b\" }\n"));
+        assert!(lines.next().unwrap().starts_with("Syntax error: "));
+        assert_eq!(lines.next(), Some("\n"));
+        assert_eq!(lines.next(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "_luaapi_54")]
+    fn warn_handler() {
+        let mut lua = Lua::with_libs(crate::LuaLibs::BASE);
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let failures = Arc::new(Mutex::new(0));
+
+        let warnings2 = warnings.clone();
+        lua.set_warn_handler(move |msg| {
+            if msg == "boom" {
+                panic!("handler panicked");
+            }
+            warnings2.lock().unwrap().push(msg.to_owned());
+        });
+        let failures2 = failures.clone();
+        lua.set_panic_handler(move |_| *failures2.lock().unwrap() += 1);
+
+        lua.execute::<()>(r#"warn("@on"); warn("a"); warn("b", "c", "d"); warn("boom")"#).unwrap();
+        assert_eq!(*warnings.lock().unwrap(), ["a", "bcd"]);
+        assert_eq!(*failures.lock().unwrap(), 1);
+
+        // Replacing the handler drops the previous one.
+        lua.set_warn_handler(|_| {});
+        lua.execute::<()>(r#"warn("e")"#).unwrap();
+        assert_eq!(warnings.lock().unwrap().len(), 2);
+    }
 }
@@ -0,0 +1,200 @@
+use std::ops::BitOr;
+
+use crate::{Lua, LuaContext};
+
+type OpenFn = unsafe extern "C" fn(*mut ffi::lua_State) -> libc::c_int;
+
+/// Set of standard libraries, opened by `Lua::with_libs` and `LuaBuilder::libs`.
+///
+/// Sets can be combined with `|`. Only the libraries that exist in the selected version of Lua
+/// are defined.
+///
+/// # Example
+///
+/// ```
+/// use hlua::{Lua, LuaLibs};
+///
+/// let mut lua = Lua::with_libs(LuaLibs::MATH | LuaLibs::STRING);
+/// assert_eq!(lua.execute::<String>("return string.rep('a', math.floor(2.5))").unwrap(), "aa");
+/// assert!(lua.execute::<bool>("return io == nil and os == nil").unwrap());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LuaLibs(u32);
+
+impl LuaLibs {
+    /// No library.
+    pub const NONE: LuaLibs = LuaLibs(0);
+    /// The base library, which contains `print`, `pcall`, `load`, etc. With LuaJIT, this also
+    /// contains the `coroutine` library.
+    pub const BASE: LuaLibs = LuaLibs(1 << 0);
+    /// The `package` library, which provides `require`.
+    pub const PACKAGE: LuaLibs = LuaLibs(1 << 1);
+    /// The `coroutine` library.
+    #[cfg(any(feature = "_luaapi_52", feature = "_luaapi_54"))]
+    pub const COROUTINE: LuaLibs = LuaLibs(1 << 2);
+    /// The `table` library.
+    pub const TABLE: LuaLibs = LuaLibs(1 << 3);
+    /// The `io` library, which gives access to the filesystem.
+    pub const IO: LuaLibs = LuaLibs(1 << 4);
+    /// The `os` library, which gives access to the environment and can run commands.
+    pub const OS: LuaLibs = LuaLibs(1 << 5);
+    /// The `string` library, which is also the metatable of strings.
+    pub const STRING: LuaLibs = LuaLibs(1 << 6);
+    /// The `math` library.
+    pub const MATH: LuaLibs = LuaLibs(1 << 7);
+    /// The `utf8` library.
+    #[cfg(feature = "_luaapi_54")]
+    pub const UTF8: LuaLibs = LuaLibs(1 << 8);
+    /// The `debug` library, which can break the guarantees of the other libraries.
+    pub const DEBUG: LuaLibs = LuaLibs(1 << 9);
+    /// The `bit32` library.
+    #[cfg(feature = "_luaapi_52")]
+    pub const BIT32: LuaLibs = LuaLibs(1 << 10);
+    /// The `bit` library of LuaJIT.
+    #[cfg(feature = "_luaapi_lj2")]
+    pub const BIT: LuaLibs = LuaLibs(1 << 11);
+    /// The `jit` library of LuaJIT.
+    #[cfg(feature = "_luaapi_lj2")]
+    pub const JIT: LuaLibs = LuaLibs(1 << 12);
+    /// The `ffi` library of LuaJIT, which gives unrestricted access to the memory of the process.
+    #[cfg(feature = "_luaapi_lj2")]
+    pub const FFI: LuaLibs = LuaLibs(1 << 13);
+    /// All the libraries, as opened by `Lua::openlibs`.
+    pub const ALL: LuaLibs = LuaLibs(u32::MAX);
+
+    /// Returns true if all the libraries of `other` are in `self`.
+    #[inline]
+    pub fn contains(self, other: LuaLibs) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for LuaLibs {
+    type Output = LuaLibs;
+
+    #[inline]
+    fn bitor(self, other: LuaLibs) -> LuaLibs {
+        LuaLibs(self.0 | other.0)
+    }
+}
+
+impl Default for LuaLibs {
+    #[inline]
+    fn default() -> LuaLibs {
+        LuaLibs::NONE
+    }
+}
+
+/// Builds a Lua context, as returned by `Lua::builder`.
+///
+/// # Example
+///
+/// ```
+/// use hlua::{Lua, LuaLibs};
+///
+/// let mut lua = Lua::builder().libs(LuaLibs::BASE | LuaLibs::TABLE).build();
+/// assert_eq!(lua.execute::<String>("return table.concat({ 'a', 'b' })").unwrap(), "ab");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LuaBuilder {
+    libs: LuaLibs,
+}
+
+impl LuaBuilder {
+    /// Selects the standard libraries to open, replacing the previous selection. By default, no
+    /// library is opened.
+    #[inline]
+    pub fn libs(mut self, libs: LuaLibs) -> LuaBuilder {
+        self.libs = libs;
+        self
+    }
+
+    /// Builds the Lua context.
+    #[inline]
+    pub fn build<'lua>(self) -> Lua<'lua> {
+        let lua = Lua::new();
+        open(lua.lua, self.libs);
+        lua
+    }
+}
+
+/// Opens the libraries of `libs`, making them available as global variables.
+pub(crate) fn open(lua: LuaContext, libs: LuaLibs) {
+    let open_if = |lib, name: &[u8], open: OpenFn| {
+        if libs.contains(lib) {
+            unsafe { open_lib(lua.as_ptr(), name, open) };
+        }
+    };
+
+    open_if(LuaLibs::BASE, BASE_NAME, ffi::luaopen_base);
+    open_if(LuaLibs::PACKAGE, ffi::LUA_LOADLIBNAME, ffi::luaopen_package);
+    #[cfg(any(feature = "_luaapi_52", feature = "_luaapi_54"))]
+    open_if(LuaLibs::COROUTINE, ffi::LUA_COLIBNAME, ffi::luaopen_coroutine);
+    open_if(LuaLibs::TABLE, ffi::LUA_TABLIBNAME, ffi::luaopen_table);
+    open_if(LuaLibs::IO, ffi::LUA_IOLIBNAME, ffi::luaopen_io);
+    open_if(LuaLibs::OS, ffi::LUA_OSLIBNAME, ffi::luaopen_os);
+    open_if(LuaLibs::STRING, ffi::LUA_STRLIBNAME, ffi::luaopen_string);
+    open_if(LuaLibs::MATH, ffi::LUA_MATHLIBNAME, ffi::luaopen_math);
+    #[cfg(feature = "_luaapi_54")]
+    open_if(LuaLibs::UTF8, ffi::LUA_UTF8LIBNAME, ffi::luaopen_utf8);
+    open_if(LuaLibs::DEBUG, ffi::LUA_DBLIBNAME, ffi::luaopen_debug);
+    #[cfg(feature = "_luaapi_52")]
+    open_if(LuaLibs::BIT32, ffi::LUA_BITLIBNAME, ffi::luaopen_bit32);
+    #[cfg(feature = "_luaapi_lj2")]
+    {
+        open_if(LuaLibs::BIT, ffi::LUA_BITLIBNAME, ffi::luaopen_bit);
+        open_if(LuaLibs::JIT, ffi::LUA_JITLIBNAME, ffi::luaopen_jit);
+        open_if(LuaLibs::FFI, ffi::LUA_FFILIBNAME, ffi::luaopen_ffi);
+    }
+}
+
+// Name under which the base library is registered.
+#[cfg(feature = "_luaapi_51")]
+const BASE_NAME: &[u8] = b"\0";
+#[cfg(any(feature = "_luaapi_52", feature = "_luaapi_54"))]
+const BASE_NAME: &[u8] = b"_G\0";
+
+// Calls `open`, which must be the function opening the library `name`, and sets the global
+// variable of the library. `name` must be nul-terminated.
+unsafe fn open_lib(lua: *mut ffi::lua_State, name: &[u8], open: OpenFn) {
+    match () {
+        // The opening functions of Lua 5.1 set the global variable themselves, but must be called
+        // through Lua.
+        #[cfg(feature = "_luaapi_51")]
+        () => {
+            ffi::lua_pushcfunction(lua, Some(open));
+            ffi::lua_pushstring(lua, name.as_ptr().cast());
+            ffi::lua_call(lua, 1, 0);
+        },
+        #[cfg(any(feature = "_luaapi_52", feature = "_luaapi_54"))]
+        () => {
+            ffi::luaL_requiref(lua, name.as_ptr().cast(), Some(open), 1);
+            ffi::lua_pop(lua, 1);
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AsLua, Lua, LuaLibs};
+
+    #[test]
+    fn only_selected_libs_are_opened() {
+        let mut lua = Lua::with_libs(LuaLibs::MATH | LuaLibs::STRING);
+        let opened: bool =
+            lua.execute("return math ~= nil and string ~= nil and ('a'):upper() == 'A'").unwrap();
+        assert!(opened);
+        for global in ["print", "io", "os", "table", "package", "require", "debug"] {
+            assert!(lua.get::<crate::AnyLuaValue, _>(global).is_none(), "{} is open", global);
+        }
+    }
+
+    #[test]
+    fn all_libs() {
+        let mut lua = Lua::builder().libs(LuaLibs::ALL).build();
+        let opened: bool = lua.execute("return print and require and io and os and true").unwrap();
+        assert!(opened);
+        assert!(LuaLibs::ALL.contains(LuaLibs::DEBUG | LuaLibs::BASE));
+        assert!(!LuaLibs::NONE.contains(LuaLibs::BASE));
+    }
+}
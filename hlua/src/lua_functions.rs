@@ -1,14 +1,19 @@
 use std::{
+    borrow::Cow,
     error::Error,
+    ffi::CString,
     fmt,
     io::{Cursor, Error as IoError, Read},
+    marker::PhantomData,
     mem,
     ptr::addr_of_mut,
 };
 
-use crate::{AsLua, AsMutLua};
+use crate::{AnyLuaValue, AsLua, AsMutLua};
 
-use crate::{LuaContext, LuaError, LuaRead, Push, PushGuard, PushOne, Void};
+use crate::{
+    ffix, raw_equal, LuaContext, LuaError, LuaRead, LuaType, Push, PushGuard, PushOne, Void,
+};
 
 /// Wrapper around a `&str`. When pushed, the content will be parsed as Lua code and turned into a
 /// function.
@@ -162,9 +167,8 @@ where
 /// Handle to a function in the Lua context.
 ///
 /// Just like you can read variables as integers and strings, you can also read Lua functions by
-/// requesting a `LuaFunction` object. Once you have a `LuaFunction` you can call it with `call()`.
-///
-/// > **Note**: Passing parameters when calling the function is not yet implemented.
+/// requesting a `LuaFunction` object. Once you have a `LuaFunction` you can call it with `call()`,
+/// or with `call_with_args()` in order to pass parameters.
 ///
 /// # Example
 ///
@@ -226,6 +230,38 @@ where
         }
     }
 
+    /// Calls the function without parameters, and reads its first return value into a type that
+    /// implements `serde::Deserialize`.
+    ///
+    /// See [`Lua::get_de`](struct.Lua.html#method.get_de) for how Lua values are mapped to serde.
+    /// Requires the `impl-serde` feature.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # #[cfg(feature = "impl-serde")] {
+    /// let mut lua = hlua::Lua::new();
+    /// let mut f = hlua::LuaFunction::load(&mut lua, "return { 1, 2, 3 }").unwrap();
+    ///
+    /// let values: Vec<u8> = f.call_de().unwrap();
+    /// assert_eq!(values, [1, 2, 3]);
+    /// # }
+    /// ```
+    #[cfg(feature = "impl-serde")]
+    pub fn call_de<T>(&mut self) -> Result<T, crate::LuaSerdeError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let (pcall_return_value, pushed_value) = match self.pcall(()) {
+            Ok(v) => v,
+            Err(_) => unreachable!(),
+        };
+        let results = LuaResults::from_pcall::<Void>(pcall_return_value, pushed_value)
+            .map_err(|err| crate::LuaSerdeError::Lua(err.into()))?;
+
+        crate::lua_serde::from_stack(results.values.raw_lua, -1)
+    }
+
     /// Calls the function with parameters.
     ///
     /// TODO: should be eventually be renamed to `call`
@@ -249,26 +285,28 @@ where
     /// let result: i32 = foo.call_with_args((18, 4)).unwrap();
     /// assert_eq!(result, 14);
     /// ```
+    ///
+    /// A chunk of code loaded with `load` receives its parameters through `...`, and can be called
+    /// multiple times:
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// let code = "local a, b = ...; return a .. b";
+    /// let mut chunk = hlua::LuaFunction::load(&mut lua, code).unwrap();
+    ///
+    /// let result: String = chunk.call_with_args((1, "x")).unwrap();
+    /// assert_eq!(result, "1x");
+    /// let result: String = chunk.call_with_args((2, "y")).unwrap();
+    /// assert_eq!(result, "2y");
+    /// ```
     #[inline]
     pub fn call_with_args<'a, V, A, E>(&'a mut self, args: A) -> Result<V, LuaFunctionCallError<E>>
     where
         A: for<'r> Push<&'r mut LuaFunction<L>, Err = E>,
         V: LuaRead<PushGuard<&'a mut L>>,
     {
-        // calling pcall pops the parameters and pushes output
-        let (pcall_return_value, pushed_value) = unsafe {
-            // lua_pcall pops the function, so we have to make a copy of it
-            let raw_lua = self.variable.as_mut_lua();
-            ffi::lua_pushvalue(raw_lua.as_ptr(), -1);
-            let num_pushed = match args.push_to_lua(self) {
-                Ok(g) => g.forget_internal(),
-                Err((err, _)) => return Err(LuaFunctionCallError::PushError(err)),
-            };
-            let pcall_return_value = ffi::lua_pcall(raw_lua.as_ptr(), num_pushed, 1, 0); // TODO: num ret values
-            let guard = PushGuard { lua: &mut self.variable, size: 1, raw_lua };
-
-            (pcall_return_value, guard)
-        };
+        let (pcall_return_value, pushed_value) =
+            self.pcall(args).map_err(LuaFunctionCallError::PushError)?;
 
         match pcall_return_value {
             0 => match LuaRead::lua_read(pushed_value) {
@@ -286,6 +324,284 @@ where
         }
     }
 
+    /// Calls the function with parameters, and returns the value passed to `error` if the
+    /// function raises an error.
+    ///
+    /// Contrary to `call_with_args`, which turns the error into a string, the error value is
+    /// read as an `AnyLuaValue`. This keeps the content of the tables passed to `error`.
+    ///
+    /// The outer `Result` is an error if we failed to push an argument or if the requested return
+    /// type doesn't match the actual return type.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::AnyLuaValue;
+    ///
+    /// let mut lua = hlua::Lua::new();
+    /// lua.openlibs();
+    /// lua.execute::<()>("function fail() error({ code = 42 }) end").unwrap();
+    ///
+    /// let mut fail: hlua::LuaFunction<_> = lua.get("fail").unwrap();
+    /// let err = fail.call_capture_error::<(), _, _>(()).unwrap().unwrap_err();
+    /// let expected = (AnyLuaValue::LuaString("code".to_owned()), AnyLuaValue::LuaNumber(42.0));
+    /// assert_eq!(err, AnyLuaValue::LuaArray(vec![expected]));
+    /// ```
+    #[inline]
+    pub fn call_capture_error<'a, V, A, E>(
+        &'a mut self,
+        args: A,
+    ) -> Result<Result<V, AnyLuaValue>, LuaFunctionCallError<E>>
+    where
+        A: for<'r> Push<&'r mut LuaFunction<L>, Err = E>,
+        V: LuaRead<PushGuard<&'a mut L>>,
+    {
+        let (pcall_return_value, pushed_value) =
+            self.pcall(args).map_err(LuaFunctionCallError::PushError)?;
+
+        match pcall_return_value {
+            0 => match LuaRead::lua_read(pushed_value) {
+                Err(_) => Err(LuaFunctionCallError::LuaError(LuaError::WrongType)),
+                Ok(x) => Ok(Ok(x)),
+            },
+            ffi::LUA_ERRMEM => panic!("lua_pcall returned LUA_ERRMEM"),
+            ffi::LUA_ERRRUN => {
+                let error_value = LuaRead::lua_read(pushed_value)
+                    .ok()
+                    .expect("can't find error value at the top of the Lua stack");
+                Ok(Err(error_value))
+            },
+            _ => panic!("Unknown error code returned by lua_pcall: {}", pcall_return_value),
+        }
+    }
+
+    /// Sets the environment of the function to `env`, then calls it with parameters like
+    /// `call_with_args` does.
+    ///
+    /// The environment is the table in which the function reads and writes its global variables,
+    /// so the function only sees the globals given in `env`. Loading the same code multiple
+    /// times and calling each chunk with its own environment isolates scripts from each other.
+    ///
+    /// With Lua 5.2 and later, the environment is the first upvalue of the function, which is
+    /// `_ENV` for the chunks returned by `load`. Other functions may not have `_ENV` as their
+    /// first upvalue, and must not be called this way. The environment is kept for the next
+    /// calls of the function.
+    ///
+    /// Functions written in C or in Rust don't have an environment, and calling them this way
+    /// returns an error without calling them. The same goes for an environment that isn't a
+    /// table.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    ///
+    /// let mut lua = hlua::Lua::new();
+    /// lua.set("count", 100);
+    ///
+    /// let code = "count = count + 1; return count";
+    /// let mut a = hlua::LuaFunction::load(&mut lua, code).unwrap();
+    /// let env = HashMap::from([("count", 1)]);
+    /// let count: i32 = a.call_with_env(env, ()).unwrap();
+    /// assert_eq!(count, 2);
+    /// assert_eq!(a.call::<i32>().unwrap(), 3);
+    /// drop(a);
+    ///
+    /// // The globals of the context haven't been modified.
+    /// assert_eq!(lua.get::<i32, _>("count"), Some(100));
+    /// ```
+    #[inline]
+    pub fn call_with_env<'a, V, P, A, E>(
+        &'a mut self,
+        env: P,
+        args: A,
+    ) -> Result<V, LuaFunctionCallError<E>>
+    where
+        P: for<'r> PushOne<&'r mut LuaFunction<L>, Err = Void>,
+        A: for<'r> Push<&'r mut LuaFunction<L>, Err = E>,
+        V: LuaRead<PushGuard<&'a mut L>>,
+    {
+        let raw_lua = self.variable.as_mut_lua().as_ptr();
+        if unsafe { ffi::lua_iscfunction(raw_lua, -1) } != 0 {
+            let msg = "can't set the environment of a C function".to_owned();
+            return Err(LuaFunctionCallError::LuaError(LuaError::ExecutionError(msg)));
+        }
+        env.push_no_err(&mut *self).assert_one_and_forget();
+        if unsafe { !ffi::lua_istable(raw_lua, -1) } {
+            unsafe { ffi::lua_pop(raw_lua, 1) };
+            let msg = "the environment of a function must be a table".to_owned();
+            return Err(LuaFunctionCallError::LuaError(LuaError::ExecutionError(msg)));
+        }
+
+        unsafe {
+            match () {
+                #[cfg(feature = "_luaapi_51")]
+                () => {
+                    ffi::lua_setfenv(raw_lua, -2);
+                },
+                #[cfg(any(feature = "_luaapi_52", feature = "_luaapi_54"))]
+                () => {
+                    if ffi::lua_setupvalue(raw_lua, -2, 1).is_null() {
+                        ffi::lua_pop(raw_lua, 1);
+                    }
+                },
+            }
+        }
+
+        self.call_with_args(args)
+    }
+
+    /// Calls the function with parameters, and returns all the values it returned, or the value
+    /// passed to `error` if the function raises an error.
+    ///
+    /// Contrary to the other calling methods, the number and the types of the return values
+    /// don't need to be known in advance. This is useful for tools such as REPLs, which display
+    /// whatever a script returned.
+    ///
+    /// The outer `Result` is an error if we failed to push an argument.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::AnyLuaValue;
+    ///
+    /// let mut lua = hlua::Lua::new();
+    /// let mut f = hlua::LuaFunction::load(&mut lua, "return 1, 'two', ...").unwrap();
+    ///
+    /// let values = f.protected_call(true).unwrap().unwrap();
+    /// assert_eq!(
+    ///     values,
+    ///     [
+    ///         AnyLuaValue::LuaNumber(1.0),
+    ///         AnyLuaValue::LuaString("two".to_owned()),
+    ///         AnyLuaValue::LuaBoolean(true),
+    ///     ]
+    /// );
+    /// ```
+    #[inline]
+    pub fn protected_call<A, E>(
+        &mut self,
+        args: A,
+    ) -> Result<Result<Vec<AnyLuaValue>, AnyLuaValue>, E>
+    where
+        A: for<'r> Push<&'r mut LuaFunction<L>, Err = E>,
+    {
+        let (pcall_return_value, mut pushed_value) =
+            self.pcall_with_results(args, ffi::LUA_MULTRET)?;
+        let size = pushed_value.size;
+
+        let values = (-size..0).map(|index| {
+            LuaRead::lua_read_at_position(&mut pushed_value, index)
+                .ok()
+                .expect("AnyLuaValue can always be read")
+        });
+
+        match pcall_return_value {
+            0 => Ok(Ok(values.collect())),
+            ffi::LUA_ERRMEM => panic!("lua_pcall returned LUA_ERRMEM"),
+            ffi::LUA_ERRRUN => {
+                Ok(Err(values.last().expect("can't find error value at the top of the Lua stack")))
+            },
+            _ => panic!("Unknown error code returned by lua_pcall: {}", pcall_return_value),
+        }
+    }
+
+    /// Calls the function with parameters, and keeps all its return values on the stack.
+    ///
+    /// Contrary to `call_with_args`, the return values aren't read. The returned `LuaResults`
+    /// gives access to each of them, so that the caller can decide what to read based on their
+    /// number and types. Returns an error if the function raises an error or if we failed to push
+    /// an argument.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::LuaType;
+    ///
+    /// let mut lua = hlua::Lua::new();
+    /// lua.execute::<()>("function parse(s) if s == '' then return false, 'empty' end \
+    ///                    return true, #s end").unwrap();
+    /// let mut parse: hlua::LuaFunction<_> = lua.get("parse").unwrap();
+    ///
+    /// let mut results = parse.call_results("hello").unwrap();
+    /// assert_eq!(results.len(), 2);
+    /// assert_eq!(results.type_of(1), Some(LuaType::Number));
+    /// match results.read::<bool>(0).unwrap() {
+    ///     true => assert_eq!(results.read::<i32>(1), Some(5)),
+    ///     false => panic!("{}", results.read::<String>(1).unwrap()),
+    /// }
+    /// ```
+    #[inline]
+    pub fn call_results<A, E>(
+        &mut self,
+        args: A,
+    ) -> Result<LuaResults<&mut L>, LuaFunctionCallError<E>>
+    where
+        A: for<'r> Push<&'r mut LuaFunction<L>, Err = E>,
+    {
+        let (pcall_return_value, pushed_value) = self
+            .pcall_with_results(args, ffi::LUA_MULTRET)
+            .map_err(LuaFunctionCallError::PushError)?;
+        LuaResults::from_pcall(pcall_return_value, pushed_value)
+    }
+
+    /// Same as `call_results`, but takes the function by value, so that the results can outlive
+    /// the borrow of the function.
+    #[inline]
+    pub fn into_call_results<A, E>(
+        mut self,
+        args: A,
+    ) -> Result<LuaResults<LuaFunction<L>>, LuaFunctionCallError<E>>
+    where
+        A: for<'r> Push<&'r mut LuaFunction<L>, Err = E>,
+    {
+        let raw_lua = self.variable.as_mut_lua();
+        let (pcall_return_value, pushed_value) = self
+            .pcall_with_results(args, ffi::LUA_MULTRET)
+            .map_err(LuaFunctionCallError::PushError)?;
+        let size = pushed_value.forget_internal();
+        LuaResults::from_pcall(pcall_return_value, PushGuard { lua: self, size, raw_lua })
+    }
+
+    // Calls the function with `lua_pcall`, and returns its return code along with the value it
+    // left on the stack.
+    #[inline]
+    fn pcall<A, E>(&mut self, args: A) -> Result<(libc::c_int, PushGuard<&mut L>), E>
+    where
+        A: for<'r> Push<&'r mut LuaFunction<L>, Err = E>,
+    {
+        self.pcall_with_results(args, 1)
+    }
+
+    // Same as `pcall`, but keeps `nresults` return values, which can be `LUA_MULTRET`. The guard
+    // holds the return values, or the error value if the call failed.
+    #[inline]
+    fn pcall_with_results<A, E>(
+        &mut self,
+        args: A,
+        nresults: libc::c_int,
+    ) -> Result<(libc::c_int, PushGuard<&mut L>), E>
+    where
+        A: for<'r> Push<&'r mut LuaFunction<L>, Err = E>,
+    {
+        // calling pcall pops the parameters and pushes output
+        unsafe {
+            // lua_pcall pops the function, so we have to make a copy of it
+            let raw_lua = self.variable.as_mut_lua();
+            let top = ffi::lua_gettop(raw_lua.as_ptr());
+            ffi::lua_pushvalue(raw_lua.as_ptr(), -1);
+            let num_pushed = match args.push_to_lua(self) {
+                Ok(g) => g.forget_internal(),
+                Err((err, _)) => return Err(err),
+            };
+            let pcall_return_value = ffi::lua_pcall(raw_lua.as_ptr(), num_pushed, nresults, 0);
+            let size = ffi::lua_gettop(raw_lua.as_ptr()) - top;
+            let guard = PushGuard { lua: &mut self.variable, size, raw_lua };
+
+            Ok((pcall_return_value, guard))
+        }
+    }
+
     /// Builds a new `LuaFunction` from the code of a reader.
     ///
     /// Returns an error if reading from the `Read` object fails or if there is a syntax error in
@@ -313,6 +629,134 @@ where
         }
     }
 
+    /// Builds a new `LuaFunction` from a buffer containing either source code or precompiled
+    /// bytecode, as produced by `string.dump` or `luac`.
+    ///
+    /// `name` is the name of the chunk, which appears in error messages and debug information.
+    /// By convention, it starts with `@` when it is the name of a file and with `=` otherwise.
+    ///
+    /// Returns a `LuaError::SyntaxError` if the buffer isn't valid source code or bytecode.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    ///
+    /// let mut f = hlua::LuaFunction::load_buffer(&mut lua, b"return 8", "=config").unwrap();
+    /// let ret: i32 = f.call().unwrap();
+    /// assert_eq!(ret, 8);
+    /// ```
+    #[inline]
+    pub fn load_buffer(
+        lua: L,
+        code: &[u8],
+        name: &str,
+    ) -> Result<LuaFunction<PushGuard<L>>, LuaError> {
+        LuaFunction::load_with_mode(lua, code, name, LoadMode::Any)
+    }
+
+    /// Same as `load_buffer`, but refuses precompiled bytecode.
+    ///
+    /// Lua doesn't verify bytecode, and malicious bytecode can crash the program or corrupt its
+    /// memory. Code coming from untrusted sources should be loaded with this function, so that
+    /// only source code, which is always checked by the parser, is accepted.
+    ///
+    /// Returns a `LuaError::SyntaxError` if the buffer contains bytecode or isn't valid source
+    /// code.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// assert!(hlua::LuaFunction::load_text_only(&mut lua, b"return 8", "=untrusted").is_ok());
+    /// assert!(hlua::LuaFunction::load_text_only(&mut lua, b"\x1bLua", "=untrusted").is_err());
+    /// ```
+    #[inline]
+    pub fn load_text_only(
+        lua: L,
+        code: &[u8],
+        name: &str,
+    ) -> Result<LuaFunction<PushGuard<L>>, LuaError> {
+        LuaFunction::load_with_mode(lua, code, name, LoadMode::Text)
+    }
+
+    /// Same as `load_buffer`, but only accepts the kinds of chunks allowed by `mode`.
+    ///
+    /// Returns a `LuaError::SyntaxError` if the buffer isn't a valid chunk of the kind that
+    /// `mode` allows.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{LoadMode, LuaFunction};
+    ///
+    /// let mut lua = hlua::Lua::new();
+    /// let code = b"return 8";
+    /// assert!(LuaFunction::load_with_mode(&mut lua, code, "=f", LoadMode::Text).is_ok());
+    /// assert!(LuaFunction::load_with_mode(&mut lua, code, "=f", LoadMode::Binary).is_err());
+    /// ```
+    pub fn load_with_mode(
+        mut lua: L,
+        code: &[u8],
+        name: &str,
+        mode: LoadMode,
+    ) -> Result<LuaFunction<PushGuard<L>>, LuaError> {
+        let name = CString::new(name).unwrap();
+        let raw_lua = lua.as_mut_lua();
+        let (buf, len) = (code.as_ptr().cast(), code.len() as libc::size_t);
+
+        let load_retval = unsafe {
+            match () {
+                #[cfg(feature = "_luaapi_51")]
+                () => {
+                    // Binary chunks start with the first character of `LUA_SIGNATURE`, which is
+                    // what Lua itself checks.
+                    let binary = code.first() == Some(&0x1b);
+                    let refused = match mode {
+                        LoadMode::Text if binary => {
+                            Some("attempt to load a binary chunk (mode is 't')")
+                        },
+                        LoadMode::Binary if !binary => {
+                            Some("attempt to load a text chunk (mode is 'b')")
+                        },
+                        _ => None,
+                    };
+                    if let Some(msg) = refused {
+                        return Err(LuaError::SyntaxError(msg.to_owned()));
+                    }
+                    ffi::luaL_loadbuffer(raw_lua.as_ptr(), buf, len, name.as_ptr())
+                },
+                #[cfg(any(feature = "_luaapi_52", feature = "_luaapi_54"))]
+                () => {
+                    let mode: &[u8] = match mode {
+                        LoadMode::Text => b"t\0",
+                        LoadMode::Binary => b"b\0",
+                        LoadMode::Any => b"bt\0",
+                    };
+                    ffi::luaL_loadbufferx(
+                        raw_lua.as_ptr(),
+                        buf,
+                        len,
+                        name.as_ptr(),
+                        mode.as_ptr().cast(),
+                    )
+                },
+            }
+        };
+        let pushed_value = PushGuard { lua, size: 1, raw_lua };
+
+        if load_retval == 0 {
+            return Ok(LuaFunction { variable: pushed_value });
+        }
+
+        let error_msg = LuaRead::lua_read(&pushed_value)
+            .ok()
+            .expect("can't find error message at the top of the Lua stack");
+
+        assert_ne!(load_retval, ffi::LUA_ERRMEM, "memory allocation error");
+        Err(LuaError::SyntaxError(error_msg))
+    }
+
     /// Builds a new `LuaFunction` from a raw string.
     ///
     /// > **Note**: This is just a wrapper around `load_from_reader`. There is no advantage in
@@ -325,6 +769,121 @@ where
     }
 }
 
+/// Kinds of chunks accepted by `LuaFunction::load_with_mode`, which correspond to the `mode`
+/// argument of Lua's `load` function.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LoadMode {
+    /// Only source code, like the `"t"` mode.
+    Text,
+    /// Only precompiled bytecode, like the `"b"` mode.
+    Binary,
+    /// Both source code and precompiled bytecode, like the `"bt"` mode.
+    Any,
+}
+
+/// Return values of a function, kept on the stack, as returned by `LuaFunction::call_results` and
+/// `Lua::execute_results`.
+///
+/// The values are numbered from `0`, like in a slice. Each of them can be read multiple times,
+/// as different types if needed. They are popped when the `LuaResults` is dropped.
+#[derive(Debug)]
+pub struct LuaResults<L> {
+    values: PushGuard<L>,
+}
+
+impl<'lua, L> LuaResults<L>
+where
+    L: AsMutLua<'lua>,
+{
+    // Builds the results of a call of `lua_pcall` that returned `pcall_return_value`, or turns the
+    // error that it left on the stack into a `LuaError`.
+    pub(crate) fn from_pcall<E>(
+        pcall_return_value: libc::c_int,
+        values: PushGuard<L>,
+    ) -> Result<LuaResults<L>, LuaFunctionCallError<E>> {
+        match pcall_return_value {
+            0 => Ok(LuaResults { values }),
+            ffi::LUA_ERRMEM => panic!("lua_pcall returned LUA_ERRMEM"),
+            ffi::LUA_ERRRUN => {
+                let error_msg = LuaRead::lua_read(values)
+                    .ok()
+                    .expect("can't find error message at the top of the Lua stack");
+                Err(LuaFunctionCallError::LuaError(LuaError::ExecutionError(error_msg)))
+            },
+            _ => panic!("Unknown error code returned by lua_pcall: {}", pcall_return_value),
+        }
+    }
+
+    /// Returns the number of values.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.values.size as usize
+    }
+
+    /// Returns true if the function didn't return anything.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.values.size == 0
+    }
+
+    /// Returns the type of the value `n`, or `None` if there are fewer values.
+    #[inline]
+    pub fn type_of(&self, n: usize) -> Option<LuaType> {
+        let index = self.index(n)?;
+        LuaType::at(self.values.raw_lua, index)
+    }
+
+    /// Returns true if the values `a` and `b` are equal, without calling the `__eq` metamethod.
+    /// Returns false if there are fewer values. See `raw_equal`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::with_libs(hlua::LuaLibs::BASE);
+    /// lua.execute::<()>("local mt = { __eq = function() return true end } \
+    ///                    a = setmetatable({}, mt); b = setmetatable({}, mt)").unwrap();
+    ///
+    /// let mut results = lua.execute_results("return a, b, a, a == b").unwrap();
+    /// assert_eq!(results.read::<bool>(3), Some(true));
+    /// assert!(!results.raw_equal(0, 1));
+    /// assert!(results.raw_equal(0, 2));
+    /// ```
+    #[inline]
+    pub fn raw_equal(&self, a: usize, b: usize) -> bool {
+        match (self.index(a), self.index(b)) {
+            (Some(a), Some(b)) => raw_equal(&self.values, a, b),
+            _ => false,
+        }
+    }
+
+    /// Reads the value `n`. Returns `None` if there are fewer values, or if the value can't be
+    /// read as `V`.
+    ///
+    /// Like with `lua_tostring`, reading a number as a string converts the value on the stack, so
+    /// that `type_of` then returns `LuaType::String`.
+    #[inline]
+    pub fn read<'a, V>(&'a mut self, n: usize) -> Option<V>
+    where
+        V: LuaRead<&'a mut PushGuard<L>>,
+    {
+        let index = self.index(n)?;
+        LuaRead::lua_read_at_position(&mut self.values, index).ok()
+    }
+
+    /// Returns the guard holding the values, which are the `size()` values at the top of the
+    /// stack.
+    #[inline]
+    pub fn into_guard(self) -> PushGuard<L> {
+        self.values
+    }
+
+    // Returns the position of the value `n` on the stack.
+    fn index(&self, n: usize) -> Option<i32> {
+        let n = i32::try_from(n).ok().filter(|n| *n < self.values.size)?;
+        Some(n - self.values.size)
+    }
+}
+
 /// Error that can happen when calling a `LuaFunction`.
 // TODO: implement Error on this
 #[derive(Debug)]
@@ -398,11 +957,129 @@ where
             Err(lua)
         }
     }
+
+    #[inline]
+    fn expected() -> Cow<'static, str> {
+        Cow::Borrowed("function")
+    }
+}
+
+/// Lua function stored in the registry, which Rust code can keep and call later.
+///
+/// Contrary to `LuaFunction`, which borrows the function on the stack, a `LuaCallback` doesn't
+/// borrow the Lua context. For example, a callback can receive a Lua function as a parameter and
+/// store it as an event handler. `A` is the type of the arguments that the function is called
+/// with, and `R` the type of its return value.
+///
+/// The function can be called in the Lua context that it was read from and in its threads,
+/// calling it elsewhere returns an error. Dropping a `LuaCallback` doesn't free the function,
+/// which Lua only collects once the callback has been passed to `release`.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use hlua::{Lua, LuaCallback};
+///
+/// let handlers = Arc::new(Mutex::new(Vec::new()));
+///
+/// let mut lua = Lua::new();
+/// let registered = handlers.clone();
+/// lua.set("on_tick", hlua::function1(move |cb: LuaCallback<(f64,), ()>| {
+///     registered.lock().unwrap().push(cb);
+/// }));
+/// lua.execute::<()>("total = 0; on_tick(function(dt) total = total + dt end)").unwrap();
+///
+/// for handler in handlers.lock().unwrap().iter() {
+///     handler.call(&mut lua, (0.5,)).unwrap();
+///     handler.call(&mut lua, (1.0,)).unwrap();
+/// }
+/// assert_eq!(lua.get::<f64, _>("total"), Some(1.5));
+/// ```
+#[derive(Debug)]
+pub struct LuaCallback<A, R> {
+    id: libc::c_int,
+    // Address of the registry of the context that holds the function.
+    registry: usize,
+    marker: PhantomData<fn(A) -> R>,
+}
+
+impl<A, R> LuaCallback<A, R> {
+    /// Calls the function with `args` in `lua`, which must be the context that the function was
+    /// read from.
+    ///
+    /// Returns an error if `lua` is another context, if the function raises an error, if the
+    /// return value can't be read as `R`, or if we failed to push an argument.
+    #[inline]
+    pub fn call<'lua, L, E>(&self, lua: L, args: A) -> Result<R, LuaFunctionCallError<E>>
+    where
+        L: AsMutLua<'lua>,
+        A: for<'r> Push<&'r mut LuaFunction<PushGuard<L>>, Err = E>,
+        R: for<'g> LuaRead<PushGuard<&'g mut PushGuard<L>>>,
+    {
+        let mut lua = lua;
+        let raw_lua = lua.as_mut_lua();
+
+        if unsafe { ffix::lua_registry_address(raw_lua) } != self.registry {
+            let msg = "the callback was read from another Lua context".to_owned();
+            return Err(LuaFunctionCallError::LuaError(LuaError::ExecutionError(msg)));
+        }
+
+        unsafe {
+            ffi::lua_rawgeti(raw_lua.as_ptr(), ffi::LUA_REGISTRYINDEX, self.id as _);
+            debug_assert!(ffi::lua_isfunction(raw_lua.as_ptr(), -1));
+        }
+
+        let mut function = LuaFunction { variable: PushGuard { lua, size: 1, raw_lua } };
+        function.call_with_args(args)
+    }
+
+    /// Removes the function from the registry of `lua`, which must be the context that it was
+    /// read from.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `lua` is another context.
+    #[inline]
+    pub fn release<'lua, L>(self, lua: L)
+    where
+        L: AsMutLua<'lua>,
+    {
+        let mut lua = lua;
+        let raw_lua = lua.as_mut_lua();
+        if unsafe { ffix::lua_registry_address(raw_lua) } != self.registry {
+            panic!("LuaCallback released in another Lua context than the one it was read from");
+        }
+        unsafe { ffi::luaL_unref(raw_lua.as_ptr(), ffi::LUA_REGISTRYINDEX, self.id) };
+    }
+}
+
+impl<'lua, L, A, R> LuaRead<L> for LuaCallback<A, R>
+where
+    L: AsMutLua<'lua>,
+{
+    #[inline]
+    fn lua_read_at_position(mut lua: L, index: i32) -> Result<LuaCallback<A, R>, L> {
+        let raw_lua = lua.as_mut_lua().as_ptr();
+
+        unsafe {
+            if !ffi::lua_isfunction(raw_lua, index) {
+                return Err(lua);
+            }
+            ffi::lua_pushvalue(raw_lua, index);
+            let id = ffi::luaL_ref(raw_lua, ffi::LUA_REGISTRYINDEX);
+            let registry = ffix::lua_registry_address(lua.as_mut_lua());
+            Ok(LuaCallback { id, registry, marker: PhantomData })
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{Lua, LuaError, LuaFunction, LuaFunctionCallError, LuaTable, Void};
+    use crate::{
+        AnyLuaString, AnyLuaValue, AsLua, LoadMode, Lua, LuaCallback, LuaError, LuaFunction,
+        LuaFunctionCallError, LuaLibs, LuaNil, LuaTable, LuaType, Void,
+    };
 
     use std::{
         error::Error,
@@ -433,6 +1110,82 @@ mod tests {
         assert_eq!(val, 2);
     }
 
+    #[test]
+    fn chunk_args_reused() {
+        let mut lua = Lua::new();
+        let mut f = LuaFunction::load(&mut lua, "local a, b = ...; return a * b").unwrap();
+        for i in 0..10 {
+            let val: i32 = f.call_with_args((i, 3)).unwrap();
+            assert_eq!(val, i * 3);
+        }
+    }
+
+    #[test]
+    fn load_buffer_source_and_bytecode() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+
+        let mut f = LuaFunction::load_buffer(&mut lua, b"return ...", "=source").unwrap();
+        let val: i32 = f.call_with_args(4).unwrap();
+        assert_eq!(val, 4);
+        drop(f);
+
+        let dumped: AnyLuaString =
+            lua.execute("return string.dump(function(a) return a * 3 end)").unwrap();
+        let mut f = LuaFunction::load_buffer(&mut lua, &dumped.0, "=dumped").unwrap();
+        let val: i32 = f.call_with_args(4).unwrap();
+        assert_eq!(val, 12);
+        drop(f);
+
+        match LuaFunction::load_buffer(&mut lua, b"return +", "=broken") {
+            Err(LuaError::SyntaxError(msg)) => assert!(msg.starts_with("broken:"), "{}", msg),
+            _ => panic!("expected a syntax error"),
+        };
+    }
+
+    #[test]
+    fn load_text_only_refuses_bytecode() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+
+        let mut f = LuaFunction::load_text_only(&mut lua, b"return ...", "=source").unwrap();
+        let val: i32 = f.call_with_args(4).unwrap();
+        assert_eq!(val, 4);
+        drop(f);
+
+        let dumped: AnyLuaString = lua.execute("return string.dump(function() end)").unwrap();
+        match LuaFunction::load_text_only(&mut lua, &dumped.0, "=dumped") {
+            Err(LuaError::SyntaxError(msg)) => assert!(msg.contains("binary chunk"), "{}", msg),
+            _ => panic!("expected bytecode to be refused"),
+        };
+    }
+
+    #[test]
+    fn load_with_mode() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+
+        let dumped: AnyLuaString =
+            lua.execute("return string.dump(function() return 7 end)").unwrap();
+        let mut f =
+            LuaFunction::load_with_mode(&mut lua, &dumped.0, "=dumped", LoadMode::Binary).unwrap();
+        assert_eq!(f.call::<i32>().unwrap(), 7);
+        drop(f);
+
+        match LuaFunction::load_with_mode(&mut lua, b"return 7", "=source", LoadMode::Binary) {
+            Err(LuaError::SyntaxError(msg)) => assert!(msg.contains("text chunk"), "{}", msg),
+            _ => panic!("expected source code to be refused"),
+        };
+        match LuaFunction::load_with_mode(&mut lua, &dumped.0, "=dumped", LoadMode::Text) {
+            Err(LuaError::SyntaxError(msg)) => assert!(msg.contains("binary chunk"), "{}", msg),
+            _ => panic!("expected bytecode to be refused"),
+        };
+        for code in [&b"return 7"[..], &dumped.0] {
+            let mut f = LuaFunction::load_with_mode(&mut lua, code, "=any", LoadMode::Any).unwrap();
+            assert_eq!(f.call::<i32>().unwrap(), 7);
+        }
+    }
+
     #[test]
     fn syntax_error() {
         let mut lua = Lua::new();
@@ -452,6 +1205,87 @@ mod tests {
         };
     }
 
+    #[test]
+    fn capture_error_value() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.execute::<()>("function foo(a) if a then error({ code = a }) end return 1 end")
+            .unwrap();
+        let mut f = lua.get::<LuaFunction<_>, _>("foo").unwrap();
+
+        let val: Result<i32, _> = f.call_capture_error(LuaNil).unwrap();
+        assert_eq!(val, Ok(1));
+
+        let err = f.call_capture_error::<i32, _, _>(7).unwrap().unwrap_err();
+        match err {
+            AnyLuaValue::LuaArray(entries) => assert_eq!(
+                entries,
+                [(AnyLuaValue::LuaString("code".to_owned()), AnyLuaValue::LuaNumber(7.))]
+            ),
+            _ => panic!("unexpected error value: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn protected_call_any_arity() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+
+        let mut f = LuaFunction::load(&mut lua, "return ...").unwrap();
+        assert_eq!(f.protected_call(()).unwrap().unwrap(), []);
+        let values = f.protected_call((1, "a", false)).unwrap().unwrap();
+        assert_eq!(
+            values,
+            [
+                AnyLuaValue::LuaNumber(1.),
+                AnyLuaValue::LuaString("a".to_owned()),
+                AnyLuaValue::LuaBoolean(false)
+            ]
+        );
+        drop(f);
+
+        let mut f = LuaFunction::load(&mut lua, "error('oops', 0)").unwrap();
+        let err = f.protected_call(()).unwrap().unwrap_err();
+        assert_eq!(err, AnyLuaValue::LuaString("oops".to_owned()));
+        drop(f);
+
+        assert_eq!(lua.stack_size(), 0);
+    }
+
+    #[test]
+    fn results_on_the_stack() {
+        let mut lua = Lua::with_libs(LuaLibs::BASE);
+
+        let mut f = LuaFunction::load(&mut lua, "return ...").unwrap();
+        {
+            let mut results = f.call_results((1, "a", LuaNil)).unwrap();
+            assert_eq!(results.len(), 3);
+            let types: Vec<_> = (0..4).map(|n| results.type_of(n)).collect();
+            assert_eq!(
+                types,
+                [Some(LuaType::Number), Some(LuaType::String), Some(LuaType::Nil), None]
+            );
+            assert_eq!(results.read::<i32>(0), Some(1));
+            assert_eq!(results.read::<String>(0).as_deref(), Some("1"));
+            assert_eq!(results.read::<String>(1).as_deref(), Some("a"));
+            assert_eq!(results.read::<i32>(1), None);
+            assert_eq!(results.read::<i32>(3), None);
+        }
+        assert!(f.call_results(()).unwrap().is_empty());
+        drop(f);
+
+        let results = lua.execute_results("local t = {} return t, t").unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results.type_of(1), Some(LuaType::Table));
+        drop(results);
+
+        match lua.execute_results("error('oops')") {
+            Err(LuaError::ExecutionError(msg)) => assert!(msg.contains("oops"), "{}", msg),
+            other => panic!("unexpected result: {:?}", other.map(|r| r.len())),
+        }
+        assert_eq!(lua.stack_size(), 0);
+    }
+
     #[test]
     fn wrong_type() {
         let mut lua = Lua::new();
@@ -501,6 +1335,111 @@ mod tests {
         }
     }
 
+    #[test]
+    fn stored_callbacks() {
+        let mut lua = Lua::with_libs(LuaLibs::BASE);
+        lua.execute::<()>("function add(a, b) return a + b end; function fail() error('oops') end")
+            .unwrap();
+
+        let add: LuaCallback<(i32, i32), i32> = lua.get("add").unwrap();
+        let fail: LuaCallback<(), ()> = lua.get("fail").unwrap();
+        assert!(lua.get::<LuaCallback<(), ()>, _>("missing").is_none());
+
+        // The callbacks keep working after the globals have been removed.
+        lua.execute::<()>("add = nil; fail = nil").unwrap();
+        assert_eq!(add.call(&mut lua, (2, 3)).unwrap(), 5);
+        assert_eq!(add.call(&mut lua, (10, -4)).unwrap(), 6);
+        match fail.call(&mut lua, ()) {
+            Err(LuaFunctionCallError::LuaError(LuaError::ExecutionError(msg))) => {
+                assert!(msg.contains("oops"), "{}", msg)
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        add.release(&mut lua);
+        fail.release(&mut lua);
+    }
+
+    #[test]
+    fn callback_in_another_context() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("function one() return 1 end").unwrap();
+        let one: LuaCallback<(), i32> = lua.get("one").unwrap();
+
+        let mut other = Lua::new();
+        match one.call(&mut other, ()) {
+            Err(LuaFunctionCallError::LuaError(LuaError::ExecutionError(msg))) => {
+                assert!(msg.contains("another Lua context"), "{}", msg)
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let other_one: LuaCallback<(), i32> = lua.get("one").unwrap();
+            other_one.release(&mut other)
+        }));
+        assert!(result.is_err());
+        assert_eq!(one.call(&mut lua, ()).unwrap(), 1);
+    }
+
+    #[test]
+    fn isolated_environments() {
+        use std::collections::HashMap;
+
+        let mut lua = Lua::new();
+        let code = "name = name .. '!'; function greet() return 'hi ' .. name end; return greet()";
+
+        let mut a = LuaFunction::load(&mut lua, code).unwrap();
+        let greeting: String = a.call_with_env(HashMap::from([("name", "a")]), ()).unwrap();
+        assert_eq!(greeting, "hi a!");
+        drop(a);
+
+        let mut b = LuaFunction::load(&mut lua, code).unwrap();
+        let greeting: String = b.call_with_env(HashMap::from([("name", "b")]), ()).unwrap();
+        assert_eq!(greeting, "hi b!");
+        drop(b);
+
+        assert!(lua.get::<LuaFunction<_>, _>("greet").is_none());
+    }
+
+    #[test]
+    fn env_of_c_function() {
+        use std::collections::HashMap;
+
+        let mut lua = Lua::new();
+        lua.set("answer", crate::function0(|| 42));
+
+        let mut answer: LuaFunction<_> = lua.get("answer").unwrap();
+        let env = HashMap::from([("x", 1)]);
+        match answer.call_with_env::<i32, _, _, _>(env, ()) {
+            Err(LuaFunctionCallError::LuaError(LuaError::ExecutionError(msg))) => {
+                assert!(msg.contains("C function"), "{}", msg)
+            },
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // The function can still be called normally.
+        assert_eq!(answer.call::<i32>().unwrap(), 42);
+    }
+
+    #[test]
+    fn env_not_a_table() {
+        let mut lua = Lua::new();
+        let mut f = LuaFunction::load(&mut lua, "return 12").unwrap();
+
+        for result in [f.call_with_env::<i32, _, _, _>(5, ()), f.call_with_env("env", ())] {
+            match result {
+                Err(LuaFunctionCallError::LuaError(LuaError::ExecutionError(msg))) => {
+                    assert!(msg.contains("must be a table"), "{}", msg)
+                },
+                other => panic!("unexpected result: {:?}", other.map(|_| ())),
+            }
+        }
+
+        // The function and its environment are left untouched.
+        assert_eq!(f.call::<i32>().unwrap(), 12);
+    }
+
     fn _assert_error() {
         // Compile-time trait checks.
         fn _assert<T: Error>(_: T) {}
@@ -0,0 +1,1219 @@
+//! Conversions between Lua values and the types that implement the `serde` traits, enabled by
+//! the `impl-serde` feature.
+
+use std::{error::Error, fmt, str};
+
+use serde::{
+    de::{
+        self, value::MapAccessDeserializer, DeserializeOwned, DeserializeSeed, IntoDeserializer,
+        MapAccess, SeqAccess, Visitor,
+    },
+    ser, Serialize,
+};
+
+use crate::{ffix, AsMutLua, LuaContext, LuaError, LuaType, Push, PushGuard, PushOne};
+
+/// Error that can happen when converting a value with `serde`.
+#[derive(Debug)]
+pub enum LuaSerdeError {
+    /// The Lua code that produced the value failed.
+    Lua(LuaError),
+    /// The value doesn't match the Rust type, or can't be represented in Lua.
+    Custom(String),
+}
+
+impl fmt::Display for LuaSerdeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LuaSerdeError::Lua(err) => fmt::Display::fmt(err, f),
+            LuaSerdeError::Custom(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl Error for LuaSerdeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LuaSerdeError::Lua(err) => Some(err),
+            LuaSerdeError::Custom(_) => None,
+        }
+    }
+}
+
+impl de::Error for LuaSerdeError {
+    #[inline]
+    fn custom<T: fmt::Display>(msg: T) -> LuaSerdeError {
+        LuaSerdeError::Custom(msg.to_string())
+    }
+}
+
+impl ser::Error for LuaSerdeError {
+    #[inline]
+    fn custom<T: fmt::Display>(msg: T) -> LuaSerdeError {
+        LuaSerdeError::Custom(msg.to_string())
+    }
+}
+
+impl From<LuaError> for LuaSerdeError {
+    #[inline]
+    fn from(err: LuaError) -> LuaSerdeError {
+        LuaSerdeError::Lua(err)
+    }
+}
+
+/// Deserializes the value at `index` on the stack, without modifying it.
+pub(crate) fn from_stack<T>(lua: LuaContext, index: i32) -> Result<T, LuaSerdeError>
+where
+    T: DeserializeOwned,
+{
+    // Deserializing tables pushes values, so relative indices wouldn't stay valid.
+    let index = match index < 0 && index > ffi::LUA_REGISTRYINDEX {
+        true => index + unsafe { ffi::lua_gettop(lua.as_ptr()) } + 1,
+        false => index,
+    };
+    T::deserialize(ValueDeserializer { lua, index, parents: None })
+}
+
+// Deserializer of the value at an absolute position on the stack.
+#[derive(Copy, Clone)]
+struct ValueDeserializer<'a> {
+    lua: LuaContext,
+    index: i32,
+    // Tables that contain the value, from the innermost one.
+    parents: Option<&'a Parents<'a>>,
+}
+
+// Linked list of the tables being deserialized, used to detect tables that contain themselves.
+struct Parents<'a> {
+    table: *const libc::c_void,
+    // Number of tables in the list, including this one.
+    depth: usize,
+    parent: Option<&'a Parents<'a>>,
+}
+
+// Maximum number of nested tables, past which deserializing would overflow the stack of the
+// thread before the stack of Lua.
+const MAX_DEPTH: usize = 128;
+
+impl<'a> ValueDeserializer<'a> {
+    #[inline]
+    fn ty(&self) -> LuaType {
+        LuaType::at(self.lua, self.index).unwrap_or(LuaType::Nil)
+    }
+
+    // Returns the bytes of the string, which must be a string and not a number, as `lua_tolstring`
+    // would convert the number in place.
+    fn bytes(&self) -> &[u8] {
+        unsafe {
+            let mut len = 0;
+            let ptr = ffi::lua_tolstring(self.lua.as_ptr(), self.index, &mut len);
+            std::slice::from_raw_parts(ptr.cast(), len)
+        }
+    }
+
+    // Returns true if the table is a non-empty sequence, in which case it is deserialized as a
+    // sequence by `deserialize_any`.
+    fn is_sequence(&self) -> bool {
+        let len = unsafe { ffix::lua_rawlen(self.lua, self.index) };
+        if len == 0 || unsafe { ffi::lua_checkstack(self.lua.as_ptr(), 2) } == 0 {
+            return false;
+        }
+
+        let mut count = 0;
+        unsafe {
+            ffi::lua_pushnil(self.lua.as_ptr());
+            while ffi::lua_next(self.lua.as_ptr(), self.index) != 0 {
+                ffi::lua_pop(self.lua.as_ptr(), 1);
+                count += 1;
+            }
+        }
+        count == len
+    }
+
+    // Returns the node to add to the parents of the values of the table, or an error if the table
+    // contains itself, if it is nested too deeply or if there is no room on the stack to read its
+    // entries.
+    fn enter_table(&self) -> Result<Parents<'a>, LuaSerdeError> {
+        let table = unsafe { ffi::lua_topointer(self.lua.as_ptr(), self.index) };
+        let depth = self.parents.map_or(0, |parent| parent.depth) + 1;
+        if depth > MAX_DEPTH {
+            return Err(de::Error::custom("too many nested tables"));
+        }
+
+        let mut parents = self.parents;
+        while let Some(parent) = parents {
+            if parent.table == table {
+                return Err(de::Error::custom("can't deserialize a table that contains itself"));
+            }
+            parents = parent.parent;
+        }
+
+        // Reading an entry of a map pushes the key, the value and a copy of the key.
+        if unsafe { ffi::lua_checkstack(self.lua.as_ptr(), 3) } == 0 {
+            return Err(de::Error::custom("too many nested tables"));
+        }
+
+        Ok(Parents { table, depth, parent: self.parents })
+    }
+
+    fn unsupported(&self) -> LuaSerdeError {
+        LuaSerdeError::Custom(format!("can't deserialize a Lua value of type {:?}", self.ty()))
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for ValueDeserializer<'a> {
+    type Error = LuaSerdeError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, LuaSerdeError>
+    where
+        V: Visitor<'de>,
+    {
+        let raw_lua = self.lua.as_ptr();
+
+        match self.ty() {
+            LuaType::Nil => visitor.visit_unit(),
+            LuaType::Boolean => {
+                visitor.visit_bool(unsafe { ffi::lua_toboolean(raw_lua, self.index) } != 0)
+            },
+            LuaType::Number => {
+                #[cfg(feature = "_luaapi_54")]
+                if unsafe { ffi::lua_isinteger(raw_lua, self.index) } != 0 {
+                    // `lua54-sys` builds Lua with 32-bit integers, the upper bits are meaningless.
+                    let n =
+                        unsafe { ffi::lua_tointegerx(raw_lua, self.index, std::ptr::null_mut()) };
+                    return visitor.visit_i64(n as i32 as i64);
+                }
+
+                let n =
+                    unsafe { ffi::lua_tonumberx(raw_lua, self.index, std::ptr::null_mut()) } as f64;
+                // Versions before 5.4 don't have integers, and with 5.4 integers that don't fit in
+                // the integers of `lua54-sys` and results such as `6 / 2` are floats. Integral
+                // numbers are passed as integers in order to be accepted by integer types, like
+                // `Lua::get` does.
+                if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 {
+                    return visitor.visit_i64(n as i64);
+                }
+                visitor.visit_f64(n)
+            },
+            LuaType::String => match str::from_utf8(self.bytes()) {
+                Ok(s) => visitor.visit_str(s),
+                Err(_) => visitor.visit_bytes(self.bytes()),
+            },
+            LuaType::Table if self.is_sequence() => self.deserialize_seq(visitor),
+            LuaType::Table => self.deserialize_map(visitor),
+            _ => Err(self.unsupported()),
+        }
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, LuaSerdeError>
+    where
+        V: Visitor<'de>,
+    {
+        match self.ty() {
+            LuaType::Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, LuaSerdeError>
+    where
+        V: Visitor<'de>,
+    {
+        match self.ty() {
+            LuaType::String => visitor.visit_bytes(self.bytes()),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, LuaSerdeError>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, LuaSerdeError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, LuaSerdeError>
+    where
+        V: Visitor<'de>,
+    {
+        match self.ty() {
+            LuaType::Table => {
+                let parents = self.enter_table()?;
+                let len = unsafe { ffix::lua_rawlen(self.lua, self.index) };
+                let mut seq = SeqDeserializer { table: self, parents: &parents, next: 1, len };
+                let value = visitor.visit_seq(&mut seq)?;
+                match seq.next > seq.len {
+                    true => Ok(value),
+                    false => Err(de::Error::invalid_length(len, &"fewer elements in the table")),
+                }
+            },
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple<V>(self, _: usize, visitor: V) -> Result<V::Value, LuaSerdeError>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _: &'static str,
+        _: usize,
+        visitor: V,
+    ) -> Result<V::Value, LuaSerdeError>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, LuaSerdeError>
+    where
+        V: Visitor<'de>,
+    {
+        match self.ty() {
+            LuaType::Table => {
+                let parents = self.enter_table()?;
+                visitor.visit_map(MapDeserializer::new(self, &parents))
+            },
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, LuaSerdeError>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_map(visitor)
+    }
+
+    // Unit variants are strings, and other variants are tables with a single entry whose key is
+    // the name of the variant.
+    fn deserialize_enum<V>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, LuaSerdeError>
+    where
+        V: Visitor<'de>,
+    {
+        match self.ty() {
+            LuaType::String => match str::from_utf8(self.bytes()) {
+                Ok(s) => visitor.visit_enum(s.into_deserializer()),
+                Err(_) => Err(de::Error::custom("the name of the variant isn't valid UTF-8")),
+            },
+            LuaType::Table => {
+                let parents = self.enter_table()?;
+                visitor.visit_enum(MapAccessDeserializer::new(MapDeserializer::new(self, &parents)))
+            },
+            _ => Err(self.unsupported()),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string unit unit_struct
+        identifier ignored_any
+    }
+}
+
+// Reads the values of a table at the indices `next..=len`.
+struct SeqDeserializer<'a> {
+    table: ValueDeserializer<'a>,
+    parents: &'a Parents<'a>,
+    next: usize,
+    len: usize,
+}
+
+impl<'de, 'a> SeqAccess<'de> for SeqDeserializer<'a> {
+    type Error = LuaSerdeError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, LuaSerdeError>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.next > self.len {
+            return Ok(None);
+        }
+
+        let lua = self.table.lua;
+        unsafe { ffi::lua_rawgeti(lua.as_ptr(), self.table.index, self.next as _) };
+        let _guard = unsafe { PushGuard::new(lua, 1) };
+        self.next += 1;
+
+        let index = unsafe { ffi::lua_gettop(lua.as_ptr()) };
+        seed.deserialize(ValueDeserializer { lua, index, parents: Some(self.parents) }).map(Some)
+    }
+
+    #[inline]
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len + 1 - self.next)
+    }
+}
+
+// Reads the entries of a table with `lua_next`.
+struct MapDeserializer<'a> {
+    table: ValueDeserializer<'a>,
+    parents: &'a Parents<'a>,
+    // Number of values pushed by the iteration: `0` before it starts and once it's over, `2`
+    // between `next_key_seed` and `next_value_seed`, `1` otherwise.
+    pushed: i32,
+}
+
+impl<'a> MapDeserializer<'a> {
+    // The room needed on the stack must have been checked by `enter_table`.
+    #[inline]
+    fn new(table: ValueDeserializer<'a>, parents: &'a Parents<'a>) -> MapDeserializer<'a> {
+        unsafe { ffi::lua_pushnil(table.lua.as_ptr()) };
+        MapDeserializer { table, parents, pushed: 1 }
+    }
+}
+
+impl<'de, 'a> MapAccess<'de> for MapDeserializer<'a> {
+    type Error = LuaSerdeError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, LuaSerdeError>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        let lua = self.table.lua;
+        if self.pushed != 1 || unsafe { ffi::lua_next(lua.as_ptr(), self.table.index) } == 0 {
+            self.pushed = 0;
+            return Ok(None);
+        }
+        self.pushed = 2;
+
+        // Reading a number as a string would convert the key in place and break `lua_next`, so a
+        // copy of the key is read instead.
+        unsafe { ffi::lua_pushvalue(lua.as_ptr(), -2) };
+        let _guard = unsafe { PushGuard::new(lua, 1) };
+        let index = unsafe { ffi::lua_gettop(lua.as_ptr()) };
+        seed.deserialize(ValueDeserializer { lua, index, parents: Some(self.parents) }).map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, LuaSerdeError>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let lua = self.table.lua;
+        if self.pushed != 2 {
+            return Err(de::Error::custom("value read before its key"));
+        }
+
+        let index = unsafe { ffi::lua_gettop(lua.as_ptr()) };
+        let value = seed.deserialize(ValueDeserializer { lua, index, parents: Some(self.parents) });
+        unsafe { ffi::lua_pop(lua.as_ptr(), 1) };
+        self.pushed = 1;
+        value
+    }
+}
+
+impl<'a> Drop for MapDeserializer<'a> {
+    #[inline]
+    fn drop(&mut self) {
+        if self.pushed != 0 {
+            unsafe { ffi::lua_pop(self.table.lua.as_ptr(), self.pushed) };
+        }
+    }
+}
+
+/// Value that is pushed by serializing it with `serde`.
+pub(crate) struct Serialized<'a, T: ?Sized>(pub(crate) &'a T);
+
+impl<'lua, 'a, L, T> Push<L> for Serialized<'a, T>
+where
+    L: AsMutLua<'lua>,
+    T: Serialize + ?Sized,
+{
+    type Err = LuaSerdeError;
+
+    fn push_to_lua(self, mut lua: L) -> Result<PushGuard<L>, (LuaSerdeError, L)> {
+        let raw_lua = lua.as_mut_lua();
+
+        // Values pushed by a serialization that fails halfway are removed all at once.
+        let top = unsafe { ffi::lua_gettop(raw_lua.as_ptr()) };
+        match self.0.serialize(ValueSerializer { lua: raw_lua }) {
+            Ok(()) => Ok(PushGuard { lua, size: 1, raw_lua }),
+            Err(err) => {
+                unsafe { ffi::lua_settop(raw_lua.as_ptr(), top) };
+                Err((err, lua))
+            },
+        }
+    }
+}
+
+impl<'lua, 'a, L, T> PushOne<L> for Serialized<'a, T>
+where
+    L: AsMutLua<'lua>,
+    T: Serialize + ?Sized,
+{
+}
+
+// Serializer that pushes exactly one value on the stack when it succeeds.
+#[derive(Copy, Clone)]
+struct ValueSerializer {
+    lua: LuaContext,
+}
+
+impl ValueSerializer {
+    // Pushes a new table, after making sure that the stack has room for the values that its
+    // serialization pushes on top of it.
+    fn push_table(&self, narr: usize, nrec: usize) -> Result<(), LuaSerdeError> {
+        unsafe {
+            if ffi::lua_checkstack(self.lua.as_ptr(), 4) == 0 {
+                return Err(ser::Error::custom("the value is too deeply nested"));
+            }
+            ffi::lua_createtable(self.lua.as_ptr(), narr as _, nrec as _);
+        }
+        Ok(())
+    }
+
+    // Pushes a table and the name of the variant, ready for the value of the variant to be
+    // pushed and stored in the table by `TableSerializer::end`.
+    fn push_variant(&self, variant: &'static str) -> Result<(), LuaSerdeError> {
+        self.push_table(0, 1)?;
+        ser::Serializer::serialize_str(*self, variant)
+    }
+}
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = ();
+    type Error = LuaSerdeError;
+    type SerializeSeq = TableSerializer;
+    type SerializeTuple = TableSerializer;
+    type SerializeTupleStruct = TableSerializer;
+    type SerializeTupleVariant = TableSerializer;
+    type SerializeMap = TableSerializer;
+    type SerializeStruct = TableSerializer;
+    type SerializeStructVariant = TableSerializer;
+
+    #[inline]
+    fn serialize_bool(self, v: bool) -> Result<(), LuaSerdeError> {
+        unsafe { ffi::lua_pushboolean(self.lua.as_ptr(), v as _) };
+        Ok(())
+    }
+
+    #[inline]
+    fn serialize_i8(self, v: i8) -> Result<(), LuaSerdeError> {
+        self.serialize_i64(v as i64)
+    }
+
+    #[inline]
+    fn serialize_i16(self, v: i16) -> Result<(), LuaSerdeError> {
+        self.serialize_i64(v as i64)
+    }
+
+    #[inline]
+    fn serialize_i32(self, v: i32) -> Result<(), LuaSerdeError> {
+        self.serialize_i64(v as i64)
+    }
+
+    // `lua54-sys` builds Lua with 32-bit integers, so larger values are pushed as floats rather
+    // than being truncated.
+    #[inline]
+    fn serialize_i64(self, v: i64) -> Result<(), LuaSerdeError> {
+        match i32::try_from(v) {
+            Ok(v) => unsafe { ffi::lua_pushinteger(self.lua.as_ptr(), v as ffi::lua_Integer) },
+            Err(_) => return self.serialize_f64(v as f64),
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn serialize_u8(self, v: u8) -> Result<(), LuaSerdeError> {
+        self.serialize_i64(v as i64)
+    }
+
+    #[inline]
+    fn serialize_u16(self, v: u16) -> Result<(), LuaSerdeError> {
+        self.serialize_i64(v as i64)
+    }
+
+    #[inline]
+    fn serialize_u32(self, v: u32) -> Result<(), LuaSerdeError> {
+        self.serialize_i64(v as i64)
+    }
+
+    #[inline]
+    fn serialize_u64(self, v: u64) -> Result<(), LuaSerdeError> {
+        match i64::try_from(v) {
+            Ok(v) => self.serialize_i64(v),
+            Err(_) => self.serialize_f64(v as f64),
+        }
+    }
+
+    #[inline]
+    fn serialize_f32(self, v: f32) -> Result<(), LuaSerdeError> {
+        self.serialize_f64(v as f64)
+    }
+
+    #[inline]
+    fn serialize_f64(self, v: f64) -> Result<(), LuaSerdeError> {
+        unsafe { ffi::lua_pushnumber(self.lua.as_ptr(), v as ffi::lua_Number) };
+        Ok(())
+    }
+
+    #[inline]
+    fn serialize_char(self, v: char) -> Result<(), LuaSerdeError> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    #[inline]
+    fn serialize_str(self, v: &str) -> Result<(), LuaSerdeError> {
+        self.serialize_bytes(v.as_bytes())
+    }
+
+    #[inline]
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), LuaSerdeError> {
+        unsafe { ffi::lua_pushlstring(self.lua.as_ptr(), v.as_ptr().cast(), v.len() as _) };
+        Ok(())
+    }
+
+    #[inline]
+    fn serialize_none(self) -> Result<(), LuaSerdeError> {
+        self.serialize_unit()
+    }
+
+    #[inline]
+    fn serialize_some<T>(self, value: &T) -> Result<(), LuaSerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    #[inline]
+    fn serialize_unit(self) -> Result<(), LuaSerdeError> {
+        unsafe { ffi::lua_pushnil(self.lua.as_ptr()) };
+        Ok(())
+    }
+
+    #[inline]
+    fn serialize_unit_struct(self, _: &'static str) -> Result<(), LuaSerdeError> {
+        self.serialize_unit()
+    }
+
+    #[inline]
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<(), LuaSerdeError> {
+        self.serialize_str(variant)
+    }
+
+    #[inline]
+    fn serialize_newtype_struct<T>(self, _: &'static str, value: &T) -> Result<(), LuaSerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<(), LuaSerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push_variant(variant)?;
+        value.serialize(self)?;
+        unsafe { ffi::lua_rawset(self.lua.as_ptr(), -3) };
+        Ok(())
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<TableSerializer, LuaSerdeError> {
+        self.push_table(len.unwrap_or(0), 0)?;
+        Ok(TableSerializer { lua: self.lua, next: 1, variant: false })
+    }
+
+    #[inline]
+    fn serialize_tuple(self, len: usize) -> Result<TableSerializer, LuaSerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    #[inline]
+    fn serialize_tuple_struct(
+        self,
+        _: &'static str,
+        len: usize,
+    ) -> Result<TableSerializer, LuaSerdeError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<TableSerializer, LuaSerdeError> {
+        self.push_variant(variant)?;
+        self.push_table(len, 0)?;
+        Ok(TableSerializer { lua: self.lua, next: 1, variant: true })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<TableSerializer, LuaSerdeError> {
+        self.push_table(0, len.unwrap_or(0))?;
+        Ok(TableSerializer { lua: self.lua, next: 1, variant: false })
+    }
+
+    #[inline]
+    fn serialize_struct(
+        self,
+        _: &'static str,
+        len: usize,
+    ) -> Result<TableSerializer, LuaSerdeError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<TableSerializer, LuaSerdeError> {
+        self.push_variant(variant)?;
+        self.push_table(0, len)?;
+        Ok(TableSerializer { lua: self.lua, next: 1, variant: true })
+    }
+}
+
+// Fills the table at the top of the stack. For variants, the table is stored in the table below
+// it, under the name of the variant that was pushed in between.
+struct TableSerializer {
+    lua: LuaContext,
+    // Index of the next element of a sequence.
+    next: i64,
+    variant: bool,
+}
+
+impl TableSerializer {
+    fn push_element<T>(&mut self, value: &T) -> Result<(), LuaSerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(ValueSerializer { lua: self.lua })?;
+        unsafe { ffi::lua_rawseti(self.lua.as_ptr(), -2, self.next as _) };
+        self.next += 1;
+        Ok(())
+    }
+
+    fn push_key<T>(&mut self, key: &T) -> Result<(), LuaSerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        key.serialize(ValueSerializer { lua: self.lua })?;
+
+        // Lua raises an error when storing a value under these keys.
+        let invalid = match LuaType::at(self.lua, -1) {
+            Some(LuaType::Nil) => true,
+            Some(LuaType::Number) => {
+                let n = unsafe { ffi::lua_tonumberx(self.lua.as_ptr(), -1, std::ptr::null_mut()) };
+                n.is_nan()
+            },
+            _ => false,
+        };
+        match invalid {
+            true => Err(ser::Error::custom("the key of a table can't be nil or NaN")),
+            false => Ok(()),
+        }
+    }
+
+    fn push_value<T>(&mut self, value: &T) -> Result<(), LuaSerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(ValueSerializer { lua: self.lua })?;
+        unsafe { ffi::lua_rawset(self.lua.as_ptr(), -3) };
+        Ok(())
+    }
+
+    fn end(self) -> Result<(), LuaSerdeError> {
+        if self.variant {
+            unsafe { ffi::lua_rawset(self.lua.as_ptr(), -3) };
+        }
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for TableSerializer {
+    type Ok = ();
+    type Error = LuaSerdeError;
+
+    #[inline]
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), LuaSerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push_element(value)
+    }
+
+    #[inline]
+    fn end(self) -> Result<(), LuaSerdeError> {
+        TableSerializer::end(self)
+    }
+}
+
+impl ser::SerializeTuple for TableSerializer {
+    type Ok = ();
+    type Error = LuaSerdeError;
+
+    #[inline]
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), LuaSerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push_element(value)
+    }
+
+    #[inline]
+    fn end(self) -> Result<(), LuaSerdeError> {
+        TableSerializer::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for TableSerializer {
+    type Ok = ();
+    type Error = LuaSerdeError;
+
+    #[inline]
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), LuaSerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push_element(value)
+    }
+
+    #[inline]
+    fn end(self) -> Result<(), LuaSerdeError> {
+        TableSerializer::end(self)
+    }
+}
+
+impl ser::SerializeTupleVariant for TableSerializer {
+    type Ok = ();
+    type Error = LuaSerdeError;
+
+    #[inline]
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), LuaSerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push_element(value)
+    }
+
+    #[inline]
+    fn end(self) -> Result<(), LuaSerdeError> {
+        TableSerializer::end(self)
+    }
+}
+
+impl ser::SerializeMap for TableSerializer {
+    type Ok = ();
+    type Error = LuaSerdeError;
+
+    #[inline]
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), LuaSerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push_key(key)
+    }
+
+    #[inline]
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), LuaSerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push_value(value)
+    }
+
+    #[inline]
+    fn end(self) -> Result<(), LuaSerdeError> {
+        TableSerializer::end(self)
+    }
+}
+
+impl ser::SerializeStruct for TableSerializer {
+    type Ok = ();
+    type Error = LuaSerdeError;
+
+    #[inline]
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), LuaSerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push_key(key)?;
+        self.push_value(value)
+    }
+
+    #[inline]
+    fn end(self) -> Result<(), LuaSerdeError> {
+        TableSerializer::end(self)
+    }
+}
+
+impl ser::SerializeStructVariant for TableSerializer {
+    type Ok = ();
+    type Error = LuaSerdeError;
+
+    #[inline]
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), LuaSerdeError>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.push_key(key)?;
+        self.push_value(value)
+    }
+
+    #[inline]
+    fn end(self) -> Result<(), LuaSerdeError> {
+        TableSerializer::end(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap};
+
+    use serde::{Deserialize, Serialize};
+
+    use crate::{AsLua, Lua, LuaError, LuaFunction, LuaSerdeError};
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    enum Shape {
+        Empty,
+        Circle { center: Point, radius: f64 },
+        Polygon(Vec<Point>),
+    }
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct Scene {
+        name: String,
+        visible: bool,
+        scale: Option<f32>,
+        shapes: Vec<Shape>,
+    }
+
+    #[test]
+    fn primitives() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("a = 5; b = 2.5; c = 'hello'; d = true; e = '5'").unwrap();
+
+        assert_eq!(lua.get_de::<i32, _>("a").unwrap(), 5);
+        assert_eq!(lua.get_de::<f64, _>("a").unwrap(), 5.0);
+        assert_eq!(lua.get_de::<f32, _>("b").unwrap(), 2.5);
+        assert_eq!(lua.get_de::<String, _>("c").unwrap(), "hello");
+        assert!(lua.get_de::<bool, _>("d").unwrap());
+        assert_eq!(lua.get_de::<Option<u8>, _>("a").unwrap(), Some(5));
+        assert_eq!(lua.get_de::<Option<u8>, _>("missing").unwrap(), None);
+        assert_eq!(lua.get_de::<(), _>("missing").unwrap(), ());
+
+        assert!(lua.get_de::<u8, _>("b").is_err());
+        assert!(lua.get_de::<i32, _>("e").is_err());
+        assert!(lua.get_de::<String, _>("a").is_err());
+    }
+
+    #[test]
+    fn integral_floats() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("x = 6 / 2; y = 2^3; z = 2.5").unwrap();
+
+        assert_eq!(lua.get_de::<u32, _>("x").unwrap(), 3);
+        assert_eq!(lua.get_de::<i32, _>("y").unwrap(), 8);
+        assert_eq!(lua.get_de::<f64, _>("x").unwrap(), 3.0);
+        assert!(lua.get_de::<u32, _>("z").is_err());
+    }
+
+    #[test]
+    fn negative_integers() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("a = -5; b = { x = -2, y = -2147483647 }").unwrap();
+
+        assert_eq!(lua.get_de::<i32, _>("a").unwrap(), -5);
+        assert_eq!(lua.get_de::<i64, _>("a").unwrap(), -5);
+        assert!(lua.get_de::<u32, _>("a").is_err());
+
+        let b: HashMap<String, i64> = lua.get_de("b").unwrap();
+        assert_eq!(b["x"], -2);
+        assert_eq!(b["y"], -2147483647);
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Tree(BTreeMap<String, Tree>);
+
+    #[derive(Debug, Deserialize)]
+    struct List(Vec<List>);
+
+    #[test]
+    fn deeply_nested_tables() {
+        let mut lua = Lua::new();
+        lua.execute::<()>(
+            r#"
+            tree = {}
+            list = {}
+            for i = 1, 127 do
+                tree = { child = tree }
+                list = { list }
+            end
+            deep = list
+            for i = 1, 20000 do
+                deep = { deep }
+            end
+        "#,
+        )
+        .unwrap();
+
+        let mut tree = &lua.get_de::<Tree, _>("tree").unwrap();
+        let mut depth = 0;
+        while let Some(child) = tree.0.get("child") {
+            tree = child;
+            depth += 1;
+        }
+        assert_eq!(depth, 127);
+
+        let mut list = &lua.get_de::<List, _>("list").unwrap();
+        let mut depth = 0;
+        while let Some(child) = list.0.first() {
+            list = child;
+            depth += 1;
+        }
+        assert_eq!(depth, 127);
+
+        // One more level is too many.
+        lua.execute::<()>("tree = { child = tree }; list = { list }").unwrap();
+        let errors = [
+            lua.get_de::<Tree, _>("tree").unwrap_err(),
+            lua.get_de::<List, _>("list").unwrap_err(),
+            lua.get_de::<List, _>("deep").unwrap_err(),
+        ];
+        for err in errors {
+            match err {
+                LuaSerdeError::Custom(msg) => assert!(msg.contains("nested"), "{}", msg),
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn cyclic_tables() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("c = {}; c.self = c; d = {}; d[1] = { d }; e = { a = {}, b = {} }")
+            .unwrap();
+        lua.execute::<()>("e.a.x = e.b").unwrap();
+
+        match lua.get_de::<Tree, _>("c") {
+            Err(LuaSerdeError::Custom(msg)) => assert!(msg.contains("itself"), "{}", msg),
+            other => panic!("{:?}", other),
+        }
+        match lua.get_de::<List, _>("d") {
+            Err(LuaSerdeError::Custom(msg)) => assert!(msg.contains("itself"), "{}", msg),
+            other => panic!("{:?}", other),
+        }
+
+        // A table that appears twice without containing itself isn't a cycle.
+        assert!(lua.get_de::<Tree, _>("e").is_ok());
+        assert_eq!(lua.stack_size(), 0);
+    }
+
+    #[test]
+    fn collections() {
+        let mut lua = Lua::new();
+        lua.execute::<()>(
+            "a = { 1, 2, 3 }; b = { x = 1, y = 2 }; c = {}; d = { [1] = 'a', [3] = 'c' }",
+        )
+        .unwrap();
+
+        assert_eq!(lua.get_de::<Vec<u8>, _>("a").unwrap(), [1, 2, 3]);
+        assert_eq!(lua.get_de::<(u8, u8, u8), _>("a").unwrap(), (1, 2, 3));
+        assert!(lua.get_de::<(u8, u8), _>("a").is_err());
+        assert_eq!(lua.get_de::<BTreeMap<u8, u8>, _>("a").unwrap().len(), 3);
+
+        let b: HashMap<String, i32> = lua.get_de("b").unwrap();
+        assert_eq!(b.len(), 2);
+        assert_eq!(b["x"], 1);
+        assert_eq!(lua.get_de::<Point, _>("b").unwrap(), Point { x: 1, y: 2 });
+
+        assert!(lua.get_de::<Vec<u8>, _>("c").unwrap().is_empty());
+        assert!(lua.get_de::<HashMap<String, u8>, _>("c").unwrap().is_empty());
+
+        let d: BTreeMap<i64, String> = lua.get_de("d").unwrap();
+        assert_eq!(d.keys().copied().collect::<Vec<_>>(), [1, 3]);
+
+        // The stack must be left untouched after reading tables, including on errors.
+        assert!(lua.get_de::<HashMap<String, String>, _>("b").is_err());
+        lua.execute::<()>("for i = 1, 100 do local _ = { i } end").unwrap();
+        assert_eq!(lua.get::<i32, _>("nothing"), None);
+    }
+
+    #[test]
+    fn nested_structs_and_enums() {
+        let mut lua = Lua::new();
+        lua.execute::<()>(
+            r#"
+            scene = {
+                name = "main",
+                visible = true,
+                shapes = {
+                    "Empty",
+                    { Circle = { center = { x = 1, y = 2 }, radius = 0.5 } },
+                    { Polygon = { { x = 0, y = 0 }, { x = 1, y = 0 }, { x = 0, y = 1 } } },
+                },
+            }
+        "#,
+        )
+        .unwrap();
+
+        let scene: Scene = lua.get_de("scene").unwrap();
+        assert_eq!(
+            scene,
+            Scene {
+                name: "main".to_owned(),
+                visible: true,
+                scale: None,
+                shapes: vec![
+                    Shape::Empty,
+                    Shape::Circle { center: Point { x: 1, y: 2 }, radius: 0.5 },
+                    Shape::Polygon(vec![
+                        Point { x: 0, y: 0 },
+                        Point { x: 1, y: 0 },
+                        Point { x: 0, y: 1 },
+                    ]),
+                ],
+            }
+        );
+
+        lua.execute::<()>("scene.shapes[1] = 'Square'").unwrap();
+        match lua.get_de::<Scene, _>("scene") {
+            Err(LuaSerdeError::Custom(msg)) => assert!(msg.contains("Square"), "{}", msg),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn call_de() {
+        let mut lua = Lua::with_libs(crate::LuaLibs::BASE);
+
+        {
+            let mut f = LuaFunction::load(&mut lua, "return { x = 3, y = 4 }").unwrap();
+            assert_eq!(f.call_de::<Point>().unwrap(), Point { x: 3, y: 4 });
+        }
+
+        let mut f = LuaFunction::load(&mut lua, "error('oops')").unwrap();
+        match f.call_de::<Point>() {
+            Err(LuaSerdeError::Lua(LuaError::ExecutionError(msg))) => assert!(msg.contains("oops")),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn set_ser() {
+        let mut lua = Lua::new();
+
+        lua.set_ser("a", &5u8).unwrap();
+        lua.set_ser("b", &[1.5, 2.5]).unwrap();
+        lua.set_ser("c", "hello").unwrap();
+        lua.set_ser("d", &Point { x: 1, y: -2 }).unwrap();
+        lua.set_ser("e", &Shape::Empty).unwrap();
+        lua.set_ser("f", &Some(true)).unwrap();
+        lua.set_ser("g", &None::<bool>).unwrap();
+        lua.set_ser("h", &u64::MAX).unwrap();
+
+        assert_eq!(lua.get::<u8, _>("a"), Some(5));
+        assert_eq!(lua.execute::<f64>("return b[1] + b[2]").unwrap(), 4.0);
+        assert_eq!(lua.execute::<i32>("return #b").unwrap(), 2);
+        assert_eq!(lua.get::<String, _>("c").unwrap(), "hello");
+        assert_eq!(lua.execute::<i32>("return d.x - d.y").unwrap(), 3);
+        assert_eq!(lua.get::<String, _>("e").unwrap(), "Empty");
+        assert_eq!(lua.get::<bool, _>("f"), Some(true));
+        assert_eq!(lua.type_of("g"), crate::LuaType::Nil);
+        assert_eq!(lua.get::<f64, _>("h"), Some(u64::MAX as f64));
+
+        let circle = Shape::Circle { center: Point { x: 0, y: 1 }, radius: 2.0 };
+        lua.set_ser("i", &circle).unwrap();
+        assert_eq!(lua.execute::<f64>("return i.Circle.radius").unwrap(), 2.0);
+
+        // Integers that don't fit in a Lua integer are pushed as floats.
+        lua.set_ser("j", &(1i64 << 40)).unwrap();
+        lua.set_ser("k", &-(1i64 << 40)).unwrap();
+        lua.set_ser("l", &(u32::MAX)).unwrap();
+        assert_eq!(lua.get::<f64, _>("j"), Some(2f64.powi(40)));
+        assert_eq!(lua.get::<f64, _>("k"), Some(-(2f64.powi(40))));
+        assert_eq!(lua.get::<f64, _>("l"), Some(f64::from(u32::MAX)));
+        assert_eq!(lua.get_de::<i64, _>("j").unwrap(), 1 << 40);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut lua = Lua::new();
+        let scene = Scene {
+            name: "main".to_owned(),
+            visible: false,
+            scale: Some(2.0),
+            shapes: vec![
+                Shape::Polygon(vec![Point { x: 0, y: 0 }, Point { x: 3, y: 4 }]),
+                Shape::Empty,
+                Shape::Circle { center: Point { x: 5, y: 5 }, radius: 1.0 },
+            ],
+        };
+
+        lua.set_ser("scene", &scene).unwrap();
+        assert_eq!(lua.get_de::<Scene, _>("scene").unwrap(), scene);
+
+        let mut map = BTreeMap::new();
+        map.insert(3, "three".to_owned());
+        map.insert(10, "ten".to_owned());
+        lua.set_ser("map", &map).unwrap();
+        assert_eq!(lua.execute::<String>("return map[10]").unwrap(), "ten");
+        assert_eq!(lua.get_de::<BTreeMap<i32, String>, _>("map").unwrap(), map);
+    }
+
+    #[test]
+    fn set_ser_errors() {
+        let mut lua = Lua::new();
+        lua.set("a", 5);
+
+        // The error happens after the first table has been built.
+        let values = vec![BTreeMap::from([(Some(1), 1)]), BTreeMap::from([(None, 2)])];
+        match lua.set_ser("a", &values) {
+            Err(LuaSerdeError::Custom(_)) => (),
+            other => panic!("{:?}", other),
+        }
+        assert!(lua.set_ser("a", &5i128).is_err());
+
+        assert_eq!(lua.get::<i32, _>("a"), Some(5));
+        assert_eq!(lua.stack_size(), 0);
+    }
+}
@@ -1,8 +1,11 @@
-use std::marker::PhantomData;
+use std::{borrow::Cow, collections::HashSet, error::Error, fmt, marker::PhantomData};
 
-use crate::LuaContext;
+use crate::{ffix, LuaContext};
 
-use crate::{AsLua, AsMutLua, LuaRead, Push, PushGuard, PushOne, Void};
+use crate::{
+    AnyLuaValue, AsLua, AsMutLua, LuaError, LuaFunctionCallError, LuaRead, LuaResults, Push,
+    PushGuard, PushOne, Void,
+};
 
 /// Represents a table stored in the Lua context.
 ///
@@ -34,7 +37,7 @@ impl<L> LuaTable<L> {
     // For example if you push one element over the table, call `offset(-1)` to know where the
     // table is.
     #[inline]
-    fn offset(&self, offset: i32) -> i32 {
+    pub(crate) fn offset(&self, offset: i32) -> i32 {
         if self.index >= 0 || self.index == ffi::LUA_REGISTRYINDEX {
             // If this table is the registry or was indexed from the bottom of the stack, its
             // current position will be unchanged.
@@ -79,6 +82,11 @@ where
             Err(lua)
         }
     }
+
+    #[inline]
+    fn expected() -> Cow<'static, str> {
+        Cow::Borrowed("table")
+    }
 }
 
 impl<'lua, L> LuaTable<L>
@@ -104,6 +112,184 @@ where
         }
     }
 
+    /// Iterates over all the key-value pairs of the table, whatever their types.
+    ///
+    /// Contrary to `iter`, this doesn't require all the keys and values to be of the same type.
+    /// Values that can't be represented by `AnyLuaValue`, such as functions, are returned as
+    /// `AnyLuaValue::LuaOther`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::AnyLuaValue;
+    ///
+    /// let mut lua = hlua::Lua::new();
+    /// lua.execute::<()>("a = { 5, foo = 'bar' }").unwrap();
+    ///
+    /// let mut table: hlua::LuaTable<_> = lua.get("a").unwrap();
+    /// let mut pairs: Vec<_> = table.pairs().collect();
+    /// pairs.sort_by_key(|(k, _)| matches!(k, AnyLuaValue::LuaString(_)));
+    ///
+    /// assert_eq!(pairs, [
+    ///     (AnyLuaValue::LuaNumber(1.0), AnyLuaValue::LuaNumber(5.0)),
+    ///     (AnyLuaValue::LuaString("foo".to_owned()), AnyLuaValue::LuaString("bar".to_owned())),
+    /// ]);
+    /// ```
+    #[inline]
+    pub fn pairs(&mut self) -> LuaTablePairs<'_, L> {
+        LuaTablePairs(self.iter())
+    }
+
+    /// Returns a number that identifies the table.
+    ///
+    /// Two `LuaTable`s have the same identity if and only if they refer to the same Lua table,
+    /// whatever their content. The identity stays the same for as long as the table is alive, but
+    /// may be reused once it has been garbage collected.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.execute::<()>("a = {}; b = a; c = {}").unwrap();
+    ///
+    /// let a = lua.get::<hlua::LuaTable<_>, _>("a").unwrap().identity();
+    /// let b = lua.get::<hlua::LuaTable<_>, _>("b").unwrap().identity();
+    /// let c = lua.get::<hlua::LuaTable<_>, _>("c").unwrap().identity();
+    /// assert_eq!(a, b);
+    /// assert_ne!(a, c);
+    /// ```
+    #[inline]
+    pub fn identity(&self) -> usize {
+        unsafe { ffi::lua_topointer(self.as_lua().as_ptr(), self.offset(0)) as usize }
+    }
+
+    /// Visits every entry of the table and of the tables it contains, depth first.
+    ///
+    /// `visitor` is called with the keys leading from this table to the entry, the last one being
+    /// the key of the entry itself, and with its value. The entries of a table are visited right
+    /// after the table. Each table is walked once, and the entries whose value is a table that has
+    /// already been walked, including this table, are visited as `WalkedValue::Visited`. Within a
+    /// table, the entries are visited in the order in which Lua iterates over them.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{AnyLuaValue, WalkedValue};
+    ///
+    /// let mut lua = hlua::Lua::new();
+    /// lua.execute::<()>("a = { b = { c = 5 } }; a.b.parent = a").unwrap();
+    ///
+    /// let mut table: hlua::LuaTable<_> = lua.get("a").unwrap();
+    /// let mut entries = Vec::new();
+    /// table.depth_first_walk(|path, value| {
+    ///     let keys: Vec<String> = path.iter().map(|key| match key {
+    ///         AnyLuaValue::LuaString(key) => key.clone(),
+    ///         _ => unreachable!(),
+    ///     }).collect();
+    ///     entries.push((keys.join("."), value));
+    /// });
+    /// entries.sort_by(|a, b| a.0.cmp(&b.0));
+    ///
+    /// assert_eq!(entries, [
+    ///     ("b".to_owned(), WalkedValue::Table),
+    ///     ("b.c".to_owned(), WalkedValue::Value(AnyLuaValue::LuaNumber(5.0))),
+    ///     ("b.parent".to_owned(), WalkedValue::Visited),
+    /// ]);
+    /// ```
+    pub fn depth_first_walk<F>(&mut self, mut visitor: F)
+    where
+        F: FnMut(&[AnyLuaValue], WalkedValue),
+    {
+        let lua = self.as_mut_lua();
+        unsafe {
+            ffi::lua_pushvalue(lua.as_ptr(), self.offset(0));
+            let top = ffi::lua_gettop(lua.as_ptr());
+            let mut visited = HashSet::new();
+            visited.insert(ffi::lua_topointer(lua.as_ptr(), top));
+            walk(lua, top, &mut Vec::new(), &mut visited, &mut visitor);
+            ffi::lua_pop(lua.as_ptr(), 1);
+        }
+    }
+
+    /// Reads the values at the consecutive indices starting from `1`, up to the length of the
+    /// table or the first `nil`.
+    ///
+    /// This is the same as reading the table as a `Vec<T>`, without having to read it again.
+    /// Returns `LuaError::WrongType` if one of the values can't be read as a `T`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.execute::<()>("a = { 9, 8, 7, name = 'numbers' }").unwrap();
+    ///
+    /// let mut table: hlua::LuaTable<_> = lua.get("a").unwrap();
+    /// assert_eq!(table.get_array::<i32>().unwrap(), [9, 8, 7]);
+    /// assert_eq!(table.get::<String, _, _>("name").unwrap(), "numbers");
+    /// assert!(table.get_array::<bool>().is_err());
+    /// ```
+    #[inline]
+    pub fn get_array<T>(&mut self) -> Result<Vec<T>, LuaError>
+    where
+        T: for<'a, 'b> LuaRead<&'a mut &'b mut LuaTable<L>>,
+    {
+        let index = self.offset(0);
+        Vec::lua_read_at_position(self, index).map_err(|_| LuaError::WrongType)
+    }
+
+    /// Estimates the number of elements of the array part and of the hash part of the table, in
+    /// the order expected by `lua_createtable`.
+    ///
+    /// No version of Lua exposes the actual sizes of the two parts, so this is a heuristic: the
+    /// array part is estimated as the length of the table, and the hash part as the number of
+    /// other entries. This is what to pass to `lua_createtable` to build a copy of the table
+    /// without reallocating. Counting the entries requires going through the whole table.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.execute::<()>("a = { 9, 8, 7, name = 'numbers', [10] = true }").unwrap();
+    ///
+    /// let mut table: hlua::LuaTable<_> = lua.get("a").unwrap();
+    /// assert_eq!(table.capacity_hint(), (3, 2));
+    /// ```
+    pub fn capacity_hint(&mut self) -> (usize, usize) {
+        let raw_lua = self.as_mut_lua();
+        let narr = unsafe { ffix::lua_rawlen(raw_lua, self.offset(0)) };
+        let mut nrec = 0;
+
+        unsafe {
+            ffi::lua_pushnil(raw_lua.as_ptr());
+            while ffi::lua_next(raw_lua.as_ptr(), self.offset(-1)) != 0 {
+                let in_array = ffi::lua_type(raw_lua.as_ptr(), -2) == ffi::LUA_TNUMBER && {
+                    let key = ffi::lua_tonumberx(raw_lua.as_ptr(), -2, std::ptr::null_mut());
+                    key.fract() == 0.0 && key >= 1.0 && key <= narr as f64
+                };
+                if !in_array {
+                    nrec += 1;
+                }
+                ffi::lua_pop(raw_lua.as_ptr(), 1);
+            }
+        }
+
+        (narr, nrec)
+    }
+
+    /// Returns a helper that reads the fields of the table by name or by position, for example
+    /// to convert the table into a struct in a `LuaRead` implementation. See `TableReader`.
+    #[inline]
+    pub fn reader(&mut self) -> TableReader<'_, L> {
+        TableReader { table: self, position: 1 }
+    }
+
+    /// Returns a helper that writes fields in the table by name, for example to fill the table
+    /// with the fields of a struct. See `TableWriter`.
+    #[inline]
+    pub fn writer(&mut self) -> TableWriter<'_, L> {
+        TableWriter { table: self }
+    }
+
     /// Loads a value in the table given its index.
     ///
     /// The index must implement the `PushOne` trait and the return type must implement the
@@ -173,6 +359,72 @@ where
         }
     }
 
+    /// Calls the method `name` of the table, like `table:name(args)` would in Lua.
+    ///
+    /// The method is looked up with `table[name]`, which goes through the `__index` metamethod,
+    /// then called with the table as first argument followed by `args`. Both happen in a
+    /// protected call, so an error raised by `__index` is returned like the errors of the method
+    /// itself. As with
+    /// `LuaFunction::call_with_args`, `args` can be a single value or a tuple, and only the first
+    /// return value is kept.
+    ///
+    /// Returns an error if the method doesn't exist or raises an error, if the return value
+    /// doesn't have the requested type, or if an argument couldn't be pushed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.execute::<()>(r#"
+    ///     account = { balance = 100 }
+    ///     function account:deposit(amount)
+    ///         self.balance = self.balance + amount
+    ///         return self.balance
+    ///     end
+    /// "#).unwrap();
+    ///
+    /// let mut account: hlua::LuaTable<_> = lua.get("account").unwrap();
+    /// let balance: i32 = account.call_method("deposit", 20).unwrap();
+    /// assert_eq!(balance, 120);
+    /// assert_eq!(account.get::<i32, _, _>("balance"), Some(120));
+    /// ```
+    #[inline]
+    pub fn call_method<'a, R, A, E>(
+        &'a mut self,
+        name: &str,
+        args: A,
+    ) -> Result<R, LuaFunctionCallError<E>>
+    where
+        R: LuaRead<PushGuard<&'a mut LuaTable<L>>>,
+        A: for<'r> Push<&'r mut LuaTable<L>, Err = E>,
+    {
+        let raw_lua = self.as_mut_lua();
+
+        unsafe {
+            let top = ffi::lua_gettop(raw_lua.as_ptr());
+
+            ffi::lua_pushcfunction(raw_lua.as_ptr(), Some(call_method_wrapper));
+            ffi::lua_pushvalue(raw_lua.as_ptr(), self.offset(-1));
+            name.push_no_err(&mut *self).assert_one_and_forget();
+
+            let num_pushed = match args.push_to_lua(self) {
+                Ok(g) => g.forget_internal(),
+                Err((err, _)) => {
+                    ffi::lua_pop(raw_lua.as_ptr(), 3);
+                    return Err(LuaFunctionCallError::PushError(err));
+                },
+            };
+
+            let pcall_return_value = ffi::lua_pcall(raw_lua.as_ptr(), num_pushed + 2, 1, 0);
+            let size = ffi::lua_gettop(raw_lua.as_ptr()) - top;
+            let guard = PushGuard { lua: self, size, raw_lua };
+
+            let results = LuaResults::from_pcall(pcall_return_value, guard)?;
+            LuaRead::lua_read(results.into_guard())
+                .map_err(|_| LuaFunctionCallError::LuaError(LuaError::WrongType))
+        }
+    }
+
     /// Inserts or modifies an elements of the table.
     ///
     /// Contrary to `checked_set`, can only be called when writing the key and value cannot fail
@@ -233,6 +485,45 @@ where
         }
     }
 
+    /// Removes an element of the table, and returns its previous value.
+    ///
+    /// The element is set to `nil` whether or not reading it as a `V` succeeds. Returns `None`
+    /// if the element didn't exist, or if its value isn't convertible to a `V`. Only values that
+    /// don't borrow the table, such as `String` or `AnyLuaValue`, can be returned.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.execute::<()>("a = { name = 'foo', size = 3 }").unwrap();
+    ///
+    /// let mut table: hlua::LuaTable<_> = lua.get("a").unwrap();
+    /// assert_eq!(table.remove::<String, _, _>("name"), Some("foo".to_owned()));
+    /// assert_eq!(table.remove::<String, _, _>("name"), None);
+    /// assert_eq!(table.get::<i32, _, _>("size"), Some(3));
+    /// ```
+    #[inline]
+    pub fn remove<V, K, E>(&mut self, key: K) -> Option<V>
+    where
+        V: for<'a> LuaRead<PushGuard<&'a mut LuaTable<L>>>,
+        K: for<'r> PushOne<&'r mut LuaTable<L>, Err = E>,
+        E: Into<Void>,
+    {
+        unsafe {
+            let raw_lua = self.as_mut_lua();
+
+            // The key is pushed once, and kept below the value for the call to `lua_settable`.
+            key.push_no_err(&mut *self).assert_one_and_forget();
+            ffi::lua_pushvalue(raw_lua.as_ptr(), -1);
+            ffi::lua_gettable(raw_lua.as_ptr(), self.offset(-2));
+            let value = V::lua_read(PushGuard { lua: &mut *self, size: 1, raw_lua }).ok();
+
+            ffi::lua_pushnil(raw_lua.as_ptr());
+            ffi::lua_settable(raw_lua.as_ptr(), self.offset(-2));
+            value
+        }
+    }
+
     /// Inserts an empty array, then loads it.
     #[inline]
     pub fn empty_array<'s, I, E>(&'s mut self, index: I) -> LuaTable<PushGuard<&'s mut LuaTable<L>>>
@@ -441,9 +732,501 @@ impl<'t, L, K, V> Drop for LuaTableIterator<'t, L, K, V> {
     }
 }
 
+/// Iterator that enumerates all the key-value pairs of a Lua table.
+///
+/// See `LuaTable::pairs` for more info.
+#[derive(Debug)]
+pub struct LuaTablePairs<'t, L: 't>(LuaTableIterator<'t, L, AnyLuaValue, AnyLuaValue>);
+
+impl<'t, 'lua, L> Iterator for LuaTablePairs<'t, L>
+where
+    L: AsMutLua<'lua> + 't,
+{
+    type Item = (AnyLuaValue, AnyLuaValue);
+
+    #[inline]
+    fn next(&mut self) -> Option<(AnyLuaValue, AnyLuaValue)> {
+        // Reading an `AnyLuaValue` never fails, so the inner iterator always yields `Some`.
+        self.0.next().map(|entry| entry.expect("reading an AnyLuaValue can't fail"))
+    }
+}
+
+/// Iterating over a `&mut LuaTable` is the same as calling `pairs`.
+///
+/// ```
+/// let mut lua = hlua::Lua::new();
+/// lua.execute::<()>("a = { 5, 6, 7 }").unwrap();
+///
+/// let mut table: hlua::LuaTable<_> = lua.get("a").unwrap();
+/// let mut count = 0;
+/// for (_key, _value) in &mut table {
+///     count += 1;
+/// }
+/// assert_eq!(count, 3);
+/// ```
+impl<'t, 'lua, L> IntoIterator for &'t mut LuaTable<L>
+where
+    L: AsMutLua<'lua> + 't,
+{
+    type Item = (AnyLuaValue, AnyLuaValue);
+    type IntoIter = LuaTablePairs<'t, L>;
+
+    #[inline]
+    fn into_iter(self) -> LuaTablePairs<'t, L> {
+        self.pairs()
+    }
+}
+
+/// Value of an entry visited by `LuaTable::depth_first_walk`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WalkedValue {
+    /// A value that isn't a table. Functions and userdata are `AnyLuaValue::LuaOther`.
+    Value(AnyLuaValue),
+    /// A table, whose entries are visited next.
+    Table,
+    /// A table that has already been walked, whose entries aren't visited again.
+    Visited,
+}
+
+// Walks the entries of the table at the absolute position `index`. `path` contains the keys
+// leading to this table, and `visited` the tables that have already been walked.
+unsafe fn walk(
+    lua: LuaContext,
+    index: i32,
+    path: &mut Vec<AnyLuaValue>,
+    visited: &mut HashSet<*const libc::c_void>,
+    visitor: &mut dyn FnMut(&[AnyLuaValue], WalkedValue),
+) {
+    // Each level of nesting pushes a key, a value, and a copy of the key.
+    if ffi::lua_checkstack(lua.as_ptr(), 3) == 0 {
+        return;
+    }
+
+    ffi::lua_pushnil(lua.as_ptr());
+    while ffi::lua_next(lua.as_ptr(), index) != 0 {
+        let top = ffi::lua_gettop(lua.as_ptr());
+        // Reading the key is done on a copy, so that strings read from numbers don't confuse
+        // `lua_next`.
+        ffi::lua_pushvalue(lua.as_ptr(), top - 1);
+        path.push(AnyLuaValue::lua_read_at_position(lua, top + 1).unwrap_or(AnyLuaValue::LuaOther));
+        ffi::lua_pop(lua.as_ptr(), 1);
+
+        if ffi::lua_istable(lua.as_ptr(), top) {
+            if visited.insert(ffi::lua_topointer(lua.as_ptr(), top)) {
+                visitor(path, WalkedValue::Table);
+                walk(lua, top, path, visited, visitor);
+            } else {
+                visitor(path, WalkedValue::Visited);
+            }
+        } else {
+            let value =
+                AnyLuaValue::lua_read_at_position(lua, top).unwrap_or(AnyLuaValue::LuaOther);
+            visitor(path, WalkedValue::Value(value));
+        }
+
+        path.pop();
+        ffi::lua_pop(lua.as_ptr(), 1);
+    }
+}
+
+/// Reads the fields of a table by name, as returned by `LuaTable::reader`.
+///
+/// Together with `TableWriter`, this allows mapping structs to tables by listing their fields.
+///
+/// # Example
+///
+/// ```
+/// use hlua::{AsMutLua, FieldError, LuaTable};
+///
+/// #[derive(Debug)]
+/// struct Server {
+///     host: String,
+///     port: u16,
+///     name: Option<String>,
+/// }
+///
+/// fn read_server<'lua, L>(table: &mut LuaTable<L>) -> Result<Server, FieldError>
+/// where
+///     L: AsMutLua<'lua>,
+/// {
+///     let mut reader = table.reader();
+///     Ok(Server {
+///         host: reader.field("host")?,
+///         port: reader.field("port")?,
+///         name: reader.field("name")?,
+///     })
+/// }
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.execute::<()>("server = { host = 'localhost', port = 8080 }").unwrap();
+///
+/// let mut table: LuaTable<_> = lua.get("server").unwrap();
+/// let server = read_server(&mut table).unwrap();
+/// assert_eq!((server.host.as_str(), server.port, server.name), ("localhost", 8080, None));
+///
+/// table.set("port", "http");
+/// assert_eq!(read_server(&mut table).unwrap_err().field(), "port");
+/// ```
+///
+/// # Example: positional records
+///
+/// Tables that are arrays of values, such as `{ 1.0, 2.0, 3.0 }`, can be read in order with
+/// `element`, here to implement `LuaRead` for a tuple struct:
+///
+/// ```
+/// use hlua::{AsMutLua, LuaRead, LuaTable};
+///
+/// #[derive(Debug, PartialEq)]
+/// struct Vec3(f32, f32, f32);
+///
+/// impl<'lua, L> LuaRead<L> for Vec3
+/// where
+///     L: AsMutLua<'lua>,
+/// {
+///     fn lua_read_at_position(lua: L, index: i32) -> Result<Vec3, L> {
+///         let mut table = LuaTable::lua_read_at_position(lua, index)?;
+///         let mut reader = table.reader();
+///         match (reader.element(), reader.element(), reader.element()) {
+///             (Ok(x), Ok(y), Ok(z)) => Ok(Vec3(x, y, z)),
+///             _ => Err(table.into_inner()),
+///         }
+///     }
+/// }
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.execute::<()>("a = { 1, 2, 3 }; b = { 1, 2 }").unwrap();
+/// assert_eq!(lua.get::<Vec3, _>("a"), Some(Vec3(1.0, 2.0, 3.0)));
+/// assert_eq!(lua.get::<Vec3, _>("b"), None);
+/// ```
+#[derive(Debug)]
+pub struct TableReader<'t, L: 't> {
+    table: &'t mut LuaTable<L>,
+    // Index of the value read by the next call to `element`.
+    position: i32,
+}
+
+impl<'t, 'lua, L> TableReader<'t, L>
+where
+    L: AsMutLua<'lua>,
+{
+    /// Reads the field `name` of the table.
+    ///
+    /// Returns an error if the field can't be read as a `T`, which includes the case where it is
+    /// missing unless `T` is an `Option`.
+    #[inline]
+    pub fn field<T>(&mut self, name: &str) -> Result<T, FieldError>
+    where
+        T: for<'a> LuaRead<PushGuard<&'a mut LuaTable<L>>>,
+    {
+        self.table.get(name).ok_or_else(|| FieldError { field: name.to_owned() })
+    }
+
+    /// Reads the value at the next position of the table, starting from `1`.
+    ///
+    /// Each call reads the index after the one read by the previous call, whether or not it
+    /// succeeded, so that the elements of a positional record can be read in order. Returns an
+    /// error if the value can't be read as a `T`, in which case `FieldError::field` returns the
+    /// index.
+    #[inline]
+    pub fn element<T>(&mut self) -> Result<T, FieldError>
+    where
+        T: for<'a> LuaRead<PushGuard<&'a mut LuaTable<L>>>,
+    {
+        let position = self.position;
+        self.position += 1;
+        self.table.get(position).ok_or_else(|| FieldError { field: position.to_string() })
+    }
+}
+
+/// Error returned by `TableReader::field` when a field can't be read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    field: String,
+}
+
+impl FieldError {
+    /// Returns the name of the field that couldn't be read.
+    #[inline]
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "field '{}' is missing or has the wrong type", self.field)
+    }
+}
+
+impl Error for FieldError {}
+
+/// Writes fields in a table by name, as returned by `LuaTable::writer`.
+///
+/// # Example
+///
+/// ```
+/// let mut lua = hlua::Lua::new();
+/// {
+///     let mut table = lua.empty_array("server");
+///     table.writer().field("host", "localhost").field("port", 8080);
+/// }
+/// let address: String = lua.execute("return server.host .. ':' .. server.port").unwrap();
+/// assert_eq!(address, "localhost:8080");
+/// ```
+#[derive(Debug)]
+pub struct TableWriter<'t, L: 't> {
+    table: &'t mut LuaTable<L>,
+}
+
+impl<'t, 'lua, L> TableWriter<'t, L>
+where
+    L: AsMutLua<'lua>,
+{
+    /// Sets the field `name` of the table to `value`. This is the same as `LuaTable::set`.
+    #[inline]
+    pub fn field<V, E>(&mut self, name: &str, value: V) -> &mut TableWriter<'t, L>
+    where
+        V: for<'r, 's> PushOne<&'r mut PushGuard<&'s mut LuaTable<L>>, Err = E>,
+        E: Into<Void>,
+    {
+        self.table.set(name, value);
+        self
+    }
+}
+
+impl<'lua, L> LuaTable<PushGuard<L>>
+where
+    L: AsMutLua<'lua>,
+{
+    /// Creates a new table filled with the pairs of keys and values of `pairs`, and loads it.
+    ///
+    /// The pairs are written in the order in which the iterator produces them, so a key that
+    /// appears multiple times ends up with its last value. Contrary to pushing a `HashMap`, the
+    /// pairs don't need to be collected first.
+    ///
+    /// The table stays on the stack of `lua`. To fill a table stored in a variable, call `extend`
+    /// on the table returned by `empty_array` instead.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{Lua, LuaTable};
+    ///
+    /// let mut lua = Lua::new();
+    /// let names = ["foo", "bar"];
+    /// let mut table = LuaTable::from_pairs(&mut lua, names.iter().map(|n| (*n, n.len() as u32)));
+    /// assert_eq!(table.get::<u32, _, _>("foo"), Some(3));
+    /// ```
+    #[inline]
+    pub fn from_pairs<I, K, V>(lua: L, pairs: I) -> LuaTable<PushGuard<L>>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        LuaTable<PushGuard<L>>: Extend<(K, V)>,
+    {
+        let mut lua = lua;
+        let raw_lua = lua.as_mut_lua();
+        let pairs = pairs.into_iter();
+        let nrec = pairs.size_hint().0.min(i32::MAX as usize) as i32;
+
+        unsafe { ffi::lua_createtable(raw_lua.as_ptr(), 0, nrec) };
+        let mut table = LuaTable { table: PushGuard { lua, size: 1, raw_lua }, index: -1 };
+        table.extend(pairs);
+        table
+    }
+}
+
+/// Writes the pairs of keys and values in the table, in order, like `LuaTable::set` does.
+///
+/// # Example
+///
+/// ```
+/// let mut lua = hlua::Lua::new();
+/// lua.empty_array("squares").extend((1..4).map(|n| (n, n * n)));
+///
+/// assert_eq!(lua.execute::<i32>("return #squares + squares[3]").unwrap(), 12);
+/// ```
+impl<'lua, L, K, V> Extend<(K, V)> for LuaTable<L>
+where
+    L: AsMutLua<'lua>,
+    K: for<'r> PushOne<&'r mut LuaTable<L>, Err = Void>,
+    V: for<'r, 's> PushOne<&'r mut PushGuard<&'s mut LuaTable<L>>, Err = Void>,
+{
+    #[inline]
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, pairs: I) {
+        for (key, value) in pairs {
+            self.set(key, value);
+        }
+    }
+}
+
+// Called in a protected call by `LuaTable::call_method` with the table, the name of the method
+// and the arguments. Looks up the method and calls it with the table followed by the arguments.
+extern "C" fn call_method_wrapper(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe {
+        let top = ffi::lua_gettop(lua);
+        if ffi::lua_checkstack(lua, top) == 0 {
+            ffi::lua_pushstring(lua, b"too many arguments\0".as_ptr().cast());
+            ffix::lua_error(lua);
+        }
+        ffi::lua_pushvalue(lua, 2);
+        ffi::lua_gettable(lua, 1);
+        ffi::lua_pushvalue(lua, 1);
+        for index in 3..=top {
+            ffi::lua_pushvalue(lua, index);
+        }
+        ffi::lua_call(lua, top - 1, 1);
+        1
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{function0, Lua, LuaTable, PushGuard};
+    use crate::{
+        function0, AnyLuaValue, AsLua, Lua, LuaError, LuaFunctionCallError, LuaTable, PushGuard,
+        WalkedValue,
+    };
+
+    #[test]
+    fn from_pairs_in_order() {
+        let mut lua = Lua::new();
+        let pairs = vec![("a", 1), ("b", 2), ("a", 3)];
+        let mut table = LuaTable::from_pairs(&mut lua, pairs);
+        assert_eq!(table.get::<i32, _, _>("a"), Some(3));
+        assert_eq!(table.get::<i32, _, _>("b"), Some(2));
+        assert_eq!(table.iter::<String, i32>().count(), 2);
+        drop(table);
+
+        lua.execute::<()>("t = { 'x' }").unwrap();
+        lua.get::<LuaTable<_>, _>("t").unwrap().extend(vec![(2, "y"), (3, "z")]);
+        assert_eq!(lua.execute::<String>("return t[1] .. t[2] .. t[3]").unwrap(), "xyz");
+    }
+
+    #[test]
+    fn get_array() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("a = { 'x', 'y', 'z' }; a[3] = nil; b = { 1, {} }; c = { k = 1 }")
+            .unwrap();
+
+        // `2` is the only border of `a`, so its length can't be anything else.
+        let mut table = lua.get::<LuaTable<_>, _>("a").unwrap();
+        assert_eq!(table.get_array::<String>().unwrap(), ["x", "y"]);
+        drop(table);
+
+        let mut table = lua.get::<LuaTable<_>, _>("b").unwrap();
+        assert!(matches!(table.get_array::<i32>(), Err(LuaError::WrongType)));
+        assert_eq!(table.get::<i32, _, _>(1), Some(1));
+        drop(table);
+
+        let mut table = lua.get::<LuaTable<_>, _>("c").unwrap();
+        assert!(table.get_array::<i32>().unwrap().is_empty());
+    }
+
+    #[test]
+    fn capacity_hint() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("a = {}; b = { 1, 2, x = 1, y = 2, [2.5] = 3, [-1] = 4 }").unwrap();
+
+        assert_eq!(lua.get::<LuaTable<_>, _>("a").unwrap().capacity_hint(), (0, 0));
+        assert_eq!(lua.get::<LuaTable<_>, _>("b").unwrap().capacity_hint(), (2, 4));
+        assert_eq!(lua.globals_table().capacity_hint(), (0, 2));
+    }
+
+    #[test]
+    fn call_method() {
+        let mut lua = Lua::with_libs(crate::LuaLibs::BASE);
+        lua.execute::<()>(
+            r#"
+            Counter = {}
+            Counter.__index = Counter
+            function Counter:add(a, b)
+                self.value = self.value + (a or 1) + (b or 0)
+                return self.value
+            end
+            function Counter:name() return "counter" end
+            counter = setmetatable({ value = 0 }, Counter)
+        "#,
+        )
+        .unwrap();
+
+        let mut counter: LuaTable<_> = lua.get("counter").unwrap();
+        assert_eq!(counter.call_method::<i32, _, _>("add", ()).unwrap(), 1);
+        assert_eq!(counter.call_method::<i32, _, _>("add", 5).unwrap(), 6);
+        assert_eq!(counter.call_method::<i32, _, _>("add", (2, 3)).unwrap(), 11);
+        assert_eq!(counter.call_method::<String, _, _>("name", ()).unwrap(), "counter");
+        assert_eq!(counter.get::<i32, _, _>("value"), Some(11));
+
+        match counter.call_method::<i32, _, _>("name", ()) {
+            Err(LuaFunctionCallError::LuaError(LuaError::WrongType)) => (),
+            other => panic!("{:?}", other),
+        }
+        match counter.call_method::<(), _, _>("missing", ()) {
+            Err(LuaFunctionCallError::LuaError(LuaError::ExecutionError(_))) => (),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(counter.call_method::<i32, _, _>("add", ()).unwrap(), 12);
+        drop(counter);
+
+        // Errors raised while looking up the method are caught.
+        lua.execute::<()>("guarded = setmetatable({}, { __index = function() error('boom') end })")
+            .unwrap();
+        let mut guarded: LuaTable<_> = lua.get("guarded").unwrap();
+        match guarded.call_method::<(), _, _>("anything", (1, 2)) {
+            Err(LuaFunctionCallError::LuaError(LuaError::ExecutionError(msg))) => {
+                assert!(msg.contains("boom"), "{}", msg)
+            },
+            other => panic!("{:?}", other),
+        }
+        drop(guarded);
+        assert_eq!(lua.stack_size(), 0);
+    }
+
+    #[test]
+    fn remove() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("a = { 10, 'x', key = true }").unwrap();
+
+        {
+            let mut table: LuaTable<_> = lua.get("a").unwrap();
+            assert_eq!(table.remove::<i32, _, _>(1), Some(10));
+            assert_eq!(table.remove::<i32, _, _>(2), None);
+            assert_eq!(
+                table.remove::<AnyLuaValue, _, _>("key"),
+                Some(AnyLuaValue::LuaBoolean(true))
+            );
+            assert_eq!(table.remove::<AnyLuaValue, _, _>("missing"), Some(AnyLuaValue::LuaNil));
+            assert_eq!(table.capacity_hint(), (0, 0));
+        }
+    }
+
+    #[test]
+    fn read_and_write_fields() {
+        let mut lua = Lua::new();
+        {
+            let mut table = lua.empty_array("point");
+            table.writer().field("x", 3).field("y", -1).field("label", "origin");
+        }
+
+        let mut table = lua.get::<LuaTable<_>, _>("point").unwrap();
+        let mut reader = table.reader();
+        assert_eq!(reader.field::<i32>("x"), Ok(3));
+        assert_eq!(reader.field::<i32>("y"), Ok(-1));
+        assert_eq!(reader.field::<Option<i32>>("z"), Ok(None));
+
+        let err = reader.field::<i32>("label").unwrap_err();
+        assert_eq!(err.field(), "label");
+        assert_eq!(err.to_string(), "field 'label' is missing or has the wrong type");
+        assert!(reader.field::<i32>("z").is_err());
+        drop(table);
+
+        lua.execute::<()>("record = { 4, 'five', true, x = 1 }").unwrap();
+        let mut table = lua.get::<LuaTable<_>, _>("record").unwrap();
+        let mut reader = table.reader();
+        assert_eq!(reader.element::<i32>(), Ok(4));
+        assert_eq!(reader.element::<i32>().unwrap_err().field(), "2");
+        assert_eq!(reader.element::<bool>(), Ok(true));
+        assert_eq!(reader.element::<Option<i32>>(), Ok(None));
+        assert_eq!(reader.field::<i32>("x"), Ok(1));
+    }
 
     #[test]
     fn iterable() {
@@ -478,6 +1261,66 @@ mod tests {
         }
     }
 
+    #[test]
+    fn for_loop_over_table() {
+        let mut lua = Lua::new();
+
+        lua.execute::<()>("a = { x = 1, y = 'two' }").unwrap();
+
+        let mut table = lua.get::<LuaTable<_>, _>("a").unwrap();
+        let mut keys = Vec::new();
+        for (key, value) in &mut table {
+            match (key, value) {
+                (AnyLuaValue::LuaString(k), AnyLuaValue::LuaNumber(v)) if k == "x" => {
+                    assert_eq!(v, 1.0);
+                    keys.push(k);
+                },
+                (AnyLuaValue::LuaString(k), AnyLuaValue::LuaString(v)) if k == "y" => {
+                    assert_eq!(v, "two");
+                    keys.push(k);
+                },
+                entry => panic!("unexpected entry: {:?}", entry),
+            }
+        }
+        keys.sort();
+        assert_eq!(keys, ["x", "y"]);
+
+        // The table can still be used after the loop.
+        assert_eq!(table.get::<i32, _, _>("x"), Some(1));
+    }
+
+    #[test]
+    fn pairs_mixed_types() {
+        let mut lua = Lua::new();
+
+        lua.execute::<()>("a = { 1, 'two', [true] = 3, x = { 4 }, f = function() end }").unwrap();
+
+        let mut table = lua.get::<LuaTable<_>, _>("a").unwrap();
+        let mut pairs: Vec<_> = table.pairs().collect();
+        pairs.sort_by(|a, b| format!("{:?}", a.0).cmp(&format!("{:?}", b.0)));
+
+        assert_eq!(
+            pairs,
+            [
+                (AnyLuaValue::LuaBoolean(true), AnyLuaValue::LuaNumber(3.0)),
+                (AnyLuaValue::LuaNumber(1.0), AnyLuaValue::LuaNumber(1.0)),
+                (AnyLuaValue::LuaNumber(2.0), AnyLuaValue::LuaString("two".to_owned())),
+                (AnyLuaValue::LuaString("f".to_owned()), AnyLuaValue::LuaOther),
+                (
+                    AnyLuaValue::LuaString("x".to_owned()),
+                    AnyLuaValue::LuaArray(vec![(
+                        AnyLuaValue::LuaNumber(1.0),
+                        AnyLuaValue::LuaNumber(4.0)
+                    )])
+                ),
+            ]
+        );
+
+        // Stopping early must leave the stack usable.
+        assert_eq!(table.pairs().take(1).count(), 1);
+        assert_eq!(table.get::<String, _, _>(2).unwrap(), "two");
+    }
+
     #[test]
     fn get_set() {
         let mut lua = Lua::new();
@@ -596,4 +1439,46 @@ mod tests {
         let mut metatable = registry.get_or_create_metatable();
         metatable.set(3, "hello");
     }
+
+    #[test]
+    fn depth_first_walk() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("a = { 1, { 2, 3 }, x = { y = {} } }; a.x.y.root = a; a[3] = a[2]")
+            .unwrap();
+
+        let mut table: LuaTable<_> = lua.get("a").unwrap();
+        let mut entries = Vec::new();
+        table.depth_first_walk(|path, value| entries.push((path.to_vec(), value)));
+
+        let find = |path: &[AnyLuaValue]| {
+            let entry = entries.iter().find(|(p, _)| p == path);
+            entry.map(|(_, value)| value.clone())
+        };
+        let num = AnyLuaValue::LuaNumber;
+        let string = |s: &str| AnyLuaValue::LuaString(s.to_owned());
+
+        assert_eq!(entries.len(), 8);
+        assert_eq!(find(&[num(1.0)]), Some(WalkedValue::Value(num(1.0))));
+        assert_eq!(find(&[string("x"), string("y")]), Some(WalkedValue::Table));
+        assert_eq!(find(&[string("x"), string("y"), string("root")]), Some(WalkedValue::Visited));
+        // The same table is walked once, under whichever key is reached first.
+        let walked = [find(&[num(2.0)]), find(&[num(3.0)])];
+        assert!(walked.contains(&Some(WalkedValue::Table)));
+        assert!(walked.contains(&Some(WalkedValue::Visited)));
+        assert_eq!(entries.iter().filter(|(_, value)| *value == WalkedValue::Table).count(), 3);
+
+        // The stack is left as it was.
+        table.set("z", 5);
+        assert_eq!(table.get::<i32, _, _>("z"), Some(5));
+    }
+
+    #[test]
+    fn depth_first_walk_registry() {
+        let mut lua = Lua::new();
+
+        let mut registry = LuaTable::registry(&mut lua);
+        let mut tables = 0;
+        registry.depth_first_walk(|_, value| tables += (value == WalkedValue::Table) as u32);
+        assert!(tables > 0);
+    }
 }
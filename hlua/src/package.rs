@@ -0,0 +1,243 @@
+use std::{
+    ffi::CString,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+};
+
+use crate::{
+    ffix, panic_handler, push_userdata, LuaContext, LuaError, LuaRead, Push, ScriptFailure,
+    UserdataOnStack,
+};
+
+// Key of the searcher installed by `set_searcher` in the registry.
+const REGISTRY_KEY: &str = "hlua_searcher";
+
+// Name of the table of searchers in the `package` table.
+#[cfg(feature = "_luaapi_51")]
+const SEARCHERS: &str = "loaders";
+#[cfg(not(feature = "_luaapi_51"))]
+const SEARCHERS: &str = "searchers";
+
+pub(crate) type SearcherFn = Box<dyn FnMut(&str) -> Option<Vec<u8>> + Send>;
+
+struct Searcher(SearcherFn);
+
+/// Appends the templates for `dir` to `package.path`.
+pub(crate) fn add_path(lua: LuaContext, dir: &Path) -> Result<(), LuaError> {
+    // Both characters have a special meaning in `package.path`.
+    if dir.to_str().map_or(true, |dir| dir.contains(';') || dir.contains('?')) {
+        let msg = format!("{} can't be added to package.path", dir.display());
+        return Err(LuaError::ExecutionError(msg));
+    }
+    let templates =
+        format!("{};{}", dir.join("?.lua").display(), dir.join("?").join("init.lua").display());
+
+    let raw_lua = lua.as_ptr();
+    unsafe {
+        if !push_package_field(raw_lua, "path") {
+            return Err(package_not_open());
+        }
+
+        let path: Option<String> = LuaRead::lua_read_at_position(lua, -1).ok();
+        let path = match path.as_deref() {
+            Some("") | None => templates,
+            Some(path) => format!("{};{}", path, templates),
+        };
+        ffi::lua_pop(raw_lua, 1);
+
+        "path".push_no_err(lua).forget_internal();
+        path.push_no_err(lua).forget_internal();
+        ffi::lua_rawset(raw_lua, -3);
+        ffi::lua_pop(raw_lua, 1);
+    }
+
+    Ok(())
+}
+
+/// Inserts `searcher` in `package.searchers` right after the searcher of `package.preload`,
+/// removing the one installed by a previous call.
+pub(crate) fn set_searcher(lua: LuaContext, searcher: SearcherFn) -> Result<(), LuaError> {
+    let raw_lua = lua.as_ptr();
+
+    unsafe {
+        if !push_package_field(raw_lua, SEARCHERS) {
+            return Err(package_not_open());
+        }
+        if !ffi::lua_istable(raw_lua, -1) {
+            ffi::lua_pop(raw_lua, 2);
+            return Err(package_not_open());
+        }
+        let searchers = ffi::lua_gettop(raw_lua);
+        let mut len = ffix::lua_rawlen(lua, searchers) as libc::c_int;
+
+        // Removing the previous searcher.
+        REGISTRY_KEY.push_no_err(lua).forget_internal();
+        ffi::lua_rawget(raw_lua, ffi::LUA_REGISTRYINDEX);
+        for index in 1..=len {
+            ffi::lua_rawgeti(raw_lua, searchers, index as _);
+            let found = ffi::lua_rawequal(raw_lua, -1, -2) != 0;
+            ffi::lua_pop(raw_lua, 1);
+
+            if found {
+                for next in index + 1..=len {
+                    ffi::lua_rawgeti(raw_lua, searchers, next as _);
+                    ffi::lua_rawseti(raw_lua, searchers, (next - 1) as _);
+                }
+                ffi::lua_pushnil(raw_lua);
+                ffi::lua_rawseti(raw_lua, searchers, len as _);
+                len -= 1;
+                break;
+            }
+        }
+        ffi::lua_pop(raw_lua, 1);
+
+        let position = len.min(1) + 1;
+        for index in (position..=len).rev() {
+            ffi::lua_rawgeti(raw_lua, searchers, index as _);
+            ffi::lua_rawseti(raw_lua, searchers, (index + 1) as _);
+        }
+
+        push_userdata(Searcher(searcher), lua, |_| {}).forget_internal();
+        ffi::lua_pushcclosure(raw_lua, Some(searcher_wrapper), 1);
+
+        REGISTRY_KEY.push_no_err(lua).forget_internal();
+        ffi::lua_pushvalue(raw_lua, -2);
+        ffi::lua_rawset(raw_lua, ffi::LUA_REGISTRYINDEX);
+
+        ffi::lua_rawseti(raw_lua, searchers, position as _);
+        ffi::lua_pop(raw_lua, 2);
+    }
+
+    Ok(())
+}
+
+#[cold]
+fn package_not_open() -> LuaError {
+    LuaError::ExecutionError("the package library isn't open".to_owned())
+}
+
+// Pushes the `package` table and its field `name`. Returns false and pushes nothing if `package`
+// isn't a table.
+unsafe fn push_package_field(raw_lua: *mut ffi::lua_State, name: &str) -> bool {
+    ffi::lua_getglobal(raw_lua, b"package\0".as_ptr().cast());
+    if !ffi::lua_istable(raw_lua, -1) {
+        ffi::lua_pop(raw_lua, 1);
+        return false;
+    }
+    ffi::lua_pushlstring(raw_lua, name.as_ptr().cast(), name.len());
+    ffi::lua_rawget(raw_lua, -2);
+    true
+}
+
+// Called by `require` with the name of the module.
+extern "C" fn searcher_wrapper(lua: *mut ffi::lua_State) -> libc::c_int {
+    let raw_lua = unsafe { LuaContext::new_unchecked(lua) };
+
+    // `lua_error` doesn't return, so everything has to be dropped before calling it.
+    let msg = match search(raw_lua) {
+        Ok(num_pushed) => return num_pushed,
+        Err(msg) => msg,
+    };
+    msg.as_str().push_no_err(raw_lua).forget_internal();
+    drop(msg);
+    unsafe { ffix::lua_error(lua) };
+}
+
+// Pushes the loader of the module, or the reason why it wasn't found. Returns the message of the
+// error to raise if it can't be loaded.
+fn search(lua: LuaContext) -> Result<libc::c_int, String> {
+    let raw_lua = lua.as_ptr();
+    let name: String = LuaRead::lua_read_at_position(lua, 1).ok().unwrap_or_default();
+
+    let code =
+        match UserdataOnStack::<Searcher, _>::lua_read_at_position(lua, ffi::lua_upvalueindex(1)) {
+            Ok(mut searcher) => panic::catch_unwind(AssertUnwindSafe(|| (searcher.0)(&name))),
+            Err(_) => Ok(None),
+        };
+
+    let code = match code {
+        Ok(Some(code)) => code,
+        Ok(None) => {
+            // Lua 5.4 separates the messages of the searchers by itself.
+            let prefix = if cfg!(feature = "_luaapi_54") { "" } else { "\n\t" };
+            format!("{}no module '{}' in the host searcher", prefix, name)
+                .push_no_err(lua)
+                .forget_internal();
+            return Ok(1);
+        },
+        Err(payload) => {
+            let msg = panic_handler::panic_message(&*payload);
+            panic_handler::notify(lua, ScriptFailure::CallbackPanic(msg));
+            return Err(format!("rust searcher panicked: {}", msg));
+        },
+    };
+
+    let chunkname = CString::new(format!("={}", name.replace('\0', ""))).unwrap();
+    let (buf, len) = (code.as_ptr().cast(), code.len() as libc::size_t);
+    let status = unsafe {
+        match () {
+            #[cfg(feature = "_luaapi_51")]
+            () => ffi::luaL_loadbuffer(raw_lua, buf, len, chunkname.as_ptr()),
+            #[cfg(any(feature = "_luaapi_52", feature = "_luaapi_54"))]
+            () => ffi::luaL_loadbufferx(raw_lua, buf, len, chunkname.as_ptr(), std::ptr::null()),
+        }
+    };
+
+    if status == 0 {
+        return Ok(1);
+    }
+
+    let error: String = LuaRead::lua_read_at_position(lua, -1).ok().unwrap_or_default();
+    unsafe { ffi::lua_pop(raw_lua, 1) };
+    Err(format!("error loading module '{}' from the host searcher:\n\t{}", name, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{Lua, LuaError};
+
+    #[test]
+    fn searcher_provides_modules() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+
+        lua.set_searcher(|name| (name == "a").then_some("return 1")).unwrap();
+        lua.set_searcher(|name| (name == "b").then_some("return 2")).unwrap();
+
+        assert_eq!(lua.execute::<i32>("return require 'b'").unwrap(), 2);
+        // The first searcher has been replaced.
+        match lua.execute::<i32>("return require 'a'") {
+            Err(LuaError::ExecutionError(msg)) => {
+                assert!(msg.contains("no module 'a' in the host searcher"))
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        lua.set_searcher(|_| Some("this is not lua")).unwrap();
+        match lua.execute::<()>("require 'c'") {
+            Err(LuaError::ExecutionError(msg)) => assert!(msg.contains("error loading module 'c'")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn package_path() {
+        let dir = std::env::temp_dir().join(format!("hlua-package-path-{}", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        fs::write(dir.join("flat.lua"), "return 'flat'").unwrap();
+        fs::write(dir.join("nested").join("init.lua"), "return 'nested'").unwrap();
+
+        let mut lua = Lua::new();
+        assert!(lua.add_package_path(&dir).is_err());
+        lua.openlibs();
+        lua.add_package_path(&dir).unwrap();
+        assert!(lua.add_package_path("a;b").is_err());
+
+        let loaded: String = lua.execute("return require('flat') .. require('nested')").unwrap();
+        assert_eq!(loaded, "flatnested");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,58 @@
+use std::any::Any;
+
+use crate::{push_userdata, LuaContext, LuaError, LuaRead, Push, PushGuard, UserdataOnStack};
+
+// Key of the handler in the registry.
+const REGISTRY_KEY: &str = "hlua_panic_handler";
+
+/// Failure reported to the handler registered with `Lua::set_panic_handler`.
+#[derive(Debug)]
+pub enum ScriptFailure<'a> {
+    /// A Rust callback panicked. The panic has been turned into a Lua error, which the script can
+    /// catch with `pcall`.
+    CallbackPanic(&'a str),
+    /// An error was returned by `Lua::execute`, `Lua::execute_from_reader` or `Lua::do_file`.
+    Error(&'a LuaError),
+}
+
+struct PanicHandler(Box<dyn FnMut(ScriptFailure) + Send>);
+
+/// Stores `handler` in the registry of the Lua context, replacing the previous one if any.
+pub(crate) fn set(lua: LuaContext, handler: Box<dyn FnMut(ScriptFailure) + Send>) {
+    unsafe {
+        REGISTRY_KEY.push_no_err(lua).forget();
+        push_userdata(PanicHandler(handler), lua, |_| {}).forget();
+        ffi::lua_rawset(lua.as_ptr(), ffi::LUA_REGISTRYINDEX);
+    }
+}
+
+/// Calls the handler stored in the registry of the Lua context, if any.
+pub(crate) fn notify(lua: LuaContext, failure: ScriptFailure) {
+    unsafe {
+        REGISTRY_KEY.push_no_err(lua).forget();
+        ffi::lua_rawget(lua.as_ptr(), ffi::LUA_REGISTRYINDEX);
+    }
+
+    let guard = unsafe { PushGuard::new(lua, 1) };
+    if let Ok(mut handler) = UserdataOnStack::<PanicHandler, _>::lua_read(guard) {
+        (handler.0)(failure);
+    }
+}
+
+/// Calls the handler with `err`, and returns it.
+#[inline]
+pub(crate) fn report(lua: LuaContext, err: LuaError) -> LuaError {
+    notify(lua, ScriptFailure::Error(&err));
+    err
+}
+
+/// Returns the message of a panic caught by `catch_unwind`.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&'static str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg
+    } else {
+        "Box<dyn Any>"
+    }
+}
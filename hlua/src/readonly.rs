@@ -0,0 +1,214 @@
+use crate::{ffix, LuaContext, LuaRead, Push};
+
+// Key of the table of read-only globals in the registry.
+const REGISTRY_KEY: &str = "hlua_readonly_globals";
+
+/// Pops the value at the top of the stack and makes it the read-only global `name`.
+///
+/// Read-only globals are stored in a separate table, which is the `__index` of the metatable of
+/// the table of globals. The `__newindex` of that metatable refuses to assign them.
+pub(crate) fn set(lua: LuaContext, name: &str) {
+    let raw_lua = lua.as_ptr();
+
+    unsafe {
+        push_table(lua);
+        name.push_no_err(lua).forget_internal();
+        ffi::lua_pushvalue(raw_lua, -3);
+        ffi::lua_rawset(raw_lua, -3);
+        ffi::lua_pop(raw_lua, 2);
+
+        // A regular global with the same name would hide the read-only one.
+        ffix::lua_pushglobaltable(lua);
+        name.push_no_err(lua).forget_internal();
+        ffi::lua_pushnil(raw_lua);
+        ffi::lua_rawset(raw_lua, -3);
+        ffi::lua_pop(raw_lua, 1);
+    }
+}
+
+/// Same as `lua_settable(lua, -3)` when the table of globals is at `-3`, except that read-only
+/// globals are replaced instead of raising an error.
+pub(crate) unsafe fn set_global(lua: LuaContext) {
+    let raw_lua = lua.as_ptr();
+
+    // Globals can only be read-only if their table has a metatable.
+    if ffi::lua_getmetatable(raw_lua, -3) != 0 {
+        ffi::lua_pop(raw_lua, 1);
+        REGISTRY_KEY.push_no_err(lua).forget_internal();
+        ffi::lua_rawget(raw_lua, ffi::LUA_REGISTRYINDEX);
+
+        if ffi::lua_istable(raw_lua, -1) {
+            ffi::lua_pushvalue(raw_lua, -3);
+            ffi::lua_rawget(raw_lua, -2);
+            let readonly = !ffi::lua_isnil(raw_lua, -1);
+            ffi::lua_pop(raw_lua, 1);
+
+            if readonly {
+                ffi::lua_pushvalue(raw_lua, -3);
+                ffi::lua_pushvalue(raw_lua, -3);
+                ffi::lua_rawset(raw_lua, -3);
+                ffi::lua_pop(raw_lua, 3);
+                return;
+            }
+        }
+        ffi::lua_pop(raw_lua, 1);
+    }
+
+    ffi::lua_settable(raw_lua, -3);
+}
+
+/// Forgets the table of read-only globals, after the table of globals has been replaced. The
+/// next read-only global installs the metatable on the new table of globals.
+pub(crate) fn reset(lua: LuaContext) {
+    unsafe {
+        REGISTRY_KEY.push_no_err(lua).forget_internal();
+        ffi::lua_pushnil(lua.as_ptr());
+        ffi::lua_rawset(lua.as_ptr(), ffi::LUA_REGISTRYINDEX);
+    }
+}
+
+/// Pushes the table that holds the global `name`: the table of read-only globals if `name` is one
+/// of them, the table of globals otherwise. Doesn't call any metamethod.
+pub(crate) unsafe fn push_owner(lua: LuaContext, name: &str) {
+    let raw_lua = lua.as_ptr();
+
+    ffix::lua_pushglobaltable(lua);
+    if ffi::lua_getmetatable(raw_lua, -1) == 0 {
+        return;
+    }
+    ffi::lua_pop(raw_lua, 2);
+
+    REGISTRY_KEY.push_no_err(lua).forget_internal();
+    ffi::lua_rawget(raw_lua, ffi::LUA_REGISTRYINDEX);
+    if ffi::lua_istable(raw_lua, -1) {
+        name.push_no_err(lua).forget_internal();
+        ffi::lua_rawget(raw_lua, -2);
+        let readonly = !ffi::lua_isnil(raw_lua, -1);
+        ffi::lua_pop(raw_lua, 1);
+        if readonly {
+            return;
+        }
+    }
+    ffi::lua_pop(raw_lua, 1);
+    ffix::lua_pushglobaltable(lua);
+}
+
+// Pushes the table of read-only globals, creating it and installing the metatable of the table
+// of globals if needed.
+unsafe fn push_table(lua: LuaContext) {
+    let raw_lua = lua.as_ptr();
+
+    REGISTRY_KEY.push_no_err(lua).forget_internal();
+    ffi::lua_rawget(raw_lua, ffi::LUA_REGISTRYINDEX);
+    if ffi::lua_istable(raw_lua, -1) {
+        return;
+    }
+    ffi::lua_pop(raw_lua, 1);
+
+    ffi::lua_newtable(raw_lua);
+    REGISTRY_KEY.push_no_err(lua).forget_internal();
+    ffi::lua_pushvalue(raw_lua, -2);
+    ffi::lua_rawset(raw_lua, ffi::LUA_REGISTRYINDEX);
+
+    ffix::lua_pushglobaltable(lua);
+    if ffi::lua_getmetatable(raw_lua, -1) == 0 {
+        ffi::lua_newtable(raw_lua);
+        ffi::lua_pushvalue(raw_lua, -1);
+        ffi::lua_setmetatable(raw_lua, -3);
+    }
+
+    "__index".push_no_err(lua).forget_internal();
+    ffi::lua_pushvalue(raw_lua, -4);
+    ffi::lua_rawset(raw_lua, -3);
+
+    "__newindex".push_no_err(lua).forget_internal();
+    ffi::lua_pushvalue(raw_lua, -4);
+    ffi::lua_pushcclosure(raw_lua, Some(newindex_wrapper), 1);
+    ffi::lua_rawset(raw_lua, -3);
+
+    ffi::lua_pop(raw_lua, 2);
+}
+
+// Called by Lua with the table of globals, the key and the value when a global that doesn't
+// exist in the table is assigned.
+extern "C" fn newindex_wrapper(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe {
+        ffi::lua_pushvalue(lua, 2);
+        ffi::lua_rawget(lua, ffi::lua_upvalueindex(1));
+        let readonly = !ffi::lua_isnil(lua, -1);
+        ffi::lua_pop(lua, 1);
+
+        if !readonly {
+            ffi::lua_settop(lua, 3);
+            ffi::lua_rawset(lua, 1);
+            return 0;
+        }
+
+        // `lua_error` doesn't return, so the message has to be dropped before calling it.
+        let raw_lua = LuaContext::new_unchecked(lua);
+        let name: Option<String> = LuaRead::lua_read_at_position(raw_lua, 2).ok();
+        ffi::luaL_where(lua, 1);
+        let msg = format!("attempt to modify read-only global '{}'", name.unwrap_or_default());
+        msg.as_str().push_no_err(raw_lua).forget_internal();
+        drop(msg);
+        ffi::lua_concat(lua, 2);
+        ffix::lua_error(lua);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{function0, AsLua, InsideCallback, Lua, LuaError, LuaLibs};
+
+    #[test]
+    fn readonly_globals() {
+        let mut lua = Lua::with_libs(LuaLibs::BASE);
+        lua.set("MAX_PLAYERS", 2);
+        lua.set_readonly("MAX_PLAYERS", 8);
+        lua.set_readonly("NAME", "server");
+
+        let read: i32 = lua.execute("return MAX_PLAYERS").unwrap();
+        assert_eq!(read, 8);
+        assert_eq!(lua.get::<String, _>("NAME").unwrap(), "server");
+
+        for code in ["MAX_PLAYERS = 10", "_G.NAME = nil"] {
+            match lua.execute::<()>(code) {
+                Err(LuaError::ExecutionError(msg)) => {
+                    assert!(msg.contains("attempt to modify read-only global"), "{}", msg)
+                },
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+        assert_eq!(lua.get::<i32, _>("MAX_PLAYERS"), Some(8));
+
+        // Other globals can still be modified.
+        lua.execute::<()>("count = 1; count = count + 1").unwrap();
+        assert_eq!(lua.get::<i32, _>("count"), Some(2));
+
+        // The host can replace read-only values.
+        lua.set("MAX_PLAYERS", 16);
+        assert_eq!(lua.execute::<i32>("return MAX_PLAYERS").unwrap(), 16);
+        assert!(lua.execute::<()>("MAX_PLAYERS = 1").is_err());
+    }
+
+    #[test]
+    fn empty_array_replaces_readonly() {
+        let mut lua = Lua::with_libs(LuaLibs::BASE);
+        lua.set_readonly("config", 1);
+
+        lua.empty_array("config").set("debug", true);
+        assert!(lua.execute::<bool>("return config.debug").unwrap());
+        assert!(lua.execute::<()>("config = 2").is_err());
+    }
+
+    #[test]
+    fn callback_replaces_readonly() {
+        let mut lua = Lua::with_libs(LuaLibs::BASE);
+        lua.set_readonly("LEVEL", 1);
+        lua.set("raise", function0(|| unsafe { InsideCallback::with(|lua| lua.set("LEVEL", 5)) }));
+
+        lua.execute::<()>("raise()").unwrap();
+        assert_eq!(lua.execute::<i32>("return LEVEL").unwrap(), 5);
+        assert!(lua.execute::<()>("LEVEL = 2").is_err());
+    }
+}
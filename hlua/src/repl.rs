@@ -0,0 +1,78 @@
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, Write},
+};
+
+use crate::{AnyLuaValue, Lua, LuaError};
+
+/// Reads lines from `input` and runs them with `Lua::eval_line`, until the end of `input`.
+pub(crate) fn run<R, W>(lua: &mut Lua<'_>, input: R, mut output: W) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut lines = input.lines();
+    let mut chunk = String::new();
+
+    loop {
+        output.write_all(if chunk.is_empty() { b"> " } else { b">> " })?;
+        output.flush()?;
+
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        if !chunk.is_empty() {
+            chunk.push('\n');
+        }
+        chunk.push_str(&line);
+
+        match lua.eval_line(&chunk) {
+            // The parser reached the end of the chunk, so more lines are needed. Lua only tells
+            // this through the message of the error, which the stock interpreter checks too.
+            Err(LuaError::SyntaxError(ref msg)) if msg.ends_with("<eof>") => continue,
+            Ok(values) if values.is_empty() => (),
+            Ok(values) => {
+                let values: Vec<_> = values.iter().map(|v| format_value(v, false)).collect();
+                writeln!(output, "{}", values.join("\t"))?;
+            },
+            Err(err) => writeln!(output, "{}", err)?,
+        }
+        chunk.clear();
+    }
+
+    writeln!(output)
+}
+
+/// Formats a value returned by `Lua::eval_line` for display. Strings are quoted inside tables
+/// only, like `print` would show them at the top level.
+fn format_value(value: &AnyLuaValue, nested: bool) -> String {
+    match value {
+        AnyLuaValue::LuaString(s) if nested => format!("{:?}", s),
+        AnyLuaValue::LuaString(s) => s.clone(),
+        AnyLuaValue::LuaAnyString(s) => format_value(
+            &AnyLuaValue::LuaString(String::from_utf8_lossy(&s.0).into_owned()),
+            nested,
+        ),
+        AnyLuaValue::LuaNumber(n) => n.to_string(),
+        AnyLuaValue::LuaInteger(n) => n.to_string(),
+        AnyLuaValue::LuaBoolean(b) => b.to_string(),
+        AnyLuaValue::LuaNil => "nil".to_owned(),
+        AnyLuaValue::LuaOther => "<function or userdata>".to_owned(),
+        AnyLuaValue::LuaArray(entries) => {
+            let mut out = String::from("{");
+            for (n, (key, value)) in entries.iter().enumerate() {
+                out.push_str(if n == 0 { " " } else { ", " });
+                match key {
+                    AnyLuaValue::LuaString(key) => out.push_str(key),
+                    key => {
+                        let _ = write!(out, "[{}]", format_value(key, true));
+                    },
+                }
+                let _ = write!(out, " = {}", format_value(value, true));
+            }
+            out.push_str(if entries.is_empty() { "}" } else { " }" });
+            out
+        },
+    }
+}
@@ -3,9 +3,10 @@ use crate::any::{AnyHashableLuaValue, AnyLuaValue};
 use crate::{ffix, AsMutLua, LuaRead, Push, PushGuard, PushOne, TuplePushError};
 
 use std::{
-    collections::{HashMap, HashSet},
-    hash::Hash,
+    collections::{hash_map::RandomState, HashMap, HashSet},
+    hash::{BuildHasher, Hash},
     iter,
+    ops::{Deref, Range, RangeInclusive},
 };
 
 #[inline]
@@ -18,7 +19,10 @@ where
     let raw_lua = lua.as_mut_lua();
 
     // creating empty table with pre-allocated array elements
-    unsafe { ffi::lua_createtable(raw_lua.as_ptr(), iterator.size_hint().0 as i32, 0) };
+    // the size hint is only used for pre-allocation, elements are pulled from the iterator and
+    // written into the table one at a time so the sequence is never buffered on the Rust side
+    let narr = iterator.size_hint().0.min(i32::MAX as usize) as i32;
+    unsafe { ffi::lua_createtable(raw_lua.as_ptr(), narr, 0) };
 
     for (elem, index) in iterator.zip(1..) {
         let size = match elem.push_to_lua(&mut lua) {
@@ -48,10 +52,13 @@ where
 {
     let raw_lua = lua.as_mut_lua();
 
-    let (nrec, _) = iterator.size_hint();
+    let nrec = iterator.size_hint().0.min(i32::MAX as usize) as i32;
 
     // creating empty table with pre-allocated non-array elements
-    unsafe { ffi::lua_createtable(raw_lua.as_ptr(), 0, nrec as i32) };
+    unsafe { ffi::lua_createtable(raw_lua.as_ptr(), 0, nrec) };
+
+    // keeping track of whether the keys are exactly `1..=len`
+    let (mut len, mut max_key, mut sequence) = (0, 0.0, true);
 
     for elem in iterator {
         let size = match elem.push_to_lua(&mut lua) {
@@ -63,14 +70,68 @@ where
 
         match size {
             0 => continue,
-            2 => unsafe { ffi::lua_settable(raw_lua.as_ptr(), -3) },
+            2 => unsafe {
+                if sequence && ffi::lua_type(raw_lua.as_ptr(), -2) == ffi::LUA_TNUMBER {
+                    let key = ffi::lua_tonumberx(raw_lua.as_ptr(), -2, std::ptr::null_mut());
+                    sequence = key.fract() == 0.0 && key >= 1.0 && key <= i32::MAX as f64;
+                    max_key = f64::max(max_key, key);
+                    len += 1;
+                } else {
+                    sequence = false;
+                }
+                ffi::lua_settable(raw_lua.as_ptr(), -3)
+            },
             _ => unreachable!(),
         }
     }
 
+    if sequence && len > 0 && max_key == len as f64 {
+        unsafe { move_to_array(raw_lua.as_ptr(), len) };
+    }
+
     Ok(PushGuard { lua, size: 1, raw_lua })
 }
 
+// Replaces the table at the top of the stack, whose keys are `1..=len`, with a table that stores
+// the same values in its array part, so that `#t` and `ipairs` behave as for a sequence. The
+// table is kept as it is if one of the keys is missing.
+unsafe fn move_to_array(raw_lua: *mut ffi::lua_State, len: i32) {
+    ffi::lua_createtable(raw_lua, len, 0);
+    for index in 1..=len {
+        ffi::lua_rawgeti(raw_lua, -2, index as _);
+        if ffi::lua_isnil(raw_lua, -1) {
+            ffi::lua_pop(raw_lua, 2);
+            return;
+        }
+        ffi::lua_rawseti(raw_lua, -2, index as _);
+    }
+
+    match () {
+        #[cfg(feature = "_luaapi_54")]
+        () => {
+            ffi::lua_copy(raw_lua, -1, -2);
+            ffi::lua_pop(raw_lua, 1);
+        },
+        #[cfg(not(feature = "_luaapi_54"))]
+        () => ffi::lua_replace(raw_lua, -2),
+    }
+}
+
+/// Wrapper around an `IntoIterator`. When pushed, the elements are written into a new Lua array.
+///
+/// The iterator is consumed lazily: each element is pushed and stored in the table before the
+/// next one is requested, so pushing a `Range` or a `Map` never collects the sequence into a
+/// `Vec` first.
+///
+/// # Example
+///
+/// ```
+/// let mut lua = hlua::Lua::new();
+/// lua.set("a", hlua::IntoIteratorWrapper((1..4).map(|n| n * 10)));
+///
+/// let sum: i32 = lua.execute("return a[1] + a[2] + a[3]").unwrap();
+/// assert_eq!(sum, 60);
+/// ```
 pub struct IntoIteratorWrapper<I: IntoIterator>(pub I);
 impl<I: IntoIterator> From<I> for IntoIteratorWrapper<I> {
     fn from(iter: I) -> Self {
@@ -108,6 +169,36 @@ where
 {
 }
 
+// Ranges are pushed as arrays containing every element, so pushing a large range builds a table
+// just as large.
+macro_rules! range_impl {
+    ($range:ident) => {
+        impl<'lua, L, T, E> Push<L> for $range<T>
+        where
+            L: AsMutLua<'lua>,
+            $range<T>: Iterator<Item = T>,
+            T: for<'a> Push<&'a mut L, Err = E>,
+        {
+            type Err = E;
+            #[inline]
+            fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (E, L)> {
+                push_iter(lua, self)
+            }
+        }
+
+        impl<'lua, L, T, E> PushOne<L> for $range<T>
+        where
+            L: AsMutLua<'lua>,
+            $range<T>: Iterator<Item = T>,
+            T: for<'a> Push<&'a mut L, Err = E>,
+        {
+        }
+    };
+}
+
+range_impl!(Range);
+range_impl!(RangeInclusive);
+
 impl<'lua, L, T, E> Push<L> for Vec<T>
 where
     L: AsMutLua<'lua>,
@@ -169,6 +260,37 @@ where
     }
 }
 
+impl<'lua, L, T, E> Push<L> for Box<[T]>
+where
+    L: AsMutLua<'lua>,
+    T: for<'a> Push<&'a mut L, Err = E>,
+{
+    type Err = E;
+
+    #[inline]
+    fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (E, L)> {
+        push_iter(lua, self.into_vec().into_iter())
+    }
+}
+
+impl<'lua, L, T, E> PushOne<L> for Box<[T]>
+where
+    L: AsMutLua<'lua>,
+    T: for<'a> Push<&'a mut L, Err = E>,
+{
+}
+
+impl<'lua, L, T> LuaRead<L> for Box<[T]>
+where
+    L: AsMutLua<'lua>,
+    T: for<'a> LuaRead<&'a mut L>,
+{
+    #[inline]
+    fn lua_read_at_position(lua: L, index: i32) -> Result<Self, L> {
+        Vec::lua_read_at_position(lua, index).map(Vec::into_boxed_slice)
+    }
+}
+
 impl<'lua, L, T, const C: usize> LuaRead<L> for [T; C]
 where
     L: AsMutLua<'lua>,
@@ -309,6 +431,421 @@ where
     }
 }
 
+/// Wrapper that reads a table only if its numeric keys can be represented exactly.
+///
+/// When reading a table into a `HashMap<AnyHashableLuaValue, AnyLuaValue>`, numeric keys that
+/// aren't integers fitting in an `i32` are stored as `AnyHashableLuaValue::LuaFloat`. Reading an
+/// `ExactKeys<M>` instead fails if the table has such a key, for code that only expects integer
+/// and non-numeric keys.
+///
+/// # Example
+///
+/// ```
+/// use std::collections::HashMap;
+/// use hlua::{AnyHashableLuaValue, AnyLuaValue, ExactKeys};
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.execute::<()>("a = { [1] = 'x', foo = 'y' }; b = { [2.5] = 'z' }").unwrap();
+///
+/// type Map = HashMap<AnyHashableLuaValue, AnyLuaValue>;
+/// assert_eq!(lua.get::<ExactKeys<Map>, _>("a").unwrap().0.len(), 2);
+/// assert!(lua.get::<ExactKeys<Map>, _>("b").is_none());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExactKeys<M>(pub M);
+
+impl<'lua, L, M> LuaRead<L> for ExactKeys<M>
+where
+    L: AsMutLua<'lua>,
+    M: LuaRead<L>,
+{
+    fn lua_read_at_position(mut lua: L, index: i32) -> Result<Self, L> {
+        let raw_lua = lua.as_mut_lua().as_ptr();
+
+        unsafe {
+            if !ffi::lua_istable(raw_lua, index) {
+                return Err(lua);
+            }
+
+            // Checking the keys before letting `M` read the table. Relative indices are shifted
+            // by the key pushed during the traversal.
+            let table = if index < 0 && index > ffi::LUA_REGISTRYINDEX { index - 1 } else { index };
+            ffi::lua_pushnil(raw_lua);
+            while ffi::lua_next(raw_lua, table) != 0 {
+                if ffi::lua_type(raw_lua, -2) == ffi::LUA_TNUMBER {
+                    let key = ffi::lua_tonumberx(raw_lua, -2, std::ptr::null_mut());
+                    if key.fract() != 0.0 || key < i32::MIN as f64 || key > i32::MAX as f64 {
+                        ffi::lua_pop(raw_lua, 2);
+                        return Err(lua);
+                    }
+                }
+                ffi::lua_pop(raw_lua, 1);
+            }
+        }
+
+        M::lua_read_at_position(lua, index).map(ExactKeys)
+    }
+}
+
+/// Wrapper that reads a table into a `HashMap` whose keys are converted with `TryFrom`.
+///
+/// Each key is first read as an `AnyHashableLuaValue`, then converted to a `K`. Reading fails if
+/// any of the keys can't be converted. This makes it possible to read a table whose keys are known
+/// in advance into a map indexed by a Rust enum.
+///
+/// # Example
+///
+/// ```
+/// use std::convert::TryFrom;
+/// use hlua::{AnyHashableLuaValue, AnyLuaValue, TypedKeys};
+///
+/// #[derive(Debug, PartialEq, Eq, Hash)]
+/// enum Key { Host, Port }
+///
+/// impl TryFrom<AnyHashableLuaValue> for Key {
+///     type Error = ();
+///     fn try_from(value: AnyHashableLuaValue) -> Result<Key, ()> {
+///         match value {
+///             AnyHashableLuaValue::LuaString(s) if s == "host" => Ok(Key::Host),
+///             AnyHashableLuaValue::LuaString(s) if s == "port" => Ok(Key::Port),
+///             _ => Err(()),
+///         }
+///     }
+/// }
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.execute::<()>("config = { host = 'localhost', port = 80 }").unwrap();
+///
+/// let TypedKeys(config) = lua.get::<TypedKeys<Key>, _>("config").unwrap();
+/// assert_eq!(config[&Key::Port], AnyLuaValue::LuaNumber(80.0));
+/// ```
+#[derive(Debug, Clone)]
+pub struct TypedKeys<K, S = RandomState>(pub HashMap<K, AnyLuaValue, S>);
+
+impl<'lua, L, K, S> LuaRead<L> for TypedKeys<K, S>
+where
+    L: AsMutLua<'lua>,
+    K: TryFrom<AnyHashableLuaValue> + Eq + Hash,
+    S: BuildHasher + Default,
+{
+    fn lua_read_at_position(lua: L, index: i32) -> Result<Self, L> {
+        let mut lua = lua;
+        let map: HashMap<AnyHashableLuaValue, AnyLuaValue> =
+            match LuaRead::lua_read_at_position(&mut lua, index) {
+                Ok(map) => map,
+                Err(_) => return Err(lua),
+            };
+
+        map.into_iter()
+            .map(|(key, value)| K::try_from(key).map(|key| (key, value)))
+            .collect::<Result<_, _>>()
+            .map(TypedKeys)
+            .map_err(|_| lua)
+    }
+}
+
+/// Table split between its array part and its other entries.
+///
+/// When read, the values at the consecutive indices starting from `1`, up to the first `nil`, are
+/// stored in `array`, and every other entry is stored in `hash`. Reading fails if one of these
+/// values or keys can't be read as the requested type.
+///
+/// When pushed, a table containing the elements of `array` at the indices starting from `1` and
+/// the entries of `hash` is created.
+///
+/// # Example
+///
+/// ```
+/// use hlua::SplitTable;
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.execute::<()>("t = { 10, 20, 30, name = 'list', [7] = 70 }").unwrap();
+///
+/// let mut t: SplitTable<i32, hlua::AnyHashableLuaValue, hlua::AnyLuaValue> =
+///     lua.get("t").unwrap();
+/// assert_eq!(t.array, [10, 20, 30]);
+/// assert_eq!(t.hash.len(), 2);
+///
+/// t.array.push(40);
+/// lua.set("t", t);
+/// assert_eq!(lua.execute::<i32>("return #t + t[4] + t[7]").unwrap(), 114);
+/// ```
+#[derive(Debug, Clone)]
+pub struct SplitTable<T, K, V> {
+    /// Values at the indices starting from `1`.
+    pub array: Vec<T>,
+    /// All the other entries.
+    pub hash: HashMap<K, V>,
+}
+
+impl<'lua, L, T, K, V> LuaRead<L> for SplitTable<T, K, V>
+where
+    L: AsMutLua<'lua>,
+    T: for<'a> LuaRead<&'a mut L>,
+    K: for<'a> LuaRead<&'a mut L> + Eq + Hash,
+    V: for<'a> LuaRead<&'a mut L>,
+{
+    fn lua_read_at_position(lua: L, index: i32) -> Result<Self, L> {
+        let mut me = lua;
+        let raw_lua = me.as_mut_lua().as_ptr();
+
+        if unsafe { !ffi::lua_istable(raw_lua, index) } {
+            return Err(me);
+        }
+
+        let mut array = Vec::new();
+        for n in 1.. {
+            unsafe { ffi::lua_rawgeti(raw_lua, index, n) };
+            let value = match unsafe { ffi::lua_isnil(raw_lua, -1) } {
+                true => None,
+                false => Some(T::lua_read_at_position(&mut me, -1).ok()),
+            };
+            unsafe { ffi::lua_pop(raw_lua, 1) };
+
+            match value {
+                None => break,
+                Some(Some(value)) => array.push(value),
+                Some(None) => return Err(me),
+            }
+        }
+
+        let len = array.len() as f64;
+        let mut hash = HashMap::new();
+
+        unsafe {
+            // Relative indices are shifted by the key pushed during the traversal.
+            let table = if index < 0 && index > ffi::LUA_REGISTRYINDEX { index - 1 } else { index };
+            ffi::lua_pushnil(raw_lua);
+
+            while ffi::lua_next(raw_lua, table) != 0 {
+                if ffi::lua_type(raw_lua, -2) == ffi::LUA_TNUMBER {
+                    let key = ffi::lua_tonumberx(raw_lua, -2, std::ptr::null_mut());
+                    if key.fract() == 0.0 && key >= 1.0 && key <= len {
+                        ffi::lua_pop(raw_lua, 1);
+                        continue;
+                    }
+                }
+
+                // The key is read from a copy, as reading a number as a string would modify it
+                // and break the traversal.
+                ffi::lua_pushvalue(raw_lua, -2);
+                let key = K::lua_read_at_position(&mut me, -1).ok();
+                let value = V::lua_read_at_position(&mut me, -2).ok();
+                ffi::lua_pop(raw_lua, 2);
+
+                match (key, value) {
+                    (Some(key), Some(value)) => {
+                        hash.insert(key, value);
+                    },
+                    _ => {
+                        ffi::lua_pop(raw_lua, 1);
+                        return Err(me);
+                    },
+                }
+            }
+        }
+
+        Ok(SplitTable { array, hash })
+    }
+}
+
+impl<'lua, L, T, K, V, E> Push<L> for SplitTable<T, K, V>
+where
+    L: AsMutLua<'lua>,
+    T: for<'a> PushOne<&'a mut L, Err = E>,
+    K: for<'a> PushOne<&'a mut L, Err = E> + Eq + Hash,
+    V: for<'a> PushOne<&'a mut L, Err = E>,
+{
+    type Err = E;
+
+    fn push_to_lua(self, mut lua: L) -> Result<PushGuard<L>, (E, L)> {
+        let raw_lua = lua.as_mut_lua();
+        let narr = self.array.len().min(i32::MAX as usize) as i32;
+        let nrec = self.hash.len().min(i32::MAX as usize) as i32;
+        unsafe { ffi::lua_createtable(raw_lua.as_ptr(), narr, nrec) };
+
+        for (elem, index) in self.array.into_iter().zip(1..) {
+            let err = match elem.push_to_lua(&mut lua) {
+                Ok(pushed) => {
+                    pushed.forget_internal();
+                    unsafe { ffi::lua_rawseti(raw_lua.as_ptr(), -2, index) };
+                    continue;
+                },
+                Err((err, _)) => err,
+            };
+            unsafe { ffi::lua_pop(raw_lua.as_ptr(), 1) };
+            return Err((err, lua));
+        }
+
+        for (key, value) in self.hash {
+            let err = match key.push_to_lua(&mut lua) {
+                Ok(key) => {
+                    key.forget_internal();
+                    None
+                },
+                Err((err, _)) => Some(err),
+            };
+            if let Some(err) = err {
+                unsafe { ffi::lua_pop(raw_lua.as_ptr(), 1) };
+                return Err((err, lua));
+            }
+
+            let err = match value.push_to_lua(&mut lua) {
+                Ok(value) => {
+                    value.forget_internal();
+                    None
+                },
+                Err((err, _)) => Some(err),
+            };
+            if let Some(err) = err {
+                // Popping the key and the table.
+                unsafe { ffi::lua_pop(raw_lua.as_ptr(), 2) };
+                return Err((err, lua));
+            }
+
+            unsafe { ffi::lua_settable(raw_lua.as_ptr(), -3) };
+        }
+
+        Ok(PushGuard { lua, size: 1, raw_lua })
+    }
+}
+
+impl<'lua, L, T, K, V, E> PushOne<L> for SplitTable<T, K, V>
+where
+    L: AsMutLua<'lua>,
+    T: for<'a> PushOne<&'a mut L, Err = E>,
+    K: for<'a> PushOne<&'a mut L, Err = E> + Eq + Hash,
+    V: for<'a> PushOne<&'a mut L, Err = E>,
+{
+}
+
+/// Wrapper that reads a table as one of several types, chosen by the string stored in one of its
+/// fields.
+///
+/// Reading a `TaggedRead<T>` checks that the value is a table, reads its field `T::TAG` as a
+/// string, and passes that string to `T::lua_read_tagged`, which reads the table as the
+/// corresponding type. Reading fails if the value isn't a table, if the tag isn't a string, or if
+/// `lua_read_tagged` fails, for example because the tag is unknown.
+///
+/// # Example
+///
+/// ```
+/// use hlua::{AsMutLua, LuaRead, LuaTable, TaggedRead, TaggedType};
+///
+/// #[derive(Debug, PartialEq)]
+/// struct Move {
+///     x: i32,
+///     y: i32,
+/// }
+///
+/// impl<'lua, L: AsMutLua<'lua>> LuaRead<L> for Move {
+///     fn lua_read_at_position(lua: L, index: i32) -> Result<Move, L> {
+///         let mut table = LuaTable::lua_read_at_position(lua, index)?;
+///         match (table.get::<i32, _, _>("x"), table.get::<i32, _, _>("y")) {
+///             (Some(x), Some(y)) => Ok(Move { x, y }),
+///             _ => Err(table.into_inner()),
+///         }
+///     }
+/// }
+///
+/// #[derive(Debug, PartialEq)]
+/// enum Message {
+///     Move(Move),
+///     Say(String),
+///     Quit,
+/// }
+///
+/// impl<'lua, L: AsMutLua<'lua>> TaggedType<L> for Message {
+///     const TAG: &'static str = "type";
+///
+///     fn lua_read_tagged(tag: &str, lua: L, index: i32) -> Result<Message, L> {
+///         match tag {
+///             "move" => Move::lua_read_at_position(lua, index).map(Message::Move),
+///             "say" => {
+///                 let mut table = LuaTable::lua_read_at_position(lua, index)?;
+///                 match table.get::<String, _, _>("text") {
+///                     Some(text) => Ok(Message::Say(text)),
+///                     None => Err(table.into_inner()),
+///                 }
+///             },
+///             "quit" => Ok(Message::Quit),
+///             _ => Err(lua),
+///         }
+///     }
+/// }
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.execute::<()>("m = { type = 'move', x = 1, y = 2 }").unwrap();
+/// let message: TaggedRead<Message> = lua.get("m").unwrap();
+/// assert_eq!(message.0, Message::Move(Move { x: 1, y: 2 }));
+///
+/// let quit = lua.execute::<TaggedRead<Message>>("return { type = 'quit' }").unwrap();
+/// assert_eq!(quit.into_inner(), Message::Quit);
+/// assert!(lua.execute::<TaggedRead<Message>>("return { type = 'jump' }").is_err());
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct TaggedRead<T>(pub T);
+
+impl<T> TaggedRead<T> {
+    /// Returns the value that was read.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for TaggedRead<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// Types that can be read through a `TaggedRead`.
+pub trait TaggedType<L>: Sized {
+    /// Key of the field containing the tag.
+    const TAG: &'static str;
+
+    /// Reads the table at `index`, whose field `TAG` contains `tag`. Returns back `lua` if the
+    /// tag is unknown or if the table doesn't match it.
+    fn lua_read_tagged(tag: &str, lua: L, index: i32) -> Result<Self, L>;
+}
+
+impl<'lua, L, T> LuaRead<L> for TaggedRead<T>
+where
+    L: AsMutLua<'lua>,
+    T: TaggedType<L>,
+{
+    fn lua_read_at_position(lua: L, index: i32) -> Result<Self, L> {
+        let mut me = lua;
+        let raw_lua = me.as_mut_lua().as_ptr();
+
+        let tag = unsafe {
+            if !ffi::lua_istable(raw_lua, index) {
+                return Err(me);
+            }
+
+            // Relative indices are shifted by the pushed key.
+            let table = if index < 0 && index > ffi::LUA_REGISTRYINDEX { index - 1 } else { index };
+            ffi::lua_pushlstring(raw_lua, T::TAG.as_ptr().cast(), T::TAG.len());
+            ffi::lua_rawget(raw_lua, table);
+            let tag = match ffi::lua_type(raw_lua, -1) == ffi::LUA_TSTRING {
+                true => String::lua_read_at_position(&mut me, -1).ok(),
+                false => None,
+            };
+            ffi::lua_pop(raw_lua, 1);
+            tag
+        };
+
+        match tag {
+            Some(tag) => T::lua_read_tagged(&tag, me, index).map(TaggedRead),
+            None => Err(me),
+        }
+    }
+}
+
 // TODO: use an enum for the error to allow different error types for K and V
 impl<'lua, L, K, V, E, S> Push<L> for HashMap<K, V, S>
 where
@@ -473,7 +1010,10 @@ mod hashbrown {
 
 #[cfg(test)]
 mod tests {
-    use crate::{AnyHashableLuaValue, AnyLuaValue, IntoIteratorWrapper, Lua, LuaTable};
+    use crate::{
+        AnyHashableLuaValue, AnyLuaValue, AsLua, AsMutLua, ExactKeys, IntoIteratorWrapper, Lua,
+        LuaFloat, LuaRead, LuaTable, SplitTable, TaggedRead, TaggedType, TypedKeys,
+    };
     use std::collections::{BTreeMap, HashMap, HashSet};
 
     #[test]
@@ -505,6 +1045,25 @@ mod tests {
         assert_eq!(values, map);
     }
 
+    #[test]
+    fn write_map_with_sequence_keys() {
+        let mut lua = Lua::with_libs(crate::LuaLibs::BASE);
+
+        let map: HashMap<i32, i32> = (1..=20).map(|n| (n, n * 10)).collect();
+        lua.set("a", map);
+        let len: i32 = lua.execute("return #a").unwrap();
+        assert_eq!(len, 20);
+        let code = "local sum = 0; for _, v in ipairs(a) do sum = sum + v end; return sum";
+        assert_eq!(lua.execute::<i32>(code).unwrap(), 2100);
+
+        // Other keys are written as they are.
+        let map: HashMap<i32, i32> = [(1, 1), (2, 2), (4, 4)].into_iter().collect();
+        lua.set("b", map.clone());
+        let mut table: LuaTable<_> = lua.get("b").unwrap();
+        let values: HashMap<i32, i32> = table.iter().filter_map(|e| e).collect();
+        assert_eq!(values, map);
+    }
+
     #[test]
     fn write_set() {
         let mut lua = Lua::new();
@@ -671,6 +1230,19 @@ mod tests {
         }
     }
 
+    #[test]
+    fn boxed_slices() {
+        let mut lua = Lua::new();
+
+        let orig: Box<[String]> = vec!["a".to_owned(), "b".to_owned()].into_boxed_slice();
+        lua.set("v", orig.clone());
+        assert_eq!(lua.execute::<String>("return v[1] .. v[2] .. #v").unwrap(), "ab2");
+
+        let read: Box<[String]> = lua.get("v").unwrap();
+        assert_eq!(read, orig);
+        assert!(lua.execute::<Box<[i32]>>("return { 1, 'x' }").is_err());
+    }
+
     #[test]
     fn reading_vec_with_empty_table_works() {
         let mut lua = Lua::new();
@@ -801,16 +1373,87 @@ mod tests {
     }
 
     #[test]
-    #[cfg(feature = "_luaapi_52")]
     fn reading_hashmap_with_floating_indexes_works() {
         let mut lua = Lua::new();
-        lua.execute::<()>(r#"v = { [-1.25] = -1, [2.5] = 42 }"#).unwrap();
+        lua.execute::<()>(r#"v = { [-1.25] = -1, [2.5] = 42, [2] = 2 }"#).unwrap();
         let read: HashMap<_, _> = lua.get("v").unwrap();
-        // It works by truncating integers in some unspecified way
-        // https://www.lua.org/manual/5.2/manual.html#lua_tointegerx
-        assert_eq!(read[&AnyHashableLuaValue::LuaInteger(-1)], AnyLuaValue::LuaNumber(-1.));
-        assert_eq!(read[&AnyHashableLuaValue::LuaInteger(2)], AnyLuaValue::LuaNumber(42.));
+        assert_eq!(
+            read[&AnyHashableLuaValue::LuaFloat(LuaFloat(-1.25))],
+            AnyLuaValue::LuaNumber(-1.)
+        );
+        assert_eq!(
+            read[&AnyHashableLuaValue::LuaFloat(LuaFloat(2.5))],
+            AnyLuaValue::LuaNumber(42.)
+        );
+        assert_eq!(read[&AnyHashableLuaValue::LuaInteger(2)], AnyLuaValue::LuaNumber(2.));
+        assert_eq!(read.len(), 3);
+    }
+
+    #[test]
+    fn exact_keys_refuses_non_integral_keys() {
+        let mut lua = Lua::new();
+        lua.execute::<()>(r#"v = { [-1.25] = -1, [2.5] = 42 }; w = { [-1] = 1, [2] = 2 }"#)
+            .unwrap();
+
+        type Map = HashMap<AnyHashableLuaValue, AnyLuaValue>;
+        assert!(lua.get::<ExactKeys<Map>, _>("v").is_none());
+        lua.execute::<()>("v = { [2^40] = 1 }").unwrap();
+        assert!(lua.get::<ExactKeys<Map>, _>("v").is_none());
+
+        let ExactKeys(read) = lua.get::<ExactKeys<Map>, _>("w").unwrap();
+        assert_eq!(read[&AnyHashableLuaValue::LuaInteger(-1)], AnyLuaValue::LuaNumber(1.));
+        assert_eq!(read[&AnyHashableLuaValue::LuaInteger(2)], AnyLuaValue::LuaNumber(2.));
+
+        // The stack must be left untouched, whether the read succeeds or not.
+    }
+
+    #[test]
+    fn reading_typed_keys() {
+        let mut lua = Lua::new();
+        lua.execute::<()>(r#"v = { a = 1, b = 2 }; w = { a = 1, bc = 2 }"#).unwrap();
+
+        let TypedKeys(read) = lua.get::<TypedKeys<char>, _>("v").unwrap();
+        assert_eq!(read[&'a'], AnyLuaValue::LuaNumber(1.));
+        assert_eq!(read[&'b'], AnyLuaValue::LuaNumber(2.));
         assert_eq!(read.len(), 2);
+
+        assert!(lua.get::<TypedKeys<char>, _>("w").is_none());
+    }
+
+    #[test]
+    fn split_table_round_trip() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("t = { 'a', 'b', [3] = 'c', [5] = 'e', x = 'y', [1.5] = 'z' }").unwrap();
+
+        let t: SplitTable<String, AnyHashableLuaValue, String> = lua.get("t").unwrap();
+        assert_eq!(t.array, ["a", "b", "c"]);
+        assert_eq!(t.hash.len(), 3);
+        assert_eq!(t.hash[&AnyHashableLuaValue::LuaInteger(5)], "e");
+        assert_eq!(t.hash[&AnyHashableLuaValue::LuaString("x".to_owned())], "y");
+        assert_eq!(t.hash[&AnyHashableLuaValue::LuaFloat(LuaFloat(1.5))], "z");
+
+        lua.set("u", t);
+        let u: SplitTable<String, AnyHashableLuaValue, String> = lua.get("u").unwrap();
+        assert_eq!(u.array, ["a", "b", "c"]);
+        assert_eq!(u.hash.len(), 3);
+
+        // Values of the wrong type in either part make the read fail.
+        assert!(lua.get::<SplitTable<i32, AnyHashableLuaValue, String>, _>("u").is_none());
+        assert!(lua.get::<SplitTable<String, AnyHashableLuaValue, i32>, _>("u").is_none());
+    }
+
+    #[test]
+    fn write_ranges() {
+        let mut lua = Lua::new();
+        lua.set("a", 1..=10);
+        lua.set("b", 3u8..6);
+        lua.set("c", 5..5);
+
+        let a: Vec<i32> = lua.get("a").unwrap();
+        assert_eq!(a, (1..=10).collect::<Vec<_>>());
+        let b: Vec<u8> = lua.get("b").unwrap();
+        assert_eq!(b, [3, 4, 5]);
+        assert_eq!(lua.execute::<i32>("return #c").unwrap(), 0);
     }
 
     #[test]
@@ -851,4 +1494,53 @@ mod tests {
                 .collect::<HashMap<_, _>>()
         );
     }
+
+    #[derive(Debug, PartialEq)]
+    enum Shape {
+        Circle(f64),
+        Square(f64),
+        Point,
+    }
+
+    impl<'lua, L: AsMutLua<'lua>> TaggedType<L> for Shape {
+        const TAG: &'static str = "kind";
+
+        fn lua_read_tagged(tag: &str, lua: L, index: i32) -> Result<Shape, L> {
+            let variant: fn(f64) -> Shape = match tag {
+                "circle" => Shape::Circle,
+                "square" => Shape::Square,
+                "point" => return Ok(Shape::Point),
+                _ => return Err(lua),
+            };
+            let mut table = LuaTable::lua_read_at_position(lua, index)?;
+            match table.get::<f64, _, _>("size") {
+                Some(size) => Ok(variant(size)),
+                None => Err(table.into_inner()),
+            }
+        }
+    }
+
+    #[test]
+    fn read_tagged() {
+        let mut lua = Lua::new();
+        lua.execute::<()>(
+            "shapes = { { kind = 'circle', size = 2 }, { kind = 'point' }, \
+             { kind = 'square', size = 1.5 } }",
+        )
+        .unwrap();
+
+        let shapes: Vec<TaggedRead<Shape>> = lua.get("shapes").unwrap();
+        let shapes: Vec<Shape> = shapes.into_iter().map(TaggedRead::into_inner).collect();
+        assert_eq!(shapes, [Shape::Circle(2.0), Shape::Point, Shape::Square(1.5)]);
+
+        for invalid in [
+            "return { kind = 'triangle', size = 1 }",
+            "return { kind = 'circle' }",
+            "return { kind = 1, size = 1 }",
+            "return { size = 1 }",
+            "return 'circle'",
+        ] {
+            assert!(lua.execute::<TaggedRead<Shape>>(invalid).is_err(), "{}", invalid);
+        }
+    }
 }
@@ -0,0 +1,203 @@
+use std::{
+    any::Any,
+    borrow::Borrow,
+    cell::RefCell,
+    marker::PhantomData,
+    mem,
+    ops::{Deref, DerefMut},
+    ptr,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        Arc,
+    },
+};
+
+use crate::{
+    functions_write::{FunctionExt, ScopedFunction},
+    push_userdata,
+    userdata::ScopedRef,
+    AsMutLua, Function, InsideCallback, Lua, LuaRead, LuaTable, OpaqueLua, Push, PushGuard,
+    PushOne, Void,
+};
+
+/// Lets Lua code access data borrowed from Rust for a limited amount of time.
+///
+/// Created by `Lua::scope`. The userdata created through a `Scope` refer to the Rust data instead
+/// of owning it, and are invalidated when the scope ends: from then on, attempting to read them
+/// from a callback fails just like reading a value of the wrong type. Likewise, the closures set
+/// through a `Scope` are dropped when it ends, and calling them afterwards raises an error.
+///
+/// The `Scope` derefs to the `Lua` context, so that scripts can be run while the borrowed data is
+/// available.
+///
+/// The borrowed data must outlive the whole scope:
+///
+/// ```compile_fail
+/// struct Foo;
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.scope(|scope| {
+///     let mut foo = Foo;
+///     scope.set_userdata("foo", &mut foo, |_| {});
+/// });
+/// ```
+pub struct Scope<'scope, 'lua: 'scope> {
+    lua: &'scope mut Lua<'lua>,
+    refs: Vec<Arc<AtomicPtr<()>>>,
+    // Drop the closures set through the scope.
+    closures: Vec<Box<dyn FnOnce()>>,
+    // Makes `'scope` invariant, so that the scope can't be shortened to accept data that doesn't
+    // live long enough.
+    marker: PhantomData<fn(&'scope ()) -> &'scope ()>,
+}
+
+impl<'scope, 'lua> Scope<'scope, 'lua> {
+    #[inline]
+    pub(crate) fn new(lua: &'scope mut Lua<'lua>) -> Scope<'scope, 'lua> {
+        Scope { lua, refs: Vec::new(), closures: Vec::new(), marker: PhantomData }
+    }
+
+    /// Sets the global variable `name` to a userdata that refers to `data`.
+    ///
+    /// The userdata reads as a `T`, which means that callbacks taking a `&T` or a `&mut T` can be
+    /// called with it. The metatable is shared between all the userdata pointing to a `T`
+    /// created by a scope, and `metatable` is only called for the first one.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #[macro_use] extern crate hlua;
+    /// # fn main() {
+    /// struct Counter { value: u32 }
+    /// implement_lua_read!(Counter);
+    ///
+    /// let mut lua = hlua::Lua::new();
+    /// let mut counter = Counter { value: 0 };
+    ///
+    /// lua.scope(|scope| {
+    ///     scope.set_userdata("counter", &mut counter, |meta| {
+    ///         hlua::MetatableBuilder::new(meta).method("incr", |c: &mut Counter| c.value += 1);
+    ///     });
+    ///     scope.execute::<()>("counter:incr(); counter:incr()").unwrap();
+    /// });
+    ///
+    /// assert_eq!(counter.value, 2);
+    /// // The userdata can't be used anymore once the scope has ended.
+    /// assert!(lua.execute::<()>("counter:incr()").is_err());
+    /// # }
+    /// ```
+    #[inline]
+    pub fn set_userdata<I, T, F>(&mut self, name: I, data: &'scope mut T, metatable: F)
+    where
+        I: Borrow<str>,
+        T: Send + Any,
+        F: FnOnce(LuaTable<OpaqueLua<'lua>>),
+    {
+        let data = ScopedRef::new(data);
+        self.refs.push(data.ptr.clone());
+        self.lua.set(name, ScopedUserdata { data, metatable });
+    }
+
+    /// Sets the global variable `name` to a function that calls `function`, which may borrow data
+    /// for the duration of the scope.
+    ///
+    /// Contrary to the functions pushed with `Lua::set`, `function` doesn't need to be `'static`:
+    /// it can capture mutable references to local variables, without going through `Arc<Mutex>`.
+    /// Like with `hlua::function`, it can be a closure or the result of one of the `functionN`
+    /// functions when the types of its parameters can't be inferred. It is dropped when the scope
+    /// ends, after which calling it from Lua raises an error. Calling it from within itself, for
+    /// example through a script that it runs, raises an error too.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// let mut lines = Vec::new();
+    ///
+    /// lua.scope(|scope| {
+    ///     scope.set_closure("log", |line: String| lines.push(line));
+    ///     scope.execute::<()>("log('hello'); log('world')").unwrap();
+    /// });
+    ///
+    /// assert_eq!(lines, ["hello", "world"]);
+    /// assert!(lua.execute::<()>("log('again')").is_err());
+    /// ```
+    pub fn set_closure<I, Z, P, R>(&mut self, name: I, function: impl Into<Function<Z, P, R>>)
+    where
+        I: Borrow<str>,
+        Z: 'scope,
+        Function<Z, P, R>: FunctionExt<P, Output = R>,
+        P: for<'p> LuaRead<&'p mut InsideCallback> + 'static,
+        R: for<'a> Push<&'a mut InsideCallback> + 'static,
+    {
+        let mut function = function.into();
+        let closure: Box<dyn FnMut(P) -> R + 'scope> =
+            Box::new(move |params| function.call_mut(params));
+        // The closure is taken out of the slot when the scope ends, so Lua never calls it once
+        // the data it borrows may be gone.
+        let closure: Box<dyn FnMut(P) -> R> = unsafe { mem::transmute(closure) };
+        let slot = Rc::new(RefCell::new(Some(closure)));
+
+        let taken = slot.clone();
+        self.closures.push(Box::new(move || drop(taken.borrow_mut().take())));
+        self.lua.set(name, ScopedFunction { slot });
+    }
+}
+
+impl<'scope, 'lua> Deref for Scope<'scope, 'lua> {
+    type Target = Lua<'lua>;
+
+    #[inline]
+    fn deref(&self) -> &Lua<'lua> {
+        self.lua
+    }
+}
+
+impl<'scope, 'lua> DerefMut for Scope<'scope, 'lua> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Lua<'lua> {
+        self.lua
+    }
+}
+
+impl<'scope, 'lua> Drop for Scope<'scope, 'lua> {
+    #[inline]
+    fn drop(&mut self) {
+        // Lua may keep the userdata alive for longer than the scope, so we invalidate them.
+        for data in self.refs.drain(..) {
+            data.store(ptr::null_mut(), Ordering::Release);
+        }
+        for drop_closure in self.closures.drain(..) {
+            drop_closure();
+        }
+    }
+}
+
+// Pushes a `ScopedRef` with its metatable.
+struct ScopedUserdata<T, F> {
+    data: ScopedRef<T>,
+    metatable: F,
+}
+
+impl<'lua, L, T, F> Push<L> for ScopedUserdata<T, F>
+where
+    L: AsMutLua<'lua>,
+    T: Send + Any,
+    F: FnOnce(LuaTable<OpaqueLua<'lua>>),
+{
+    type Err = Void;
+
+    #[inline]
+    fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+        Ok(push_userdata(self.data, lua, self.metatable))
+    }
+}
+
+impl<'lua, L, T, F> PushOne<L> for ScopedUserdata<T, F>
+where
+    L: AsMutLua<'lua>,
+    T: Send + Any,
+    F: FnOnce(LuaTable<OpaqueLua<'lua>>),
+{
+}
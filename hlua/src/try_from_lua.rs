@@ -0,0 +1,173 @@
+use std::{borrow::Cow, error::Error, fmt};
+
+use crate::{AsLua, LuaRead, LuaType};
+
+/// Error returned when a value can't be read, describing why.
+///
+/// Contrary to `LuaRead`, which only gives back the Lua context, this carries what was expected,
+/// the type of the value that was found instead, and where it was found.
+///
+/// # Example
+///
+/// ```
+/// let mut lua = hlua::Lua::new();
+/// lua.execute::<()>("t = {}").unwrap();
+///
+/// let err = lua.try_get::<i32, _>("t").unwrap_err();
+/// assert_eq!(err.to_string(), "expected integer, found table at global t");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReadError {
+    expected: Cow<'static, str>,
+    found: Option<LuaType>,
+    index: i32,
+    location: Option<String>,
+}
+
+impl ReadError {
+    /// Returns a description of the values that were expected, as returned by
+    /// `LuaRead::expected`.
+    #[inline]
+    pub fn expected(&self) -> &str {
+        &self.expected
+    }
+
+    /// Returns the type of the value that was found, or `None` if there was no value at the
+    /// index.
+    #[inline]
+    pub fn found(&self) -> Option<LuaType> {
+        self.found
+    }
+
+    /// Returns the index on the stack of the value that couldn't be read.
+    #[inline]
+    pub fn index(&self) -> i32 {
+        self.index
+    }
+
+    /// Returns where the value came from, for example `global t`, if known.
+    #[inline]
+    pub fn location(&self) -> Option<&str> {
+        self.location.as_deref()
+    }
+
+    /// Sets where the value came from, which replaces the index in the error message.
+    #[inline]
+    pub fn with_location<S: Into<String>>(mut self, location: S) -> ReadError {
+        self.location = Some(location.into());
+        self
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected {}, found ", self.expected)?;
+        match self.found {
+            Some(ty) => write!(f, "{}", ty)?,
+            None => f.write_str("no value")?,
+        }
+        match self.location {
+            Some(ref location) => write!(f, " at {}", location),
+            None => write!(f, " at index {}", self.index),
+        }
+    }
+}
+
+impl Error for ReadError {}
+
+/// Types that can be read from a Lua context, with an error describing why the read failed.
+///
+/// This is implemented for every type that implements `LuaRead`, using `LuaRead::expected` to
+/// describe the values it accepts.
+pub trait TryFromLua<L>: Sized {
+    /// Reads the value at `index`, or returns an error along with the Lua context.
+    fn try_from_lua(lua: L, index: i32) -> Result<Self, (ReadError, L)>;
+}
+
+impl<'lua, L, T> TryFromLua<L> for T
+where
+    L: AsLua<'lua>,
+    T: LuaRead<L>,
+{
+    #[inline]
+    fn try_from_lua(lua: L, index: i32) -> Result<T, (ReadError, L)> {
+        match T::lua_read_at_position(lua, index) {
+            Ok(value) => Ok(value),
+            Err(lua) => {
+                let found = LuaType::at(lua.as_lua(), index);
+                let err = ReadError { expected: T::expected(), found, index, location: None };
+                Err((err, lua))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AsMutLua, Lua, LuaFunction, LuaTable, LuaType, PushGuard, TryFromLua};
+
+    #[test]
+    fn try_get_errors() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("a = 5; b = 'hello'; c = { 1 }").unwrap();
+
+        let err = lua.try_get::<i32, _>("c").unwrap_err();
+        assert_eq!(err.expected(), "integer");
+        assert_eq!(err.found(), Some(LuaType::Table));
+        assert_eq!(err.location(), Some("global c"));
+        assert_eq!(err.to_string(), "expected integer, found table at global c");
+
+        assert_eq!(
+            lua.try_get::<bool, _>("missing").unwrap_err().to_string(),
+            "expected boolean, found nil at global missing"
+        );
+        assert_eq!(
+            lua.try_get::<Option<f64>, _>("b").unwrap_err().to_string(),
+            "expected number or nil, found string at global b"
+        );
+        assert_eq!(
+            lua.try_get::<LuaTable<_>, _>("a").unwrap_err().to_string(),
+            "expected table, found number at global a"
+        );
+        assert_eq!(
+            lua.try_get::<LuaFunction<_>, _>("b").unwrap_err().to_string(),
+            "expected function, found string at global b"
+        );
+
+        // Types that don't describe themselves fall back to their Rust name.
+        let err = lua.try_get::<std::net::Ipv4Addr, _>("a").unwrap_err();
+        assert_eq!(err.expected(), std::any::type_name::<std::net::Ipv4Addr>());
+    }
+
+    #[test]
+    fn try_get_values() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("a = 5; b = 'hello'; c = { 1, 2 }").unwrap();
+
+        assert_eq!(lua.try_get::<i32, _>("a").unwrap(), 5);
+        assert_eq!(lua.try_get::<String, _>("b").unwrap(), "hello");
+        assert_eq!(lua.try_get::<Option<u8>, _>("missing").unwrap(), None);
+        let mut c: LuaTable<_> = lua.try_get("c").unwrap();
+        assert_eq!(c.get::<i32, _, _>(2), Some(2));
+    }
+
+    #[test]
+    fn error_at_index() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("t = true").unwrap();
+
+        let raw_lua = lua.as_mut_lua();
+        unsafe { ffi::lua_pushboolean(raw_lua.as_ptr(), 1) };
+        let guard = unsafe { PushGuard::new(&mut lua, 1) };
+
+        let result = <u32 as TryFromLua<_>>::try_from_lua(guard, -1);
+        match result {
+            Err((err, _)) => {
+                assert_eq!(err.index(), -1);
+                assert_eq!(err.location(), None);
+                assert_eq!(err.to_string(), "expected integer, found boolean at index -1");
+            },
+            Ok(_) => panic!(),
+        }
+    }
+}
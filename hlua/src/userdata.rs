@@ -1,13 +1,19 @@
 use std::{
     any::{Any, TypeId},
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    ffi::c_void,
     marker::PhantomData,
     mem,
     ops::{Deref, DerefMut},
-    ptr::{addr_of, NonNull},
+    panic::{self, AssertUnwindSafe},
+    ptr::NonNull,
+    sync::{atomic::AtomicPtr, Arc, Mutex, MutexGuard, Weak},
 };
 
 use crate::{
-    AsLua, AsMutLua, InsideCallback, LuaContext, LuaRead, LuaTable, OpaqueLua, Push, PushGuard,
+    functions_write::set_read_failure, AsLua, AsMutLua, Function, InsideCallback, LuaContext,
+    LuaRead, LuaTable, OpaqueLua, Push, PushGuard, PushOne, StrictRead, Void,
 };
 
 mod raw {
@@ -113,7 +119,8 @@ mod raw {
 
     pub mod util {
         use super::{data_ptr, head_ptr, Head};
-        use std::{any::TypeId, ffi::c_void};
+        use crate::userdata::ScopedRef;
+        use std::{any::TypeId, ffi::c_void, sync::atomic::Ordering};
 
         /// Checks if the userdata pointed to by `ptr` is of type `T`.
         pub unsafe fn validate_type<T: 'static>(ptr: *mut c_void) -> bool {
@@ -130,18 +137,48 @@ mod raw {
             &*data_ptr::<T>(ptr)
         }
 
-        /// Returns a mutable reference to the inner data.
-        pub unsafe fn data_mut<'a, T>(ptr: *mut c_void) -> &'a mut T {
-            &mut *data_ptr::<T>(ptr)
-        }
-
         /// Returns a mutable reference to the inner data.
         ///
         /// This also checks so that the pointer is not null and validates that the type matches.
-        /// If you know that the pointer is valid you can use [`data_mut`] instead.
         pub unsafe fn data_mut_checked<'a, T: 'static>(ptr: *mut c_void) -> Option<&'a mut T> {
-            (!ptr.is_null() && validate_type::<T>(ptr)).then(|| data_mut::<T>(ptr))
+            resolve::<T>(ptr).map(|data| &mut *data)
         }
+
+        /// Returns a pointer to the `T` the userdata pointed to by `ptr` holds or refers to.
+        ///
+        /// Returns `None` if the pointer is null, if the userdata isn't a `T` or a `ScopedRef<T>`,
+        /// or if it is a `ScopedRef<T>` whose scope has ended.
+        pub unsafe fn resolve<T: 'static>(ptr: *mut c_void) -> Option<*mut T> {
+            if ptr.is_null() {
+                return None;
+            }
+
+            if validate_type::<T>(ptr) {
+                Some(data_ptr::<T>(ptr))
+            } else if validate_type::<ScopedRef<T>>(ptr) {
+                let target = data_ref::<ScopedRef<T>>(ptr).ptr.load(Ordering::Acquire);
+                (!target.is_null()).then(|| target.cast())
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Userdata that refers to data owned by Rust code instead of holding it, see `Scope`.
+///
+/// Reading a `T` from Lua also accepts a `ScopedRef<T>`. The pointer is set to null when the scope
+/// that created it ends, after which reads fail.
+pub(crate) struct ScopedRef<T> {
+    pub(crate) ptr: Arc<AtomicPtr<()>>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> ScopedRef<T> {
+    #[inline]
+    pub(crate) fn new(data: &mut T) -> ScopedRef<T> {
+        let ptr = (data as *mut T).cast();
+        ScopedRef { ptr: Arc::new(AtomicPtr::new(ptr)), marker: PhantomData }
     }
 }
 
@@ -178,7 +215,504 @@ extern "C" fn destructor_wrapper<T: 'static>(lua: *mut ffi::lua_State) -> libc::
 ///  - `metatable`: Function that fills the metatable of the object.
 ///
 #[inline]
-pub fn push_userdata<'lua, L, T, F>(data: T, mut lua: L, metatable: F) -> PushGuard<L>
+pub fn push_userdata<'lua, L, T, F>(data: T, lua: L, metatable: F) -> PushGuard<L>
+where
+    F: FnOnce(LuaTable<OpaqueLua<'lua>>),
+    L: AsMutLua<'lua>,
+    T: Send + Any + 'static,
+{
+    push_userdata_impl(data, lua, metatable, true)
+}
+
+/// Pushes an object as a user data, without ever calling its destructor.
+///
+/// This is the same as `push_userdata`, except that no `__gc` function is installed, so that the
+/// garbage collector frees the object without calling `Drop`. This avoids the cost of the
+/// destructor calls when the objects own nothing that needs to be released, for example when
+/// many short-lived objects are created and the whole `Lua` is thrown away afterwards.
+///
+/// The metatable of the objects pushed by this function isn't shared with the ones pushed by
+/// `push_userdata`, so `metatable` is called again for the first object of each kind.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+///
+/// struct Counted(Arc<AtomicUsize>);
+/// impl Drop for Counted {
+///     fn drop(&mut self) { self.0.fetch_add(1, Ordering::SeqCst); }
+/// }
+///
+/// let drops = Arc::new(AtomicUsize::new(0));
+/// {
+///     let mut lua = hlua::Lua::new();
+///     hlua::push_userdata_no_drop(Counted(drops.clone()), &mut lua, |_| {});
+/// }
+/// assert_eq!(drops.load(Ordering::SeqCst), 0);
+/// ```
+#[inline]
+pub fn push_userdata_no_drop<'lua, L, T, F>(data: T, lua: L, metatable: F) -> PushGuard<L>
+where
+    F: FnOnce(LuaTable<OpaqueLua<'lua>>),
+    L: AsMutLua<'lua>,
+    T: Send + Any + 'static,
+{
+    push_userdata_impl(data, lua, metatable, false)
+}
+
+/// Object shared with Rust code, pushed as a user data.
+///
+/// When pushed, the `Arc<Mutex<T>>` is stored in a user data the same way `push_userdata` would,
+/// with `metatable` filling its metatable. The methods of the metatable can then take a
+/// [`Locked<T>`](struct.Locked.html) parameter, which locks the mutex for the duration of the
+/// call. Every user data pushed from clones of the same `Arc` refers to the same object, and the
+/// `Arc` itself can be read back with a `UserdataOnStack<Arc<Mutex<T>>, _>`.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::{Arc, Mutex};
+/// use hlua::{Locked, MetatableBuilder, SharedUserdata};
+///
+/// struct Counter { value: u32 }
+///
+/// let counter = Arc::new(Mutex::new(Counter { value: 0 }));
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.set("counter", SharedUserdata::new(counter.clone(), |meta| {
+///     MetatableBuilder::new(meta).method("incr", |mut c: Locked<Counter>| c.value += 1);
+/// }));
+///
+/// lua.execute::<()>("counter:incr(); counter:incr()").unwrap();
+/// assert_eq!(counter.lock().unwrap().value, 2);
+/// ```
+pub struct SharedUserdata<T, F> {
+    data: Arc<Mutex<T>>,
+    metatable: F,
+}
+
+impl<T, F> SharedUserdata<T, F> {
+    /// Wraps `data`, whose metatable is filled by `metatable` the first time an object of type
+    /// `T` is pushed.
+    #[inline]
+    pub fn new(data: Arc<Mutex<T>>, metatable: F) -> SharedUserdata<T, F> {
+        SharedUserdata { data, metatable }
+    }
+}
+
+impl<'lua, L, T, F> Push<L> for SharedUserdata<T, F>
+where
+    L: AsMutLua<'lua>,
+    T: Send + 'static,
+    F: FnOnce(LuaTable<OpaqueLua<'lua>>),
+{
+    type Err = Void;
+
+    #[inline]
+    fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+        Ok(push_userdata(self.data, lua, self.metatable))
+    }
+}
+
+impl<'lua, L, T, F> PushOne<L> for SharedUserdata<T, F>
+where
+    L: AsMutLua<'lua>,
+    T: Send + 'static,
+    F: FnOnce(LuaTable<OpaqueLua<'lua>>),
+{
+}
+
+/// Object pushed with `SharedUserdata`, locked for as long as the `Locked` is alive.
+///
+/// Taking a `Locked<T>` parameter in a callback locks the mutex before the callback runs, and
+/// unlocks it when the callback returns. If another thread holds the lock, the call waits for it
+/// to be released. If the mutex is poisoned or already locked by the current thread, reading the
+/// parameter fails and the call raises a Lua error instead of deadlocking.
+///
+/// This means that passing the same object twice to a callback, as in `f(a, a)`, or calling a
+/// method that locks the object from within a callback that has it locked, raises an error.
+pub struct Locked<T: 'static> {
+    // Declared before `_shared` so that the lock is released before the `Arc` is dropped.
+    guard: MutexGuard<'static, T>,
+    _shared: Arc<Mutex<T>>,
+}
+
+thread_local! {
+    // Addresses of the mutexes locked by the `Locked` of this thread.
+    static LOCKED_MUTEXES: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+impl<'lua, L, T> LuaRead<L> for Locked<T>
+where
+    L: AsMutLua<'lua>,
+    T: Send + 'static,
+{
+    #[inline]
+    fn lua_read_at_position(lua: L, index: i32) -> Result<Locked<T>, L> {
+        let shared = unsafe {
+            let ptr = ffi::lua_touserdata(lua.as_lua().as_ptr(), index);
+            match raw::util::resolve::<Arc<Mutex<T>>>(ptr) {
+                Some(shared) => (*shared).clone(),
+                None => return Err(lua),
+            }
+        };
+
+        // Locking a mutex that this thread already holds would deadlock.
+        let address = Arc::as_ptr(&shared) as usize;
+        if LOCKED_MUTEXES.with(|locked| locked.borrow().contains(&address)) {
+            return Err(lua);
+        }
+
+        let guard = match shared.lock() {
+            Ok(guard) => guard,
+            Err(_) => return Err(lua),
+        };
+        // The mutex lives for as long as `_shared`, which is dropped after the guard.
+        let guard = unsafe { mem::transmute::<MutexGuard<'_, T>, MutexGuard<'static, T>>(guard) };
+
+        LOCKED_MUTEXES.with(|locked| locked.borrow_mut().push(address));
+        Ok(Locked { guard, _shared: shared })
+    }
+}
+
+impl<T: 'static> Drop for Locked<T> {
+    #[inline]
+    fn drop(&mut self) {
+        let address = Arc::as_ptr(&self._shared) as usize;
+        LOCKED_MUTEXES.with(|locked| {
+            let mut locked = locked.borrow_mut();
+            if let Some(pos) = locked.iter().rposition(|&a| a == address) {
+                locked.swap_remove(pos);
+            }
+        });
+    }
+}
+
+impl<T: 'static> Deref for Locked<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T: 'static> DerefMut for Locked<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+/// Object owned by Rust code, pushed as a user data that holds a `Weak<T>`.
+///
+/// Contrary to `SharedUserdata`, Lua doesn't keep the object alive: once the last `Arc` is
+/// dropped on the Rust side, the user data still exists in Lua but no longer refers to anything.
+/// The methods of the metatable can take an [`Upgraded<T>`](struct.Upgraded.html) parameter,
+/// which upgrades the weak reference for the duration of the call, and fails to be read if the
+/// object has been dropped, in which case the call raises a Lua error. This is the alternative to
+/// `Lua::scope` for objects whose lifetime isn't tied to a block of Rust code.
+///
+/// # Example
+///
+/// ```
+/// use std::sync::{atomic::{AtomicU32, Ordering}, Arc};
+/// use hlua::{MetatableBuilder, Upgraded, WeakUserdata};
+///
+/// struct Window { clicks: AtomicU32 }
+///
+/// let window = Arc::new(Window { clicks: AtomicU32::new(0) });
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.set("window", WeakUserdata::new(&window, |meta| {
+///     MetatableBuilder::new(meta)
+///         .method("click", |w: Upgraded<Window>| w.clicks.fetch_add(1, Ordering::SeqCst));
+/// }));
+///
+/// lua.execute::<()>("window:click()").unwrap();
+/// assert_eq!(window.clicks.load(Ordering::SeqCst), 1);
+///
+/// drop(window);
+/// assert!(lua.execute::<()>("window:click()").is_err());
+/// ```
+pub struct WeakUserdata<T, F> {
+    data: Weak<T>,
+    metatable: F,
+}
+
+impl<T, F> WeakUserdata<T, F> {
+    /// Wraps a weak reference to `data`, whose metatable is filled by `metatable` the first time
+    /// an object of type `T` is pushed this way.
+    #[inline]
+    pub fn new(data: &Arc<T>, metatable: F) -> WeakUserdata<T, F> {
+        WeakUserdata { data: Arc::downgrade(data), metatable }
+    }
+}
+
+impl<'lua, L, T, F> Push<L> for WeakUserdata<T, F>
+where
+    L: AsMutLua<'lua>,
+    T: Send + Sync + 'static,
+    F: FnOnce(LuaTable<OpaqueLua<'lua>>),
+{
+    type Err = Void;
+
+    #[inline]
+    fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+        Ok(push_userdata(self.data, lua, self.metatable))
+    }
+}
+
+impl<'lua, L, T, F> PushOne<L> for WeakUserdata<T, F>
+where
+    L: AsMutLua<'lua>,
+    T: Send + Sync + 'static,
+    F: FnOnce(LuaTable<OpaqueLua<'lua>>),
+{
+}
+
+/// Object pushed with `WeakUserdata`, kept alive for as long as the `Upgraded` is alive.
+///
+/// Reading an `Upgraded<T>` fails if the value isn't a user data pushed with `WeakUserdata<T>`,
+/// or if the object has been dropped on the Rust side. When a callback is called with an object
+/// that has been dropped, the error says so instead of complaining about the types of its
+/// parameters.
+pub struct Upgraded<T>(Arc<T>);
+
+impl<T> Upgraded<T> {
+    /// Returns the `Arc` that keeps the object alive.
+    #[inline]
+    pub fn into_inner(self) -> Arc<T> {
+        self.0
+    }
+}
+
+impl<'lua, L, T> LuaRead<L> for Upgraded<T>
+where
+    L: AsLua<'lua>,
+    T: Send + Sync + 'static,
+{
+    #[inline]
+    fn lua_read_at_position(lua: L, index: i32) -> Result<Upgraded<T>, L> {
+        let weak = unsafe {
+            let ptr = ffi::lua_touserdata(lua.as_lua().as_ptr(), index);
+            raw::util::resolve::<Weak<T>>(ptr)
+        };
+
+        match weak.map(|weak| unsafe { (*weak).upgrade() }) {
+            Some(Some(data)) => Ok(Upgraded(data)),
+            Some(None) => {
+                set_read_failure("was called with an object that has been dropped");
+                Err(lua)
+            },
+            None => Err(lua),
+        }
+    }
+}
+
+impl<T> Deref for Upgraded<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+type CastFn<D> = Box<dyn Fn(*mut c_void) -> Option<Box<D>> + Send>;
+
+/// Conversions from userdata of various types to a common trait object `D`, used to read
+/// `DynUserdata<D>`.
+///
+/// Each Rust type pushed as a userdata is registered with a function that converts a reference
+/// to it into a `Box<D>`, for example by cloning it. Once installed with
+/// `Lua::set_userdata_casts`, reading a `DynUserdata<D>` looks up the conversion of the type of
+/// the userdata, so that a callback can accept any of the registered types.
+///
+/// # Example
+///
+/// ```
+/// #[macro_use] extern crate hlua;
+/// # fn main() {
+/// use hlua::{DynUserdata, UserdataCasts};
+///
+/// trait Shape { fn area(&self) -> f64; }
+///
+/// #[derive(Clone)]
+/// struct Square(f64);
+/// impl Shape for Square { fn area(&self) -> f64 { self.0 * self.0 } }
+/// implement_lua_push!(Square, |_| {});
+///
+/// #[derive(Clone)]
+/// struct Circle(f64);
+/// impl Shape for Circle { fn area(&self) -> f64 { 3.0 * self.0 * self.0 } }
+/// implement_lua_push!(Circle, |_| {});
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.set_userdata_casts(
+///     UserdataCasts::<dyn Shape>::new()
+///         .register(|s: &Square| Box::new(s.clone()))
+///         .register(|c: &Circle| Box::new(c.clone())),
+/// );
+///
+/// lua.set("area", hlua::function1(|shape: DynUserdata<dyn Shape>| shape.0.area()));
+/// lua.set("square", Square(2.0));
+/// lua.set("circle", Circle(1.0));
+/// assert_eq!(lua.execute::<f64>("return area(square) + area(circle)").unwrap(), 7.0);
+/// # }
+/// ```
+pub struct UserdataCasts<D: ?Sized + 'static> {
+    casts: HashMap<TypeId, CastFn<D>>,
+}
+
+impl<D: ?Sized + 'static> UserdataCasts<D> {
+    /// Builds an empty set of conversions.
+    #[inline]
+    pub fn new() -> UserdataCasts<D> {
+        UserdataCasts { casts: HashMap::new() }
+    }
+
+    /// Registers the conversion of userdata holding a `T`, replacing the previous one for this
+    /// type.
+    ///
+    /// The conversion also applies to the userdata created by `Scope::set_userdata` that refer
+    /// to a `T`.
+    pub fn register<T>(mut self, cast: fn(&T) -> Box<D>) -> UserdataCasts<D>
+    where
+        T: Any,
+    {
+        for typeid in [TypeId::of::<T>(), TypeId::of::<ScopedRef<T>>()] {
+            let cast = move |ptr: *mut c_void| {
+                unsafe { raw::util::resolve::<T>(ptr) }.map(|data| cast(unsafe { &*data }))
+            };
+            self.casts.insert(typeid, Box::new(cast));
+        }
+        self
+    }
+
+    // Returns the registry key of the conversions to `D`.
+    fn registry_key() -> String {
+        format!("hlua_userdata_casts_{:?}", TypeId::of::<D>())
+    }
+}
+
+impl<D: ?Sized + 'static> Default for UserdataCasts<D> {
+    #[inline]
+    fn default() -> UserdataCasts<D> {
+        UserdataCasts::new()
+    }
+}
+
+/// Installs `casts` in the registry of `lua`, replacing the previous conversions to `D`.
+pub(crate) fn set_casts<D: ?Sized + 'static>(lua: LuaContext, casts: UserdataCasts<D>) {
+    unsafe {
+        UserdataCasts::<D>::registry_key().push_no_err(lua).forget_internal();
+        push_userdata(casts, lua, |_| {}).forget_internal();
+        ffi::lua_rawset(lua.as_ptr(), ffi::LUA_REGISTRYINDEX);
+    }
+}
+
+/// Userdata of any of the types registered in the `UserdataCasts<D>` installed with
+/// `Lua::set_userdata_casts`, converted to a `Box<D>`.
+///
+/// Reading fails if the value isn't a userdata, if no conversions to `D` are installed, if its
+/// type isn't registered, or if the conversion panics.
+#[derive(Debug)]
+pub struct DynUserdata<D: ?Sized>(pub Box<D>);
+
+impl<'lua, L, D> LuaRead<L> for DynUserdata<D>
+where
+    L: AsMutLua<'lua>,
+    D: ?Sized + 'static,
+{
+    fn lua_read_at_position(lua: L, index: i32) -> Result<DynUserdata<D>, L> {
+        let raw_lua = lua.as_lua();
+        let typeid = match userdata_type_id(&raw_lua, index) {
+            Some(typeid) => typeid,
+            None => return Err(lua),
+        };
+        let ptr = unsafe { ffi::lua_touserdata(raw_lua.as_ptr(), index) };
+
+        let converted = {
+            UserdataCasts::<D>::registry_key().push_no_err(raw_lua).forget_internal();
+            unsafe { ffi::lua_rawget(raw_lua.as_ptr(), ffi::LUA_REGISTRYINDEX) };
+            let guard = unsafe { PushGuard::new(raw_lua, 1) };
+            match UserdataOnStack::<UserdataCasts<D>, _>::lua_read(guard) {
+                // Arguments are read outside of `catch_unwind`, so the conversion mustn't unwind.
+                Ok(casts) => casts.casts.get(&typeid).and_then(|cast| {
+                    panic::catch_unwind(AssertUnwindSafe(|| cast(ptr))).ok().flatten()
+                }),
+                Err(_) => None,
+            }
+        };
+
+        match converted {
+            Some(converted) => Ok(DynUserdata(converted)),
+            None => Err(lua),
+        }
+    }
+}
+
+impl<D: ?Sized> Deref for DynUserdata<D> {
+    type Target = D;
+
+    #[inline]
+    fn deref(&self) -> &D {
+        &self.0
+    }
+}
+
+impl<D: ?Sized> DerefMut for DynUserdata<D> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut D {
+        &mut self.0
+    }
+}
+
+// Addresses whose light userdata are the registry keys of the metatables of each type. The first
+// byte is the key of the metatable with a destructor, the second one of the metatable without.
+//
+// Light userdata are much cheaper to push and to look up than strings, which matters since the
+// metatable is fetched every time a userdata is pushed. Each thread caches the keys it has used,
+// so that only the first lookup of a type takes the lock.
+static METATABLE_KEYS: Mutex<BTreeMap<TypeId, &'static [u8; 2]>> = Mutex::new(BTreeMap::new());
+
+thread_local! {
+    static METATABLE_KEYS_CACHE: RefCell<BTreeMap<TypeId, &'static [u8; 2]>> =
+        const { RefCell::new(BTreeMap::new()) };
+}
+
+// Returns the registry key of the metatable of the type `typeid`, with or without a destructor.
+// If no key exists yet, creates one if `create` is true and returns `None` otherwise.
+#[inline]
+fn metatable_key(typeid: TypeId, with_drop: bool, create: bool) -> Option<*mut c_void> {
+    #[cold]
+    fn slow_path(typeid: TypeId, create: bool) -> Option<&'static [u8; 2]> {
+        let mut keys = METATABLE_KEYS.lock().unwrap_or_else(|err| err.into_inner());
+        let keys = match keys.get(&typeid) {
+            Some(keys) => *keys,
+            None if create => *keys.entry(typeid).or_insert_with(|| Box::leak(Box::new([0; 2]))),
+            None => return None,
+        };
+        METATABLE_KEYS_CACHE.with(|cache| cache.borrow_mut().insert(typeid, keys));
+        Some(keys)
+    }
+
+    let keys = METATABLE_KEYS_CACHE
+        .with(|cache| cache.borrow().get(&typeid).copied())
+        .or_else(|| slow_path(typeid, create))?;
+    let key = &keys[usize::from(!with_drop)];
+    Some(key as *const u8 as *mut c_void)
+}
+
+#[inline]
+fn push_userdata_impl<'lua, L, T, F>(
+    data: T,
+    mut lua: L,
+    metatable: F,
+    with_drop: bool,
+) -> PushGuard<L>
 where
     F: FnOnce(LuaTable<OpaqueLua<'lua>>),
     L: AsMutLua<'lua>,
@@ -187,7 +721,7 @@ where
     /// This allows the compiler to not instantiate the entire function once
     /// for each different `L` that might call the outer function.
     #[inline(never)]
-    unsafe fn inner<'lua, T, F>(data: T, mut lua: LuaContext, metatable: F)
+    unsafe fn inner<'lua, T, F>(data: T, mut lua: LuaContext, metatable: F, with_drop: bool)
     where
         F: FnOnce(LuaTable<OpaqueLua<'lua>>),
         T: Send + Any + 'static,
@@ -196,21 +730,21 @@ where
         unsafe fn create_metatable<'lua, T, F>(
             raw_lua: LuaContext,
             metatable: F,
-            tid_ptr: *const i8,
-            tid_len: usize,
+            key: *mut c_void,
+            with_gc: bool,
         ) where
             F: FnOnce(LuaTable<OpaqueLua<'lua>>),
             T: Send + Any + 'static,
         {
             // Create and register a metatable for T.
             ffi::lua_pop(raw_lua.as_ptr(), 1);
-            ffi::lua_createtable(raw_lua.as_ptr(), 0, i32::from(mem::needs_drop::<T>()));
-            ffi::lua_pushlstring(raw_lua.as_ptr(), tid_ptr, tid_len);
+            ffi::lua_createtable(raw_lua.as_ptr(), 0, i32::from(with_gc));
+            ffi::lua_pushlightuserdata(raw_lua.as_ptr(), key);
             ffi::lua_pushvalue(raw_lua.as_ptr(), -2);
             ffi::lua_rawset(raw_lua.as_ptr(), ffi::LUA_REGISTRYINDEX);
 
             // Only assign "__gc" if T needs to be dropped.
-            if mem::needs_drop::<T>() {
+            if with_gc {
                 "__gc".push_no_err(raw_lua).forget();
                 ffi::lua_pushcfunction(raw_lua.as_ptr(), Some(destructor_wrapper::<T>));
                 ffi::lua_rawset(raw_lua.as_ptr(), -3);
@@ -226,28 +760,330 @@ where
         let raw_lua = lua.as_mut_lua();
         raw::create(data, |len| ffi::lua_newuserdata(raw_lua.as_ptr(), len));
 
-        // Get TypeId of T.
-        let typeid = TypeId::of::<T>();
-        let tid_ptr = addr_of!(typeid).cast();
-        let tid_len = std::mem::size_of::<TypeId>();
+        // Types that don't need to be dropped share the same metatable in both cases.
+        let with_gc = with_drop && mem::needs_drop::<T>();
+        let with_drop = with_gc || !mem::needs_drop::<T>();
+        let key = metatable_key(TypeId::of::<T>(), with_drop, true).unwrap();
 
         // Get the metatable if one already exists.
-        ffi::lua_pushlstring(raw_lua.as_ptr(), tid_ptr, tid_len);
+        ffi::lua_pushlightuserdata(raw_lua.as_ptr(), key);
         ffi::lua_rawget(raw_lua.as_ptr(), ffi::LUA_REGISTRYINDEX);
 
         // If no metatable exists, create one.
         if ffi::lua_isnil(raw_lua.as_ptr(), -1) {
-            create_metatable::<'_, T, _>(raw_lua, metatable, tid_ptr, tid_len);
+            create_metatable::<'_, T, _>(raw_lua, metatable, key, with_gc);
         }
 
         ffi::lua_setmetatable(raw_lua.as_ptr(), -2);
     }
 
     let raw_lua = lua.as_mut_lua();
-    unsafe { inner(data, raw_lua, metatable) };
+    unsafe { inner(data, raw_lua, metatable, with_drop) };
     PushGuard { lua, size: 1, raw_lua }
 }
 
+// `__index` metamethod installed by `MetatableBuilder` once a getter has been registered.
+// Upvalue 1 is the table of methods and fields, upvalue 2 is the table of getters.
+extern "C" fn index_dispatcher(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe {
+        // Arguments are `(object, key)`.
+        ffi::lua_pushvalue(lua, 2);
+        ffi::lua_rawget(lua, ffi::lua_upvalueindex(2));
+        if !ffi::lua_isnil(lua, -1) {
+            ffi::lua_pushvalue(lua, 1);
+            ffi::lua_call(lua, 1, 1);
+            return 1;
+        }
+
+        ffi::lua_pop(lua, 1);
+        ffi::lua_pushvalue(lua, 2);
+        ffi::lua_rawget(lua, ffi::lua_upvalueindex(1));
+        1
+    }
+}
+
+// `__newindex` metamethod installed by `MetatableBuilder` once a setter has been registered.
+// Upvalue 1 is the table of setters.
+extern "C" fn newindex_dispatcher(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe {
+        // Arguments are `(object, key, value)`.
+        ffi::lua_pushvalue(lua, 2);
+        ffi::lua_rawget(lua, ffi::lua_upvalueindex(1));
+        if ffi::lua_isnil(lua, -1) {
+            // `lua_error` doesn't return, so the message has to be dropped before.
+            {
+                let raw_lua = LuaContext::new_unchecked(lua);
+                let msg = match StrictRead::<String>::lua_read_at_position(raw_lua, 2) {
+                    Ok(StrictRead(key)) => format!("cannot assign to field '{}'", key),
+                    Err(_) => "cannot assign to this field".to_owned(),
+                };
+                msg.push_no_err(raw_lua).forget();
+            }
+            crate::ffix::lua_error(lua);
+        }
+
+        ffi::lua_pushvalue(lua, 1);
+        ffi::lua_pushvalue(lua, 3);
+        ffi::lua_call(lua, 2, 0);
+        0
+    }
+}
+
+/// Helper that fills the metatable of a userdata, meant to be used from the closure passed to
+/// `push_userdata` or `implement_lua_push!`.
+///
+/// Methods and fields are stored in the `__index` table of the metatable, which is created the
+/// first time it is needed. Methods that take the object as their first parameter need
+/// `implement_lua_read!` to be used on the type.
+///
+/// Getters and setters expose computed fields: once one is registered, `obj.name` and
+/// `obj.name = value` from Lua call them with the object. Assigning to a field that has no setter
+/// raises a Lua error.
+///
+/// # Example
+///
+/// ```
+/// #[macro_use] extern crate hlua;
+/// # fn main() {
+/// struct Foo { name: String }
+///
+/// implement_lua_read!(Foo);
+/// implement_lua_push!(Foo, |meta| {
+///     hlua::MetatableBuilder::new(meta)
+///         .method("greet", |this: &Foo| format!("hello {}", this.name))
+///         .field("kind", "foo")
+///         .tostring(|this: &Foo| this.name.clone());
+/// });
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.openlibs();
+/// lua.set("foo", Foo { name: "bob".to_owned() });
+///
+/// let greeting: String = lua.execute("return foo:greet()").unwrap();
+/// assert_eq!(greeting, "hello bob");
+/// let kind: String = lua.execute("return foo.kind").unwrap();
+/// assert_eq!(kind, "foo");
+/// let name: String = lua.execute("return tostring(foo)").unwrap();
+/// assert_eq!(name, "bob");
+/// # }
+/// ```
+///
+/// # Example: getters and setters
+///
+/// ```
+/// #[macro_use] extern crate hlua;
+/// # fn main() {
+/// struct Point { x: f64, y: f64 }
+///
+/// implement_lua_read!(Point);
+/// implement_lua_push!(Point, |meta| {
+///     hlua::MetatableBuilder::new(meta)
+///         .getter("x", |p: &Point| p.x)
+///         .setter("x", |p: &mut Point, x: f64| p.x = x)
+///         .getter("y", |p: &Point| p.y)
+///         .setter("y", |p: &mut Point, y: f64| p.y = y);
+/// });
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.set("p", Point { x: 1.0, y: 2.0 });
+///
+/// lua.execute::<()>("p.x = p.x + 1").unwrap();
+/// assert_eq!(lua.execute::<f64>("return p.x + p.y").unwrap(), 4.0);
+/// # }
+/// ```
+pub struct MetatableBuilder<'lua> {
+    metatable: LuaTable<OpaqueLua<'lua>>,
+    // Whether `__index` and `__newindex` have been replaced with the dispatchers.
+    has_getters: bool,
+    has_setters: bool,
+}
+
+impl<'lua> MetatableBuilder<'lua> {
+    /// Starts building on top of the given metatable.
+    #[inline]
+    pub fn new(metatable: LuaTable<OpaqueLua<'lua>>) -> MetatableBuilder<'lua> {
+        MetatableBuilder { metatable, has_getters: false, has_setters: false }
+    }
+
+    /// Adds a method that Lua code can call with `obj:name(...)`.
+    #[inline]
+    pub fn method<Z, P, R>(self, name: &str, function: impl Into<Function<Z, P, R>>) -> Self
+    where
+        Function<Z, P, R>: PushOne<OpaqueLua<'lua>, Err = Void>,
+    {
+        self.index_set(name, function.into())
+    }
+
+    /// Adds a constant value that Lua code can read with `obj.name`.
+    #[inline]
+    pub fn field<V, E>(self, name: &str, value: V) -> Self
+    where
+        V: PushOne<OpaqueLua<'lua>, Err = E>,
+        E: Into<Void>,
+    {
+        self.index_set(name, value)
+    }
+
+    /// Adds a getter called with the object when Lua code reads `obj.name`.
+    ///
+    /// Getters take precedence over methods and fields with the same name.
+    #[inline]
+    pub fn getter<Z, P, R>(mut self, name: &str, function: impl Into<Function<Z, P, R>>) -> Self
+    where
+        Function<Z, P, R>: PushOne<OpaqueLua<'lua>, Err = Void>,
+    {
+        unsafe {
+            let pushed = self.push_getters_table();
+            self.set_on_top(name, function.into(), pushed);
+        }
+        self
+    }
+
+    /// Adds a setter called with the object and the new value when Lua code writes
+    /// `obj.name = value`.
+    #[inline]
+    pub fn setter<Z, P, R>(mut self, name: &str, function: impl Into<Function<Z, P, R>>) -> Self
+    where
+        Function<Z, P, R>: PushOne<OpaqueLua<'lua>, Err = Void>,
+    {
+        unsafe {
+            let pushed = self.push_setters_table();
+            self.set_on_top(name, function.into(), pushed);
+        }
+        self
+    }
+
+    /// Sets the `__tostring` metamethod, which is used by the `tostring` Lua function.
+    #[inline]
+    pub fn tostring<Z, P, R>(self, function: impl Into<Function<Z, P, R>>) -> Self
+    where
+        Function<Z, P, R>: PushOne<OpaqueLua<'lua>, Err = Void>,
+    {
+        self.metamethod("__tostring", function)
+    }
+
+    /// Sets an arbitrary metamethod, for example `__eq` or `__add`.
+    #[inline]
+    pub fn metamethod<Z, P, R>(mut self, name: &str, function: impl Into<Function<Z, P, R>>) -> Self
+    where
+        Function<Z, P, R>: PushOne<OpaqueLua<'lua>, Err = Void>,
+    {
+        let raw_lua = self.metatable.as_mut_lua();
+        unsafe {
+            name.push_no_err(raw_lua).forget();
+            function.into().push_no_err(OpaqueLua::new(&mut self.metatable)).forget();
+            ffi::lua_rawset(raw_lua.as_ptr(), self.metatable.offset(-2));
+        }
+        self
+    }
+
+    /// Returns the metatable being built.
+    #[inline]
+    pub fn into_inner(self) -> LuaTable<OpaqueLua<'lua>> {
+        self.metatable
+    }
+
+    // Sets `metatable.__index[name] = value`, creating the `__index` table if necessary.
+    fn index_set<V, E>(mut self, name: &str, value: V) -> Self
+    where
+        V: PushOne<OpaqueLua<'lua>, Err = E>,
+        E: Into<Void>,
+    {
+        unsafe {
+            let pushed = self.push_index_table();
+            self.set_on_top(name, value, pushed);
+        }
+        self
+    }
+
+    // Sets `name` to `value` in the table at the top of the stack, then pops `pushed` elements.
+    unsafe fn set_on_top<V, E>(&mut self, name: &str, value: V, pushed: i32)
+    where
+        V: PushOne<OpaqueLua<'lua>, Err = E>,
+        E: Into<Void>,
+    {
+        let raw_lua = self.metatable.as_mut_lua();
+        name.push_no_err(raw_lua).forget();
+        value.push_no_err(OpaqueLua::new(&mut self.metatable)).forget();
+        ffi::lua_rawset(raw_lua.as_ptr(), -3);
+        ffi::lua_pop(raw_lua.as_ptr(), pushed);
+    }
+
+    // Pushes the table of methods and fields, creating it if necessary. Returns the number of
+    // elements that have been pushed.
+    unsafe fn push_index_table(&mut self) -> i32 {
+        let raw_lua = self.metatable.as_mut_lua();
+        "__index".push_no_err(raw_lua).forget();
+        ffi::lua_rawget(raw_lua.as_ptr(), self.metatable.offset(-1));
+
+        if self.has_getters {
+            // The table is the first upvalue of the dispatcher.
+            ffi::lua_getupvalue(raw_lua.as_ptr(), -1, 1);
+            return 2;
+        }
+
+        if !ffi::lua_istable(raw_lua.as_ptr(), -1) {
+            ffi::lua_pop(raw_lua.as_ptr(), 1);
+            ffi::lua_newtable(raw_lua.as_ptr());
+            "__index".push_no_err(raw_lua).forget();
+            ffi::lua_pushvalue(raw_lua.as_ptr(), -2);
+            ffi::lua_rawset(raw_lua.as_ptr(), self.metatable.offset(-3));
+        }
+        1
+    }
+
+    // Pushes the table of getters, replacing `__index` with the dispatcher if necessary. Returns
+    // the number of elements that have been pushed.
+    unsafe fn push_getters_table(&mut self) -> i32 {
+        let raw_lua = self.metatable.as_mut_lua();
+
+        if self.has_getters {
+            "__index".push_no_err(raw_lua).forget();
+            ffi::lua_rawget(raw_lua.as_ptr(), self.metatable.offset(-1));
+            ffi::lua_getupvalue(raw_lua.as_ptr(), -1, 2);
+            return 2;
+        }
+
+        let pushed = self.push_index_table();
+        ffi::lua_newtable(raw_lua.as_ptr());
+        "__index".push_no_err(raw_lua).forget();
+        ffi::lua_pushvalue(raw_lua.as_ptr(), -3);
+        ffi::lua_pushvalue(raw_lua.as_ptr(), -3);
+        ffi::lua_pushcclosure(raw_lua.as_ptr(), Some(index_dispatcher), 2);
+        ffi::lua_rawset(raw_lua.as_ptr(), self.metatable.offset(-(pushed + 3)));
+        self.has_getters = true;
+        pushed + 1
+    }
+
+    // Pushes the table of setters, replacing `__newindex` with the dispatcher if necessary.
+    // Returns the number of elements that have been pushed.
+    unsafe fn push_setters_table(&mut self) -> i32 {
+        let raw_lua = self.metatable.as_mut_lua();
+
+        if self.has_setters {
+            "__newindex".push_no_err(raw_lua).forget();
+            ffi::lua_rawget(raw_lua.as_ptr(), self.metatable.offset(-1));
+            ffi::lua_getupvalue(raw_lua.as_ptr(), -1, 1);
+            return 2;
+        }
+
+        ffi::lua_newtable(raw_lua.as_ptr());
+        "__newindex".push_no_err(raw_lua).forget();
+        ffi::lua_pushvalue(raw_lua.as_ptr(), -2);
+        ffi::lua_pushcclosure(raw_lua.as_ptr(), Some(newindex_dispatcher), 1);
+        ffi::lua_rawset(raw_lua.as_ptr(), self.metatable.offset(-3));
+        self.has_setters = true;
+        1
+    }
+}
+
+impl<'lua> From<LuaTable<OpaqueLua<'lua>>> for MetatableBuilder<'lua> {
+    #[inline]
+    fn from(metatable: LuaTable<OpaqueLua<'lua>>) -> MetatableBuilder<'lua> {
+        MetatableBuilder::new(metatable)
+    }
+}
+
 ///
 #[inline]
 pub fn read_userdata<'t, 'c, T>(
@@ -263,6 +1099,69 @@ where
     }
 }
 
+/// Returns the `TypeId` of the Rust object held by the userdata at `index`.
+///
+/// Returns `None` if the value isn't a userdata created by `push_userdata`. This lets code that
+/// handles userdata of various types find out which one it was given before reading it.
+///
+/// Userdata created by `Scope::set_userdata` don't hold the object they refer to, and their type
+/// doesn't match the one of the object.
+///
+/// # Example
+///
+/// ```
+/// #[macro_use] extern crate hlua;
+/// # fn main() {
+/// use std::any::TypeId;
+/// use hlua::Push;
+///
+/// struct Foo;
+/// implement_lua_push!(Foo, |_| {});
+///
+/// let mut lua = hlua::Lua::new();
+/// let guard = Foo.push_no_err(&mut lua);
+/// assert_eq!(hlua::userdata_type_id(&guard, -1), Some(TypeId::of::<Foo>()));
+/// # }
+/// ```
+pub fn userdata_type_id<'lua, L>(lua: &L, index: i32) -> Option<TypeId>
+where
+    L: AsLua<'lua>,
+{
+    let raw_lua = lua.as_lua();
+    let ptr = raw_lua.as_ptr();
+
+    unsafe {
+        // Foreign userdata may be too small to hold a head.
+        if ffi::lua_type(ptr, index) != ffi::LUA_TUSERDATA
+            || crate::ffix::lua_rawlen(raw_lua, index) < mem::size_of::<raw::Head>()
+        {
+            return None;
+        }
+
+        let data = ffi::lua_touserdata(ptr, index);
+        if data.is_null() || ffi::lua_getmetatable(ptr, index) == 0 {
+            return None;
+        }
+
+        // The head is only trusted if the metatable is one of those registered for its type.
+        let typeid = raw::util::head_ref(data).type_id;
+        let registered = [true, false].into_iter().any(|with_drop| {
+            let key = match metatable_key(typeid, with_drop, false) {
+                Some(key) => key,
+                None => return false,
+            };
+            ffi::lua_pushlightuserdata(ptr, key);
+            ffi::lua_rawget(ptr, ffi::LUA_REGISTRYINDEX);
+            let registered = ffi::lua_rawequal(ptr, -1, -2) != 0;
+            ffi::lua_pop(ptr, 1);
+            registered
+        });
+        ffi::lua_pop(ptr, 1);
+
+        registered.then_some(typeid)
+    }
+}
+
 /// Represents a user data located inside the Lua context.
 #[derive(Debug)]
 pub struct UserdataOnStack<T, L> {
@@ -280,7 +1179,7 @@ where
     fn lua_read_at_position(lua: L, index: i32) -> Result<UserdataOnStack<T, L>, L> {
         unsafe {
             match NonNull::new(ffi::lua_touserdata(lua.as_lua().as_ptr(), index)) {
-                Some(x) if raw::util::validate_type::<T>(x.as_ptr()) => {
+                Some(x) if raw::util::resolve::<T>(x.as_ptr()).is_some() => {
                     Ok(UserdataOnStack { variable: lua, index, marker: PhantomData })
                 },
                 _ => Err(lua),
@@ -311,6 +1210,22 @@ where
     }
 }
 
+impl<'lua, T, L> UserdataOnStack<T, L>
+where
+    L: AsLua<'lua>,
+    T: 'lua + Any,
+{
+    /// Returns a number that identifies the userdata.
+    ///
+    /// Two `UserdataOnStack` have the same identity if and only if they refer to the same Lua
+    /// object. The identity stays the same for as long as the userdata is alive, but may be
+    /// reused once it has been garbage collected.
+    #[inline]
+    pub fn identity(&self) -> usize {
+        unsafe { ffi::lua_topointer(self.variable.as_lua().as_ptr(), self.index) as usize }
+    }
+}
+
 impl<'lua, T, L> Deref for UserdataOnStack<T, L>
 where
     L: AsLua<'lua>,
@@ -322,7 +1237,8 @@ where
     fn deref(&self) -> &T {
         unsafe {
             let ptr = self.variable.as_lua().as_ptr();
-            raw::util::data_ref::<T>(ffi::lua_touserdata(ptr, self.index))
+            let data = raw::util::resolve::<T>(ffi::lua_touserdata(ptr, self.index));
+            &*data.expect("the scope of the userdata has ended")
         }
     }
 }
@@ -336,7 +1252,8 @@ where
     fn deref_mut(&mut self) -> &mut T {
         unsafe {
             let ptr = self.variable.as_lua().as_ptr();
-            raw::util::data_mut::<T>(ffi::lua_touserdata(ptr, self.index))
+            let data = raw::util::resolve::<T>(ffi::lua_touserdata(ptr, self.index));
+            &mut *data.expect("the scope of the userdata has ended")
         }
     }
 }
@@ -1,6 +1,20 @@
-use std::{borrow::Cow, marker::PhantomData, mem, ops::Deref, slice, str};
-
-use crate::{AnyLuaString, AsLua, AsMutLua, LuaRead, Push, PushGuard, PushOne, Void};
+use std::{
+    borrow::Cow,
+    error::Error,
+    fmt,
+    marker::PhantomData,
+    mem,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    num::{
+        NonZeroI16, NonZeroI32, NonZeroI8, NonZeroIsize, NonZeroU16, NonZeroU32, NonZeroU8,
+        NonZeroUsize,
+    },
+    ops::Deref,
+    slice, str,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{AnyLuaString, AsLua, AsMutLua, LuaContext, LuaRead, Push, PushGuard, PushOne, Void};
 
 macro_rules! integer_impl(
     ($t:ident) => (
@@ -28,6 +42,11 @@ macro_rules! integer_impl(
                     _ => Ok(val as $t)
                 }
             }
+
+            #[inline]
+            fn expected() -> Cow<'static, str> {
+                Cow::Borrowed("integer")
+            }
         }
     );
 );
@@ -68,6 +87,11 @@ macro_rules! unsigned_impl(
                     _ => Ok(val as $t)
                 }
             }
+
+            #[inline]
+            fn expected() -> Cow<'static, str> {
+                Cow::Borrowed("integer")
+            }
         }
     );
 );
@@ -98,6 +122,11 @@ macro_rules! numeric_impl(
                     _ => Ok(val as $t)
                 }
             }
+
+            #[inline]
+            fn expected() -> Cow<'static, str> {
+                Cow::Borrowed("number")
+            }
         }
     );
 );
@@ -115,7 +144,194 @@ unsigned_impl!(u32);
 numeric_impl!(f32);
 numeric_impl!(f64);
 
-#[derive(Copy, Clone)]
+// Pointer-sized integers are only read, and the read fails instead of truncating the value when
+// it doesn't fit.
+//
+// There is no 64-bit integer path, even on 64-bit targets: `lua54-sys` builds Lua with 32-bit
+// `lua_Integer`s, and Lua 5.1 and 5.2 only have floats. Values outside of the `i32` range are
+// therefore stored as floats, and reading them as floats is exact up to 2^53. Past that, the
+// value is read if it is integral and in range, but Lua may already have rounded it.
+macro_rules! pointer_sized_impl(
+    ($t:ident) => (
+        impl<'lua, L> LuaRead<L> for $t where L: AsLua<'lua> {
+            #[inline]
+            fn lua_read_at_position(lua: L, index: i32) -> Result<$t, L> {
+                let mut success = mem::MaybeUninit::uninit();
+                let raw_lua = lua.as_lua().as_ptr();
+
+                let val = unsafe { ffi::lua_tonumberx(raw_lua, index, success.as_mut_ptr()) };
+                // `as` saturates, this is fine since the bounds of `$t` are well within the
+                // bounds of `i128`.
+                let val = (val.fract() == 0.0).then(|| <$t>::try_from(val as i128).ok()).flatten();
+
+                match (unsafe { success.assume_init() }, val) {
+                    (0, _) | (_, None) => Err(lua),
+                    (_, Some(val)) => Ok(val),
+                }
+            }
+
+            #[inline]
+            fn expected() -> Cow<'static, str> {
+                Cow::Borrowed("integer")
+            }
+        }
+    );
+);
+
+pointer_sized_impl!(isize);
+pointer_sized_impl!(usize);
+
+// Non-zero integers are pushed as their inner integer, and reading them fails if the value is
+// zero.
+macro_rules! non_zero_impl(
+    ($t:ident, $inner:ident) => (
+        impl<'lua, L> Push<L> for $t where L: AsMutLua<'lua> {
+            type Err = Void;
+
+            #[inline]
+            fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+                self.get().push_to_lua(lua)
+            }
+        }
+
+        impl<'lua, L> PushOne<L> for $t where L: AsMutLua<'lua> {
+        }
+
+        non_zero_impl!(read $t, $inner);
+    );
+
+    (read $t:ident, $inner:ident) => (
+        impl<'lua, L> LuaRead<L> for $t where L: AsLua<'lua> {
+            #[inline]
+            fn lua_read_at_position(lua: L, index: i32) -> Result<$t, L> {
+                match $inner::lua_read_at_position(lua.as_lua(), index) {
+                    Ok(val) => $t::new(val).ok_or(lua),
+                    Err(_) => Err(lua),
+                }
+            }
+
+            #[inline]
+            fn expected() -> Cow<'static, str> {
+                Cow::Borrowed("non-zero integer")
+            }
+        }
+    );
+);
+
+non_zero_impl!(NonZeroI8, i8);
+non_zero_impl!(NonZeroI16, i16);
+non_zero_impl!(NonZeroI32, i32);
+non_zero_impl!(NonZeroU8, u8);
+non_zero_impl!(NonZeroU16, u16);
+non_zero_impl!(NonZeroU32, u32);
+non_zero_impl!(read NonZeroIsize, isize);
+non_zero_impl!(read NonZeroUsize, usize);
+
+// Mutable references to `Copy` primitives are pushed by copying the value they point to.
+macro_rules! copy_ref_impl(
+    ($($t:ty),*) => ($(
+        impl<'a, 'lua, L> Push<L> for &'a mut $t where L: AsMutLua<'lua> {
+            type Err = Void;
+
+            #[inline]
+            fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+                (*self).push_to_lua(lua)
+            }
+        }
+
+        impl<'a, 'lua, L> PushOne<L> for &'a mut $t where L: AsMutLua<'lua> {
+        }
+    )*);
+);
+
+copy_ref_impl!(i8, i16, i32, u8, u16, u32, f32, f64, bool);
+
+/// Type of a Lua value, as returned by `lua_type`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LuaType {
+    /// `nil`.
+    Nil,
+    /// A boolean.
+    Boolean,
+    /// A light userdata, which is a raw pointer.
+    LightUserdata,
+    /// A number.
+    Number,
+    /// A string.
+    String,
+    /// A table.
+    Table,
+    /// A function written in Lua, C or Rust.
+    Function,
+    /// A full userdata.
+    Userdata,
+    /// A coroutine.
+    Thread,
+}
+
+impl LuaType {
+    /// Returns the type of the value at `index`, or `None` if the index isn't valid.
+    pub(crate) fn at(lua: LuaContext, index: i32) -> Option<LuaType> {
+        let types = [
+            (ffi::LUA_TNIL, LuaType::Nil),
+            (ffi::LUA_TBOOLEAN, LuaType::Boolean),
+            (ffi::LUA_TLIGHTUSERDATA, LuaType::LightUserdata),
+            (ffi::LUA_TNUMBER, LuaType::Number),
+            (ffi::LUA_TSTRING, LuaType::String),
+            (ffi::LUA_TTABLE, LuaType::Table),
+            (ffi::LUA_TFUNCTION, LuaType::Function),
+            (ffi::LUA_TUSERDATA, LuaType::Userdata),
+            (ffi::LUA_TTHREAD, LuaType::Thread),
+        ];
+
+        let raw_type = unsafe { ffi::lua_type(lua.as_ptr(), index) };
+        types.iter().find(|(t, _)| *t == raw_type).map(|(_, ty)| *ty)
+    }
+
+    /// Returns the name of the type, as returned by the `type` function of Lua, except for light
+    /// userdata.
+    #[inline]
+    pub fn name(self) -> &'static str {
+        match self {
+            LuaType::Nil => "nil",
+            LuaType::Boolean => "boolean",
+            LuaType::LightUserdata => "light userdata",
+            LuaType::Number => "number",
+            LuaType::String => "string",
+            LuaType::Table => "table",
+            LuaType::Function => "function",
+            LuaType::Userdata => "userdata",
+            LuaType::Thread => "thread",
+        }
+    }
+}
+
+impl fmt::Display for LuaType {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The `nil` value.
+///
+/// Pushing a `LuaNil` pushes `nil`, and reading one only succeeds if the value is `nil`, or is
+/// a missing argument of a callback. This states the intent more clearly than `None::<T>` when
+/// a value is expected to be `nil`. Note that `Lua::get` returns `None` for globals that are
+/// `nil`, whatever the type being read.
+///
+/// # Example
+///
+/// ```
+/// use hlua::LuaNil;
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.execute::<()>("cache = {}").unwrap();
+///
+/// assert_eq!(lua.execute::<LuaNil>("return cache.key").unwrap(), LuaNil);
+/// assert!(lua.execute::<LuaNil>("return false").is_err());
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct LuaNil;
 
 impl<'lua, L> Push<L> for LuaNil
@@ -134,6 +350,218 @@ where
 
 impl<'lua, L> PushOne<L> for LuaNil where L: AsMutLua<'lua> {}
 
+impl<'lua, L> LuaRead<L> for LuaNil
+where
+    L: AsLua<'lua>,
+{
+    #[inline]
+    fn lua_read_at_position(lua: L, index: i32) -> Result<LuaNil, L> {
+        match LuaType::at(lua.as_lua(), index) {
+            Some(LuaType::Nil) => Ok(LuaNil),
+            _ => Err(lua),
+        }
+    }
+
+    #[inline]
+    fn lua_read_out_of_bounds(_: L) -> Result<LuaNil, L> {
+        Ok(LuaNil)
+    }
+
+    #[inline]
+    fn expected() -> Cow<'static, str> {
+        Cow::Borrowed("nil")
+    }
+}
+
+// Only used for its address, which identifies `LuaNull` values.
+static NULL_MARKER: u8 = 0;
+
+#[inline]
+fn null_marker() -> *mut libc::c_void {
+    &NULL_MARKER as *const u8 as *mut libc::c_void
+}
+
+/// Explicit null value, distinct from `nil`.
+///
+/// Lua doesn't store `nil` in tables: assigning `nil` to a field removes it, and `lua_next` (and
+/// thus `pairs` and `LuaTable::iter`) never returns it. Pushing `None` for an `Option` therefore
+/// produces a table in which the field is missing. `LuaNull` is pushed as a light userdata that
+/// is kept in tables like any other value, so that "explicitly null" and "missing" can be told
+/// apart. Scripts can compare values against it if it is exposed to them, for example in a
+/// global variable.
+///
+/// Reading a `LuaNull` only succeeds if the value is this marker. See also `Nullable`.
+///
+/// # Example
+///
+/// ```
+/// let mut lua = hlua::Lua::new();
+/// lua.set("null", hlua::LuaNull);
+/// lua.execute::<()>("t = { a = null, b = nil }").unwrap();
+///
+/// assert!(lua.execute::<bool>("return t.a == null and t.b == nil").unwrap());
+/// assert!(lua.get_path::<hlua::LuaNull>("t.a").is_some());
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LuaNull;
+
+impl LuaNull {
+    #[inline]
+    fn is_at(lua: LuaContext, index: i32) -> bool {
+        unsafe {
+            ffi::lua_islightuserdata(lua.as_ptr(), index)
+                && ffi::lua_touserdata(lua.as_ptr(), index) == null_marker()
+        }
+    }
+}
+
+impl<'lua, L> Push<L> for LuaNull
+where
+    L: AsMutLua<'lua>,
+{
+    type Err = Void;
+
+    #[inline]
+    fn push_to_lua(self, mut lua: L) -> Result<PushGuard<L>, (Void, L)> {
+        let raw_lua = lua.as_mut_lua();
+        unsafe { ffi::lua_pushlightuserdata(raw_lua.as_ptr(), null_marker()) };
+        Ok(PushGuard { lua, size: 1, raw_lua })
+    }
+}
+
+impl<'lua, L> PushOne<L> for LuaNull where L: AsMutLua<'lua> {}
+
+impl<'lua, L> LuaRead<L> for LuaNull
+where
+    L: AsLua<'lua>,
+{
+    #[inline]
+    fn lua_read_at_position(lua: L, index: i32) -> Result<LuaNull, L> {
+        match LuaNull::is_at(lua.as_lua(), index) {
+            true => Ok(LuaNull),
+            false => Err(lua),
+        }
+    }
+}
+
+/// Raw pointer pushed as a Lua light userdata.
+///
+/// Light userdata are plain values that Lua compares and stores without allocating anything, so
+/// they are a cheap way to pass opaque handles to scripts, for example an index into a slab
+/// owned by the host. Unlike `push_userdata`, nothing is owned by Lua: the pointer is never
+/// dereferenced nor freed by this library, and nothing guarantees that it is still valid when it
+/// is read back. Scripts can't create light userdata, but they can keep one around after the
+/// object it points to is gone, so host code must check the pointer it reads before using it.
+///
+/// All light userdata share the same metatable, if any. `LuaNull` is also a light userdata, and
+/// is read as a `LightUserdata` too.
+///
+/// # Example
+///
+/// ```
+/// use hlua::LightUserdata;
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.set("handle", LightUserdata(42 as *mut _));
+///
+/// let handle: LightUserdata = lua.get("handle").unwrap();
+/// assert_eq!(handle.0 as usize, 42);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LightUserdata(pub *mut libc::c_void);
+
+impl<'lua, L> Push<L> for LightUserdata
+where
+    L: AsMutLua<'lua>,
+{
+    type Err = Void;
+
+    #[inline]
+    fn push_to_lua(self, mut lua: L) -> Result<PushGuard<L>, (Void, L)> {
+        let raw_lua = lua.as_mut_lua();
+        unsafe { ffi::lua_pushlightuserdata(raw_lua.as_ptr(), self.0) };
+        Ok(PushGuard { lua, size: 1, raw_lua })
+    }
+}
+
+impl<'lua, L> PushOne<L> for LightUserdata where L: AsMutLua<'lua> {}
+
+impl<'lua, L> LuaRead<L> for LightUserdata
+where
+    L: AsLua<'lua>,
+{
+    #[inline]
+    fn lua_read_at_position(lua: L, index: i32) -> Result<LightUserdata, L> {
+        unsafe {
+            match ffi::lua_islightuserdata(lua.as_lua().as_ptr(), index) {
+                true => Ok(LightUserdata(ffi::lua_touserdata(lua.as_lua().as_ptr(), index))),
+                false => Err(lua),
+            }
+        }
+    }
+}
+
+/// Optional value that is pushed as `LuaNull` instead of `nil` when absent.
+///
+/// Pushing a `Nullable(None)` pushes `LuaNull`, and reading `LuaNull` produces a
+/// `Nullable(None)`. Reading `nil` fails, so that reading a missing field of a table with
+/// `LuaTable::get` returns `None` while reading a field set to null returns
+/// `Some(Nullable(None))`.
+///
+/// # Example
+///
+/// ```
+/// use hlua::Nullable;
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.execute::<()>("user = {}").unwrap();
+/// let mut user: hlua::LuaTable<_> = lua.get("user").unwrap();
+/// user.set("email", Nullable::<i32>(None));
+///
+/// assert_eq!(user.get::<Nullable<String>, _, _>("email"), Some(Nullable(None)));
+/// assert_eq!(user.get::<Nullable<String>, _, _>("phone"), None);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Nullable<T>(pub Option<T>);
+
+impl<'lua, L, T, E> Push<L> for Nullable<T>
+where
+    T: Push<L, Err = E>,
+    L: AsMutLua<'lua>,
+{
+    type Err = E;
+
+    #[inline]
+    fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (E, L)> {
+        match self.0 {
+            Some(val) => val.push_to_lua(lua),
+            None => Ok(LuaNull.push_no_err(lua)),
+        }
+    }
+}
+
+impl<'lua, L, T, E> PushOne<L> for Nullable<T>
+where
+    T: PushOne<L, Err = E>,
+    L: AsMutLua<'lua>,
+{
+}
+
+impl<'lua, T, L> LuaRead<L> for Nullable<T>
+where
+    T: LuaRead<L>,
+    L: AsLua<'lua>,
+{
+    #[inline]
+    fn lua_read_at_position(lua: L, index: i32) -> Result<Nullable<T>, L> {
+        if LuaNull::is_at(lua.as_lua(), index) {
+            return Ok(Nullable(None));
+        }
+
+        T::lua_read_at_position(lua, index).map(|val| Nullable(Some(val)))
+    }
+}
+
 impl<'lua, L> Push<L> for String
 where
     L: AsMutLua<'lua>,
@@ -178,6 +606,11 @@ where
             Err(_) => Err(lua),
         }
     }
+
+    #[inline]
+    fn expected() -> Cow<'static, str> {
+        Cow::Borrowed("string")
+    }
 }
 
 impl<'lua, L> Push<L> for AnyLuaString
@@ -213,6 +646,11 @@ where
         let c_slice = unsafe { slice::from_raw_parts(c_str.cast(), size) };
         Ok(AnyLuaString(c_slice.to_vec()))
     }
+
+    #[inline]
+    fn expected() -> Cow<'static, str> {
+        Cow::Borrowed("string")
+    }
 }
 
 impl<'lua, 's, L> Push<L> for &'s str
@@ -291,130 +729,721 @@ impl<L> Deref for StringInLua<L> {
     type Target = str;
 
     #[inline]
-    fn deref(&self) -> &str {
-        let slice = unsafe { slice::from_raw_parts(self.ptr.cast(), self.len) };
-        unsafe { str::from_utf8(slice).unwrap_unchecked() }
+    fn deref(&self) -> &str {
+        let slice = unsafe { slice::from_raw_parts(self.ptr.cast(), self.len) };
+        unsafe { str::from_utf8(slice).unwrap_unchecked() }
+    }
+}
+
+/// Copies the bytes of the string at `index` on the stack of `lua` into `buf`, and returns the
+/// number of bytes written.
+///
+/// This is the same as reading an `AnyLuaString`, except that the content goes into a buffer
+/// provided by the caller instead of a newly-allocated `Vec`. As with the other string types,
+/// numbers are converted to strings.
+///
+/// # Example
+///
+/// ```
+/// use hlua::Push;
+///
+/// let mut lua = hlua::Lua::new();
+/// let value = "a\0b".push_no_err(&mut lua);
+///
+/// let mut buf = [0; 16];
+/// let len = hlua::read_bytes_into(&value, -1, &mut buf).unwrap();
+/// assert_eq!(&buf[..len], b"a\0b");
+///
+/// let mut small = [0; 2];
+/// assert!(hlua::read_bytes_into(&value, -1, &mut small).is_err());
+/// ```
+pub fn read_bytes_into<'lua, L>(lua: L, index: i32, buf: &mut [u8]) -> Result<usize, ReadBytesError>
+where
+    L: AsLua<'lua>,
+{
+    let mut len = mem::MaybeUninit::uninit();
+    let ptr = unsafe { ffi::lua_tolstring(lua.as_lua().as_ptr(), index, len.as_mut_ptr()) };
+    if ptr.is_null() {
+        return Err(ReadBytesError::WrongType);
+    }
+
+    let len = unsafe { len.assume_init() };
+    if len > buf.len() {
+        return Err(ReadBytesError::BufferTooSmall { len });
+    }
+
+    let bytes = unsafe { slice::from_raw_parts(ptr.cast(), len) };
+    buf[..len].copy_from_slice(bytes);
+    Ok(len)
+}
+
+/// Returns true if the values at `index1` and `index2` on the stack of `lua` are equal, without
+/// calling the `__eq` metamethod.
+///
+/// This wraps `lua_rawequal`. Tables, functions and userdata are only equal to themselves, so
+/// this is the comparison to use for caches keyed by Lua values, where a `__eq` metamethod could
+/// consider different objects equal or raise an error. Returns false if one of the indices isn't
+/// valid. See also `LuaResults::raw_equal`.
+///
+/// # Example
+///
+/// ```
+/// let mut lua = hlua::Lua::new();
+/// let values = lua.execute_results("return 'a', 'a', 2").unwrap().into_guard();
+///
+/// assert!(hlua::raw_equal(&values, -3, -2));
+/// assert!(!hlua::raw_equal(&values, -3, -1));
+/// ```
+#[inline]
+pub fn raw_equal<'lua, L>(lua: L, index1: i32, index2: i32) -> bool
+where
+    L: AsLua<'lua>,
+{
+    unsafe { ffi::lua_rawequal(lua.as_lua().as_ptr(), index1, index2) != 0 }
+}
+
+/// Error returned by `read_bytes_into`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadBytesError {
+    /// The value is neither a string nor a number.
+    WrongType,
+    /// The buffer is smaller than the string, whose length is `len`. Nothing has been written.
+    BufferTooSmall {
+        /// Length of the string in bytes.
+        len: usize,
+    },
+}
+
+impl fmt::Display for ReadBytesError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadBytesError::WrongType => write!(f, "The value isn't a string"),
+            ReadBytesError::BufferTooSmall { len } => {
+                write!(f, "The buffer is too small for a string of {} bytes", len)
+            },
+        }
+    }
+}
+
+impl Error for ReadBytesError {}
+
+/// String of at most `N` bytes, stored inline instead of on the heap.
+///
+/// Reading an `InlineString` copies the bytes of a Lua string into the buffer, with
+/// `read_bytes_into`, and fails if the string is longer than `N` bytes. This avoids allocating a
+/// `String` for each value when reading many short strings, for example the keys of a
+/// configuration table. As with `AnyLuaString`, the content doesn't have to be valid UTF-8, and
+/// numbers are converted to strings.
+///
+/// # Example
+///
+/// ```
+/// use hlua::InlineString;
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.execute::<()>("short = 'key'; long = 'a much longer string'").unwrap();
+///
+/// let key: InlineString<8> = lua.get("short").unwrap();
+/// assert_eq!(key.as_bytes(), b"key");
+/// assert_eq!(key.to_str(), Ok("key"));
+/// assert!(lua.get::<InlineString<8>, _>("long").is_none());
+/// ```
+#[derive(Clone, Copy)]
+pub struct InlineString<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> InlineString<N> {
+    /// Returns the content of the string.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Returns the content of the string, or an error if it isn't valid UTF-8.
+    #[inline]
+    pub fn to_str(&self) -> Result<&str, str::Utf8Error> {
+        str::from_utf8(self.as_bytes())
+    }
+
+    /// Returns the length of the string in bytes.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the string is empty.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<const N: usize> fmt::Debug for InlineString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&String::from_utf8_lossy(self.as_bytes()), f)
+    }
+}
+
+impl<const N: usize> PartialEq for InlineString<N> {
+    #[inline]
+    fn eq(&self, other: &InlineString<N>) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl<const N: usize> Eq for InlineString<N> {}
+
+impl<'lua, L, const N: usize> Push<L> for InlineString<N>
+where
+    L: AsMutLua<'lua>,
+{
+    type Err = Void;
+
+    #[inline]
+    fn push_to_lua(self, mut lua: L) -> Result<PushGuard<L>, (Void, L)> {
+        let raw_lua = lua.as_mut_lua();
+        unsafe { ffi::lua_pushlstring(raw_lua.as_ptr(), self.buf.as_ptr().cast(), self.len) };
+        Ok(PushGuard { lua, size: 1, raw_lua })
+    }
+}
+
+impl<'lua, L, const N: usize> PushOne<L> for InlineString<N> where L: AsMutLua<'lua> {}
+
+impl<'lua, L, const N: usize> LuaRead<L> for InlineString<N>
+where
+    L: AsLua<'lua>,
+{
+    #[inline]
+    fn lua_read_at_position(lua: L, index: i32) -> Result<InlineString<N>, L> {
+        let mut buf = [0; N];
+        match read_bytes_into(&lua, index, &mut buf) {
+            Ok(len) => Ok(InlineString { buf, len }),
+            Err(_) => Err(lua),
+        }
+    }
+}
+
+impl<'lua, L> Push<L> for bool
+where
+    L: AsMutLua<'lua>,
+{
+    type Err = Void;
+
+    #[inline]
+    fn push_to_lua(self, mut lua: L) -> Result<PushGuard<L>, (Void, L)> {
+        let raw_lua = lua.as_mut_lua();
+        unsafe { ffi::lua_pushboolean(raw_lua.as_ptr(), libc::c_int::from(self)) };
+        Ok(PushGuard { lua, size: 1, raw_lua })
+    }
+}
+
+impl<'lua, L> PushOne<L> for bool where L: AsMutLua<'lua> {}
+
+// Only booleans are read. Converting other values with the truthiness rules of Lua would read
+// `0` and `""` as `true`, which is rarely what is wanted.
+impl<'lua, L> LuaRead<L> for bool
+where
+    L: AsLua<'lua>,
+{
+    #[inline]
+    fn lua_read_at_position(lua: L, index: i32) -> Result<bool, L> {
+        let raw_lua = lua.as_lua();
+        match unsafe { ffi::lua_isboolean(raw_lua.as_ptr(), index) } {
+            true => Ok(unsafe { ffi::lua_toboolean(raw_lua.as_ptr(), index) != 0 }),
+            false => Err(lua),
+        }
+    }
+
+    #[inline]
+    fn expected() -> Cow<'static, str> {
+        Cow::Borrowed("boolean")
+    }
+}
+
+impl<'lua, L> Push<L> for ()
+where
+    L: AsMutLua<'lua>,
+{
+    type Err = Void;
+
+    #[inline]
+    fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+        let raw_lua = lua.as_lua();
+        Ok(PushGuard { lua, size: 0, raw_lua })
+    }
+}
+
+impl<'lua, L> LuaRead<L> for ()
+where
+    L: AsLua<'lua>,
+{
+    #[inline]
+    fn lua_read_at_position(_: L, _: i32) -> Result<(), L> {
+        Ok(())
+    }
+}
+
+impl<'lua, L, T, E> Push<L> for Option<T>
+where
+    T: Push<L, Err = E>,
+    L: AsMutLua<'lua>,
+{
+    type Err = E;
+
+    #[inline]
+    fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (E, L)> {
+        match self {
+            Some(val) => val.push_to_lua(lua),
+            None => Ok(LuaNil.push_no_err(lua)),
+        }
+    }
+}
+
+impl<'lua, L, T, E> PushOne<L> for Option<T>
+where
+    T: PushOne<L, Err = E>,
+    L: AsMutLua<'lua>,
+{
+}
+
+impl<'lua, T, L> LuaRead<L> for Option<T>
+where
+    T: LuaRead<L>,
+    L: AsLua<'lua>,
+{
+    #[inline]
+    fn lua_read_at_position(lua: L, index: i32) -> Result<Option<T>, L> {
+        if unsafe { ffi::lua_isnoneornil(lua.as_lua().as_ptr(), index) } {
+            return Ok(None);
+        }
+
+        T::lua_read_at_position(lua, index).map(Some)
+    }
+
+    #[inline]
+    fn lua_read_out_of_bounds(_: L) -> Result<Self, L> {
+        Ok(None)
+    }
+
+    #[inline]
+    fn expected() -> Cow<'static, str> {
+        Cow::Owned(format!("{} or nil", T::expected()))
+    }
+}
+
+impl<'lua, 'str, L> Push<L> for Cow<'str, str>
+where
+    L: AsMutLua<'lua>,
+{
+    type Err = Void;
+
+    #[inline]
+    fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+        match self {
+            Cow::Borrowed(val) => val.push_to_lua(lua),
+            Cow::Owned(val) => val.push_to_lua(lua),
+        }
+    }
+}
+
+impl<'lua, 'str, L> PushOne<L> for Cow<'str, str> where L: AsMutLua<'lua> {}
+
+// Types that are represented in Lua by their string representation. They are pushed with
+// `Display`, and reading them fails if the string can't be parsed with `FromStr`.
+macro_rules! string_repr_impl(
+    ($t:ty) => (
+        impl<'lua, L> Push<L> for $t where L: AsMutLua<'lua> {
+            type Err = Void;
+
+            #[inline]
+            fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+                self.to_string().push_to_lua(lua)
+            }
+        }
+
+        impl<'lua, L> PushOne<L> for $t where L: AsMutLua<'lua> {
+        }
+
+        impl<'lua, L> LuaRead<L> for $t where L: AsLua<'lua> {
+            #[inline]
+            fn lua_read_at_position(lua: L, index: i32) -> Result<$t, L> {
+                match StringInLua::lua_read_at_position(lua.as_lua(), index) {
+                    Ok(s) => s.parse().map_err(|_| lua),
+                    Err(_) => Err(lua),
+                }
+            }
+        }
+    );
+);
+
+string_repr_impl!(IpAddr);
+string_repr_impl!(Ipv4Addr);
+string_repr_impl!(Ipv6Addr);
+string_repr_impl!(SocketAddr);
+// Lua numbers can't hold 128-bit integers without loss, so they are stored as decimal strings.
+string_repr_impl!(i128);
+string_repr_impl!(u128);
+
+// Timestamps are represented as the number of seconds since the Unix epoch, like the values
+// returned by `os.time`. Reading fails for negative and non-finite numbers.
+impl<'lua, L> Push<L> for SystemTime
+where
+    L: AsMutLua<'lua>,
+{
+    type Err = Void;
+
+    #[inline]
+    fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+        let secs = match self.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs_f64(),
+            Err(err) => -err.duration().as_secs_f64(),
+        };
+        secs.push_to_lua(lua)
+    }
+}
+
+impl<'lua, L> PushOne<L> for SystemTime where L: AsMutLua<'lua> {}
+
+impl<'lua, L> LuaRead<L> for SystemTime
+where
+    L: AsLua<'lua>,
+{
+    #[inline]
+    fn lua_read_at_position(lua: L, index: i32) -> Result<SystemTime, L> {
+        let secs = match f64::lua_read_at_position(lua.as_lua(), index) {
+            Ok(secs) => secs,
+            Err(_) => return Err(lua),
+        };
+        // `try_from_secs_f64` refuses negative and non-finite numbers.
+        Duration::try_from_secs_f64(secs)
+            .ok()
+            .and_then(|since| UNIX_EPOCH.checked_add(since))
+            .ok_or(lua)
     }
 }
 
-impl<'lua, L> Push<L> for bool
+/// Wrapper that pushes a value as the string returned by its `Display` implementation.
+///
+/// This is the same as pushing `value.to_string()`, except that the value is formatted directly
+/// into the Lua string without allocating a `String` first.
+///
+/// # Example
+///
+/// ```
+/// use hlua::PushDisplay;
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.set("a", PushDisplay(format_args!("{}-{}", 1, 2)));
+///
+/// let a: String = lua.get("a").unwrap();
+/// assert_eq!(a, "1-2");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PushDisplay<T>(pub T);
+
+impl<'lua, L, T> Push<L> for PushDisplay<T>
 where
     L: AsMutLua<'lua>,
+    T: fmt::Display,
 {
     type Err = Void;
 
     #[inline]
     fn push_to_lua(self, mut lua: L) -> Result<PushGuard<L>, (Void, L)> {
         let raw_lua = lua.as_mut_lua();
-        unsafe { ffi::lua_pushboolean(raw_lua.as_ptr(), libc::c_int::from(self)) };
+
+        let mut writer =
+            LuaStringWriter { lua: raw_lua.as_ptr(), buf: [0; 256], len: 0, pieces: 0 };
+        if fmt::write(&mut writer, format_args!("{}", self.0)).is_err() {
+            unsafe { ffi::lua_pop(raw_lua.as_ptr(), writer.pieces) };
+            panic!("a Display implementation returned an error unexpectedly");
+        }
+        writer.flush();
+        // Concatenating no piece pushes an empty string.
+        unsafe { ffi::lua_concat(raw_lua.as_ptr(), writer.pieces) };
+
         Ok(PushGuard { lua, size: 1, raw_lua })
     }
 }
 
-impl<'lua, L> PushOne<L> for bool where L: AsMutLua<'lua> {}
-
-impl<'lua, L> LuaRead<L> for bool
+impl<'lua, L, T> PushOne<L> for PushDisplay<T>
 where
-    L: AsLua<'lua>,
+    L: AsMutLua<'lua>,
+    T: fmt::Display,
 {
-    #[inline]
-    fn lua_read_at_position(lua: L, index: i32) -> Result<bool, L> {
-        let raw_lua = lua.as_lua();
-        match unsafe { ffi::lua_isboolean(raw_lua.as_ptr(), index) } {
-            true => Ok(unsafe { ffi::lua_toboolean(raw_lua.as_ptr(), index) != 0 }),
-            false => Err(lua),
+}
+
+// Writes formatted text to the Lua stack. The text is accumulated in `buf` and pushed in pieces,
+// which are concatenated regularly in order to keep the number of stack slots used bounded.
+struct LuaStringWriter {
+    lua: *mut ffi::lua_State,
+    buf: [u8; 256],
+    len: usize,
+    pieces: i32,
+}
+
+impl LuaStringWriter {
+    const MAX_PIECES: i32 = 8;
+
+    fn flush(&mut self) {
+        if self.len != 0 {
+            let len = mem::replace(&mut self.len, 0);
+            Self::push_piece(self.lua, &mut self.pieces, &self.buf[..len]);
+        }
+    }
+
+    fn push_piece(lua: *mut ffi::lua_State, pieces: &mut i32, piece: &[u8]) {
+        unsafe {
+            ffi::lua_pushlstring(lua, piece.as_ptr().cast(), piece.len() as libc::size_t);
+            *pieces += 1;
+            if *pieces == Self::MAX_PIECES {
+                ffi::lua_concat(lua, *pieces);
+                *pieces = 1;
+            }
         }
     }
 }
 
-impl<'lua, L> Push<L> for ()
-where
-    L: AsMutLua<'lua>,
-{
-    type Err = Void;
+impl fmt::Write for LuaStringWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if s.len() > self.buf.len() - self.len {
+            self.flush();
+        }
+
+        if s.len() > self.buf.len() {
+            Self::push_piece(self.lua, &mut self.pieces, s.as_bytes());
+        } else {
+            self.buf[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+            self.len += s.len();
+        }
+        Ok(())
+    }
+}
+
+/// Wrapper that reads a value without letting Lua coerce it from another type.
+///
+/// Lua normally converts numbers to strings and numeric strings to numbers when they are read,
+/// which means that the string `"2"` can be read as an `i32`. Reading a `StrictRead<T>` instead
+/// checks the type of the value beforehand and fails if it isn't the one `T` expects.
+///
+/// # Example
+///
+/// ```
+/// use hlua::StrictRead;
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.set("a", "2");
+///
+/// assert_eq!(lua.get::<i32, _>("a"), Some(2));
+/// assert_eq!(lua.get::<StrictRead<i32>, _>("a"), None);
+/// assert_eq!(lua.get::<StrictRead<String>, _>("a"), Some(StrictRead("2".to_owned())));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct StrictRead<T>(pub T);
 
+impl<T> StrictRead<T> {
+    /// Returns the value that was read.
     #[inline]
-    fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
-        let raw_lua = lua.as_lua();
-        Ok(PushGuard { lua, size: 0, raw_lua })
+    pub fn into_inner(self) -> T {
+        self.0
     }
 }
 
-impl<'lua, L> LuaRead<L> for ()
+impl<T> Deref for StrictRead<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+/// Types that can be read through a `StrictRead`.
+pub trait StrictType {
+    /// The Lua type, as returned by `lua_type`, that a value must have to be read as `Self`.
+    const LUA_TYPE: i32;
+}
+
+macro_rules! strict_type_impl(
+    ($lua_type:expr => $($t:ty),+) => (
+        $(
+            impl StrictType for $t {
+                const LUA_TYPE: i32 = $lua_type;
+            }
+        )+
+    );
+);
+
+strict_type_impl!(ffi::LUA_TNUMBER => i8, i16, i32, isize, u8, u16, u32, usize, f32, f64);
+strict_type_impl!(ffi::LUA_TSTRING => String, AnyLuaString);
+strict_type_impl!(ffi::LUA_TBOOLEAN => bool);
+
+impl<L> StrictType for StringInLua<L> {
+    const LUA_TYPE: i32 = ffi::LUA_TSTRING;
+}
+
+impl<'lua, L, T> LuaRead<L> for StrictRead<T>
 where
     L: AsLua<'lua>,
+    T: LuaRead<L> + StrictType,
 {
     #[inline]
-    fn lua_read_at_position(_: L, _: i32) -> Result<(), L> {
-        Ok(())
+    fn lua_read_at_position(lua: L, index: i32) -> Result<StrictRead<T>, L> {
+        if unsafe { ffi::lua_type(lua.as_lua().as_ptr(), index) } != T::LUA_TYPE {
+            return Err(lua);
+        }
+
+        T::lua_read_at_position(lua, index).map(StrictRead)
     }
 }
 
-impl<'lua, L, T, E> Push<L> for Option<T>
+/// A Lua number, which tells whether it is an integer or a float.
+///
+/// Since Lua 5.3, numbers have an integer subtype: `5` is an integer while `5.0` and `5 / 1` are
+/// floats, even though they compare equal. Reading a `LuaNumber` keeps this distinction, which all
+/// the other numeric reads erase. Older versions of Lua don't have integers, and every number is
+/// read as a `Float`.
+///
+/// Contrary to the other numeric types, strings are never converted: reading a string as a
+/// `LuaNumber` fails. Pushing a `LuaNumber` pushes a value of the same subtype, except for
+/// integers outside of the range of `i32`, which are pushed as floats.
+///
+/// # Example
+///
+/// ```
+/// use hlua::LuaNumber;
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.execute::<()>("a = 5; b = 5.0; c = '5'").unwrap();
+///
+/// assert_eq!(lua.get::<LuaNumber, _>("b"), Some(LuaNumber::Float(5.0)));
+/// assert_eq!(lua.get::<LuaNumber, _>("c"), None);
+///
+/// // `Integer(5)` with Lua 5.4, `Float(5.0)` with older versions.
+/// let a: LuaNumber = lua.get("a").unwrap();
+/// # let _ = a;
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LuaNumber {
+    /// A number with the integer subtype.
+    Integer(i64),
+    /// A number with the float subtype.
+    Float(f64),
+}
+
+impl<'lua, L> Push<L> for LuaNumber
 where
-    T: Push<L, Err = E>,
     L: AsMutLua<'lua>,
 {
-    type Err = E;
+    type Err = Void;
 
     #[inline]
-    fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (E, L)> {
+    fn push_to_lua(self, mut lua: L) -> Result<PushGuard<L>, (Void, L)> {
+        let raw_lua = lua.as_mut_lua();
+
         match self {
-            Some(val) => val.push_to_lua(lua),
-            None => Ok(LuaNil.push_no_err(lua)),
+            // `lua54-sys` builds Lua with 32-bit integers, larger values are pushed as floats.
+            #[cfg(feature = "_luaapi_54")]
+            LuaNumber::Integer(n) => match i32::try_from(n) {
+                Ok(n) => unsafe { ffi::lua_pushinteger(raw_lua.as_ptr(), n as _) },
+                Err(_) => unsafe { ffi::lua_pushnumber(raw_lua.as_ptr(), n as _) },
+            },
+            #[cfg(not(feature = "_luaapi_54"))]
+            LuaNumber::Integer(n) => unsafe { ffi::lua_pushnumber(raw_lua.as_ptr(), n as _) },
+            LuaNumber::Float(n) => unsafe { ffi::lua_pushnumber(raw_lua.as_ptr(), n as _) },
         }
+
+        Ok(PushGuard { lua, size: 1, raw_lua })
     }
 }
 
-impl<'lua, L, T, E> PushOne<L> for Option<T>
-where
-    T: PushOne<L, Err = E>,
-    L: AsMutLua<'lua>,
-{
-}
+impl<'lua, L> PushOne<L> for LuaNumber where L: AsMutLua<'lua> {}
 
-impl<'lua, T, L> LuaRead<L> for Option<T>
+impl<'lua, L> LuaRead<L> for LuaNumber
 where
-    T: LuaRead<L>,
     L: AsLua<'lua>,
 {
     #[inline]
-    fn lua_read_at_position(lua: L, index: i32) -> Result<Option<T>, L> {
-        if unsafe { ffi::lua_isnoneornil(lua.as_lua().as_ptr(), index) } {
-            return Ok(None);
+    fn lua_read_at_position(lua: L, index: i32) -> Result<LuaNumber, L> {
+        if LuaType::at(lua.as_lua(), index) != Some(LuaType::Number) {
+            return Err(lua);
         }
 
-        T::lua_read_at_position(lua, index).map(Some)
+        let raw_lua = lua.as_lua().as_ptr();
+        unsafe {
+            #[cfg(feature = "_luaapi_54")]
+            if ffi::lua_isinteger(raw_lua, index) != 0 {
+                // `lua54-sys` builds Lua with 32-bit integers, the upper bits are meaningless.
+                let n = ffi::lua_tointegerx(raw_lua, index, std::ptr::null_mut());
+                return Ok(LuaNumber::Integer(n as i32 as i64));
+            }
+
+            Ok(LuaNumber::Float(ffi::lua_tonumberx(raw_lua, index, std::ptr::null_mut()) as f64))
+        }
     }
 
     #[inline]
-    fn lua_read_out_of_bounds(_: L) -> Result<Self, L> {
-        Ok(None)
+    fn expected() -> Cow<'static, str> {
+        Cow::Borrowed("number")
     }
 }
 
-impl<'lua, 'str, L> Push<L> for Cow<'str, str>
-where
-    L: AsMutLua<'lua>,
-{
-    type Err = Void;
+#[cfg(feature = "impl-uuid")]
+mod uuid {
+    use std::str;
 
-    #[inline]
-    fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
-        match self {
-            Cow::Borrowed(val) => val.push_to_lua(lua),
-            Cow::Owned(val) => val.push_to_lua(lua),
+    use uuid::Uuid;
+
+    use crate::{read_bytes_into, AsLua, AsMutLua, LuaRead, Push, PushGuard, PushOne, Void};
+
+    /// Pushed as a lowercase hyphenated string, such as
+    /// `"67e55044-10b1-426f-9247-bb680e5fe0c8"`.
+    impl<'lua, L> Push<L> for Uuid
+    where
+        L: AsMutLua<'lua>,
+    {
+        type Err = Void;
+
+        #[inline]
+        fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+            let mut buf = Uuid::encode_buffer();
+            let s: &str = self.hyphenated().encode_lower(&mut buf);
+            s.push_to_lua(lua)
         }
     }
-}
 
-impl<'lua, 'str, L> PushOne<L> for Cow<'str, str> where L: AsMutLua<'lua> {}
+    impl<'lua, L> PushOne<L> for Uuid where L: AsMutLua<'lua> {}
+
+    /// Reads any of the formats accepted by `Uuid::parse_str`. Fails if the value isn't a string
+    /// or isn't a valid UUID.
+    impl<'lua, L> LuaRead<L> for Uuid
+    where
+        L: AsLua<'lua>,
+    {
+        #[inline]
+        fn lua_read_at_position(lua: L, index: i32) -> Result<Uuid, L> {
+            // The longest format is the URN one, `urn:uuid:` followed by the hyphenated form.
+            let mut buf = [0; 45];
+            let parsed = read_bytes_into(&lua, index, &mut buf).ok().and_then(|len| {
+                let s = str::from_utf8(&buf[..len]).ok()?;
+                Uuid::parse_str(s).ok()
+            });
+            parsed.ok_or(lua)
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
-    use std::borrow::Cow;
+    use std::{borrow::Cow, fmt};
 
-    use crate::{AnyLuaString, AnyLuaValue, Lua, StringInLua};
+    use crate::{
+        read_bytes_into, AnyLuaString, AnyLuaValue, AsLua, InlineString, LightUserdata, Lua,
+        LuaNil, LuaNull, LuaNumber, LuaTable, Nullable, Push, PushDisplay, ReadBytesError,
+        StrictRead, StringInLua,
+    };
 
     #[test]
     fn read_i32s() {
@@ -565,6 +1594,22 @@ mod tests {
         assert_eq!(x, 2);
     }
 
+    #[test]
+    fn write_mut_refs() {
+        let mut lua = Lua::new();
+
+        let mut values = [1, 2, 3];
+        for (i, v) in values.iter_mut().enumerate() {
+            *v *= 10;
+            lua.set(format!("v{}", i), v);
+        }
+        let mut flag = true;
+        lua.set("flag", &mut flag);
+
+        assert_eq!(lua.get::<i32, _>("v2"), Some(30));
+        assert_eq!(lua.get::<bool, _>("flag"), Some(true));
+    }
+
     #[test]
     fn readwrite_floats() {
         let mut lua = Lua::new();
@@ -599,6 +1644,22 @@ mod tests {
         assert_eq!(y, false);
     }
 
+    #[test]
+    fn bools_are_read_strictly() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("zero = 0; one = 1; empty = ''; str = 'true'; f = false").unwrap();
+
+        for name in ["zero", "one", "empty", "str"] {
+            assert_eq!(lua.get::<bool, _>(name), None, "{}", name);
+            assert_eq!(lua.get::<Option<bool>, _>(name), None, "{}", name);
+        }
+        assert_eq!(lua.get::<Option<bool>, _>("f"), Some(Some(false)));
+        assert!(lua.execute::<bool>("return 0").is_err());
+        assert_eq!(lua.execute::<Option<bool>>("return nil").unwrap(), None);
+        assert!(lua.execute::<Option<bool>>("return 0").is_err());
+        assert_eq!(lua.execute::<Option<bool>>("return true").unwrap(), Some(true));
+    }
+
     #[test]
     fn readwrite_strings() {
         let mut lua = Lua::new();
@@ -662,6 +1723,49 @@ mod tests {
         }
     }
 
+    #[test]
+    fn bytes_into_buffer() {
+        let mut lua = Lua::new();
+        let value = lua.execute::<AnyLuaString>("return '\\x01\\x00\\xff'").unwrap();
+        let value = value.push_no_err(&mut lua);
+
+        let mut buf = [7; 4];
+        assert_eq!(read_bytes_into(&value, -1, &mut buf), Ok(3));
+        assert_eq!(buf, [1, 0, 255, 7]);
+        assert_eq!(read_bytes_into(&value, -1, &mut buf[..3]), Ok(3));
+
+        let mut small = [7; 2];
+        assert_eq!(
+            read_bytes_into(&value, -1, &mut small),
+            Err(ReadBytesError::BufferTooSmall { len: 3 })
+        );
+        assert_eq!(small, [7, 7]);
+        drop(value);
+
+        let value = true.push_no_err(&mut lua);
+        assert_eq!(read_bytes_into(&value, -1, &mut buf), Err(ReadBytesError::WrongType));
+    }
+
+    #[test]
+    fn nullable_fields_round_trip() {
+        let mut lua = Lua::new();
+        {
+            let mut table = lua.empty_array("t");
+            table.set("some", Nullable(Some(5)));
+            table.set("null", Nullable::<i32>(None));
+            table.set("nil", None::<i32>);
+        }
+
+        let mut table: LuaTable<_> = lua.get("t").unwrap();
+        assert_eq!(table.get::<Nullable<i32>, _, _>("some"), Some(Nullable(Some(5))));
+        assert_eq!(table.get::<Nullable<i32>, _, _>("null"), Some(Nullable(None)));
+        assert_eq!(table.get::<Nullable<i32>, _, _>("nil"), None);
+        assert_eq!(table.get::<LuaNull, _, _>("null"), Some(LuaNull));
+        assert_eq!(table.get::<LuaNull, _, _>("some"), None);
+        // Only the null field is seen by `lua_next`, in addition to the value.
+        assert_eq!(table.iter::<String, AnyLuaValue>().count(), 2);
+    }
+
     #[test]
     fn push_opt() {
         let mut lua = Lua::new();
@@ -706,4 +1810,278 @@ mod tests {
         assert_eq!(lua.get("ref_value"), Some("foo".to_string()));
         assert_eq!(lua.get("own_value"), Some("bar".to_string()));
     }
+
+    #[test]
+    fn strict_read_refuses_coercion() {
+        let mut lua = Lua::new();
+
+        lua.set("num", 2);
+        lua.set("numstr", "2");
+        lua.set("boolean", true);
+
+        assert_eq!(lua.get::<StrictRead<i32>, _>("num"), Some(StrictRead(2)));
+        assert_eq!(lua.get::<StrictRead<f64>, _>("num"), Some(StrictRead(2.0)));
+        assert_eq!(lua.get::<StrictRead<String>, _>("num"), None);
+        assert_eq!(lua.get::<StrictRead<AnyLuaString>, _>("num"), None);
+
+        assert_eq!(lua.get::<StrictRead<i32>, _>("numstr"), None);
+        assert_eq!(lua.get::<StrictRead<u8>, _>("numstr"), None);
+        assert_eq!(lua.get::<StrictRead<String>, _>("numstr").unwrap().into_inner(), "2");
+
+        assert_eq!(lua.get::<StrictRead<bool>, _>("boolean"), Some(StrictRead(true)));
+        assert_eq!(lua.get::<StrictRead<bool>, _>("num"), None);
+
+        // The non-strict reads still coerce.
+        assert_eq!(lua.get::<i32, _>("numstr"), Some(2));
+        assert_eq!(lua.get::<String, _>("num").as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn read_pointer_sized() {
+        let mut lua = Lua::new();
+
+        lua.execute::<()>("a = 12; b = -3; c = 2.5; d = 2^70; e = '7'").unwrap();
+
+        assert_eq!(lua.get::<usize, _>("a"), Some(12));
+        assert_eq!(lua.get::<isize, _>("a"), Some(12));
+        assert_eq!(lua.get::<isize, _>("b"), Some(-3));
+        assert_eq!(lua.get::<usize, _>("b"), None);
+        assert_eq!(lua.get::<usize, _>("c"), None);
+        assert_eq!(lua.get::<isize, _>("d"), None);
+        assert_eq!(lua.get::<usize, _>("d"), None);
+        assert_eq!(lua.get::<usize, _>("e"), Some(7));
+
+        #[cfg(target_pointer_width = "64")]
+        {
+            lua.execute::<()>("f = 2^40; g = -2^40; h = 2^53").unwrap();
+            assert_eq!(lua.get::<usize, _>("f"), Some(1 << 40));
+            assert_eq!(lua.get::<isize, _>("g"), Some(-(1 << 40)));
+            assert_eq!(lua.get::<usize, _>("h"), Some(1 << 53));
+        }
+    }
+
+    #[test]
+    fn big_integers_as_strings() {
+        let mut lua = Lua::new();
+
+        lua.set("id", u128::MAX);
+        assert_eq!(lua.execute::<String>("return id").unwrap(), u128::MAX.to_string());
+        assert_eq!(lua.get::<u128, _>("id"), Some(u128::MAX));
+        assert_eq!(lua.get::<i128, _>("id"), None);
+
+        lua.set("neg", i128::MIN);
+        assert_eq!(lua.get::<i128, _>("neg"), Some(i128::MIN));
+        assert_eq!(lua.get::<u128, _>("neg"), None);
+
+        lua.execute::<()>("small = 12; word = 'twelve'; float = 1.5").unwrap();
+        assert_eq!(lua.get::<i128, _>("small"), Some(12));
+        assert_eq!(lua.get::<i128, _>("word"), None);
+        assert_eq!(lua.get::<i128, _>("float"), None);
+    }
+
+    #[test]
+    fn net_addresses() {
+        use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+        let mut lua = Lua::new();
+
+        lua.set("ip", Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(lua.execute::<String>("return ip").unwrap(), "10.0.0.1");
+        assert_eq!(lua.get::<IpAddr, _>("ip"), Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))));
+        assert_eq!(lua.get::<Ipv6Addr, _>("ip"), None);
+
+        lua.set("v6", Ipv6Addr::LOCALHOST);
+        assert_eq!(lua.execute::<String>("return v6").unwrap(), "::1");
+
+        lua.execute::<()>("addr = '127.0.0.1:8080'; bad = '127.0.0.1:'; num = 12").unwrap();
+        let addr: SocketAddr = lua.get("addr").unwrap();
+        assert_eq!(addr, SocketAddr::from(([127, 0, 0, 1], 8080)));
+        assert_eq!(lua.get::<SocketAddr, _>("bad"), None);
+        assert_eq!(lua.get::<IpAddr, _>("num"), None);
+
+        // Also usable as callback parameters.
+        lua.set("port", crate::function1(|addr: SocketAddr| addr.port()));
+        assert_eq!(lua.execute::<u16>("return port('[::1]:53')").unwrap(), 53);
+    }
+
+    #[test]
+    fn non_zero_integers() {
+        use std::num::{NonZeroI32, NonZeroU8, NonZeroUsize};
+
+        let mut lua = Lua::new();
+
+        lua.set("a", NonZeroI32::new(-7).unwrap());
+        lua.set("zero", 0);
+        lua.set("big", 300);
+
+        assert_eq!(lua.get::<i32, _>("a"), Some(-7));
+        assert_eq!(lua.get::<NonZeroI32, _>("a"), NonZeroI32::new(-7));
+        assert_eq!(lua.get::<NonZeroI32, _>("zero"), None);
+        assert_eq!(lua.get::<NonZeroUsize, _>("zero"), None);
+        assert_eq!(lua.get::<NonZeroUsize, _>("big"), NonZeroUsize::new(300));
+        assert_eq!(lua.get::<NonZeroU8, _>("zero"), None);
+
+        // Zero is refused at the boundary when used as a parameter.
+        lua.set("id", crate::function1(|id: NonZeroI32| id.get()));
+        assert_eq!(lua.execute::<i32>("return id(5)").unwrap(), 5);
+        assert!(lua.execute::<i32>("return id(0)").is_err());
+    }
+
+    #[test]
+    fn push_display() {
+        struct Repeat<'a>(&'a str, usize);
+        impl fmt::Display for Repeat<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                (0..self.1).try_for_each(|_| f.write_str(self.0))
+            }
+        }
+
+        let mut lua = Lua::new();
+
+        lua.set("empty", PushDisplay(""));
+        lua.set("short", PushDisplay(12.5));
+        // Long enough to be pushed in many pieces.
+        lua.set("long", PushDisplay(Repeat("abc", 1000)));
+        lua.set("huge", PushDisplay(Repeat(&"x".repeat(300), 3)));
+
+        assert_eq!(lua.get::<String, _>("empty").unwrap(), "");
+        assert_eq!(lua.get::<String, _>("short").unwrap(), "12.5");
+        assert_eq!(lua.get::<String, _>("long").unwrap(), "abc".repeat(1000));
+        assert_eq!(lua.get::<String, _>("huge").unwrap(), "x".repeat(900));
+        assert_eq!(lua.stack_size(), 0);
+    }
+
+    #[test]
+    fn system_time() {
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        let mut lua = Lua::new();
+        let time = UNIX_EPOCH + Duration::from_millis(1_500_000_000_250);
+        lua.set("t", time);
+        assert_eq!(lua.get::<f64, _>("t"), Some(1_500_000_000.25));
+        assert_eq!(lua.get::<SystemTime, _>("t"), Some(time));
+
+        lua.set("before", UNIX_EPOCH - Duration::from_secs(10));
+        assert_eq!(lua.get::<f64, _>("before"), Some(-10.0));
+
+        for invalid in ["-1", "0/0", "1/0", "'soon'"] {
+            let result = lua.execute::<SystemTime>(&format!("return {}", invalid));
+            assert!(result.is_err(), "{}", invalid);
+        }
+        assert_eq!(lua.execute::<SystemTime>("return 0").unwrap(), UNIX_EPOCH);
+    }
+
+    #[test]
+    fn integer_or_float() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("a = 7; b = 7.0; c = 2^53; d = 'x'").unwrap();
+
+        let a: LuaNumber = lua.get("a").unwrap();
+        match () {
+            #[cfg(feature = "_luaapi_54")]
+            () => assert_eq!(a, LuaNumber::Integer(7)),
+            #[cfg(not(feature = "_luaapi_54"))]
+            () => assert_eq!(a, LuaNumber::Float(7.0)),
+        }
+        assert_eq!(lua.get::<LuaNumber, _>("b"), Some(LuaNumber::Float(7.0)));
+        assert_eq!(lua.get::<LuaNumber, _>("c"), Some(LuaNumber::Float(2f64.powi(53))));
+        assert_eq!(lua.get::<LuaNumber, _>("d"), None);
+
+        // The subtype is kept when the number is pushed back.
+        lua.set("e", a);
+        lua.set("f", LuaNumber::Float(7.0));
+        assert_eq!(lua.get::<LuaNumber, _>("e"), Some(a));
+        assert_eq!(lua.get::<LuaNumber, _>("f"), Some(LuaNumber::Float(7.0)));
+    }
+
+    #[test]
+    fn integer_or_float_limits() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("a = -5").unwrap();
+        lua.set("b", LuaNumber::Integer(i32::MIN.into()));
+
+        match () {
+            #[cfg(feature = "_luaapi_54")]
+            () => {
+                assert_eq!(lua.get::<LuaNumber, _>("a"), Some(LuaNumber::Integer(-5)));
+                assert_eq!(lua.get::<LuaNumber, _>("b"), Some(LuaNumber::Integer(i32::MIN.into())));
+            },
+            #[cfg(not(feature = "_luaapi_54"))]
+            () => assert_eq!(lua.get::<LuaNumber, _>("a"), Some(LuaNumber::Float(-5.0))),
+        }
+
+        // Integers that don't fit in a Lua integer are pushed as floats.
+        lua.set("c", LuaNumber::Integer(1 << 40));
+        lua.set("d", LuaNumber::Integer(-(1 << 40)));
+        assert_eq!(lua.get::<LuaNumber, _>("c"), Some(LuaNumber::Float(2f64.powi(40))));
+        assert_eq!(lua.get::<LuaNumber, _>("d"), Some(LuaNumber::Float(-(2f64.powi(40)))));
+    }
+
+    #[test]
+    fn light_userdata() {
+        let mut lua = Lua::new();
+        let mut slot = 0u32;
+        let ptr = &mut slot as *mut u32 as *mut libc::c_void;
+
+        lua.set("a", LightUserdata(ptr));
+        lua.set("b", LightUserdata(ptr));
+        lua.set("c", LightUserdata(std::ptr::null_mut()));
+        lua.execute::<()>("d = {}").unwrap();
+
+        assert!(lua.execute::<bool>("return a == b and a ~= c").unwrap());
+        assert_eq!(lua.get::<LightUserdata, _>("a"), Some(LightUserdata(ptr)));
+        assert_eq!(lua.get::<LightUserdata, _>("c"), Some(LightUserdata(std::ptr::null_mut())));
+        assert_eq!(lua.get::<LightUserdata, _>("d"), None);
+        assert!(lua.get::<LuaNull, _>("a").is_none());
+    }
+
+    #[test]
+    fn inline_strings() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("a = 'abcd'; b = 'abcde'; c = 12; d = ''; e = {}; f = '\\xff'").unwrap();
+
+        let a: InlineString<4> = lua.get("a").unwrap();
+        assert_eq!(a.as_bytes(), b"abcd");
+        assert_eq!(a.len(), 4);
+        assert!(lua.get::<InlineString<4>, _>("b").is_none());
+        assert_eq!(lua.get::<InlineString<4>, _>("c").unwrap().to_str(), Ok("12"));
+        assert!(lua.get::<InlineString<4>, _>("d").unwrap().is_empty());
+        assert!(lua.get::<InlineString<0>, _>("d").is_some());
+        assert!(lua.get::<InlineString<4>, _>("e").is_none());
+        assert!(lua.get::<InlineString<4>, _>("f").unwrap().to_str().is_err());
+
+        lua.set("g", a);
+        assert_eq!(lua.get::<String, _>("g").unwrap(), "abcd");
+    }
+
+    #[test]
+    #[cfg(feature = "impl-uuid")]
+    fn uuids() {
+        use uuid::Uuid;
+
+        let mut lua = Lua::new();
+        let id = Uuid::parse_str("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap();
+
+        lua.set("id", id);
+        assert_eq!(lua.get::<String, _>("id").unwrap(), "67e55044-10b1-426f-9247-bb680e5fe0c8");
+        assert_eq!(lua.get::<Uuid, _>("id"), Some(id));
+
+        lua.execute::<()>("a = '67E55044-10B1-426F-9247-BB680E5FE0C8'; b = 'not a uuid'; c = 5")
+            .unwrap();
+        assert_eq!(lua.get::<Uuid, _>("a"), Some(id));
+        assert_eq!(lua.get::<Uuid, _>("b"), None);
+        assert_eq!(lua.get::<Uuid, _>("c"), None);
+    }
+
+    #[test]
+    fn read_nil() {
+        let mut lua = Lua::new();
+        lua.set("f", crate::function2(|a: LuaNil, b: Option<i32>| b.is_none() && a == LuaNil));
+
+        assert!(lua.execute::<bool>("return f(nil, nil)").unwrap());
+        assert!(lua.execute::<bool>("return f()").unwrap());
+        assert!(lua.execute::<bool>("return f(false)").is_err());
+        assert!(lua.execute::<LuaNil>("return nil").is_ok());
+        assert!(lua.execute::<LuaNil>("return 0").is_err());
+    }
 }
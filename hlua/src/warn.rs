@@ -0,0 +1,79 @@
+use std::{
+    ffi::CStr,
+    panic::{self, AssertUnwindSafe},
+};
+
+use crate::{
+    panic_handler, push_userdata, LuaContext, LuaRead, Push, PushGuard, ScriptFailure,
+    UserdataOnStack,
+};
+
+// Key of the handler in the registry.
+const REGISTRY_KEY: &str = "hlua_warn_handler";
+
+struct WarnHandler {
+    // Lua only passes `ud` to the handler, so the context is kept to report panics.
+    lua: LuaContext,
+    handler: Box<dyn FnMut(&str) + Send>,
+    // Pieces of the message being built, when `warn` is called with several arguments.
+    pending: Vec<u8>,
+}
+
+// The context is only used from the handler, which runs on the thread that owns the `Lua`.
+unsafe impl Send for WarnHandler {}
+
+/// Stores `handler` in the registry of the Lua context and installs it with `lua_setwarnf`,
+/// replacing the previous one if any.
+pub(crate) fn set(lua: LuaContext, handler: Box<dyn FnMut(&str) + Send>) {
+    let handler = WarnHandler { lua, handler, pending: Vec::new() };
+
+    unsafe {
+        REGISTRY_KEY.push_no_err(lua).forget();
+        push_userdata(handler, lua, |_| {}).forget();
+
+        // The user data is kept alive by the registry, so its address stays valid until the
+        // handler is replaced or the context is closed.
+        ffi::lua_pushvalue(lua.as_ptr(), -1);
+        let ud = {
+            let guard = PushGuard::new(lua, 1);
+            let mut ud = UserdataOnStack::<WarnHandler, _>::lua_read(guard).ok().unwrap();
+            &mut *ud as *mut WarnHandler
+        };
+
+        ffi::lua_setwarnf(lua.as_ptr(), Some(warn_wrapper), ud.cast());
+        ffi::lua_rawset(lua.as_ptr(), ffi::LUA_REGISTRYINDEX);
+    }
+}
+
+/// Uninstalls the handler before the context is closed, so that warnings emitted by finalizers
+/// don't reach a handler that has already been destroyed.
+pub(crate) fn clear(lua: LuaContext) {
+    unsafe { ffi::lua_setwarnf(lua.as_ptr(), None, std::ptr::null_mut()) };
+}
+
+// Called by Lua for each piece of a warning. `tocont` is non-zero when more pieces follow.
+extern "C" fn warn_wrapper(ud: *mut libc::c_void, msg: *const libc::c_char, tocont: libc::c_int) {
+    let handler = unsafe { &mut *ud.cast::<WarnHandler>() };
+    let piece = unsafe { CStr::from_ptr(msg) }.to_bytes();
+
+    // Control messages such as `@on` and `@off` are only meaningful to the default handler.
+    if handler.pending.is_empty() && tocont == 0 && piece.starts_with(b"@") {
+        return;
+    }
+
+    handler.pending.extend_from_slice(piece);
+    if tocont != 0 {
+        return;
+    }
+
+    let message = String::from_utf8_lossy(&handler.pending).into_owned();
+    handler.pending.clear();
+
+    // This can be called by the garbage collector, where raising an error isn't allowed, so
+    // panics are only reported to the panic handler.
+    let result = panic::catch_unwind(AssertUnwindSafe(|| (handler.handler)(&message)));
+    if let Err(payload) = result {
+        let msg = panic_handler::panic_message(&*payload);
+        panic_handler::notify(handler.lua, ScriptFailure::CallbackPanic(msg));
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    marker::PhantomData,
+    ptr,
+    sync::{
+        atomic::{AtomicPtr, Ordering},
+        Arc,
+    },
+};
+
+use crate::{push_userdata, AsMutLua, LuaContext, LuaRead, PushGuard, UserdataOnStack};
+
+// Key of the table holding the referenced values in the registry.
+const REGISTRY_KEY: &str = "hlua_weak_refs";
+// Key of the `StateGuard` of the context in the registry.
+const STATE_KEY: &str = "hlua_weak_refs_state";
+
+/// Reference to a Lua value that doesn't prevent it from being garbage collected.
+///
+/// The value is stored in a table of the registry whose values are weak, so that it can be
+/// retrieved with `get` for as long as something else keeps it alive. This is useful to keep a
+/// Rust-side index of objects created by scripts without leaking them.
+///
+/// Each reference uses its own address as the key of the value in that table, which guarantees
+/// that a reference never sees an object that was stored by another one, even after its value has
+/// been collected. Dropping the reference removes the entry, so that values that aren't
+/// collectable, such as numbers, booleans and strings, don't stay in the table. A reference that
+/// outlives its Lua context can still be dropped.
+///
+/// # Example
+///
+/// ```
+/// let mut lua = hlua::Lua::new();
+/// lua.openlibs();
+/// lua.execute::<()>("obj = {}").unwrap();
+///
+/// let weak = {
+///     let mut table: hlua::LuaTable<_> = lua.get("obj").unwrap();
+///     hlua::WeakLuaRef::new(&mut table, -1)
+/// };
+/// assert!(weak.get(&mut lua).is_some());
+///
+/// lua.execute::<()>("obj = nil; collectgarbage()").unwrap();
+/// assert!(weak.get(&mut lua).is_none());
+/// ```
+#[derive(Debug)]
+pub struct WeakLuaRef {
+    // Only used for its address, which is unique for as long as the reference lives.
+    key: Box<u8>,
+    // Main thread of the context, or null once the context has been closed.
+    state: Arc<AtomicPtr<ffi::lua_State>>,
+    // Dropping the reference modifies the context, which can't be used from another thread.
+    marker: PhantomData<LuaContext>,
+}
+
+impl WeakLuaRef {
+    /// Creates a weak reference to the value at `index` on the stack of `lua`.
+    pub fn new<'lua, L>(lua: L, index: i32) -> WeakLuaRef
+    where
+        L: AsMutLua<'lua>,
+    {
+        let mut lua = lua;
+        let raw_lua = lua.as_mut_lua().as_ptr();
+        let key = Box::new(0);
+        let state = unsafe { state(raw_lua) };
+
+        unsafe {
+            ffi::lua_pushvalue(raw_lua, index);
+            push_table(raw_lua);
+            ffi::lua_pushlightuserdata(raw_lua, key_ptr(&key));
+            ffi::lua_pushvalue(raw_lua, -3);
+            ffi::lua_rawset(raw_lua, -3);
+            ffi::lua_pop(raw_lua, 2);
+        }
+
+        WeakLuaRef { key, state, marker: PhantomData }
+    }
+
+    /// Pushes the referenced value on the stack of `lua`, or returns `None` if it has been
+    /// collected.
+    ///
+    /// `lua` must be the context in which the reference was created. With another context, the
+    /// value is never found.
+    pub fn get<'lua, L>(&self, lua: L) -> Option<PushGuard<L>>
+    where
+        L: AsMutLua<'lua>,
+    {
+        let mut lua = lua;
+        let raw_lua = lua.as_mut_lua();
+
+        unsafe {
+            push_table(raw_lua.as_ptr());
+            ffi::lua_pushlightuserdata(raw_lua.as_ptr(), key_ptr(&self.key));
+            ffi::lua_rawget(raw_lua.as_ptr(), -2);
+            // Replacing the table with the value.
+            ffi::lua_copy(raw_lua.as_ptr(), -1, -2);
+            ffi::lua_pop(raw_lua.as_ptr(), 1);
+
+            if ffi::lua_isnil(raw_lua.as_ptr(), -1) {
+                ffi::lua_pop(raw_lua.as_ptr(), 1);
+                return None;
+            }
+        }
+
+        Some(PushGuard { lua, size: 1, raw_lua })
+    }
+}
+
+impl Drop for WeakLuaRef {
+    fn drop(&mut self) {
+        let raw_lua = self.state.load(Ordering::Acquire);
+        if raw_lua.is_null() || unsafe { ffi::lua_checkstack(raw_lua, 3) } == 0 {
+            return;
+        }
+
+        unsafe {
+            push_table(raw_lua);
+            ffi::lua_pushlightuserdata(raw_lua, key_ptr(&self.key));
+            ffi::lua_pushnil(raw_lua);
+            ffi::lua_rawset(raw_lua, -3);
+            ffi::lua_pop(raw_lua, 1);
+        }
+    }
+}
+
+// Stored in the registry, and dropped when the context is closed, which tells the references
+// that they must not use it anymore.
+struct StateGuard(Arc<AtomicPtr<ffi::lua_State>>);
+
+impl Drop for StateGuard {
+    #[inline]
+    fn drop(&mut self) {
+        self.0.store(ptr::null_mut(), Ordering::Release);
+    }
+}
+
+// Returns the state shared by the references of the context, creating it if it doesn't exist yet.
+unsafe fn state(raw_lua: *mut ffi::lua_State) -> Arc<AtomicPtr<ffi::lua_State>> {
+    let lua: LuaContext = ptr::NonNull::new_unchecked(raw_lua);
+
+    ffi::lua_pushlstring(raw_lua, STATE_KEY.as_ptr().cast(), STATE_KEY.len());
+    ffi::lua_rawget(raw_lua, ffi::LUA_REGISTRYINDEX);
+    if let Ok(guard) = UserdataOnStack::<StateGuard, _>::lua_read(lua) {
+        let state = guard.0.clone();
+        ffi::lua_pop(raw_lua, 1);
+        return state;
+    }
+    ffi::lua_pop(raw_lua, 1);
+
+    // References may be dropped while a coroutine is running, or after it has been collected,
+    // so they use the main thread when it is known.
+    let main_thread = match () {
+        #[cfg(feature = "_luaapi_51")]
+        () => raw_lua,
+        #[cfg(any(feature = "_luaapi_52", feature = "_luaapi_54"))]
+        () => {
+            ffi::lua_rawgeti(raw_lua, ffi::LUA_REGISTRYINDEX, ffi::LUA_RIDX_MAINTHREAD as _);
+            let thread = ffi::lua_tothread(raw_lua, -1);
+            ffi::lua_pop(raw_lua, 1);
+            thread
+        },
+    };
+
+    let state = Arc::new(AtomicPtr::new(main_thread));
+    ffi::lua_pushlstring(raw_lua, STATE_KEY.as_ptr().cast(), STATE_KEY.len());
+    push_userdata(StateGuard(state.clone()), lua, |_| {}).forget_internal();
+    ffi::lua_rawset(raw_lua, ffi::LUA_REGISTRYINDEX);
+    state
+}
+
+#[inline]
+fn key_ptr(key: &u8) -> *mut libc::c_void {
+    key as *const u8 as *mut libc::c_void
+}
+
+// Pushes the table of weak values, creating it if it doesn't exist yet.
+unsafe fn push_table(raw_lua: *mut ffi::lua_State) {
+    ffi::lua_pushlstring(raw_lua, REGISTRY_KEY.as_ptr().cast(), REGISTRY_KEY.len());
+    ffi::lua_rawget(raw_lua, ffi::LUA_REGISTRYINDEX);
+    if !ffi::lua_isnil(raw_lua, -1) {
+        return;
+    }
+    ffi::lua_pop(raw_lua, 1);
+
+    ffi::lua_createtable(raw_lua, 0, 0);
+    ffi::lua_createtable(raw_lua, 0, 1);
+    ffi::lua_pushlstring(raw_lua, "__mode".as_ptr().cast(), 6);
+    ffi::lua_pushlstring(raw_lua, "v".as_ptr().cast(), 1);
+    ffi::lua_rawset(raw_lua, -3);
+    ffi::lua_setmetatable(raw_lua, -2);
+
+    ffi::lua_pushlstring(raw_lua, REGISTRY_KEY.as_ptr().cast(), REGISTRY_KEY.len());
+    ffi::lua_pushvalue(raw_lua, -2);
+    ffi::lua_rawset(raw_lua, ffi::LUA_REGISTRYINDEX);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{AnyLuaValue, AsLua, Lua, LuaRead, LuaTable, Push, WeakLuaRef};
+
+    #[test]
+    fn weak_ref_follows_value() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.execute::<()>("a = { value = 5 }; b = { value = 6 }").unwrap();
+
+        let a = {
+            let mut a: LuaTable<_> = lua.get("a").unwrap();
+            WeakLuaRef::new(&mut a, -1)
+        };
+        let b = {
+            let mut b: LuaTable<_> = lua.get("b").unwrap();
+            WeakLuaRef::new(&mut b, -1)
+        };
+
+        {
+            let mut table = LuaTable::lua_read(a.get(&mut lua).unwrap()).ok().unwrap();
+            assert_eq!(table.get::<i32, _, _>("value"), Some(5));
+        }
+
+        lua.execute::<()>("a = nil; collectgarbage()").unwrap();
+        assert!(a.get(&mut lua).is_none());
+
+        // Creating a new reference must not expose the value of a collected one.
+        lua.execute::<()>("c = {}").unwrap();
+        let c = {
+            let mut c: LuaTable<_> = lua.get("c").unwrap();
+            WeakLuaRef::new(&mut c, -1)
+        };
+        assert!(a.get(&mut lua).is_none());
+        assert!(c.get(&mut lua).is_some());
+
+        {
+            let mut table = LuaTable::lua_read(b.get(&mut lua).unwrap()).ok().unwrap();
+            assert_eq!(table.get::<i32, _, _>("value"), Some(6));
+        }
+    }
+
+    #[test]
+    fn dropping_releases_entry() {
+        let mut lua = Lua::new();
+        let count = |lua: &mut Lua| {
+            let mut registry = LuaTable::registry(lua);
+            let mut table: LuaTable<_> = registry.get("hlua_weak_refs").unwrap();
+            table.iter::<AnyLuaValue, AnyLuaValue>().count()
+        };
+
+        let n = WeakLuaRef::new(&mut 5.push_no_err(&mut lua), -1);
+        let s = WeakLuaRef::new(&mut "hello".push_no_err(&mut lua), -1);
+        assert_eq!(count(&mut lua), 2);
+        drop(n);
+        assert_eq!(count(&mut lua), 1);
+        assert!(s.get(&mut lua).is_some());
+        drop(s);
+        assert_eq!(count(&mut lua), 0);
+    }
+
+    #[test]
+    fn drop_after_close() {
+        let mut lua = Lua::new();
+        let weak = WeakLuaRef::new(&mut 5.push_no_err(&mut lua), -1);
+        drop(lua);
+        drop(weak);
+    }
+}
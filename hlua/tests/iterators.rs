@@ -0,0 +1,68 @@
+//! Tests making sure that pushing iterators doesn't buffer them on the Rust side.
+
+extern crate hlua;
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+// Lua allocates through the C allocator, so everything going through this allocator while
+// pushing has been allocated by Rust code.
+struct TrackingAllocator;
+
+thread_local! {
+    static TRACKING: Cell<bool> = const { Cell::new(false) };
+    static LARGEST_ALLOC: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if TRACKING.with(Cell::get) {
+            LARGEST_ALLOC.with(|l| l.set(l.get().max(layout.size())));
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if TRACKING.with(Cell::get) {
+            LARGEST_ALLOC.with(|l| l.set(l.get().max(new_size)));
+        }
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: TrackingAllocator = TrackingAllocator;
+
+#[test]
+fn push_large_range_without_buffering() {
+    const LEN: i32 = 1_000_000;
+
+    let mut lua = hlua::Lua::new();
+
+    TRACKING.with(|t| t.set(true));
+    lua.set("a", hlua::IntoIteratorWrapper(0..LEN));
+    TRACKING.with(|t| t.set(false));
+
+    let largest = LARGEST_ALLOC.with(Cell::get);
+    assert!(largest < LEN as usize, "pushing allocated {} bytes at once", largest);
+
+    assert_eq!(lua.execute::<i32>("return #a").unwrap(), LEN);
+    assert_eq!(lua.execute::<i32>("return a[1]").unwrap(), 0);
+    assert_eq!(lua.execute::<i32>("return a[#a]").unwrap(), LEN - 1);
+}
+
+#[test]
+fn push_mapped_iterator() {
+    let mut lua = hlua::Lua::new();
+
+    lua.set("a", hlua::IntoIteratorWrapper((1..=5).map(|n| n * n)));
+
+    let read: Vec<i32> = lua.get("a").unwrap();
+    assert_eq!(read, [1, 4, 9, 16, 25]);
+}
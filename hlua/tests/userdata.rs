@@ -68,6 +68,37 @@ fn destructor_called() {
     assert!(*locked);
 }
 
+#[test]
+fn destructor_not_called_without_drop() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct Foo(Arc<AtomicUsize>);
+
+    impl Drop for Foo {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let drops = Arc::new(AtomicUsize::new(0));
+
+    {
+        let mut lua = hlua::Lua::new();
+        let mut first = hlua::push_userdata_no_drop(Foo(drops.clone()), &mut lua, |_| {});
+        // Pushing the same type with `push_userdata` must still run its destructor.
+        let mut second = hlua::push_userdata(Foo(drops.clone()), &mut first, |_| {});
+        let third = hlua::push_userdata_no_drop(Foo(drops.clone()), &mut second, |_| {});
+
+        let ty = std::any::TypeId::of::<Foo>();
+        assert_eq!(hlua::userdata_type_id(&third, -1), Some(ty));
+        assert_eq!(hlua::userdata_type_id(&third, -2), Some(ty));
+        assert_eq!(hlua::userdata_type_id(&third, -3), Some(ty));
+    }
+
+    assert_eq!(drops.load(Ordering::SeqCst), 1);
+}
+
 #[test]
 fn type_check() {
     #[derive(Clone)]
@@ -259,6 +290,28 @@ fn metatables_reused() {
     assert!(equals);
 }
 
+#[test]
+fn metatables_reused_across_threads() {
+    struct Foo;
+    implement_lua_push!(Foo, |mut meta| meta.set("__eq", hlua::function0(|| true)));
+
+    let mut lua = hlua::Lua::new();
+    lua.set("a", Foo);
+
+    // Another thread finds the same key for the metatable of `Foo`.
+    std::thread::spawn(|| {
+        let mut lua = hlua::Lua::new();
+        lua.set("a", Foo);
+        lua.set("b", Foo);
+        assert!(lua.execute::<bool>("return a == b").unwrap());
+    })
+    .join()
+    .unwrap();
+
+    lua.set("b", Foo);
+    assert!(lua.execute::<bool>("return a == b").unwrap());
+}
+
 #[test]
 fn metatables_different() {
     struct Foo;
@@ -279,6 +332,66 @@ fn metatables_different() {
     assert_eq!(is_20, 20);
 }
 
+#[test]
+fn metatable_builder() {
+    struct Foo {
+        name: String,
+    }
+
+    implement_lua_read!(Foo);
+    implement_lua_push!(Foo, |meta| {
+        hlua::MetatableBuilder::new(meta)
+            .method("greet", |this: &Foo| format!("hello {}", this.name))
+            .method("rename", |this: &mut Foo, name: String| this.name = name)
+            .field("answer", 42)
+            .tostring(|this: &Foo| format!("Foo({})", this.name));
+    });
+
+    let mut lua = hlua::Lua::new();
+    lua.openlibs();
+    lua.set("foo", Foo { name: "bob".to_owned() });
+
+    assert_eq!(lua.execute::<String>("return foo:greet()").unwrap(), "hello bob");
+    assert_eq!(lua.execute::<i32>("return foo.answer").unwrap(), 42);
+    assert_eq!(lua.execute::<String>("return tostring(foo)").unwrap(), "Foo(bob)");
+
+    lua.execute::<()>("foo:rename('alice')").unwrap();
+    assert_eq!(lua.execute::<String>("return foo:greet()").unwrap(), "hello alice");
+}
+
+#[test]
+fn metatable_builder_getters_setters() {
+    struct Point {
+        x: f64,
+        y: f64,
+    }
+
+    implement_lua_read!(Point);
+    implement_lua_push!(Point, |meta| {
+        hlua::MetatableBuilder::new(meta)
+            .method("norm", |p: &Point| (p.x * p.x + p.y * p.y).sqrt())
+            .getter("x", |p: &Point| p.x)
+            .setter("x", |p: &mut Point, x: f64| p.x = x)
+            .getter("y", |p: &Point| p.y)
+            .setter("y", |p: &mut Point, y: f64| p.y = y)
+            .field("dimensions", 2);
+    });
+
+    let mut lua = hlua::Lua::new();
+    lua.set("p", Point { x: 1.0, y: 4.0 });
+
+    lua.execute::<()>("p.x = p.x + 2").unwrap();
+    assert_eq!(lua.execute::<f64>("return p.x").unwrap(), 3.0);
+    assert_eq!(lua.execute::<f64>("return p:norm()").unwrap(), 5.0);
+    assert_eq!(lua.execute::<i32>("return p.dimensions").unwrap(), 2);
+    assert!(lua.execute::<bool>("return p.z == nil").unwrap());
+
+    match lua.execute::<()>("p.z = 1") {
+        Err(hlua::LuaError::ExecutionError(msg)) => assert!(msg.contains("'z'"), "{}", msg),
+        _ => panic!("assigning to an unknown field should fail"),
+    }
+}
+
 #[test]
 fn valid_alignment() {
     macro_rules! validate_alignment {
@@ -362,3 +475,310 @@ fn valid_zst_alignment() {
     validate_alignment!(lua, 8192);
     validate_alignment!(lua, 16384);
 }
+
+#[test]
+fn scoped_userdata() {
+    struct Counter {
+        value: u32,
+    }
+    implement_lua_read!(Counter);
+
+    let mut lua = hlua::Lua::new();
+    let mut counter = Counter { value: 5 };
+
+    let read = lua.scope(|scope| {
+        scope.set_userdata("counter", &mut counter, |meta| {
+            hlua::MetatableBuilder::new(meta)
+                .method("incr", |c: &mut Counter| c.value += 1)
+                .method("get", |c: &Counter| c.value);
+        });
+        scope.execute::<()>("kept = counter; counter:incr()").unwrap();
+        scope.execute::<u32>("return kept:get()").unwrap()
+    });
+
+    assert_eq!(read, 6);
+    assert_eq!(counter.value, 6);
+
+    // Lua still holds the userdata, but it doesn't point to `counter` anymore.
+    match lua.execute::<()>("kept:incr()") {
+        Err(hlua::LuaError::ExecutionError(_)) => {},
+        _ => panic!("using userdata after its scope ended should fail"),
+    }
+    assert!(lua.get::<hlua::UserdataOnStack<Counter, _>, _>("kept").is_none());
+    assert_eq!(counter.value, 6);
+}
+
+#[test]
+fn userdata_identity() {
+    use std::collections::HashMap;
+
+    struct Foo;
+    implement_lua_push!(Foo, |_| {});
+    implement_lua_read!(Foo);
+
+    let mut lua = hlua::Lua::new();
+    lua.set("a", Foo);
+    lua.set("c", Foo);
+    lua.execute::<()>("b = a").unwrap();
+
+    let mut names = HashMap::new();
+    for name in ["a", "b", "c"] {
+        let id = lua.get::<hlua::UserdataOnStack<Foo, _>, _>(name).unwrap().identity();
+        names.entry(id).or_insert_with(Vec::new).push(name);
+    }
+
+    let mut groups: Vec<_> = names.into_values().collect();
+    groups.sort();
+    assert_eq!(groups, [vec!["a", "b"], vec!["c"]]);
+}
+
+#[test]
+fn userdata_type_id() {
+    use hlua::Push;
+    use std::any::TypeId;
+
+    struct Foo;
+    implement_lua_push!(Foo, |_| {});
+    struct Bar(#[allow(dead_code)] u64);
+    implement_lua_push!(Bar, |_| {});
+
+    let mut lua = hlua::Lua::new();
+
+    let guard = (Foo, Bar(5), 12, "hello").push_to_lua(&mut lua).ok().unwrap();
+    assert_eq!(hlua::userdata_type_id(&guard, -4), Some(TypeId::of::<Foo>()));
+    assert_eq!(hlua::userdata_type_id(&guard, -3), Some(TypeId::of::<Bar>()));
+    assert_eq!(hlua::userdata_type_id(&guard, -2), None);
+    assert_eq!(hlua::userdata_type_id(&guard, -1), None);
+    drop(guard);
+
+    // Userdata that hlua didn't create are rejected.
+    unsafe {
+        let raw = hlua::AsMutLua::as_mut_lua(&mut lua).as_ptr();
+        hlua::ffi::lua_newuserdata(raw, 64);
+        assert_eq!(hlua::userdata_type_id(&lua, -1), None);
+        hlua::ffi::lua_pop(raw, 1);
+    }
+}
+
+#[test]
+fn shared_userdata_locks() {
+    use hlua::{Locked, MetatableBuilder, SharedUserdata};
+    use std::sync::{Arc, Mutex};
+
+    struct Counter {
+        value: u32,
+    }
+
+    fn metatable(meta: hlua::LuaTable<hlua::OpaqueLua>) {
+        MetatableBuilder::new(meta)
+            .method("incr", |mut c: Locked<Counter>| c.value += 1)
+            .method("get", |c: Locked<Counter>| c.value);
+    }
+
+    let counter = Arc::new(Mutex::new(Counter { value: 0 }));
+
+    let mut lua = hlua::Lua::new();
+    lua.set("a", SharedUserdata::new(counter.clone(), metatable));
+    lua.set("b", SharedUserdata::new(counter.clone(), metatable));
+
+    lua.execute::<()>("a:incr(); b:incr()").unwrap();
+    counter.lock().unwrap().value += 10;
+    assert_eq!(lua.execute::<u32>("return a:get()").unwrap(), 12);
+
+    // The lock must have been released once the callbacks returned.
+    assert!(counter.try_lock().is_ok());
+
+    let poisoner = counter.clone();
+    std::thread::spawn(move || {
+        let _guard = poisoner.lock().unwrap();
+        panic!("poisoning the mutex");
+    })
+    .join()
+    .unwrap_err();
+
+    assert!(lua.execute::<u32>("return a:get()").is_err());
+}
+
+#[test]
+fn shared_userdata_locked_twice() {
+    use hlua::{Locked, SharedUserdata};
+    use std::sync::{Arc, Mutex};
+
+    struct Counter {
+        value: u32,
+    }
+
+    let counter = Arc::new(Mutex::new(Counter { value: 1 }));
+
+    let mut lua = hlua::Lua::new();
+    lua.set("a", SharedUserdata::new(counter.clone(), |_| ()));
+    lua.set("b", SharedUserdata::new(counter.clone(), |_| ()));
+    lua.set("sum", hlua::function2(|a: Locked<Counter>, b: Locked<Counter>| a.value + b.value));
+
+    // Both arguments point to the same mutex, so the second lock must fail
+    // instead of deadlocking.
+    assert!(lua.execute::<u32>("return sum(a, a)").is_err());
+    assert!(lua.execute::<u32>("return sum(a, b)").is_err());
+    assert!(counter.try_lock().is_ok());
+}
+
+#[test]
+fn shared_userdata_locked_by_another_thread() {
+    use hlua::{Locked, SharedUserdata};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::{thread, time::Duration};
+
+    let counter = Arc::new(Mutex::new(1u32));
+
+    let mut lua = hlua::Lua::new();
+    lua.set("a", SharedUserdata::new(counter.clone(), |_| ()));
+    lua.set("incr", hlua::function1(|mut a: Locked<u32>| *a += 1));
+
+    // The call waits for the other thread to release the lock instead of failing.
+    let (locked_tx, locked_rx) = mpsc::channel();
+    let other = counter.clone();
+    let holder = thread::spawn(move || {
+        let mut value = other.lock().unwrap();
+        locked_tx.send(()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        *value *= 10;
+    });
+    locked_rx.recv().unwrap();
+    lua.execute::<()>("incr(a)").unwrap();
+    holder.join().unwrap();
+
+    assert_eq!(*counter.lock().unwrap(), 11);
+}
+
+#[test]
+fn weak_userdata_invalidates() {
+    use hlua::{LuaError, MetatableBuilder, Upgraded, WeakUserdata};
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    struct Counter {
+        value: AtomicU32,
+    }
+
+    fn metatable(meta: hlua::LuaTable<hlua::OpaqueLua>) {
+        MetatableBuilder::new(meta)
+            .method("incr", |c: Upgraded<Counter>| c.value.fetch_add(1, Ordering::SeqCst))
+            .method("get", |c: Upgraded<Counter>| c.value.load(Ordering::SeqCst));
+    }
+
+    let counter = Arc::new(Counter { value: AtomicU32::new(0) });
+
+    let mut lua = hlua::Lua::new();
+    lua.set("a", WeakUserdata::new(&counter, metatable));
+    lua.set("b", WeakUserdata::new(&counter, metatable));
+    lua.set(
+        "read",
+        hlua::function1(|c: Upgraded<Counter>| Arc::strong_count(&c.into_inner()) as u32),
+    );
+
+    lua.execute::<()>("a:incr(); b:incr()").unwrap();
+    assert_eq!(lua.execute::<u32>("return a:get()").unwrap(), 2);
+    // Lua doesn't keep the object alive, and the callbacks release it when they return.
+    assert_eq!(lua.execute::<u32>("return read(a)").unwrap(), 2);
+    assert_eq!(Arc::strong_count(&counter), 1);
+
+    drop(counter);
+    match lua.execute::<u32>("return a:get()") {
+        Err(LuaError::ExecutionError(msg)) => {
+            assert!(msg.contains("object that has been dropped"), "{}", msg)
+        },
+        other => panic!("{:?}", other),
+    }
+    assert!(lua.execute::<()>("b:incr()").is_err());
+    // Values of other types still give the generic error.
+    match lua.execute::<u32>("return read(5)") {
+        Err(LuaError::ExecutionError(msg)) => assert!(msg.contains("wrong parameter types")),
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn dyn_userdata_from_registered_types() {
+    use hlua::{DynUserdata, UserdataCasts};
+
+    trait Shape {
+        fn name(&self) -> String;
+    }
+
+    #[derive(Clone)]
+    struct Square(u32);
+    impl Shape for Square {
+        fn name(&self) -> String {
+            format!("square {}", self.0)
+        }
+    }
+    implement_lua_push!(Square, |_| {});
+
+    #[derive(Clone)]
+    struct Circle(u32);
+    impl Shape for Circle {
+        fn name(&self) -> String {
+            format!("circle {}", self.0)
+        }
+    }
+    implement_lua_push!(Circle, |_| {});
+
+    struct Unregistered;
+    implement_lua_push!(Unregistered, |_| {});
+
+    let mut lua = hlua::Lua::new();
+    lua.set("draw", hlua::function1(|shape: DynUserdata<dyn Shape>| shape.name()));
+    lua.set("square", Square(2));
+    lua.set("circle", Circle(3));
+    lua.set("other", Unregistered);
+
+    // Nothing is installed yet.
+    assert!(lua.execute::<String>("return draw(square)").is_err());
+
+    lua.set_userdata_casts(
+        UserdataCasts::<dyn Shape>::new()
+            .register(|s: &Square| Box::new(s.clone()))
+            .register(|c: &Circle| Box::new(c.clone())),
+    );
+    assert_eq!(lua.execute::<String>("return draw(square)").unwrap(), "square 2");
+    assert_eq!(lua.execute::<String>("return draw(circle)").unwrap(), "circle 3");
+    assert!(lua.execute::<String>("return draw(other)").is_err());
+    assert!(lua.execute::<String>("return draw(5)").is_err());
+
+    let mut scoped = Square(7);
+    let name = lua.scope(|scope| {
+        scope.set_userdata("scoped", &mut scoped, |_| {});
+        scope.execute::<String>("return draw(scoped)").unwrap()
+    });
+    assert_eq!(name, "square 7");
+
+    // Replacing the conversions drops the previous ones.
+    lua.set_userdata_casts(
+        UserdataCasts::<dyn Shape>::new().register(|c: &Circle| Box::new(c.clone())),
+    );
+    assert!(lua.execute::<String>("return draw(square)").is_err());
+}
+
+#[test]
+fn userdata_in_table_argument() {
+    struct Foo {
+        value: i32,
+    }
+    implement_lua_push!(Foo, |_| {});
+    implement_lua_read!(Foo);
+
+    let mut lua = hlua::Lua::new();
+    lua.set("obj1", Foo { value: 3 });
+    lua.set("obj2", Foo { value: 4 });
+    lua.set("sum", hlua::function1(|foos: Vec<&Foo>| foos.iter().map(|f| f.value).sum::<i32>()));
+    lua.set("first", hlua::function1(|foos: [&Foo; 2]| foos[0].value));
+
+    let sum: i32 = lua.execute("return sum({ obj1, obj2 })").unwrap();
+    assert_eq!(sum, 7);
+    assert_eq!(lua.execute::<i32>("return first({ obj2, obj1 })").unwrap(), 4);
+
+    // Values that aren't a `Foo` are rejected.
+    assert!(lua.execute::<i32>("return sum({ obj1, 5 })").is_err());
+}